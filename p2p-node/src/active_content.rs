@@ -0,0 +1,234 @@
+/// Uploads a browser would execute if served from our origin: HTML, XHTML,
+/// and SVG that carries script (`BLOCK_ACTIVE_CONTENT`).
+///
+/// Both the declared content type and the leading bytes are checked, so HTML
+/// declared as `image/png` is still caught.
+pub fn is_active(content_type: &str, bytes: &[u8]) -> bool {
+    let declared = essence(content_type);
+    if matches!(declared.as_str(), "text/html" | "application/xhtml+xml") {
+        return true;
+    }
+    if sniff(bytes) == Some("text/html") {
+        return true;
+    }
+    let svg = declared == "image/svg+xml" || head_text(bytes).contains("<svg");
+    svg && has_script(&String::from_utf8_lossy(bytes).to_ascii_lowercase())
+}
+
+/// The content type the leading bytes identify, for formats with an
+/// unambiguous signature plus HTML and SVG prologues. `None` when nothing
+/// matched.
+pub fn sniff(bytes: &[u8]) -> Option<&'static str> {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xFF\xD8\xFF", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"BM", "image/bmp"),
+        (b"%PDF-", "application/pdf"),
+        (b"\x1F\x8B", "application/gzip"),
+        (b"PK\x03\x04", "application/zip"),
+    ];
+    if bytes.len() >= 12 && &bytes[..4] == b"RIFF" && &bytes[8..12] == b"WEBP" {
+        return Some("image/webp");
+    }
+    if let Some((_, ty)) = SIGNATURES.iter().find(|(sig, _)| bytes.starts_with(sig)) {
+        return Some(ty);
+    }
+    let head = head_text(bytes);
+    let start = head.trim_start_matches('\u{feff}').trim_start();
+    if ["<!doctype html", "<html", "<script"]
+        .iter()
+        .any(|tag| start.starts_with(tag))
+    {
+        return Some("text/html");
+    }
+    (start.starts_with("<svg") || (start.starts_with("<?xml") && start.contains("<svg")))
+        .then_some("image/svg+xml")
+}
+
+/// The first `SNIFF_LEN` bytes as lowercase text.
+fn head_text(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(&bytes[..bytes.len().min(SNIFF_LEN)]).to_ascii_lowercase()
+}
+
+/// Declared types that should never render inline from our origin. SVG is
+/// included whatever its content, since any SVG may carry script.
+pub fn is_active_type(content_type: &str) -> bool {
+    matches!(
+        essence(content_type).as_str(),
+        "text/html" | "application/xhtml+xml" | "image/svg+xml"
+    )
+}
+
+/// Leading bytes needed to recognise every format [`sniff`] knows.
+pub const SNIFF_LEN: usize = 512;
+
+/// `type/subtype` without parameters, lowercased.
+pub fn essence(content_type: &str) -> String {
+    content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase()
+}
+
+/// Script elements, `javascript:` URLs or `on*=` event handler attributes.
+///
+/// URLs are matched the way a browser reads them: character references
+/// decoded (`javascript&#58;`), then tabs and newlines dropped.
+fn has_script(markup: &str) -> bool {
+    if markup.contains("<script") {
+        return true;
+    }
+    let urls: String = decode_entities(markup)
+        .chars()
+        .filter(|c| !matches!(c, '\t' | '\n' | '\r'))
+        .collect();
+    if urls.to_ascii_lowercase().contains("javascript:") {
+        return true;
+    }
+    markup.match_indices("on").any(|(i, _)| {
+        let preceded = markup[..i]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_whitespace() || c == '/');
+        let rest = &markup[i + 2..];
+        let name_len = rest.chars().take_while(|c| c.is_ascii_alphabetic()).count();
+        preceded && name_len > 0 && rest[name_len..].trim_start().starts_with('=')
+    })
+}
+
+/// `markup` with numeric (`&#58;`, `&#x3a;`) and a few named (`&colon;`)
+/// character references replaced by their characters. Anything else is
+/// left as it is.
+fn decode_entities(markup: &str) -> String {
+    let mut out = String::with_capacity(markup.len());
+    let mut rest = markup;
+    while let Some(i) = rest.find('&') {
+        out.push_str(&rest[..i]);
+        match decode_entity(&rest[i + 1..]) {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[i + 1 + len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The character the reference at the start of `s` (just past its `&`)
+/// stands for, and its length. The `;` is optional after a number, as
+/// browsers allow.
+fn decode_entity(s: &str) -> Option<(char, usize)> {
+    const NAMED: &[(&str, char)] = &[
+        ("colon;", ':'),
+        ("tab;", '\t'),
+        ("newline;", '\n'),
+        ("lpar;", '('),
+        ("rpar;", ')'),
+        ("lt;", '<'),
+        ("gt;", '>'),
+        ("quot;", '"'),
+        ("apos;", '\''),
+        ("amp;", '&'),
+    ];
+    if let Some(num) = s.strip_prefix('#') {
+        let (digits, radix, prefix) = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => (hex, 16, 2),
+            None => (num, 10, 1),
+        };
+        let len = digits.chars().take_while(|c| c.is_digit(radix)).count();
+        let c = u32::from_str_radix(digits.get(..len).filter(|d| !d.is_empty())?, radix)
+            .ok()
+            .and_then(char::from_u32)?;
+        let semicolon = usize::from(digits[len..].starts_with(';'));
+        return Some((c, prefix + len + semicolon));
+    }
+    NAMED
+        .iter()
+        .find(|(name, _)| {
+            s.get(..name.len())
+                .is_some_and(|head| head.eq_ignore_ascii_case(name))
+        })
+        .map(|&(name, c)| (c, name.len()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_is_active_by_type_or_content() {
+        assert!(is_active("text/html; charset=utf-8", b"hello"));
+        assert!(is_active("application/xhtml+xml", b""));
+        assert!(is_active("image/png", b"\n  <!DOCTYPE html><p>hi"));
+        assert!(!is_active("image/png", b"\x89PNG\r\n\x1a\n"));
+    }
+
+    #[test]
+    fn only_scripted_svg_is_active() {
+        let plain = br#"<svg xmlns="http://www.w3.org/2000/svg"><rect width="1"/></svg>"#;
+        assert!(!is_active("image/svg+xml", plain));
+        assert!(is_active(
+            "image/svg+xml",
+            b"<svg><script>alert(1)</script></svg>"
+        ));
+        assert!(is_active(
+            "image/svg+xml",
+            b"<svg onload = \"alert(1)\"></svg>"
+        ));
+        // Sniffed even when declared as something else
+        assert!(is_active(
+            "application/octet-stream",
+            b"<?xml version=\"1.0\"?><svg><a href=\"javascript:x()\"/></svg>"
+        ));
+        // Character references and tabs don't hide a javascript: URL
+        for href in [
+            "javascript&#58;x()",
+            "javascript&#x3A;x()",
+            "javascript&#0000058x()",
+            "javascript&colon;x()",
+            "&#106;avascript:x()",
+            "java&#9;script:x()",
+            "java\nscript:x()",
+        ] {
+            let svg = format!("<svg><a href=\"{href}\"/></svg>");
+            assert!(is_active("image/svg+xml", svg.as_bytes()), "{href}");
+        }
+        assert!(!is_active(
+            "image/svg+xml",
+            b"<svg><text>fish &amp; chips &#9731; &bogus;</text></svg>"
+        ));
+        // "on" inside words isn't an event handler
+        assert!(!is_active(
+            "image/svg+xml",
+            b"<svg><text>button=1</text></svg>"
+        ));
+    }
+
+    #[test]
+    fn sniffs_signatures() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n...."), Some("image/png"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(
+            sniff(b"\n<?xml version=\"1.0\"?><svg/>"),
+            Some("image/svg+xml")
+        );
+        assert_eq!(sniff(b"\xEF\xBB\xBF<!DOCTYPE html>"), Some("text/html"));
+        assert_eq!(sniff(b"plain words"), None);
+        assert_eq!(sniff(b""), None);
+    }
+
+    #[test]
+    fn svg_type_is_served_as_attachment() {
+        assert!(is_active_type("IMAGE/SVG+XML"));
+        assert!(!is_active_type("image/png"));
+    }
+}
@@ -0,0 +1,105 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderValue},
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+
+pub const MSGPACK: &str = "application/msgpack";
+
+/// Wire format for API responses, negotiated from the `Accept` header.
+///
+/// JSON is the default; `Accept: application/msgpack` (or `x-msgpack`) opts
+/// into MessagePack, which roughly halves `/status` for frequent pollers.
+/// An entry with `q=0` refuses the type rather than asking for it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiFormat {
+    Json,
+    MsgPack,
+}
+
+impl ApiFormat {
+    pub fn from_accept(accept: Option<&str>) -> Self {
+        let wants_msgpack = accept.is_some_and(|a| {
+            a.split(',').any(|entry| {
+                let mut parts = entry.split(';').map(str::trim);
+                let m = parts.next().unwrap_or_default();
+                let q = parts
+                    .find_map(|p| p.strip_prefix("q="))
+                    .and_then(|q| q.parse::<f32>().ok())
+                    .unwrap_or(1.0);
+                (m.eq_ignore_ascii_case(MSGPACK) || m.eq_ignore_ascii_case("application/x-msgpack"))
+                    && q > 0.0
+            })
+        });
+        if wants_msgpack {
+            ApiFormat::MsgPack
+        } else {
+            ApiFormat::Json
+        }
+    }
+
+    /// Serialize `value` in this format. MessagePack keeps field names so
+    /// clients decode it into the same shape as the JSON.
+    pub fn render<T: Serialize>(self, value: &T) -> Response {
+        match self {
+            ApiFormat::Json => Json(value).into_response(),
+            ApiFormat::MsgPack => match rmp_serde::to_vec_named(value) {
+                Ok(body) => (
+                    [(header::CONTENT_TYPE, HeaderValue::from_static(MSGPACK))],
+                    body,
+                )
+                    .into_response(),
+                Err(e) => {
+                    (axum::http::StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+                }
+            },
+        }
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ApiFormat {
+    type Rejection = std::convert::Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let accept = parts
+            .headers
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok());
+        Ok(ApiFormat::from_accept(accept))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_from_accept() {
+        assert_eq!(ApiFormat::from_accept(None), ApiFormat::Json);
+        assert_eq!(ApiFormat::from_accept(Some("*/*")), ApiFormat::Json);
+        assert_eq!(
+            ApiFormat::from_accept(Some("application/json")),
+            ApiFormat::Json
+        );
+        assert_eq!(
+            ApiFormat::from_accept(Some("application/msgpack")),
+            ApiFormat::MsgPack
+        );
+        assert_eq!(
+            ApiFormat::from_accept(Some("application/json;q=0.5, application/x-msgpack")),
+            ApiFormat::MsgPack
+        );
+        assert_eq!(
+            ApiFormat::from_accept(Some("application/msgpack;q=0, application/json")),
+            ApiFormat::Json
+        );
+        assert_eq!(
+            ApiFormat::from_accept(Some("application/msgpack; q=0.0")),
+            ApiFormat::Json
+        );
+    }
+}
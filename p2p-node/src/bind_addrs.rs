@@ -0,0 +1,105 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use iroh_base::NodeAddr;
+
+/// IPs the iroh endpoint may use (`BIND_ADDRS`), for multi-homed hosts whose
+/// VPN or virtual interfaces would otherwise be advertised to peers.
+///
+/// With exactly one address of a family the endpoint binds to it; with more
+/// it binds the wildcard for that family and only filters what it advertises.
+/// A family with no listed address isn't advertised at all.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BindAddrs {
+    ips: Vec<IpAddr>,
+}
+
+impl BindAddrs {
+    /// Parse a comma-separated IP list. Returns `None` when it names no valid
+    /// address, along with the entries that didn't parse.
+    pub fn parse(list: &str) -> (Option<Self>, Vec<String>) {
+        let mut ips = Vec::new();
+        let mut invalid = Vec::new();
+        for s in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match s.parse::<IpAddr>() {
+                Ok(ip) if !ips.contains(&ip) => ips.push(ip),
+                Ok(_) => {}
+                Err(_) => invalid.push(s.to_string()),
+            }
+        }
+        ((!ips.is_empty()).then_some(Self { ips }), invalid)
+    }
+
+    /// The IPv4 address to bind, when exactly one is listed.
+    pub fn v4(&self) -> Option<Ipv4Addr> {
+        match self
+            .ips
+            .iter()
+            .filter(|ip| ip.is_ipv4())
+            .collect::<Vec<_>>()[..]
+        {
+            [IpAddr::V4(ip)] => Some(*ip),
+            _ => None,
+        }
+    }
+
+    /// The IPv6 address to bind, when exactly one is listed.
+    pub fn v6(&self) -> Option<Ipv6Addr> {
+        match self
+            .ips
+            .iter()
+            .filter(|ip| ip.is_ipv6())
+            .collect::<Vec<_>>()[..]
+        {
+            [IpAddr::V6(ip)] => Some(*ip),
+            _ => None,
+        }
+    }
+
+    pub fn permits(&self, addr: &SocketAddr) -> bool {
+        self.ips.contains(&addr.ip())
+    }
+
+    /// `addr` with every direct address outside the list removed.
+    pub fn filter(&self, mut addr: NodeAddr) -> NodeAddr {
+        addr.direct_addresses.retain(|a| self.permits(a));
+        addr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_single_address_per_family() {
+        let (addrs, invalid) = BindAddrs::parse("192.168.1.5, eth0, ::1");
+        let addrs = addrs.unwrap();
+        assert_eq!(invalid, vec!["eth0"]);
+        assert_eq!(addrs.v4(), Some(Ipv4Addr::new(192, 168, 1, 5)));
+        assert_eq!(addrs.v6(), Some(Ipv6Addr::LOCALHOST));
+
+        let (addrs, _) = BindAddrs::parse("10.0.0.1,10.0.0.2");
+        let addrs = addrs.unwrap();
+        assert_eq!(addrs.v4(), None);
+        assert_eq!(addrs.v6(), None);
+        assert_eq!(BindAddrs::parse(" , nope").0, None);
+    }
+
+    #[test]
+    fn filters_advertised_addresses() {
+        let (addrs, _) = BindAddrs::parse("192.168.1.5");
+        let node = iroh::SecretKey::generate(rand::thread_rng()).public();
+        let addr = NodeAddr::new(node).with_direct_addresses([
+            "192.168.1.5:4000".parse().unwrap(),
+            "10.8.0.2:4000".parse().unwrap(),
+            "[fe80::1]:4000".parse().unwrap(),
+        ]);
+        let filtered = addrs.unwrap().filter(addr);
+        let kept: Vec<String> = filtered
+            .direct_addresses
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(kept, ["192.168.1.5:4000"]);
+    }
+}
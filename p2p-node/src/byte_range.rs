@@ -0,0 +1,82 @@
+/// How to answer a request's `Range` header against a resource of known length.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ByteRange {
+    /// Serve the whole resource with 200: no header, a malformed one, or a
+    /// multi-range request.
+    Full,
+    /// Serve `start..=end` with 206.
+    Partial { start: u64, end: u64 },
+    /// Answer 416: the range lies entirely past the end.
+    Unsatisfiable,
+}
+
+impl ByteRange {
+    /// Interpret a `Range` header value (`bytes=0-99`, `bytes=100-`,
+    /// `bytes=-100`) for a resource of `len` bytes. An end past the last byte
+    /// is clamped to it.
+    pub fn parse(header: &str, len: u64) -> Self {
+        let Some(spec) = header.trim().strip_prefix("bytes=") else {
+            return ByteRange::Full;
+        };
+        if spec.contains(',') {
+            return ByteRange::Full;
+        }
+        let Some((first, last)) = spec.trim().split_once('-') else {
+            return ByteRange::Full;
+        };
+        let (first, last) = (first.trim(), last.trim());
+        let (start, end) = if first.is_empty() {
+            // Suffix range: the final `last` bytes
+            let Ok(suffix) = last.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            if suffix == 0 || len == 0 {
+                return ByteRange::Unsatisfiable;
+            }
+            (len.saturating_sub(suffix), len - 1)
+        } else {
+            let Ok(start) = first.parse::<u64>() else {
+                return ByteRange::Full;
+            };
+            let end = if last.is_empty() {
+                u64::MAX
+            } else {
+                match last.parse::<u64>() {
+                    Ok(end) if end >= start => end,
+                    _ => return ByteRange::Full,
+                }
+            };
+            if start >= len {
+                return ByteRange::Unsatisfiable;
+            }
+            (start, end.min(len - 1))
+        };
+        ByteRange::Partial { start, end }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_ranges() {
+        let partial = |start, end| ByteRange::Partial { start, end };
+        assert_eq!(ByteRange::parse("bytes=0-4", 10), partial(0, 4));
+        assert_eq!(ByteRange::parse("bytes=6-", 10), partial(6, 9));
+        assert_eq!(ByteRange::parse("bytes=-3", 10), partial(7, 9));
+        assert_eq!(ByteRange::parse("bytes=-30", 10), partial(0, 9));
+        assert_eq!(ByteRange::parse("bytes=8-100", 10), partial(8, 9));
+    }
+
+    #[test]
+    fn falls_back_or_refuses() {
+        assert_eq!(ByteRange::parse("bytes=0-1,4-5", 10), ByteRange::Full);
+        assert_eq!(ByteRange::parse("items=0-1", 10), ByteRange::Full);
+        assert_eq!(ByteRange::parse("bytes=5-2", 10), ByteRange::Full);
+        assert_eq!(ByteRange::parse("bytes=x-", 10), ByteRange::Full);
+        assert_eq!(ByteRange::parse("bytes=10-", 10), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=-0", 10), ByteRange::Unsatisfiable);
+        assert_eq!(ByteRange::parse("bytes=0-", 0), ByteRange::Unsatisfiable);
+    }
+}
@@ -4,20 +4,95 @@ use iroh_blobs::{
     protocol::{ChunkRanges, ChunkRangesExt, GetRequest},
     Hash,
 };
-use rand::{seq::SliceRandom, thread_rng, Rng};
+use rand::{seq::SliceRandom, Rng};
+use serde::Serialize;
 
-/// Build a randomized list of `GetRequest`s covering the blob in fixed-size chunks.
+/// How many stripes each provider should get, so a slow provider only holds
+/// back a small share of the blob.
+const TARGET_STRIPES_PER_PROVIDER: u64 = 4;
+
+/// Largest stripe span a caller may request (64 MiB of chunks).
+pub const MAX_STRIPE_SPAN: u64 = 1 << 16;
+
+/// Smallest stripe worth a separate request (16 chunks = 16 KiB); anything
+/// finer spends more on request overhead than it gains in parallelism.
+const MIN_STRIPE_CHUNKS: u64 = 16;
+
+/// Pick a `stripe_span` for [`randomized_get_requests`] given the blob size and
+/// how many providers will share it.
 ///
-/// This helper is experimental and not wired into the production download flow.
-/// It can be used to explore alternative striping strategies where the chunk
-/// order is shuffled before issuing fetches to peers.
-#[allow(dead_code)]
-pub fn randomized_get_requests(hash: Hash, total_chunks: u64, stripe_span: u64) -> Vec<GetRequest> {
-    let mut rng = thread_rng();
-    randomized_get_requests_with_rng(hash, total_chunks, stripe_span, &mut rng)
+/// Aims for [`TARGET_STRIPES_PER_PROVIDER`] stripes per provider, but never
+/// goes below [`MIN_STRIPE_CHUNKS`] (so many providers don't over-fragment a
+/// small blob) and never above the blob itself (so tiny blobs are one stripe).
+pub fn recommended_stripe_span(total_chunks: u64, provider_count: usize) -> u64 {
+    if total_chunks == 0 {
+        return 1;
+    }
+    let providers = (provider_count as u64).max(1);
+    let stripes = providers.saturating_mul(TARGET_STRIPES_PER_PROVIDER);
+    let span = total_chunks.div_ceil(stripes);
+    span.max(MIN_STRIPE_CHUNKS).min(total_chunks)
+}
+
+/// A caller-requested stripe span, if it is in `1..=MAX_STRIPE_SPAN`.
+pub fn validate_stripe_span(span: u64) -> Result<u64, String> {
+    if (1..=MAX_STRIPE_SPAN).contains(&span) {
+        Ok(span)
+    } else {
+        Err(format!(
+            "stripe_span must be between 1 and {MAX_STRIPE_SPAN}"
+        ))
+    }
 }
 
-/// Same as [`randomized_get_requests`] but accepts an explicit RNG for testing.
+/// The randomized stripe plan for one transfer: `span_override` when the
+/// caller gave one, otherwise [`recommended_stripe_span`] for the provider
+/// count.
+///
+/// The plan behind `DOWNLOAD_MODE=chunk_stripe`: shuffling the stripe order
+/// spreads each provider's share across the whole blob.
+pub fn stripe_plan<R: Rng + ?Sized>(
+    hash: Hash,
+    total_chunks: u64,
+    provider_count: usize,
+    span_override: Option<u64>,
+    rng: &mut R,
+) -> Vec<GetRequest> {
+    let span =
+        span_override.unwrap_or_else(|| recommended_stripe_span(total_chunks, provider_count));
+    randomized_get_requests_with_rng(hash, total_chunks, span, rng)
+}
+
+/// Chunk ranges of a blob that completed download parts have delivered.
+///
+/// A provider that silently drops a stripe still lets the split download
+/// finish, so before exporting we compare this against the blob's full range
+/// and fetch whatever is missing.
+#[derive(Debug, Default)]
+pub struct Coverage {
+    covered: ChunkRanges,
+}
+
+impl Coverage {
+    /// Add the root-blob ranges of a completed request.
+    pub fn record(&mut self, req: &GetRequest) {
+        if let Some((0, ranges)) = req.ranges.as_single() {
+            self.covered |= ranges.clone();
+        }
+    }
+
+    /// Ranges of `0..total_chunks` that no completed part delivered.
+    pub fn missing(&self, total_chunks: u64) -> ChunkRanges {
+        ChunkRanges::chunks(0..total_chunks) - &self.covered
+    }
+}
+
+/// A request for just the `missing` ranges, or `None` if nothing is missing.
+pub fn follow_up_request(hash: Hash, missing: ChunkRanges) -> Option<GetRequest> {
+    (!missing.is_empty()).then(|| GetRequest::blob_ranges(hash, missing))
+}
+
+/// Build a randomized list of `GetRequest`s covering the blob in fixed-size chunks.
 pub fn randomized_get_requests_with_rng<R: Rng + ?Sized>(
     hash: Hash,
     total_chunks: u64,
@@ -36,11 +111,74 @@ pub fn randomized_get_requests_with_rng<R: Rng + ?Sized>(
         .map(|start| {
             let end = min(total_chunks, start.saturating_add(span));
             let ranges = ChunkRanges::chunks(start..end);
-            GetRequest::blob_ranges(hash.clone(), ranges)
+            GetRequest::blob_ranges(hash, ranges)
+        })
+        .collect()
+}
+
+/// The same stripes as [`randomized_get_requests`], in offset order; the
+/// baseline the randomized plan is compared against.
+pub fn sequential_get_requests(hash: Hash, total_chunks: u64, stripe_span: u64) -> Vec<GetRequest> {
+    let span = stripe_span.max(1);
+    (0..total_chunks)
+        .step_by(span as usize)
+        .map(|start| {
+            let end = min(total_chunks, start.saturating_add(span));
+            GetRequest::blob_ranges(hash, ChunkRanges::chunks(start..end))
         })
         .collect()
 }
 
+/// Shape of a stripe plan over `0..total_chunks`, for comparing strategies
+/// without a live transfer.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PlanSummary {
+    pub stripes: usize,
+    /// Distinct chunks requested by at least one stripe.
+    pub covered_chunks: u64,
+    /// `covered_chunks / total_chunks`.
+    pub coverage: f64,
+    /// Most stripes requesting any one chunk; 1 means no overlap.
+    pub max_stripes_per_offset: u32,
+    /// First chunk of each stripe, in request order.
+    pub order: Vec<u64>,
+}
+
+/// Summarize root-blob `requests` against a blob of `total_chunks` chunks.
+pub fn summarize_plan(requests: &[GetRequest], total_chunks: u64) -> PlanSummary {
+    let mut hits = vec![0u32; total_chunks as usize];
+    let mut order = Vec::with_capacity(requests.len());
+    for req in requests {
+        let Some((0, ranges)) = req.ranges.as_single() else {
+            continue;
+        };
+        // Boundaries alternate start, end; a trailing start is open-ended
+        let bounds = ranges.boundaries();
+        if let Some(first) = bounds.first() {
+            order.push(first.0);
+        }
+        for pair in bounds.chunks(2) {
+            let start = pair[0].0.min(total_chunks);
+            let end = pair.get(1).map_or(total_chunks, |e| e.0.min(total_chunks));
+            for hit in &mut hits[start as usize..end as usize] {
+                *hit += 1;
+            }
+        }
+    }
+    let covered_chunks = hits.iter().filter(|&&h| h > 0).count() as u64;
+    PlanSummary {
+        stripes: requests.len(),
+        covered_chunks,
+        coverage: if total_chunks == 0 {
+            0.0
+        } else {
+            covered_chunks as f64 / total_chunks as f64
+        },
+        max_stripes_per_offset: hits.iter().copied().max().unwrap_or(0),
+        order,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use rand::{rngs::StdRng, SeedableRng};
@@ -60,4 +198,121 @@ mod tests {
             .collect::<std::collections::HashSet<_>>();
         assert_eq!(unique.len(), requests.len());
     }
+
+    #[test]
+    fn coverage_reports_dropped_stripe() {
+        let hash = Hash::from_bytes([3; 32]);
+        let mut rng = StdRng::seed_from_u64(1);
+        let requests = randomized_get_requests_with_rng(hash, 64, 16, &mut rng);
+        let mut coverage = Coverage::default();
+        // Every stripe but the one starting at chunk 32 completes
+        let dropped = ChunkRanges::chunks(32..48);
+        for req in &requests {
+            if req.ranges.as_single().map(|(_, r)| r) != Some(&dropped) {
+                coverage.record(req);
+            }
+        }
+        assert_eq!(coverage.missing(64), dropped);
+
+        let follow_up = follow_up_request(hash, coverage.missing(64)).unwrap();
+        assert_eq!(follow_up.hash, hash);
+        assert_eq!(follow_up.ranges.as_single(), Some((0, &dropped)));
+
+        for req in &requests {
+            coverage.record(req);
+        }
+        assert!(coverage.missing(64).is_empty());
+        assert!(follow_up_request(hash, coverage.missing(64)).is_none());
+    }
+
+    #[test]
+    fn plans_cover_everything_once_in_different_orders() {
+        let hash = Hash::from_bytes([4; 32]);
+        let sequential = summarize_plan(&sequential_get_requests(hash, 50, 16), 50);
+        assert_eq!(sequential.stripes, 4);
+        assert_eq!(sequential.covered_chunks, 50);
+        assert_eq!(sequential.coverage, 1.0);
+        assert_eq!(sequential.max_stripes_per_offset, 1);
+        assert_eq!(sequential.order, vec![0, 16, 32, 48]);
+
+        let mut rng = StdRng::seed_from_u64(9);
+        let randomized = summarize_plan(
+            &randomized_get_requests_with_rng(hash, 50, 16, &mut rng),
+            50,
+        );
+        let mut order = randomized.order.clone();
+        order.sort();
+        assert_eq!(order, sequential.order);
+        assert_eq!(
+            (randomized.covered_chunks, randomized.max_stripes_per_offset),
+            (50, 1)
+        );
+    }
+
+    #[test]
+    fn summary_counts_overlap() {
+        let hash = Hash::from_bytes([5; 32]);
+        let mut requests = sequential_get_requests(hash, 32, 16);
+        requests.push(GetRequest::blob_ranges(hash, ChunkRanges::chunks(8..24)));
+        let summary = summarize_plan(&requests, 40);
+        assert_eq!(summary.max_stripes_per_offset, 2);
+        assert_eq!(summary.covered_chunks, 32);
+        assert_eq!(summary.coverage, 0.8);
+    }
+
+    #[test]
+    fn stripe_plan_honors_span_override() {
+        let hash = Hash::from_bytes([6; 32]);
+        let mut rng = StdRng::seed_from_u64(5);
+        let sizes = |plan: Vec<GetRequest>| -> Vec<u64> {
+            let mut sizes: Vec<u64> = plan
+                .iter()
+                .map(|req| {
+                    let b = req.ranges.as_single().unwrap().1.boundaries();
+                    b[1].0 - b[0].0
+                })
+                .collect();
+            sizes.sort();
+            sizes
+        };
+
+        // 100 chunks in stripes of 30: three full ones and the 10-chunk tail
+        let plan = stripe_plan(hash, 100, 2, Some(30), &mut rng);
+        assert_eq!(sizes(plan), vec![10, 30, 30, 30]);
+        // No override: the recommendation (1024 / (2 * 4) = 128) applies
+        let plan = stripe_plan(hash, 1024, 2, None, &mut rng);
+        assert_eq!(sizes(plan), vec![128; 8]);
+
+        assert_eq!(validate_stripe_span(30), Ok(30));
+        assert!(validate_stripe_span(0).is_err());
+        assert!(validate_stripe_span(MAX_STRIPE_SPAN + 1).is_err());
+    }
+
+    #[test]
+    fn recommended_span_single_provider() {
+        // 1024 chunks / (1 provider * 4 stripes)
+        assert_eq!(recommended_stripe_span(1024, 1), 256);
+        // Zero providers is treated like one rather than dividing by zero
+        assert_eq!(recommended_stripe_span(1024, 0), 256);
+    }
+
+    #[test]
+    fn recommended_span_many_providers_is_floored() {
+        assert_eq!(recommended_stripe_span(1024, 100), MIN_STRIPE_CHUNKS);
+        assert_eq!(recommended_stripe_span(1 << 20, 64), 4096);
+    }
+
+    #[test]
+    fn recommended_span_tiny_blobs() {
+        assert_eq!(recommended_stripe_span(0, 3), 1);
+        assert_eq!(recommended_stripe_span(1, 3), 1);
+        assert_eq!(recommended_stripe_span(5, 8), 5);
+        let hash = Hash::from_bytes([2; 32]);
+        let mut rng = StdRng::seed_from_u64(7);
+        let span = recommended_stripe_span(5, 8);
+        assert_eq!(
+            randomized_get_requests_with_rng(hash, 5, span, &mut rng).len(),
+            1
+        );
+    }
 }
@@ -0,0 +1,57 @@
+/// `Content-Disposition` value naming `filename` (RFC 6266).
+///
+/// `filename=` carries an ASCII fallback with quotes and backslashes escaped
+/// and anything else outside printable ASCII replaced by `_`. Names that
+/// needed replacing also get `filename*=UTF-8''…` with the exact name
+/// percent-encoded, which browsers prefer when present.
+pub fn value(disposition: &str, filename: &str) -> String {
+    let mut fallback = String::with_capacity(filename.len());
+    for c in filename.chars() {
+        match c {
+            '"' | '\\' => {
+                fallback.push('\\');
+                fallback.push(c);
+            }
+            ' '..='~' => fallback.push(c),
+            _ => fallback.push('_'),
+        }
+    }
+    let mut value = format!("{disposition}; filename=\"{fallback}\"");
+    if !filename.chars().all(|c| matches!(c, ' '..='~')) {
+        value.push_str("; filename*=UTF-8''");
+        for b in filename.bytes() {
+            if b.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&b) {
+                value.push(b as char);
+            } else {
+                value.push_str(&format!("%{b:02X}"));
+            }
+        }
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_names_are_quoted() {
+        assert_eq!(value("inline", "cat.png"), "inline; filename=\"cat.png\"");
+        assert_eq!(
+            value("attachment", "say \"hi\".svg"),
+            "attachment; filename=\"say \\\"hi\\\".svg\""
+        );
+    }
+
+    #[test]
+    fn other_names_get_an_encoded_form() {
+        assert_eq!(
+            value("inline", "café 1.jpg"),
+            "inline; filename=\"caf_ 1.jpg\"; filename*=UTF-8''caf%C3%A9%201.jpg"
+        );
+        assert_eq!(
+            value("inline", "a\r\nb"),
+            "inline; filename=\"a__b\"; filename*=UTF-8''a%0D%0Ab"
+        );
+    }
+}
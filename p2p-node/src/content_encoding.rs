@@ -0,0 +1,99 @@
+use std::io::Read;
+
+/// Whether an `Accept-Encoding` header value admits gzip, either by name or
+/// through `*`, with a non-zero quality.
+pub fn accepts_gzip(accept_encoding: &str) -> bool {
+    accept_encoding.split(',').any(|entry| {
+        let mut parts = entry.split(';').map(str::trim);
+        let coding = parts.next().unwrap_or_default().to_ascii_lowercase();
+        let q = parts
+            .find_map(|p| p.strip_prefix("q="))
+            .and_then(|q| q.parse::<f32>().ok())
+            .unwrap_or(1.0);
+        matches!(coding.as_str(), "gzip" | "x-gzip" | "*") && q > 0.0
+    })
+}
+
+/// The stored form of a declared `Content-Encoding`: `None` for identity,
+/// `gzip` for gzip. Anything else is an error, since we couldn't decode it
+/// for a client that doesn't accept it.
+pub fn normalize(value: &str) -> Result<Option<&'static str>, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "" | "identity" => Ok(None),
+        "gzip" | "x-gzip" => Ok(Some("gzip")),
+        other => Err(format!(
+            "unsupported content encoding {other:?}; only gzip is accepted"
+        )),
+    }
+}
+
+/// Decode a gzip stream in full, failing once the output passes `limit`
+/// bytes so a small bomb can't exhaust memory. Blocking.
+pub fn gunzip(bytes: &[u8], limit: usize) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    flate2::read::GzDecoder::new(bytes)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut out)?;
+    if out.len() > limit {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("gzip content decodes to more than {limit} bytes"),
+        ));
+    }
+    Ok(out)
+}
+
+/// The decoded length a gzip file records in its trailer (the size of the
+/// last member, mod 2^32), so `HEAD` can answer without decompressing.
+pub async fn decoded_len(path: &std::path::Path) -> std::io::Result<u64> {
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+    let mut file = tokio::fs::File::open(path).await?;
+    file.seek(std::io::SeekFrom::End(-4)).await?;
+    let mut trailer = [0u8; 4];
+    file.read_exact(&mut trailer).await?;
+    Ok(u32::from_le_bytes(trailer) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn gzip_acceptance() {
+        assert!(accepts_gzip("gzip, deflate, br"));
+        assert!(accepts_gzip("br;q=1.0, GZIP;q=0.5"));
+        assert!(accepts_gzip("*"));
+        assert!(!accepts_gzip("gzip;q=0, br"));
+        assert!(!accepts_gzip("identity"));
+        assert!(!accepts_gzip(""));
+    }
+
+    #[test]
+    fn gunzip_roundtrip() {
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(b"pixels").unwrap();
+        assert_eq!(gunzip(&enc.finish().unwrap(), 6).unwrap(), b"pixels");
+        assert!(gunzip(b"not gzip", 100).is_err());
+    }
+
+    #[test]
+    fn gunzip_stops_at_the_limit() {
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::best());
+        enc.write_all(&vec![0u8; 4 << 20]).unwrap();
+        let bomb = enc.finish().unwrap();
+        assert!(bomb.len() < 16 * 1024);
+        let err = gunzip(&bomb, 1 << 20).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(gunzip(&bomb, 4 << 20).unwrap().len(), 4 << 20);
+    }
+
+    #[test]
+    fn only_gzip_and_identity_are_accepted() {
+        assert_eq!(normalize(" GZIP "), Ok(Some("gzip")));
+        assert_eq!(normalize("x-gzip"), Ok(Some("gzip")));
+        assert_eq!(normalize("identity"), Ok(None));
+        assert_eq!(normalize(""), Ok(None));
+        assert!(normalize("br").is_err());
+    }
+}
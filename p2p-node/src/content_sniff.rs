@@ -0,0 +1,27 @@
+use crate::active_content::essence;
+
+/// Whether `declared` names a different type than `sniffed`, as
+/// [`crate::active_content::sniff`] reports it. Common aliases (`image/jpg`,
+/// `image/x-png`) count as the same type.
+pub fn contradicts(declared: &str, sniffed: &str) -> bool {
+    let declared = essence(declared);
+    let declared = match declared.as_str() {
+        "image/jpg" | "image/pjpeg" => "image/jpeg",
+        "image/x-png" => "image/png",
+        "application/x-gzip" => "application/gzip",
+        other => other,
+    };
+    declared != sniffed
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn aliases_and_parameters_are_not_contradictions() {
+        assert!(!contradicts("image/JPG", "image/jpeg"));
+        assert!(!contradicts("image/png; name=a.png", "image/png"));
+        assert!(contradicts("text/plain", "image/png"));
+    }
+}
@@ -0,0 +1,220 @@
+//! Provider-side egress throttling (`PROVIDER_EGRESS_BPS`).
+//!
+//! iroh-blobs writes to the QUIC send stream itself and has no writer hook,
+//! so the limit is applied at the connection layer: with a limit set,
+//! [`ThrottledBlobs`] takes blob connections in place of `BlobsProtocol`,
+//! decodes each `Get` request, and writes the bao encoding from the store
+//! through one token bucket shared by every connection. Other request kinds
+//! (get-many, observe, push) are refused; this node never sends them.
+
+use std::{
+    collections::VecDeque,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[cfg(any(test, feature = "p2p_notify"))]
+use {
+    futures_util::StreamExt,
+    iroh::{
+        endpoint::{Connection, RecvStream, SendStream},
+        protocol::{AcceptError, ProtocolHandler},
+    },
+    iroh_blobs::{
+        hashseq::HashSeq,
+        protocol::{GetRequest, Request},
+        provider::CountingReader,
+        BlobsProtocol,
+    },
+    std::sync::Arc,
+    tracing::{debug, warn},
+};
+
+/// Share of a second's worth of bytes that may go out in one burst.
+const BURST_SECS: f64 = 0.1;
+
+/// How far back [`EgressLimiter::rate`] looks; older sends are dropped.
+const RATE_WINDOW: Duration = Duration::from_secs(1);
+
+/// A byte-rate token bucket that also measures what went through it.
+///
+/// Writers take tokens before sending and sleep off any deficit, so a write
+/// larger than the burst is still allowed, just paid for afterwards.
+#[derive(Debug)]
+pub struct EgressLimiter {
+    bps: u64,
+    bucket: Mutex<(f64, Instant)>,
+    sent: Mutex<VecDeque<(Instant, u64)>>,
+}
+
+impl EgressLimiter {
+    pub fn new(bps: u64) -> Self {
+        Self {
+            bps,
+            bucket: Mutex::new((bps as f64 * BURST_SECS, Instant::now())),
+            sent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Account for `len` bytes about to be sent, waiting as long as the
+    /// bucket is in deficit.
+    pub async fn take(&self, len: usize) {
+        let now = Instant::now();
+        let wait = {
+            let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+            let (tokens, updated) = &mut *bucket;
+            let rate = self.bps as f64;
+            let elapsed = now.saturating_duration_since(*updated).as_secs_f64();
+            *tokens = (*tokens + elapsed * rate).min(rate * BURST_SECS) - len as f64;
+            *updated = now;
+            (*tokens < 0.0).then(|| Duration::from_secs_f64(-*tokens / rate))
+        };
+        {
+            let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+            prune(&mut sent, now);
+            sent.push_back((now, len as u64));
+        }
+        if let Some(wait) = wait {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Bytes sent over the last second.
+    pub fn rate(&self) -> u64 {
+        let mut sent = self.sent.lock().unwrap_or_else(|e| e.into_inner());
+        prune(&mut sent, Instant::now());
+        sent.iter().map(|(_, len)| len).sum()
+    }
+}
+
+/// Drop sends older than `RATE_WINDOW` before `now`, so the record stays
+/// bounded whether or not anyone asks for the rate.
+fn prune(sent: &mut VecDeque<(Instant, u64)>, now: Instant) {
+    let Some(cutoff) = now.checked_sub(RATE_WINDOW) else {
+        return;
+    };
+    while sent.front().is_some_and(|(at, _)| *at < cutoff) {
+        sent.pop_front();
+    }
+}
+
+/// The blobs protocol handler, throttled when a limiter is set.
+#[cfg(any(test, feature = "p2p_notify"))]
+#[derive(Debug, Clone)]
+pub struct ThrottledBlobs {
+    pub blobs: BlobsProtocol,
+    pub limiter: Option<Arc<EgressLimiter>>,
+}
+
+#[cfg(any(test, feature = "p2p_notify"))]
+impl ProtocolHandler for ThrottledBlobs {
+    async fn accept(&self, conn: Connection) -> Result<(), AcceptError> {
+        let Some(limiter) = self.limiter.clone() else {
+            return self.blobs.accept(conn).await;
+        };
+        while let Ok((send, recv)) = conn.accept_bi().await {
+            let store = self.blobs.store().clone();
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_stream(&store, &limiter, send, recv).await {
+                    debug!(?e, "throttled blob request ended early");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    async fn shutdown(&self) {
+        self.blobs.shutdown().await
+    }
+}
+
+#[cfg(any(test, feature = "p2p_notify"))]
+async fn serve_stream(
+    store: &iroh_blobs::api::Store,
+    limiter: &EgressLimiter,
+    mut send: SendStream,
+    mut recv: RecvStream,
+) -> anyhow::Result<()> {
+    let request = Request::read_async(&mut CountingReader::new(&mut recv)).await?;
+    let Request::Get(request) = request else {
+        warn!(
+            ?request,
+            "refusing non-get blob request while egress is throttled"
+        );
+        send.reset(0u8.into())?;
+        return Ok(());
+    };
+    recv.read_to_end(0).await?;
+    serve_get(store, limiter, &request, &mut send).await?;
+    send.finish()?;
+    Ok(())
+}
+
+/// Write the response to `request` as `handle_get` in iroh-blobs would, but
+/// paced by `limiter`.
+#[cfg(any(test, feature = "p2p_notify"))]
+async fn serve_get(
+    store: &iroh_blobs::api::Store,
+    limiter: &EgressLimiter,
+    request: &GetRequest,
+    send: &mut SendStream,
+) -> anyhow::Result<()> {
+    let mut hash_seq = None;
+    for (offset, ranges) in request.ranges.iter_non_empty_infinite() {
+        let hash = if offset == 0 {
+            request.hash
+        } else {
+            if hash_seq.is_none() {
+                hash_seq = Some(HashSeq::try_from(store.get_bytes(request.hash).await?)?);
+            }
+            let Some(hash_seq) = &hash_seq else {
+                break;
+            };
+            match hash_seq.get(usize::try_from(offset - 1)?) {
+                Some(hash) => hash,
+                None => break,
+            }
+        };
+        let mut encoded = store.export_bao(hash, ranges.clone()).into_byte_stream();
+        while let Some(bytes) = encoded.next().await {
+            let bytes = bytes?;
+            limiter.take(bytes.len()).await;
+            send.write_all(&bytes).await?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn limiter_paces_to_the_configured_rate() {
+        let limiter = EgressLimiter::new(100_000);
+        let start = Instant::now();
+        // 10 KB of burst, then 20 KB at 100 KB/s
+        for _ in 0..30 {
+            limiter.take(1_000).await;
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(190), "{elapsed:?}");
+        assert_eq!(limiter.rate(), 30_000);
+    }
+
+    #[tokio::test]
+    async fn take_forgets_sends_outside_the_window() {
+        let limiter = EgressLimiter::new(1_000_000);
+        let old = Instant::now() - RATE_WINDOW * 2;
+        limiter
+            .sent
+            .lock()
+            .unwrap()
+            .extend([(old, 500), (old, 500)]);
+        limiter.take(10).await;
+        // Pruned by take alone, without anyone calling rate()
+        assert_eq!(limiter.sent.lock().unwrap().len(), 1);
+        assert_eq!(limiter.rate(), 10);
+    }
+}
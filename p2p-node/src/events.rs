@@ -0,0 +1,156 @@
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::notify_log::NotifyRecord;
+
+/// Events buffered per `/events` subscriber before it starts missing some.
+pub const EVENT_BUFFER: usize = 256;
+
+/// One entry of the `GET /events` audit stream, serialized as a JSON line
+/// tagged by `event`.
+///
+/// `transfer_id` is the id `/receive` returned; it is `None` for work that
+/// isn't part of a receive (e.g. a notify fanned out after an upload).
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    TransferStarted {
+        transfer_id: String,
+        hash: String,
+        filename: String,
+    },
+    Progress {
+        transfer_id: Option<String>,
+        bytes_received: u64,
+        bytes_total: Option<u64>,
+    },
+    ProviderTried {
+        transfer_id: Option<String>,
+        provider: String,
+    },
+    PartComplete {
+        transfer_id: Option<String>,
+        provider: Option<String>,
+        range: String,
+    },
+    TransferCompleted {
+        transfer_id: String,
+        hash: String,
+        bytes: Option<u64>,
+    },
+    Error {
+        transfer_id: Option<String>,
+        message: String,
+    },
+    NotifySent {
+        peer: String,
+        via: String,
+        delivered: bool,
+        /// The receiver's transfer id, when its P2P ack carried one.
+        transfer_id: Option<String>,
+    },
+    /// A peer's node address was learned, or changed. `source` is where it
+    /// came from: `status` (its `/status`) or `peer_file` (`PEER_ADDRS_FILE`).
+    PeerResolved {
+        url: String,
+        node_id: String,
+        source: String,
+    },
+    /// A peer's address was dropped from the address book.
+    PeerLost {
+        url: String,
+        node_id: String,
+        reason: String,
+    },
+    /// A notify message was sent or received; see `GET /notifies`.
+    NotifyMessage(NotifyRecord),
+    /// The subscriber fell behind and `missed` events were dropped for it.
+    Gap { missed: u64 },
+}
+
+/// Fan-out of [`Event`]s to every `/events` subscriber.
+///
+/// Backed by a bounded broadcast channel: publishing never blocks, and a
+/// subscriber that lags more than [`EVENT_BUFFER`] events behind loses the
+/// oldest ones and sees a [`Event::Gap`] in their place.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self {
+            tx: broadcast::channel(EVENT_BUFFER).0,
+        }
+    }
+}
+
+impl EventBus {
+    /// Publish to current subscribers; dropped when there are none.
+    pub fn emit(&self, event: Event) {
+        let _ = self.tx.send(event);
+    }
+
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            rx: self.tx.subscribe(),
+        }
+    }
+}
+
+pub struct Subscription {
+    rx: broadcast::Receiver<Event>,
+}
+
+impl Subscription {
+    /// The next event, a [`Event::Gap`] if some were dropped, or `None` once
+    /// the bus is gone.
+    pub async fn next(&mut self) -> Option<Event> {
+        match self.rx.recv().await {
+            Ok(event) => Some(event),
+            Err(broadcast::error::RecvError::Lagged(missed)) => Some(Event::Gap { missed }),
+            Err(broadcast::error::RecvError::Closed) => None,
+        }
+    }
+
+    /// Each event as one newline-terminated JSON line.
+    pub fn into_ndjson(self) -> impl futures_util::Stream<Item = Result<String, std::io::Error>> {
+        futures_util::stream::unfold(self, |mut sub| async move {
+            let event = sub.next().await?;
+            let mut line = serde_json::to_string(&event).unwrap_or_default();
+            line.push('\n');
+            Some((Ok(line), sub))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(n: u64) -> Event {
+        Event::Progress {
+            transfer_id: None,
+            bytes_received: n,
+            bytes_total: None,
+        }
+    }
+
+    #[test]
+    fn serializes_tagged_snake_case() {
+        let line = serde_json::to_value(Event::Gap { missed: 3 }).unwrap();
+        assert_eq!(line, serde_json::json!({ "event": "gap", "missed": 3 }));
+    }
+
+    #[tokio::test]
+    async fn slow_subscriber_sees_gap_then_newest_events() {
+        let bus = EventBus::default();
+        let mut sub = bus.subscribe();
+        for n in 0..EVENT_BUFFER as u64 + 2 {
+            bus.emit(progress(n));
+        }
+        assert_eq!(sub.next().await, Some(Event::Gap { missed: 2 }));
+        assert_eq!(sub.next().await, Some(progress(2)));
+    }
+}
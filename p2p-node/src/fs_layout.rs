@@ -0,0 +1,196 @@
+//! `FS_LAYOUT=tree`: every current image also lands in `data_dir/by-hash/`
+//! and `data_dir/by-name/`, for tools that watch directories.
+//!
+//! `by-hash/<hash>` is the canonical copy. `by-name/<filename>` is a relative
+//! symlink to it (a hard link where symlinks aren't available). A name
+//! already taken by different content gets the hash prefix appended instead
+//! of replacing it. Entries go away when their blob leaves the store.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+use tokio::fs;
+
+pub const BY_HASH: &str = "by-hash";
+pub const BY_NAME: &str = "by-name";
+
+/// Hex digits of the hash added to a colliding name.
+const COLLISION_PREFIX: usize = 8;
+
+/// Where images are written besides `current.img` (`FS_LAYOUT`).
+#[derive(Debug, Default, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FsLayout {
+    /// `current.img` only.
+    #[default]
+    Flat,
+    Tree,
+}
+
+impl FsLayout {
+    pub fn from_env_value(v: &str) -> Self {
+        match v.trim().to_ascii_lowercase().as_str() {
+            "tree" => FsLayout::Tree,
+            _ => FsLayout::Flat,
+        }
+    }
+}
+
+/// Copy `image` (the current image, with content hash `hash`) into the tree
+/// and link `filename` to it. Returns the `by-name` path used.
+pub async fn publish(
+    data_dir: &Path,
+    image: &Path,
+    hash: &str,
+    filename: &str,
+) -> io::Result<PathBuf> {
+    let by_hash = data_dir.join(BY_HASH);
+    let by_name = data_dir.join(BY_NAME);
+    fs::create_dir_all(&by_hash).await?;
+    fs::create_dir_all(&by_name).await?;
+
+    let canonical = by_hash.join(hash);
+    if fs::metadata(&canonical).await.is_err() {
+        let staged = by_hash.join(format!(".{hash}.part"));
+        fs::copy(image, &staged).await?;
+        fs::rename(&staged, &canonical).await?;
+    }
+
+    let target = link_target(hash);
+    let name = safe_name(filename).unwrap_or_else(|| hash.to_string());
+    let mut link = by_name.join(&name);
+    if taken_by_other(&link, &target).await {
+        link = by_name.join(disambiguate(&name, hash));
+    }
+    if points_to(&link, &target).await {
+        return Ok(link);
+    }
+    let staged = by_name.join(format!(".{hash}.link"));
+    let _ = fs::remove_file(&staged).await;
+    make_link(&target, &canonical, &staged).await?;
+    fs::rename(&staged, &link).await?;
+    Ok(link)
+}
+
+/// Remove `hash` from the tree: its canonical copy and every name linked to
+/// it. Missing entries are not an error.
+pub async fn remove(data_dir: &Path, hash: &str) -> io::Result<()> {
+    let target = link_target(hash);
+    match fs::read_dir(data_dir.join(BY_NAME)).await {
+        Ok(mut entries) => {
+            while let Some(entry) = entries.next_entry().await? {
+                if points_to(&entry.path(), &target).await {
+                    fs::remove_file(entry.path()).await?;
+                }
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+    match fs::remove_file(data_dir.join(BY_HASH).join(hash)).await {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// `filename` reduced to a single safe path component: the last segment,
+/// with control characters replaced. `None` if nothing usable is left.
+fn safe_name(filename: &str) -> Option<String> {
+    let last = filename.rsplit(['/', '\\']).next().unwrap_or_default();
+    let name: String = last
+        .chars()
+        .map(|c| if c.is_control() { '_' } else { c })
+        .collect();
+    let name = name.trim();
+    (!name.is_empty() && name != "." && name != ".." && !name.starts_with('.'))
+        .then(|| name.to_string())
+}
+
+/// `photo.png` for hash `abcdef12…` becomes `photo-abcdef12.png`.
+fn disambiguate(name: &str, hash: &str) -> String {
+    let prefix = &hash[..hash.len().min(COLLISION_PREFIX)];
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{stem}-{prefix}.{ext}"),
+        _ => format!("{name}-{prefix}"),
+    }
+}
+
+/// What a `by-name` symlink to `hash` contains.
+fn link_target(hash: &str) -> PathBuf {
+    Path::new("..").join(BY_HASH).join(hash)
+}
+
+async fn points_to(link: &Path, target: &Path) -> bool {
+    fs::read_link(link).await.is_ok_and(|t| t == target)
+}
+
+/// Whether `link` exists and is something other than a link to `target`.
+async fn taken_by_other(link: &Path, target: &Path) -> bool {
+    fs::symlink_metadata(link).await.is_ok() && !points_to(link, target).await
+}
+
+#[cfg(unix)]
+async fn make_link(target: &Path, _canonical: &Path, link: &Path) -> io::Result<()> {
+    fs::symlink(target, link).await
+}
+
+#[cfg(not(unix))]
+async fn make_link(_target: &Path, canonical: &Path, link: &Path) -> io::Result<()> {
+    fs::hard_link(canonical, link).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_single_safe_components() {
+        assert_eq!(safe_name("a/b/photo.png").as_deref(), Some("photo.png"));
+        assert_eq!(safe_name("..\\x.bin").as_deref(), Some("x.bin"));
+        assert_eq!(safe_name("bad\nname").as_deref(), Some("bad_name"));
+        assert_eq!(safe_name("dir/.."), None);
+        assert_eq!(safe_name(".hidden"), None);
+        assert_eq!(safe_name(""), None);
+        assert_eq!(
+            disambiguate("photo.png", "abcdef1234"),
+            "photo-abcdef12.png"
+        );
+        assert_eq!(disambiguate("README", "abcdef1234"), "README-abcdef12");
+    }
+
+    #[tokio::test]
+    async fn collisions_get_a_hash_suffix_and_removal_cleans_up() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("current.img");
+        fs::write(&image, b"one").await.unwrap();
+        let first = publish(dir.path(), &image, "aaaaaaaaaa", "x.png")
+            .await
+            .unwrap();
+        assert_eq!(first, dir.path().join(BY_NAME).join("x.png"));
+        // Republishing is a no-op
+        assert_eq!(
+            publish(dir.path(), &image, "aaaaaaaaaa", "x.png")
+                .await
+                .unwrap(),
+            first
+        );
+
+        fs::write(&image, b"two").await.unwrap();
+        let second = publish(dir.path(), &image, "bbbbbbbbbb", "x.png")
+            .await
+            .unwrap();
+        assert_eq!(second, dir.path().join(BY_NAME).join("x-bbbbbbbb.png"));
+        assert_eq!(fs::read(&first).await.unwrap(), b"one");
+        assert_eq!(fs::read(&second).await.unwrap(), b"two");
+
+        remove(dir.path(), "aaaaaaaaaa").await.unwrap();
+        assert!(fs::symlink_metadata(&first).await.is_err());
+        assert!(fs::metadata(dir.path().join(BY_HASH).join("aaaaaaaaaa"))
+            .await
+            .is_err());
+        assert_eq!(fs::read(&second).await.unwrap(), b"two");
+    }
+}
@@ -0,0 +1,234 @@
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use tokio::sync::Mutex;
+
+/// How long an `Idempotency-Key` keeps replaying its first response.
+pub const IDEMPOTENCY_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Most keys remembered at once; the oldest is forgotten to make room.
+pub const IDEMPOTENCY_MAX_KEYS: usize = 1024;
+
+/// Responses of completed `/upload` requests by `Idempotency-Key`, so a
+/// client retrying after a network error gets the original ticket and hash
+/// back instead of a second ingest and notify.
+///
+/// Each key also records a digest of the body it was first used with, so a
+/// key reused for different content is refused rather than replayed.
+#[derive(Debug)]
+pub struct IdempotencyCache {
+    ttl: Duration,
+    cap: usize,
+    entries: HashMap<String, Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    at: Instant,
+    digest: String,
+    /// `None` while the first request with the key is still running.
+    body: Option<serde_json::Value>,
+}
+
+/// What to do with a keyed request, from [`IdempotencyCache::claim`].
+#[derive(Debug, PartialEq)]
+pub enum Claim {
+    /// First use of the key: it is now marked in flight for this request.
+    New,
+    /// An earlier request with the same key and body is still running.
+    InFlight,
+    /// The same key and body completed; here is its response.
+    Replay(serde_json::Value),
+    /// The key was used with a different body.
+    Mismatch,
+}
+
+impl Default for IdempotencyCache {
+    fn default() -> Self {
+        Self::new(IDEMPOTENCY_TTL, IDEMPOTENCY_MAX_KEYS)
+    }
+}
+
+impl IdempotencyCache {
+    pub fn new(ttl: Duration, cap: usize) -> Self {
+        Self {
+            ttl,
+            cap,
+            entries: HashMap::new(),
+        }
+    }
+
+    /// Look `key` up for a request whose body has `digest`, marking it in
+    /// flight when it is new (or its earlier use has expired).
+    pub fn claim(&mut self, key: &str, digest: &str, now: Instant) -> Claim {
+        let ttl = self.ttl;
+        match self
+            .entries
+            .get(key)
+            .filter(|e| now.saturating_duration_since(e.at) < ttl)
+        {
+            Some(e) if e.digest != digest => Claim::Mismatch,
+            Some(Entry {
+                body: Some(body), ..
+            }) => Claim::Replay(body.clone()),
+            Some(_) => Claim::InFlight,
+            None => {
+                self.insert(key.to_string(), digest.to_string(), None, now);
+                Claim::New
+            }
+        }
+    }
+
+    /// Record the response of the request that claimed `key`.
+    pub fn complete(&mut self, key: String, digest: String, body: serde_json::Value, now: Instant) {
+        self.insert(key, digest, Some(body), now);
+    }
+
+    /// Forget an in-flight `key` whose request failed, so a retry runs again.
+    pub fn abandon(&mut self, key: &str) {
+        if self.entries.get(key).is_some_and(|e| e.body.is_none()) {
+            self.entries.remove(key);
+        }
+    }
+
+    fn insert(
+        &mut self,
+        key: String,
+        digest: String,
+        body: Option<serde_json::Value>,
+        now: Instant,
+    ) {
+        if self.cap == 0 {
+            return;
+        }
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, e| now.saturating_duration_since(e.at) < ttl);
+        if self.entries.len() >= self.cap && !self.entries.contains_key(&key) {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.at)
+                .map(|(k, _)| k.clone());
+            if let Some(oldest) = oldest {
+                self.entries.remove(&oldest);
+            }
+        }
+        self.entries.insert(
+            key,
+            Entry {
+                at: now,
+                digest,
+                body,
+            },
+        );
+    }
+}
+
+/// A key claimed by a running request. Storing the response with
+/// [`Pending::complete`] lets retries replay it; dropping it any other way
+/// (an error response, a client that went away) abandons the key.
+pub struct Pending {
+    cache: Arc<Mutex<IdempotencyCache>>,
+    key: String,
+    digest: String,
+    done: bool,
+}
+
+impl Pending {
+    pub fn new(cache: Arc<Mutex<IdempotencyCache>>, key: String, digest: String) -> Self {
+        Self {
+            cache,
+            key,
+            digest,
+            done: false,
+        }
+    }
+
+    pub async fn complete(mut self, body: serde_json::Value) {
+        self.done = true;
+        let (key, digest) = (
+            std::mem::take(&mut self.key),
+            std::mem::take(&mut self.digest),
+        );
+        self.cache
+            .lock()
+            .await
+            .complete(key, digest, body, Instant::now());
+    }
+}
+
+impl Drop for Pending {
+    fn drop(&mut self) {
+        if self.done {
+            return;
+        }
+        if let Ok(mut cache) = self.cache.try_lock() {
+            cache.abandon(&self.key);
+            return;
+        }
+        // Drop can't await the lock; a runtime is always there for handlers
+        let (cache, key) = (self.cache.clone(), std::mem::take(&mut self.key));
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move { cache.lock().await.abandon(&key) });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn replays_until_expiry_and_stays_bounded() {
+        let mut cache = IdempotencyCache::new(Duration::from_secs(10), 2);
+        let t0 = Instant::now();
+        assert_eq!(cache.claim("a", "d1", t0), Claim::New);
+        cache.complete("a".into(), "d1".into(), json!({"hash": "h1"}), t0);
+        assert_eq!(
+            cache.claim("a", "d1", t0 + Duration::from_secs(9)),
+            Claim::Replay(json!({"hash": "h1"}))
+        );
+        assert_eq!(
+            cache.claim("a", "d1", t0 + Duration::from_secs(10)),
+            Claim::New
+        );
+
+        cache.complete(
+            "b".into(),
+            "d".into(),
+            json!(2),
+            t0 + Duration::from_secs(11),
+        );
+        cache.complete(
+            "c".into(),
+            "d".into(),
+            json!(3),
+            t0 + Duration::from_secs(12),
+        );
+        let later = t0 + Duration::from_secs(12);
+        assert_eq!(cache.claim("b", "d", later), Claim::Replay(json!(2)));
+        assert_eq!(cache.claim("c", "d", later), Claim::Replay(json!(3)));
+        // "a" was the oldest and made room
+        assert_eq!(cache.entries.len(), 2);
+    }
+
+    #[test]
+    fn in_flight_and_mismatched_keys() {
+        let mut cache = IdempotencyCache::new(Duration::from_secs(10), 8);
+        let now = Instant::now();
+        assert_eq!(cache.claim("k", "d1", now), Claim::New);
+        assert_eq!(cache.claim("k", "d1", now), Claim::InFlight);
+        assert_eq!(cache.claim("k", "d2", now), Claim::Mismatch);
+        cache.abandon("k");
+        assert_eq!(cache.claim("k", "d2", now), Claim::New);
+        cache.complete("k".into(), "d2".into(), json!(1), now);
+        // A completed response is never abandoned
+        cache.abandon("k");
+        assert_eq!(cache.claim("k", "d1", now), Claim::Mismatch);
+    }
+}
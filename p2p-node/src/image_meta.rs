@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// Serving metadata written next to an exported image (`current.img.meta.json`).
+///
+/// `NodeState` is in-memory only, so without this a restarted node would serve
+/// `current.img` with no filename or content type.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageMeta {
+    pub filename: String,
+    pub content_type: String,
+    /// `Content-Encoding` the image was stored with, e.g. `gzip`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    pub hash: String,
+    pub bytes: u64,
+    /// Perceptual hash, for images hashed with the `phash` feature.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phash: Option<String>,
+}
+
+/// Sidecar location for an exported image.
+pub fn sidecar_path(image: &Path) -> PathBuf {
+    let mut name = image.file_name().unwrap_or_default().to_os_string();
+    name.push(".meta.json");
+    image.with_file_name(name)
+}
+
+pub async fn write(image: &Path, meta: &ImageMeta) -> anyhow::Result<()> {
+    fs::write(sidecar_path(image), serde_json::to_vec_pretty(meta)?).await?;
+    Ok(())
+}
+
+/// The sidecar for `image`, if one exists and parses.
+pub async fn read(image: &Path) -> Option<ImageMeta> {
+    let bytes = fs::read(sidecar_path(image)).await.ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sidecar_sits_next_to_image() {
+        assert_eq!(
+            sidecar_path(Path::new("/data/current.img")),
+            PathBuf::from("/data/current.img.meta.json")
+        );
+    }
+
+    #[tokio::test]
+    async fn roundtrip() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("current.img");
+        assert_eq!(read(&image).await, None);
+        let meta = ImageMeta {
+            filename: "cat.png".into(),
+            content_type: "image/png".into(),
+            content_encoding: Some("gzip".into()),
+            hash: "abc".into(),
+            bytes: 3,
+            phash: Some("8f3c00a1e2d45b67".into()),
+        };
+        write(&image, &meta).await.unwrap();
+        assert_eq!(read(&image).await, Some(meta));
+    }
+}
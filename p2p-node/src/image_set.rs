@@ -0,0 +1,194 @@
+//! Every image this node has completed, kept side by side under
+//! `data_dir/images/<hash>.img` so `GET /image/{hash}` can serve any of them
+//! while `current.img` moves on.
+//!
+//! Each copy gets an [`ImageMeta`] sidecar, which is how the set is rebuilt
+//! after a restart. It is written whatever `IMAGE_SIDECAR` says: that only
+//! governs the user-facing `current.img.meta.json`, and a copy without one
+//! could never be listed, trimmed or removed again. Copies go away when
+//! their blob leaves the store, or when more than [`MAX_HELD_IMAGES`] newer
+//! ones push them out.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use tokio::fs;
+
+use crate::image_meta::{self, ImageMeta};
+
+pub const IMAGES_DIR: &str = "images";
+
+/// Most images held at once; the oldest copies go first.
+pub const MAX_HELD_IMAGES: usize = 32;
+
+/// Where the copy of `hash` lives.
+pub fn path(data_dir: &Path, hash: &str) -> PathBuf {
+    data_dir.join(IMAGES_DIR).join(format!("{hash}.img"))
+}
+
+/// Keep `image` (the just-completed `current.img`) as `meta.hash`, with its
+/// sidecar. A hard link where possible, since `current.img` is only ever
+/// replaced by rename; a copy otherwise.
+pub async fn keep(data_dir: &Path, image: &Path, meta: &ImageMeta) -> io::Result<()> {
+    fs::create_dir_all(data_dir.join(IMAGES_DIR)).await?;
+    let dest = path(data_dir, &meta.hash);
+    if fs::metadata(&dest).await.is_err() {
+        let staged = data_dir
+            .join(IMAGES_DIR)
+            .join(format!(".{}.part", meta.hash));
+        let _ = fs::remove_file(&staged).await;
+        if fs::hard_link(image, &staged).await.is_err() {
+            fs::copy(image, &staged).await?;
+        }
+        fs::rename(&staged, &dest).await?;
+    }
+    image_meta::write(&dest, meta)
+        .await
+        .map_err(io::Error::other)
+}
+
+/// Drop the copy of `hash` and its sidecar. Missing files are not an error.
+pub async fn remove(data_dir: &Path, hash: &str) -> io::Result<()> {
+    let image = path(data_dir, hash);
+    for path in [image_meta::sidecar_path(&image), image] {
+        match fs::remove_file(&path).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The images on disk with a sidecar that matches them, oldest first.
+///
+/// Anything else under `images/` (a copy with no or a stale sidecar, an
+/// interrupted `keep`) is deleted, since nothing would ever list it again.
+pub async fn load(data_dir: &Path) -> Vec<ImageMeta> {
+    let Ok(mut entries) = fs::read_dir(data_dir.join(IMAGES_DIR)).await else {
+        return Vec::new();
+    };
+    let mut found = Vec::new();
+    while let Ok(Some(entry)) = entries.next_entry().await {
+        let image = entry.path();
+        if image.extension().is_some_and(|e| e == "part") {
+            let _ = fs::remove_file(&image).await;
+            continue;
+        }
+        if image.extension().is_none_or(|e| e != "img") {
+            continue;
+        }
+        let described = match (image_meta::read(&image).await, fs::metadata(&image).await) {
+            (Some(meta), Ok(m)) if m.len() == meta.bytes && image == path(data_dir, &meta.hash) => {
+                Some((m.modified().unwrap_or(SystemTime::UNIX_EPOCH), meta))
+            }
+            _ => None,
+        };
+        match described {
+            Some(found_image) => found.push(found_image),
+            None => {
+                for path in [image_meta::sidecar_path(&image), image] {
+                    let _ = fs::remove_file(&path).await;
+                }
+            }
+        }
+    }
+    found.sort_by_key(|(at, _)| *at);
+    found.into_iter().map(|(_, meta)| meta).collect()
+}
+
+/// Add `meta` to `images` as the newest, replacing an older entry for the
+/// same hash. Returns the entries that fell outside [`MAX_HELD_IMAGES`],
+/// whose copies the caller should [`remove`].
+pub fn push(images: &mut Vec<ImageMeta>, meta: ImageMeta) -> Vec<ImageMeta> {
+    images.retain(|m| m.hash != meta.hash);
+    images.push(meta);
+    trim(images)
+}
+
+/// Drop the oldest of `images` down to [`MAX_HELD_IMAGES`], returning them.
+pub fn trim(images: &mut Vec<ImageMeta>) -> Vec<ImageMeta> {
+    let excess = images.len().saturating_sub(MAX_HELD_IMAGES);
+    images.drain(..excess).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(hash: &str, bytes: u64) -> ImageMeta {
+        ImageMeta {
+            filename: format!("{hash}.png"),
+            content_type: "image/png".into(),
+            content_encoding: None,
+            hash: hash.into(),
+            bytes,
+            phash: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn kept_images_survive_current_being_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("current.img");
+        fs::write(&image, b"one").await.unwrap();
+        keep(dir.path(), &image, &meta("aa", 3)).await.unwrap();
+        // current.img is replaced by rename, never rewritten in place
+        let next = dir.path().join("next.img");
+        fs::write(&next, b"second").await.unwrap();
+        fs::rename(&next, &image).await.unwrap();
+        keep(dir.path(), &image, &meta("bb", 6)).await.unwrap();
+
+        assert_eq!(fs::read(path(dir.path(), "aa")).await.unwrap(), b"one");
+        assert_eq!(fs::read(path(dir.path(), "bb")).await.unwrap(), b"second");
+        let hashes: Vec<_> = load(dir.path()).await.into_iter().map(|m| m.hash).collect();
+        assert_eq!(hashes.len(), 2);
+
+        remove(dir.path(), "aa").await.unwrap();
+        remove(dir.path(), "aa").await.unwrap();
+        let hashes: Vec<_> = load(dir.path()).await.into_iter().map(|m| m.hash).collect();
+        assert_eq!(hashes, ["bb"]);
+    }
+
+    #[tokio::test]
+    async fn load_deletes_copies_it_cannot_describe() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = dir.path().join("current.img");
+        fs::write(&image, b"one").await.unwrap();
+        keep(dir.path(), &image, &meta("aa", 3)).await.unwrap();
+        // Left by a run that wrote no sidecar, and by an interrupted keep
+        let bare = path(dir.path(), "bb");
+        fs::write(&bare, b"two").await.unwrap();
+        let part = dir.path().join(IMAGES_DIR).join(".cc.part");
+        fs::write(&part, b"three").await.unwrap();
+
+        let hashes: Vec<_> = load(dir.path()).await.into_iter().map(|m| m.hash).collect();
+        assert_eq!(hashes, ["aa"]);
+        assert!(!bare.exists());
+        assert!(!part.exists());
+        assert!(path(dir.path(), "aa").exists());
+    }
+
+    #[test]
+    fn push_moves_a_repeat_to_the_end() {
+        let mut images = vec![meta("aa", 1), meta("bb", 1)];
+        assert!(push(&mut images, meta("aa", 1)).is_empty());
+        let hashes: Vec<_> = images.iter().map(|m| m.hash.as_str()).collect();
+        assert_eq!(hashes, ["bb", "aa"]);
+    }
+
+    #[test]
+    fn push_evicts_the_oldest_past_the_cap() {
+        let mut images = Vec::new();
+        for i in 0..MAX_HELD_IMAGES {
+            assert!(push(&mut images, meta(&format!("{i:02}"), 1)).is_empty());
+        }
+        let evicted = push(&mut images, meta("new", 1));
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(evicted[0].hash, "00");
+        assert_eq!(images.len(), MAX_HELD_IMAGES);
+        assert_eq!(images.last().unwrap().hash, "new");
+    }
+}
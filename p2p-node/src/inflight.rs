@@ -0,0 +1,144 @@
+use std::path::Path;
+
+use iroh_base::NodeAddr;
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// A P2P receive that was started and hasn't finished yet.
+///
+/// Persisted so a node restarted mid-download knows what it was fetching and
+/// from whom; the partial blob itself survives in the store.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InFlight {
+    pub transfer_id: String,
+    pub hash: String,
+    pub filename: String,
+    pub content_type: String,
+    /// `Content-Encoding` the sender stores the blob under (`gzip`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+    /// The provider named by the notify or ticket, tried alongside known peers.
+    pub provider: Option<NodeAddr>,
+    /// Further providers the request named (`provider_node_ids`).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub more_providers: Vec<NodeAddr>,
+    /// Stripe size, in chunks, the request asked for (`DOWNLOAD_MODE=chunk_stripe`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stripe_span: Option<u64>,
+    /// Size the notify declared (`bytes_total`), checked against what arrives
+    /// under `SIZE_MISMATCH_TOLERANCE_PCT`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub declared_size: Option<u64>,
+    /// Restarted from disk after a restart (`AUTO_RESUME`).
+    #[serde(default)]
+    pub resumed: bool,
+}
+
+/// In-flight receives keyed by transfer id, persisted as JSON in the data dir.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct InFlightSet {
+    transfers: Vec<InFlight>,
+}
+
+impl InFlightSet {
+    /// Load from `path`, starting empty if it is missing or unreadable.
+    pub async fn load(path: &Path) -> Self {
+        match fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// Track `transfer`, replacing an entry with the same transfer id.
+    ///
+    /// Concurrent receives of one hash each keep their own entry.
+    pub fn insert(&mut self, transfer: InFlight) {
+        self.remove(&transfer.transfer_id);
+        self.transfers.push(transfer);
+    }
+
+    pub fn remove(&mut self, transfer_id: &str) {
+        self.transfers.retain(|t| t.transfer_id != transfer_id);
+    }
+
+    pub fn transfers(&self) -> &[InFlight] {
+        &self.transfers
+    }
+}
+
+impl InFlight {
+    /// A receive of `hash` from `providers`, first one first, with no
+    /// further options.
+    pub fn new(
+        transfer_id: String,
+        hash: String,
+        filename: String,
+        content_type: String,
+        mut providers: Vec<NodeAddr>,
+    ) -> Self {
+        let provider = (!providers.is_empty()).then(|| providers.remove(0));
+        Self {
+            transfer_id,
+            hash,
+            filename,
+            content_type,
+            content_encoding: None,
+            provider,
+            more_providers: providers,
+            stripe_span: None,
+            declared_size: None,
+            resumed: false,
+        }
+    }
+
+    /// Every provider the request named, first one first.
+    pub fn providers(&self) -> Vec<NodeAddr> {
+        self.provider
+            .iter()
+            .chain(&self.more_providers)
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(hash: &str, id: &str) -> InFlight {
+        InFlight::new(
+            id.into(),
+            hash.into(),
+            "f.png".into(),
+            "image/png".into(),
+            Vec::new(),
+        )
+    }
+
+    #[tokio::test]
+    async fn tracks_one_entry_per_transfer_and_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inflight.json");
+        let mut set = InFlightSet::load(&path).await;
+        assert!(set.transfers().is_empty());
+
+        set.insert(transfer("a", "1"));
+        set.insert(transfer("b", "2"));
+        set.insert(transfer("a", "3"));
+        set.insert(transfer("c", "2"));
+        set.save(&path).await.unwrap();
+
+        let mut loaded = InFlightSet::load(&path).await;
+        let ids: Vec<_> = loaded.transfers().iter().map(|t| &t.transfer_id).collect();
+        assert_eq!(ids, ["1", "3", "2"]);
+        // Finishing one receive of "a" leaves the other one tracked
+        loaded.remove("1");
+        loaded.remove("2");
+        assert_eq!(loaded.transfers(), [transfer("a", "3")]);
+    }
+}
@@ -0,0 +1,219 @@
+use std::{fmt, sync::Arc};
+
+/// A rewrite applied to uploaded bytes before they are stored and shared.
+///
+/// Transforms run in order on the blocking pool, so they may decode and
+/// re-encode. One that doesn't apply to the content returns it unchanged;
+/// the hash peers are notified of is always that of the final bytes.
+pub trait IngestTransform: fmt::Debug + Send + Sync {
+    fn transform(&self, bytes: Vec<u8>, content_type: String) -> (Vec<u8>, String);
+}
+
+/// Leaves content as uploaded.
+#[derive(Debug)]
+pub struct NoOp;
+
+impl IngestTransform for NoOp {
+    fn transform(&self, bytes: Vec<u8>, content_type: String) -> (Vec<u8>, String) {
+        (bytes, content_type)
+    }
+}
+
+/// Drops EXIF/XMP (APP1), IPTC (APP13) and comment segments from JPEGs.
+///
+/// Only the header segments before the scan are touched; ICC profiles and
+/// Adobe colour markers are kept since they change how the image renders.
+/// Anything that doesn't parse as a JPEG is passed through.
+#[derive(Debug)]
+pub struct StripJpegMetadata;
+
+impl IngestTransform for StripJpegMetadata {
+    fn transform(&self, bytes: Vec<u8>, content_type: String) -> (Vec<u8>, String) {
+        match strip_jpeg_metadata(&bytes) {
+            Some(stripped) => (stripped, content_type),
+            None => (bytes, content_type),
+        }
+    }
+}
+
+fn strip_jpeg_metadata(bytes: &[u8]) -> Option<Vec<u8>> {
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut out = Vec::with_capacity(bytes.len());
+    out.extend_from_slice(&bytes[..2]);
+    let mut i = 2;
+    loop {
+        if *bytes.get(i)? != 0xFF {
+            return None;
+        }
+        let marker = *bytes.get(i + 1)?;
+        match marker {
+            // Fill byte before a marker
+            0xFF => {
+                i += 1;
+                continue;
+            }
+            // Standalone markers carry no length
+            0x01 | 0xD0..=0xD7 => {
+                out.extend_from_slice(&bytes[i..i + 2]);
+                i += 2;
+                continue;
+            }
+            // Start of scan: entropy-coded data follows, copy the rest as is
+            0xDA => {
+                out.extend_from_slice(&bytes[i..]);
+                return Some(out);
+            }
+            _ => {}
+        }
+        let len = u16::from_be_bytes([*bytes.get(i + 2)?, *bytes.get(i + 3)?]) as usize;
+        let end = i + 2 + len;
+        if len < 2 || end > bytes.len() {
+            return None;
+        }
+        if !matches!(marker, 0xE1 | 0xED | 0xFE) {
+            out.extend_from_slice(&bytes[i..end]);
+        }
+        i = end;
+    }
+}
+
+/// Scales PNGs and JPEGs down so neither side exceeds `max` pixels,
+/// re-encoding in the same format. Smaller or undecodable images pass through.
+#[cfg(feature = "image_transforms")]
+#[derive(Debug)]
+pub struct Downscale {
+    pub max: u32,
+}
+
+#[cfg(feature = "image_transforms")]
+impl IngestTransform for Downscale {
+    fn transform(&self, bytes: Vec<u8>, content_type: String) -> (Vec<u8>, String) {
+        use image::{imageops::FilterType, ImageFormat};
+
+        let Ok(format) = image::guess_format(&bytes) else {
+            return (bytes, content_type);
+        };
+        if !matches!(format, ImageFormat::Png | ImageFormat::Jpeg) {
+            return (bytes, content_type);
+        }
+        let Ok(img) = image::load_from_memory_with_format(&bytes, format) else {
+            return (bytes, content_type);
+        };
+        if img.width().max(img.height()) <= self.max {
+            return (bytes, content_type);
+        }
+        let scaled = img.resize(self.max, self.max, FilterType::Triangle);
+        let mut out = std::io::Cursor::new(Vec::new());
+        match scaled.write_to(&mut out, format) {
+            Ok(()) => (out.into_inner(), content_type),
+            Err(_) => (bytes, content_type),
+        }
+    }
+}
+
+/// The transforms selected by `INGEST_TRANSFORMS`, applied in order.
+#[derive(Debug, Clone, Default)]
+pub struct IngestPipeline {
+    steps: Vec<Arc<dyn IngestTransform>>,
+}
+
+impl IngestPipeline {
+    /// Build from a comma-separated list (`noop`, `strip_exif`, and with the
+    /// `image_transforms` feature `downscale`, which needs `max_dimension`).
+    /// Returns the pipeline and the names that couldn't be used.
+    #[cfg_attr(not(feature = "image_transforms"), allow(unused_variables))]
+    pub fn from_env_values(list: &str, max_dimension: Option<u32>) -> (Self, Vec<String>) {
+        let mut steps: Vec<Arc<dyn IngestTransform>> = Vec::new();
+        let mut unknown = Vec::new();
+        for name in list.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match name {
+                "noop" => steps.push(Arc::new(NoOp)),
+                "strip_exif" => steps.push(Arc::new(StripJpegMetadata)),
+                #[cfg(feature = "image_transforms")]
+                "downscale" => match max_dimension {
+                    Some(max) => steps.push(Arc::new(Downscale { max })),
+                    None => unknown.push(name.to_string()),
+                },
+                _ => unknown.push(name.to_string()),
+            }
+        }
+        (Self { steps }, unknown)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn apply(&self, bytes: Vec<u8>, content_type: String) -> (Vec<u8>, String) {
+        self.steps
+            .iter()
+            .fold((bytes, content_type), |(b, ct), step| step.transform(b, ct))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SOI, APP0 (JFIF), APP1 (Exif), COM, then a scan that ends the file.
+    fn jpeg_with_exif() -> Vec<u8> {
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x06, b'J', b'F', b'I', b'F']);
+        jpeg.extend_from_slice(&[0xFF, 0xE1, 0x00, 0x08, b'E', b'x', b'i', b'f', 0, 0]);
+        jpeg.extend_from_slice(&[0xFF, 0xFE, 0x00, 0x04, b'h', b'i']);
+        jpeg.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn strips_exif_and_comments_but_keeps_the_rest() {
+        let (out, ct) = StripJpegMetadata.transform(jpeg_with_exif(), "image/jpeg".into());
+        assert_eq!(ct, "image/jpeg");
+        let mut expected = vec![0xFF, 0xD8];
+        expected.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x06, b'J', b'F', b'I', b'F']);
+        expected.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn non_jpeg_and_truncated_input_pass_through() {
+        let png = b"\x89PNG\r\n\x1a\nrest".to_vec();
+        assert_eq!(
+            StripJpegMetadata
+                .transform(png.clone(), "image/png".into())
+                .0,
+            png
+        );
+        let mut truncated = jpeg_with_exif();
+        truncated.truncate(12);
+        assert_eq!(
+            StripJpegMetadata
+                .transform(truncated.clone(), "image/jpeg".into())
+                .0,
+            truncated
+        );
+    }
+
+    #[test]
+    fn pipeline_from_env_values() {
+        let (pipeline, unknown) = IngestPipeline::from_env_values(" noop, strip_exif,resize", None);
+        assert_eq!(unknown, vec!["resize"]);
+        assert_eq!(pipeline.steps.len(), 2);
+        let (out, _) = pipeline.apply(jpeg_with_exif(), "image/jpeg".into());
+        assert!(out.len() < jpeg_with_exif().len());
+        assert!(IngestPipeline::from_env_values("", None).0.is_empty());
+    }
+
+    #[cfg(feature = "image_transforms")]
+    #[test]
+    fn downscales_large_png() {
+        let img = image::RgbImage::new(40, 20);
+        let mut png = std::io::Cursor::new(Vec::new());
+        img.write_to(&mut png, image::ImageFormat::Png).unwrap();
+        let (out, _) = Downscale { max: 10 }.transform(png.into_inner(), "image/png".into());
+        let scaled = image::load_from_memory(&out).unwrap();
+        assert_eq!((scaled.width(), scaled.height()), (10, 5));
+    }
+}
@@ -0,0 +1,110 @@
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+};
+
+use tracing::{
+    field::{Field, Visit},
+    Event, Subscriber,
+};
+use tracing_subscriber::{layer::Context, Layer};
+
+/// Log lines kept for `GET /diagnostics`.
+pub const LOG_RING_LINES: usize = 500;
+
+/// The most recent log lines, kept in memory by a `tracing` layer so a
+/// diagnostics bundle can include them without access to the node's stdout.
+///
+/// Fields whose names suggest a credential (`key`, `token`, `secret`,
+/// `password`, `sig`) are recorded as `[redacted]`. Clones share the buffer,
+/// so one can be installed as the layer and another read from.
+#[derive(Debug, Clone)]
+pub struct LogRing {
+    cap: usize,
+    lines: Arc<Mutex<VecDeque<String>>>,
+}
+
+impl LogRing {
+    pub fn new(cap: usize) -> Self {
+        Self {
+            cap,
+            lines: Arc::new(Mutex::new(VecDeque::with_capacity(cap))),
+        }
+    }
+
+    /// Retained lines, oldest first.
+    pub fn lines(&self) -> Vec<String> {
+        let lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        lines.iter().cloned().collect()
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap_or_else(|e| e.into_inner());
+        if lines.len() == self.cap {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+}
+
+impl<S: Subscriber> Layer<S> for LogRing {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if self.cap == 0 {
+            return;
+        }
+        let meta = event.metadata();
+        let mut line = format!(
+            "{} {:>5} {}:",
+            crate::unix_now(),
+            meta.level(),
+            meta.target()
+        );
+        event.record(&mut LineVisitor(&mut line));
+        self.push(line);
+    }
+}
+
+struct LineVisitor<'a>(&'a mut String);
+
+impl Visit for LineVisitor<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        let name = field.name();
+        if is_secret(name) {
+            let _ = write!(self.0, " {name}=[redacted]");
+        } else if name == "message" {
+            let _ = write!(self.0, " {value:?}");
+        } else {
+            let _ = write!(self.0, " {name}={value:?}");
+        }
+    }
+}
+
+fn is_secret(field: &str) -> bool {
+    let field = field.to_ascii_lowercase();
+    ["key", "token", "secret", "password", "sig"]
+        .iter()
+        .any(|s| field.contains(s))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    #[test]
+    fn keeps_newest_lines_and_redacts_secrets() {
+        let ring = LogRing::new(2);
+        let subscriber = tracing_subscriber::registry().with(ring.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("first");
+            tracing::warn!(peer = "a", "second");
+            tracing::info!(api_token = "hunter2", "third");
+        });
+        let lines = ring.lines();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].contains("WARN") && lines[0].ends_with(r#" second peer="a""#));
+        assert!(lines[1].contains("api_token=[redacted]"));
+        assert!(!lines[1].contains("hunter2"));
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// The Lamport clock ordering image updates across nodes: the version of the
+/// current image and the node that set it. Persisted in the data dir so a
+/// restarted node doesn't take an old notify for a new one.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LogicalClock {
+    pub version: u64,
+    /// Node id (or URL, without P2P) of whoever set `version`. Breaks ties
+    /// between nodes that reached the same version independently.
+    #[serde(default)]
+    pub origin: String,
+}
+
+impl LogicalClock {
+    /// Load from `path`, starting at zero if it is missing or unreadable.
+    pub async fn load(path: &Path) -> Self {
+        match fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// Whether an update stamped `version` by `origin` is newer than ours.
+    /// Equal versions go to the larger origin, so every node settles on the
+    /// same image when two uploads race.
+    pub fn is_newer(&self, version: u64, origin: &str) -> bool {
+        (version, origin) > (self.version, self.origin.as_str())
+    }
+
+    /// Stamp a local update by `origin`: one past anything seen so far.
+    pub fn tick(&mut self, origin: &str) -> u64 {
+        self.version += 1;
+        self.origin = origin.to_string();
+        self.version
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_by_version_then_origin() {
+        let clock = LogicalClock {
+            version: 3,
+            origin: "b".into(),
+        };
+        assert!(clock.is_newer(4, "a"));
+        assert!(!clock.is_newer(2, "z"));
+        assert!(clock.is_newer(3, "c"));
+        assert!(!clock.is_newer(3, "a"));
+        // A repeat of the update we hold is not newer
+        assert!(!clock.is_newer(3, "b"));
+    }
+
+    #[tokio::test]
+    async fn survives_a_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("clock.json");
+        assert_eq!(LogicalClock::load(&path).await, LogicalClock::default());
+        let mut clock = LogicalClock::default();
+        assert_eq!(clock.tick("node-a"), 1);
+        clock.save(&path).await.unwrap();
+        assert_eq!(LogicalClock::load(&path).await, clock);
+    }
+}
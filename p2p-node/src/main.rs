@@ -1,31 +1,163 @@
-use std::{collections::HashMap, env, net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    env,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use axum::middleware::{from_fn, Next};
+use axum::middleware::{from_fn, from_fn_with_state, Next};
 use axum::{
-    extract::{DefaultBodyLimit, Multipart, State},
-    http::{HeaderValue, StatusCode},
-    response::{IntoResponse, Response},
+    extract::{ConnectInfo, DefaultBodyLimit, FromRequest, Multipart, Path, Query, State},
+    http::{HeaderMap, HeaderValue, StatusCode},
+    response::{
+        sse::{self, KeepAlive, Sse},
+        IntoResponse, Response,
+    },
     routing::{get, post},
     Json, Router,
 };
 use futures_util::StreamExt;
 #[cfg(all(not(test), feature = "p2p_notify"))]
 use iroh::protocol::Router as IrohRouter;
-use iroh::Endpoint;
+use iroh::{endpoint::Source, Endpoint, Watcher};
 use iroh_base::{NodeAddr, PublicKey};
-use iroh_blobs::api::downloader::{DownloadProgessItem, DownloadRequest, Shuffled, SplitStrategy};
-use iroh_blobs::protocol::GetRequest;
+use iroh_blobs::api::downloader::{DownloadProgessItem, DownloadRequest, SplitStrategy};
+use iroh_blobs::api::proto::BlobStatus;
+use iroh_blobs::protocol::{ChunkRanges, ChunkRangesExt, GetRequest};
+use iroh_blobs::store::fs::options::{GcConfig, Options as FsStoreOptions};
 use iroh_blobs::{store::fs::FsStore, BlobsProtocol};
-use rand::{thread_rng, Rng};
+use rand::{seq::SliceRandom, thread_rng, Rng};
 use serde::{Deserialize, Serialize};
 use tokio::{fs, sync::Mutex, time::sleep};
-use tokio_util::io::ReaderStream;
-use tower_http::cors::CorsLayer;
-use tracing::{error, info, warn};
+use tokio_util::{io::ReaderStream, sync::CancellationToken};
+use tower_http::{compression::CompressionLayer, cors::CorsLayer};
+use tracing::{debug, error, info, warn};
 
 mod notify;
 use notify::{send_notify, NotifyMsg};
 mod chunk_strategy;
+use chunk_strategy::Coverage;
+mod signing;
+use signing::{SignedQuery, UrlSigner};
+mod api_format;
+mod image_meta;
+use api_format::ApiFormat;
+mod store_cache;
+use image_meta::ImageMeta;
+use store_cache::StoreUsage;
+mod provider_score;
+use logical_clock::LogicalClock;
+use provider_score::ProviderScores;
+mod active_content;
+mod bind_addrs;
+mod byte_range;
+mod content_disposition;
+mod content_encoding;
+mod content_sniff;
+mod egress;
+mod events;
+mod fs_layout;
+mod idempotency;
+mod image_set;
+mod inflight;
+mod ingest_transform;
+mod log_ring;
+mod logical_clock;
+mod notify_acl;
+mod notify_log;
+mod phash;
+mod rate_limit;
+mod state_cell;
+mod transfer_rate;
+use bind_addrs::BindAddrs;
+use inflight::{InFlight, InFlightSet};
+use ingest_transform::IngestPipeline;
+use log_ring::LogRing;
+use notify_acl::NotifyAcl;
+use notify_log::{Direction, NotifyLog};
+use rate_limit::RateLimiter;
+use state_cell::StateCell;
+#[cfg(feature = "mmap_serve")]
+mod mmap_image;
+mod peer_file;
+use events::{Event, EventBus};
+use fs_layout::FsLayout;
+
+/// How often the store's garbage collector runs when `STORE_MAX_BYTES` or
+/// `BLOB_TTL_MS` is set. Evicted blobs are untagged immediately and their disk
+/// space is reclaimed on the next pass.
+const STORE_GC_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Default wait in `POST /upload_and_sync` for peers to report the new hash.
+const SYNC_TIMEOUT: Duration = Duration::from_secs(30);
+/// Longest `timeout_ms` a caller may ask `POST /upload_and_sync` to wait.
+const MAX_SYNC_TIMEOUT: Duration = Duration::from_secs(60);
+/// How often `POST /upload_and_sync` re-polls peers' `/status`.
+const SYNC_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Largest blob, in chunks, `POST /compare_strategies` will plan (64 MiB).
+const MAX_COMPARE_CHUNKS: u64 = 1 << 16;
+
+/// Most providers a `/receive` hint (`provider_node_id` plus
+/// `provider_node_ids`) can name; the rest are ignored rather than dialed.
+const MAX_HINTED_PROVIDERS: usize = 16;
+
+/// Per-peer wait for `/status` in `GET /cluster/status`.
+const CLUSTER_STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default wait for one peer's `/status` in address discovery
+/// (`PEER_STATUS_TIMEOUT_MS`).
+const PEER_STATUS_TIMEOUT: Duration = Duration::from_secs(2);
+/// Default cap on a peer's `/status` body in address discovery
+/// (`PEER_STATUS_MAX_BYTES`); a real one is a few KiB.
+const PEER_STATUS_MAX_BYTES: usize = 1024 * 1024;
+/// Default `max_distance` for `GET /similar`: out of 64 bits, re-encodes and
+/// light edits of one picture stay well under this.
+const SIMILAR_MAX_DISTANCE: u32 = 10;
+/// How long a `GET /cluster/status` result is reused, so polling UIs don't
+/// multiply into a `/status` fan-out per request.
+const CLUSTER_STATUS_CACHE: Duration = Duration::from_secs(1);
+/// Longest `ACTIVE_SEARCH` spends polling peers for a hash.
+const ACTIVE_SEARCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Peers `ACTIVE_SEARCH` polls at once.
+const ACTIVE_SEARCH_FANOUT: usize = 8;
+
+/// Size of the random blob `POST /throughput` sends when none is requested.
+const THROUGHPUT_DEFAULT_BYTES: u64 = 4 * 1024 * 1024;
+/// Largest blob `POST /throughput` will generate, and `/throughput/fetch` accept.
+const THROUGHPUT_MAX_BYTES: u64 = 64 * 1024 * 1024;
+/// How long the peer gets to fetch the `POST /throughput` blob.
+const THROUGHPUT_TIMEOUT: Duration = Duration::from_secs(60);
+/// `POST /throughput` runs and `/throughput/fetch` downloads at once; more get
+/// 429. Bounds the random blobs on disk and the scratch stores in memory.
+const THROUGHPUT_MAX_RUNS: usize = 2;
+/// Prefix of the tags holding `POST /throughput` blobs, cleared at startup in
+/// case a run was cut short.
+const THROUGHPUT_TAG_PREFIX: &str = "throughput-";
+
+/// How long `POST /ticket/inspect` waits on the provider for a blob's size.
+const TICKET_INSPECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Body size limit for the upload routes unless `MAX_UPLOAD_BYTES` is set.
+const DEFAULT_MAX_UPLOAD_BYTES: usize = 20 * 1024 * 1024;
+/// Body size limit for everything else, all small JSON requests.
+const JSON_BODY_LIMIT: usize = 64 * 1024;
+
+/// Default pause before the sequential fallback after a failed split or
+/// parallel download (`FALLBACK_DELAY_MS`); jittered down to half.
+const FALLBACK_DELAY: Duration = Duration::from_millis(250);
+
+/// First backoff between HTTP `/receive` notify attempts; doubles each retry.
+const HTTP_NOTIFY_BACKOFF: Duration = Duration::from_millis(200);
+/// Upper bound on time spent retrying one peer's HTTP notify.
+const HTTP_NOTIFY_MAX_WAIT: Duration = Duration::from_secs(5);
+/// How often undelivered peers are checked for a newly resolved address
+/// during the `NOTIFY_P2P_UPGRADE_MS` window.
+const NOTIFY_UPGRADE_POLL: Duration = Duration::from_millis(250);
+
+/// First backoff between attempts to export a received blob to `current.img`.
+const EXPORT_BACKOFF: Duration = Duration::from_millis(50);
 
 /// Shared runtime state for the node.
 ///
@@ -33,18 +165,180 @@ mod chunk_strategy;
 /// and the HTTP-visible `NodeState`. `NodeState` is wrapped in a `tokio::sync::Mutex`
 /// so concurrent async tasks (HTTP handlers, download progress loop, timers) can
 /// safely read/write status. Keep lock sections short to avoid contention.
+///
+/// `endpoint` and `blobs` are `None` when running with `DISTRIBUTION=http`;
+/// iroh-dependent paths go through [`NodeShared::endpoint`] to fail cleanly.
 #[derive(Clone, Debug)]
 pub struct NodeShared {
-    pub endpoint: Endpoint,
-    pub blobs: BlobsProtocol,
+    pub endpoint: Option<Endpoint>,
+    pub blobs: Option<BlobsProtocol>,
     pub store: Arc<FsStore>,
-    state: Arc<Mutex<NodeState>>, // for HTTP reporting
+    pub distribution: Distribution,
+    pub public_url: String, // base URL peers use to reach our HTTP API
+    state: Arc<StateCell<NodeState>>, // for HTTP reporting; writes wake /status/stream
     pub data_dir: PathBuf,
     pub peers_http: Vec<String>,
     pub peers_addrs: Arc<Mutex<HashMap<String, NodeAddr>>>, // url -> NodeAddr
+    pub peer_images: Arc<Mutex<HashMap<String, String>>>,   // url -> hash the peer reports holding
+    pub peer_image_sizes: Arc<Mutex<HashMap<String, (String, u64)>>>, // peer -> (hash, size) it last reported
+    pub size_tolerance: Option<f64>, // percent a received blob may differ from its declared size
+    pub peer_addrs_file: Option<PathBuf>, // static peers, see `load_peer_addrs_file`
+    pub peer_addr_seen: Arc<Mutex<HashMap<String, std::time::Instant>>>, // url -> last /status resolution
+    pub peer_addr_ttl: Option<Duration>, // drop addresses not re-resolved for this long
+    pub peer_status_timeout: Duration,   // per-request cap on a discovery /status fetch
+    pub peer_status_max_bytes: usize,    // larger discovery /status bodies are rejected
+    pub http_blob_fallback: bool,
+    pub http_notify_retries: u32, // extra attempts for the HTTP /receive fallback
+    pub notify_upgrade_window: Option<Duration>, // retry failed HTTP notifies over P2P this long
+    pub export_retries: u32,      // extra attempts to export a received blob
+    pub verify_upload: bool,      // read uploads back from the store before sharing
+    pub ingest: IngestPipeline,   // INGEST_TRANSFORMS applied to uploads before storing
+    pub keep_image_during_receive: bool, // serve the old image until a receive completes
+    pub block_active_content: bool, // reject HTML/XHTML/scripted SVG uploads with 415
+    pub sequential_max_candidates: Option<usize>, // cap on sequential fallback providers
+    pub prefer_lan: bool,         // try providers found by local discovery before the rest
+    pub single_provider_parallelism: usize, // concurrent range requests when one provider is known
+    pub verify_content_type: ContentTypeCheck, // sniff received images against the declared type
+    pub egress: Option<Arc<egress::EgressLimiter>>, // PROVIDER_EGRESS_BPS on blobs we serve
+    pub idempotency: Arc<Mutex<idempotency::IdempotencyCache>>, // /upload responses by Idempotency-Key
+    pub adopt_lock: Arc<Mutex<()>>, // held while current.img and its state change together
+    pub clock_file: Arc<Mutex<()>>, // orders writes of clock.json, outside the state lock
+    pub seq_order: SeqOrder,        // sequential fallback candidate order
+    pub seq_shuffle_seed: Option<u64>, // makes SEQ_ORDER=shuffle reproducible
+    pub fallback_delay: Duration,   // jittered pause before sequential after a failed split
+    pub active_search: bool,        // poll every peer for a hash nobody is known to hold
+    pub download_mode: DownloadMode, // how a multi-provider download is striped
+    pub sequential_completion: SequentialCompletion,
+    pub paused: Arc<tokio::sync::watch::Sender<bool>>, // POST /pause gate for transfers
+    pub cancel: Arc<Mutex<CancellationToken>>, // POST /cancel; each receive holds a child token
+    pub image_changed: Arc<tokio::sync::watch::Sender<Option<String>>>, // hash of each completed image
+    pub receive_deadline: Option<Duration>, // hard cap on one receive_by_discovery
+    pub split_stall: Option<Duration>,      // abandon a split download idle this long
+    pub verify_slots: Arc<tokio::sync::Semaphore>, // VERIFY_CONCURRENCY hash checks at once
+    pub upload_slots: Option<UploadSlots>,  // MAX_CONCURRENT_UPLOADS; `None` is unlimited
+    pub max_upload_bytes: usize,            // MAX_UPLOAD_BYTES: body limit of the upload routes
+    pub url_signer: Option<UrlSigner>,      // set when URL_SIGNING_KEY is configured
+    pub rate_limit: Option<Arc<RateLimiter>>, // RATE_LIMIT_RPS on mutating endpoints
+    pub store_max_bytes: Option<u64>,
+    pub blob_ttl: Option<Duration>, // untag stored blobs this long after their last store
+    pub pinned: HashSet<iroh_blobs::Hash>, // never evicted from the store
+    pub store_usage: Arc<Mutex<StoreUsage>>,
+    pub provider_scores: Arc<Mutex<ProviderScores>>, // persisted in DATA_DIR/provider_scores.json
+    pub default_content_type: String,                // for uploads whose part has no content type
+    pub image_sidecar: bool, // write current.img.meta.json next to the served image
+    pub fs_layout: FsLayout, // also keep images under by-hash/ and by-name/
+    pub placeholder: Option<Placeholder>, // served by /image while has_image is false
+    pub events: EventBus,    // feeds GET /events
+    pub notify_log: NotifyLog, // notify messages sent and received, for GET /notifies
+    pub notify_acl: NotifyAcl, // whose P2P notifies start a download
+    pub bind_addrs: Option<BindAddrs>, // BIND_ADDRS; filters the addresses we advertise
+    pub inflight: Arc<Mutex<InFlightSet>>, // persisted in DATA_DIR/inflight.json
+    pub cluster_status: Arc<Mutex<Option<(std::time::Instant, serde_json::Value)>>>, // last /cluster/status
+    pub http_client: reqwest::Client, // shared by outbound peer requests, so connections are reused
+    pub throughput_slots: Arc<tokio::sync::Semaphore>, // THROUGHPUT_MAX_RUNS speed tests at once
+    #[cfg(feature = "mmap_serve")]
+    pub mapped_image: Arc<mmap_image::MappedImage>, // current.img for GET /image
     pub latency_min: u64,
     pub latency_max: u64,
     pub stream_sleep_ms: u64,
+    pub demo_mode: bool, // off: no injected latency or stream sleeps, whatever their settings
+    pub diagnostics_token: Option<String>, // bearer token for GET /diagnostics; unset disables it
+    pub log_ring: LogRing, // recent log lines for GET /diagnostics
+}
+
+/// Image shown at `/image` before any real image exists (`PLACEHOLDER_IMAGE`).
+///
+/// Loaded once at startup. It never counts as the node's image: `has_image`,
+/// the current hash and the ETag are untouched.
+#[derive(Clone, Debug)]
+pub struct Placeholder {
+    pub bytes: bytes::Bytes,
+    pub content_type: String,
+}
+
+impl Placeholder {
+    async fn load(path: &std::path::Path) -> std::io::Result<Self> {
+        let content_type = match path.extension().and_then(|e| e.to_str()) {
+            Some("png") => "image/png",
+            Some("jpg" | "jpeg") => "image/jpeg",
+            Some("gif") => "image/gif",
+            Some("webp") => "image/webp",
+            Some("svg") => "image/svg+xml",
+            _ => "application/octet-stream",
+        };
+        Ok(Self {
+            bytes: fs::read(path).await?.into(),
+            content_type: content_type.to_string(),
+        })
+    }
+
+    /// The placeholder as a response; `head` leaves out the body.
+    fn response(&self, head: bool) -> Response {
+        let mut headers = HeaderMap::new();
+        headers.insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+        if let Ok(ct) = HeaderValue::from_str(&self.content_type) {
+            headers.insert("Content-Type", ct);
+        }
+        headers.insert("Content-Length", HeaderValue::from(self.bytes.len()));
+        // Don't let a browser keep showing it once the real image lands
+        headers.insert("Cache-Control", HeaderValue::from_static("no-store"));
+        if head {
+            (headers, ()).into_response()
+        } else {
+            (headers, self.bytes.clone()).into_response()
+        }
+    }
+}
+
+/// Bound on simultaneous `/upload` and `/store/add` ingests, each of which
+/// buffers the whole file.
+#[derive(Clone, Debug)]
+pub struct UploadSlots {
+    slots: Arc<tokio::sync::Semaphore>,
+    max: usize,
+    /// Wait for a free slot instead of answering 429 (`UPLOAD_QUEUE`).
+    queue: bool,
+}
+
+impl UploadSlots {
+    pub fn new(max: usize, queue: bool) -> Self {
+        Self {
+            slots: Arc::new(tokio::sync::Semaphore::new(max)),
+            max,
+            queue,
+        }
+    }
+
+    /// A slot held until the returned permit drops, or `None` when all are
+    /// taken and queueing is off.
+    async fn acquire(&self) -> Option<tokio::sync::OwnedSemaphorePermit> {
+        if self.queue {
+            self.slots.clone().acquire_owned().await.ok()
+        } else {
+            self.slots.clone().try_acquire_owned().ok()
+        }
+    }
+}
+
+/// How content moves between nodes (`DISTRIBUTION` env).
+///
+/// `Http` skips iroh entirely so the HTTP baseline can be benchmarked in
+/// isolation: peers are notified via `POST /receive` and pull `/blob/{hash}`
+/// from the provider's URL.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Distribution {
+    P2p,
+    Http,
+}
+
+impl Distribution {
+    fn from_env_value(v: &str) -> Self {
+        match v.trim().to_ascii_lowercase().as_str() {
+            "http" => Distribution::Http,
+            _ => Distribution::P2p,
+        }
+    }
 }
 
 /// Middleware: add Access-Control-Allow-Private-Network for PNA preflights from secure contexts
@@ -57,44 +351,459 @@ async fn add_pna_header(req: axum::http::Request<axum::body::Body>, next: Next)
     res
 }
 
+/// Middleware: when `URL_SIGNING_KEY` is set, only let through requests that
+/// carry a valid, unexpired `?exp=&sig=` for their path. Open otherwise.
+async fn require_signed_url(
+    State(shared): State<Arc<NodeShared>>,
+    req: axum::http::Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    if let Some(signer) = &shared.url_signer {
+        let query = Query::<SignedQuery>::try_from_uri(req.uri())
+            .map(|q| q.0)
+            .unwrap_or_default();
+        if let Err(e) = signer.verify(req.uri().path(), &query, unix_now()) {
+            warn!(?e, path = %req.uri().path(), "rejected image request with bad signature");
+            return (StatusCode::FORBIDDEN, "invalid or expired signature").into_response();
+        }
+    }
+    next.run(req).await
+}
+
+/// Middleware: with `RATE_LIMIT_RPS` set, answer 429 with `Retry-After` to a
+/// client IP that exceeds its token bucket. Only mutating methods count;
+/// reads, health checks and CORS preflights are never limited.
+async fn rate_limit(
+    State(shared): State<Arc<NodeShared>>,
+    req: axum::http::Request<axum::body::Body>,
+    next: Next,
+) -> Response {
+    if let Some(limiter) = &shared.rate_limit {
+        if !req.method().is_safe() {
+            // Without connection info (never the case under `main`) all
+            // clients share one bucket
+            let client = req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|c| c.0.ip())
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            if let Err(wait) = limiter.check(client, std::time::Instant::now()) {
+                let secs = wait.as_secs_f64().ceil().max(1.0) as u64;
+                warn!(%client, path = %req.uri().path(), "rate limited");
+                return (
+                    StatusCode::TOO_MANY_REQUESTS,
+                    [(axum::http::header::RETRY_AFTER, secs.to_string())],
+                    "rate limit exceeded",
+                )
+                    .into_response();
+            }
+        }
+    }
+    next.run(req).await
+}
+
 /// Status exposed at `GET /status`.
 ///
 /// Invariant: `has_image == true` only after the blob has been FULLY received
 /// and exported to `current.img` on disk. This guarantees `/image` and
 /// `/image_stream` can immediately serve the completed file when the UI sees
 /// `has_image: true`.
-#[derive(Debug, Default, Serialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 struct NodeState {
     node_name: String,
     node_addr: Option<String>,
+    /// `node_addr` with the relay URL and direct addresses we advertise, so
+    /// peers without working discovery can still dial us. Filled in by the
+    /// `/status` handler.
+    node_addr_full: Option<NodeAddr>,
     has_image: bool,
     current_filename: Option<String>,
     content_type: Option<String>,
+    /// `Content-Encoding` the current image was uploaded with (e.g. `gzip`);
+    /// `None` for plain bytes.
+    content_encoding: Option<String>,
     current_hash: Option<String>,
     bytes_total: Option<u64>,
     bytes_received: u64,
     progress: f32,
+    /// Receive speed over the last few seconds of the current transfer; 0
+    /// when idle.
+    bytes_per_sec: f32,
+    /// Seconds left at `bytes_per_sec`, once the total size is known (or
+    /// estimated) and bytes are moving.
+    eta_seconds: Option<u64>,
+    #[serde(skip)]
+    rate_window: transfer_rate::RateWindow,
     stripe_providers: HashMap<String, Vec<String>>,
+    /// Live parameters of the in-flight transfer; `None` when idle.
+    download_strategy: Option<DownloadStrategy>,
+    /// Why the most recent receive failed; cleared when a new one starts.
+    last_error: Option<String>,
+    /// Unique per receive attempt, so repeat receives of one hash are
+    /// distinguishable across polls.
+    transfer_id: Option<String>,
+    /// The current transfer was picked up again after a restart.
+    resumed: bool,
+    /// `Content-Encoding` the blob of the current transfer was announced
+    /// with; becomes `content_encoding` when the transfer completes.
+    #[serde(skip)]
+    incoming_encoding: Option<String>,
+    /// `(hash, size)` the current transfer's notify declared; checked and
+    /// cleared when the transfer ends.
+    #[serde(skip)]
+    declared_size: Option<(String, u64)>,
+    /// `progress` is against a size a peer reported, not one we verified; it
+    /// stays below 100 until the transfer completes.
+    progress_estimated: bool,
+    /// Configured `SEQUENTIAL_COMPLETION`; filled in by the `/status` handler.
+    sequential_completion: SequentialCompletion,
+    /// Transfers are held by `POST /pause`; filled in by the `/status` handler.
+    paused: bool,
+    /// Configured `DEMO_MODE`; filled in by the `/status` handler.
+    demo_mode: bool,
+    /// The image endpoints need signed URLs (`URL_SIGNING_KEY`); filled in
+    /// by the `/status` handler.
+    signed_urls: bool,
+    /// Configured `SEQ_ORDER`; filled in by the `/status` handler.
+    seq_order: SeqOrder,
+    /// Bytes/second served to blob downloaders over the last second; only
+    /// measured with `PROVIDER_EGRESS_BPS`. Filled in by the `/status` handler.
+    egress_bps: Option<u64>,
+    /// Logical clock of the current image: bumped by each upload, raised to
+    /// the version of each adopted notify. Orders updates across nodes
+    /// without trusting wall clocks. Persisted in `DATA_DIR/clock.json`.
+    version: u64,
+    /// Who set `version`; ties on `version` go to the larger origin.
+    #[serde(skip)]
+    version_origin: String,
+    /// Perceptual hash of the current image (16 hex digits), with the
+    /// `phash` feature; `None` for non-image or encoded content.
+    phash: Option<String>,
+    /// Every image held under `images/`, oldest first; the last one is
+    /// normally the current image. Served at `GET /image/{hash}` and listed
+    /// at `GET /images`, not in `/status`.
+    #[serde(skip)]
+    images: Vec<ImageMeta>,
+    /// Position of the image on show among the held images of an `image/*`
+    /// type, with `SLIDESHOW_MS`; `None` until the first slide.
+    slide_index: Option<usize>,
+}
+
+impl NodeState {
+    fn clock(&self) -> LogicalClock {
+        LogicalClock {
+            version: self.version,
+            origin: self.version_origin.clone(),
+        }
+    }
+
+    fn set_clock(&mut self, clock: LogicalClock) {
+        self.version = clock.version;
+        self.version_origin = clock.origin;
+    }
+
+    /// Forget the speed and ETA of the previous transfer.
+    fn clear_rate(&mut self) {
+        self.bytes_per_sec = 0.0;
+        self.eta_seconds = None;
+        self.rate_window.clear();
+    }
+
+    /// Serve the current image as `content_type`, and describe its held copy
+    /// the same way.
+    fn set_content_type(&mut self, content_type: &str) {
+        self.content_type = Some(content_type.to_string());
+        let current = self.current_hash.clone();
+        if let Some(held) = self
+            .images
+            .iter_mut()
+            .find(|m| Some(&m.hash) == current.as_ref())
+        {
+            held.content_type = content_type.to_string();
+        }
+    }
+}
+
+/// When the sequential fallback counts a download as done
+/// (`SEQUENTIAL_COMPLETION`).
+///
+/// `AllComplete` is for reliability testing: after the first provider
+/// delivers, the blob is fetched again from up to `providers - 1` further
+/// candidates and the receive fails if any of them doesn't deliver the same
+/// content.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+pub enum SequentialCompletion {
+    #[default]
+    FirstComplete,
+    AllComplete {
+        providers: usize,
+    },
+}
+
+impl SequentialCompletion {
+    fn from_env_values(mode: &str, providers: Option<&str>) -> Self {
+        match mode.trim().to_ascii_lowercase().as_str() {
+            "all" => SequentialCompletion::AllComplete {
+                providers: providers
+                    .and_then(|p| p.trim().parse().ok())
+                    .filter(|&n: &usize| n > 1)
+                    .unwrap_or(2),
+            },
+            _ => SequentialCompletion::FirstComplete,
+        }
+    }
+}
+
+/// How the sequential fallback orders its candidate providers (`SEQ_ORDER`).
+#[derive(Debug, Default, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SeqOrder {
+    /// `PEERS` order, then peers learned since (by URL), then the notifying
+    /// provider.
+    Insertion,
+    /// Random, or reproducible with `SEQ_SHUFFLE_SEED`.
+    Shuffle,
+    /// Best provider reputation first; ties keep insertion order.
+    #[default]
+    Scored,
+    /// Lowest round-trip time iroh has measured first; unmeasured last.
+    Latency,
+}
+
+impl SeqOrder {
+    fn from_env_value(v: &str) -> Self {
+        match v.trim().to_ascii_lowercase().as_str() {
+            "insertion" => SeqOrder::Insertion,
+            "shuffle" => SeqOrder::Shuffle,
+            "latency" => SeqOrder::Latency,
+            _ => SeqOrder::Scored,
+        }
+    }
+}
+
+/// How a download from several providers is divided between them
+/// (`DOWNLOAD_MODE`).
+#[derive(Debug, Default, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadMode {
+    /// iroh-blobs' own `SplitStrategy::Split`.
+    #[default]
+    Split,
+    /// Shuffled fixed-size stripes from `chunk_strategy::randomized_get_requests`,
+    /// dealt round-robin to the providers, each stripe its own request.
+    ChunkStripe,
+}
+
+impl DownloadMode {
+    fn from_env_value(v: &str) -> Self {
+        match v.trim().to_ascii_lowercase().as_str() {
+            "chunk_stripe" => DownloadMode::ChunkStripe,
+            _ => DownloadMode::Split,
+        }
+    }
+
+    /// The `download_strategy.mode` a download in this mode reports.
+    fn as_str(self) -> &'static str {
+        match self {
+            DownloadMode::Split => "split",
+            DownloadMode::ChunkStripe => "chunk_stripe",
+        }
+    }
+}
+
+/// What to do when a received image's bytes contradict the content type the
+/// notifying peer declared (`VERIFY_CONTENT_TYPE`).
+#[derive(Debug, Default, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentTypeCheck {
+    /// Trust the declared type.
+    #[default]
+    Off,
+    /// Serve with the sniffed type instead.
+    Correct,
+    /// Keep the declared type but report the mismatch in `last_error`.
+    Flag,
+}
+
+impl ContentTypeCheck {
+    fn from_env_value(v: &str) -> Self {
+        match v.trim().to_ascii_lowercase().as_str() {
+            "1" | "true" | "correct" => ContentTypeCheck::Correct,
+            "flag" => ContentTypeCheck::Flag,
+            _ => ContentTypeCheck::Off,
+        }
+    }
+}
+
+/// What the node is currently doing to fetch a blob, for UI narration.
+///
+/// `round` counts attempts within one transfer: the split attempt is round 1
+/// and every sequential candidate (or the HTTP fallback) bumps it by one.
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+struct DownloadStrategy {
+    /// `split`, `sequential` or `http`.
+    mode: String,
+    /// Providers in play: the split set, the candidate list, or HTTP peers.
+    providers: usize,
+    round: u32,
+    current_provider: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct StatusPeerResp {
     node_addr: Option<String>,
+    /// Absent from older peers; one we can't parse is treated as absent.
+    #[serde(default, deserialize_with = "lenient")]
+    node_addr_full: Option<NodeAddr>,
+    #[serde(default)]
+    has_image: bool,
+    #[serde(default)]
+    current_hash: Option<String>,
+    #[serde(default)]
+    bytes_total: Option<u64>,
 }
 
-#[derive(Deserialize)]
+impl StatusPeerResp {
+    /// The peer's address: the full one when it names the same node as
+    /// `node_addr`, else the bare node id, left to discovery.
+    fn addr(&self) -> Option<NodeAddr> {
+        let node_id: PublicKey = self.node_addr.as_deref()?.parse().ok()?;
+        match &self.node_addr_full {
+            Some(full) if full.node_id == node_id => Some(full.clone()),
+            _ => Some(NodeAddr::from(node_id)),
+        }
+    }
+}
+
+/// `None` for a value that doesn't deserialize as `T`, instead of failing
+/// the whole document.
+fn lenient<'de, D, T>(d: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: serde::de::DeserializeOwned,
+{
+    let value = serde_json::Value::deserialize(d)?;
+    Ok(serde_json::from_value(value).ok())
+}
+
+#[derive(Serialize, Deserialize)]
 struct ReceiveBody {
     ticket: Option<String>,
     hash: Option<String>,
     filename: String,
     content_type: String,
     provider_node_id: Option<String>,
+    /// More providers known to hold the blob, as an array or a comma list.
+    /// Tried alongside `provider_node_id` and the known peers.
+    #[serde(
+        default,
+        deserialize_with = "string_or_list",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    provider_node_ids: Vec<String>,
+    provider_url: Option<String>,
+    /// The notifier's logical clock; see `NotifyMsg::version`.
+    version: Option<u64>,
+    /// Declared blob size; see `NotifyMsg::bytes_total`.
+    bytes_total: Option<u64>,
+    /// Stripe size in chunks for `DOWNLOAD_MODE=chunk_stripe`, in place of
+    /// the recommended one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    stripe_span: Option<u64>,
+    /// See `NotifyMsg::content_encoding`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    content_encoding: Option<String>,
+}
+
+/// `["a", "b"]` or `"a,b"` as a list; blanks are dropped.
+fn string_or_list<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum StringOrList {
+        String(String),
+        List(Vec<String>),
+    }
+    let items = match StringOrList::deserialize(deserializer)? {
+        StringOrList::String(s) => s.split(',').map(str::to_string).collect(),
+        StringOrList::List(items) => items,
+    };
+    Ok(items
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect())
+}
+
+/// `provider` followed by the parseable ids of `ids` (`provider_node_ids`),
+/// without repeats and at most `MAX_HINTED_PROVIDERS` in all. Unparseable
+/// ids are skipped.
+fn hinted_providers(provider: Option<NodeAddr>, ids: &[String]) -> Vec<NodeAddr> {
+    let mut providers: Vec<NodeAddr> = provider.into_iter().collect();
+    for id in ids {
+        if providers.len() >= MAX_HINTED_PROVIDERS {
+            warn!(
+                hinted = ids.len(),
+                "/receive: too many provider node ids; dialing the first {MAX_HINTED_PROVIDERS}"
+            );
+            break;
+        }
+        match id.parse::<PublicKey>() {
+            Ok(node_id) if !providers.iter().any(|p| p.node_id == node_id) => {
+                providers.push(NodeAddr::from(node_id));
+            }
+            Ok(_) => {}
+            Err(e) => warn!(%id, ?e, "/receive: ignoring invalid provider node id"),
+        }
+    }
+    providers
+}
+
+#[derive(Deserialize)]
+struct InspectBody {
+    ticket: String,
+}
+
+#[derive(Deserialize)]
+struct ThroughputBody {
+    peer_url: Option<String>,
+    /// Alternative to `peer_url`: a peer whose node id we've resolved.
+    node_id: Option<String>,
+    bytes: Option<u64>,
+}
+
+/// What a peer reports back from `POST /throughput/fetch`.
+#[derive(Debug, Serialize, Deserialize)]
+struct FetchTiming {
+    node_id: String,
+    bytes: u64,
+    elapsed_ms: u64,
+}
+
+#[derive(Deserialize)]
+struct CompareBody {
+    total_chunks: u64,
+    span: u64,
+    seed: u64,
+}
+
+#[derive(Deserialize)]
+struct SignBody {
+    path: Option<String>,
+    ttl_secs: Option<u64>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
+    use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+    let log_ring = LogRing::new(log_ring::LOG_RING_LINES);
+    tracing_subscriber::registry()
+        .with(tracing_subscriber::EnvFilter::from_default_env())
+        .with(tracing_subscriber::fmt::layer())
+        .with(log_ring.clone())
         .init();
 
     let node_name = env::var("NODE_NAME").unwrap_or_else(|_| "node".into());
@@ -111,6 +820,25 @@ async fn main() -> anyhow::Result<()> {
         .filter(|s| !s.trim().is_empty())
         .map(|s| s.trim().to_string())
         .collect();
+    let peer_addrs_file = env::var("PEER_ADDRS_FILE")
+        .ok()
+        .filter(|s| !s.trim().is_empty())
+        .map(PathBuf::from);
+    let peer_addr_ttl = env::var("PEER_ADDR_TTL_SECS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&n: &u64| n > 0)
+        .map(Duration::from_secs);
+    let peer_status_timeout = env::var("PEER_STATUS_TIMEOUT_MS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&n: &u64| n > 0)
+        .map_or(PEER_STATUS_TIMEOUT, Duration::from_millis);
+    let peer_status_max_bytes = env::var("PEER_STATUS_MAX_BYTES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or(PEER_STATUS_MAX_BYTES);
     let latency_min: u64 = env::var("LATENCY_MS_MIN")
         .ok()
         .and_then(|x| x.parse().ok())
@@ -123,6 +851,179 @@ async fn main() -> anyhow::Result<()> {
         .ok()
         .and_then(|x| x.parse().ok())
         .unwrap_or(30);
+    let demo_mode = env_flag("DEMO_MODE", true);
+    let diagnostics_token = env::var("DIAGNOSTICS_TOKEN").ok().filter(|t| !t.is_empty());
+    if !demo_mode {
+        info!("DEMO_MODE off: injected latency and stream sleeps disabled");
+    }
+    let http_blob_fallback = env_flag("HTTP_BLOB_FALLBACK", false);
+    let receive_deadline = env::var("RECEIVE_DEADLINE_MS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&ms: &u64| ms > 0)
+        .map(Duration::from_millis);
+    let split_stall = env::var("SPLIT_STALL_MS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&ms: &u64| ms > 0)
+        .map(Duration::from_millis);
+    let egress = env::var("PROVIDER_EGRESS_BPS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&bps: &u64| bps > 0)
+        .map(|bps| Arc::new(egress::EgressLimiter::new(bps)));
+    let rate_limit = env::var("RATE_LIMIT_RPS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&rps: &f64| rps > 0.0)
+        .map(|rps| Arc::new(RateLimiter::new(rps)));
+    let max_upload_bytes = env::var("MAX_UPLOAD_BYTES")
+        .ok()
+        .and_then(|x| x.trim().parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or(DEFAULT_MAX_UPLOAD_BYTES);
+    let upload_slots = env::var("MAX_CONCURRENT_UPLOADS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .map(|max| UploadSlots::new(max, env_flag("UPLOAD_QUEUE", false)));
+    let verify_concurrency: usize = env::var("VERIFY_CONCURRENCY")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
+    let http_notify_retries: u32 = env::var("HTTP_NOTIFY_RETRIES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(2);
+    let size_tolerance = env::var("SIZE_MISMATCH_TOLERANCE_PCT")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&pct: &f64| pct >= 0.0);
+    let notify_upgrade_window = env::var("NOTIFY_P2P_UPGRADE_MS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&ms: &u64| ms > 0)
+        .map(Duration::from_millis);
+    let (notify_acl, invalid) = NotifyAcl::from_lists(
+        env::var("NOTIFY_ALLOW").ok().as_deref(),
+        env::var("NOTIFY_DENY").ok().as_deref(),
+    );
+    if !invalid.is_empty() {
+        warn!(
+            ?invalid,
+            "ignoring NOTIFY_ALLOW/NOTIFY_DENY entries that aren't node ids"
+        );
+    }
+    let (bind_addrs, invalid) = BindAddrs::parse(&env::var("BIND_ADDRS").unwrap_or_default());
+    if !invalid.is_empty() {
+        warn!(
+            ?invalid,
+            "ignoring BIND_ADDRS entries that aren't IP addresses"
+        );
+    }
+    let (ingest, unknown) = IngestPipeline::from_env_values(
+        &env::var("INGEST_TRANSFORMS").unwrap_or_default(),
+        env::var("INGEST_MAX_DIMENSION")
+            .ok()
+            .and_then(|x| x.parse().ok())
+            .filter(|&px: &u32| px > 0),
+    );
+    if !unknown.is_empty() {
+        warn!(
+            ?unknown,
+            "ignoring INGEST_TRANSFORMS entries that are unknown or not enabled"
+        );
+    }
+    let export_retries: u32 = env::var("EXPORT_RETRIES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .unwrap_or(2);
+    let announce_interval = env::var("ANNOUNCE_INTERVAL_SECS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&s: &u64| s > 0)
+        .map(Duration::from_secs);
+    let slideshow_interval = env::var("SLIDESHOW_MS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&ms: &u64| ms > 0)
+        .map(Duration::from_millis);
+    let image_sidecar = env_flag("IMAGE_SIDECAR", true);
+    let fs_layout = FsLayout::from_env_value(&env::var("FS_LAYOUT").unwrap_or_default());
+    let verify_upload = env_flag("VERIFY_UPLOAD", false);
+    let keep_image_during_receive = env_flag("KEEP_IMAGE_DURING_RECEIVE", false);
+    let prefer_lan = env_flag("PREFER_LAN_PROVIDERS", true);
+    let active_search = env_flag("ACTIVE_SEARCH", false);
+    let single_provider_parallelism: usize = env::var("SINGLE_PROVIDER_PARALLELISM")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&n: &usize| n > 0)
+        .unwrap_or(1);
+    let block_active_content = env_flag("BLOCK_ACTIVE_CONTENT", false);
+    let placeholder = match env::var("PLACEHOLDER_IMAGE") {
+        Ok(path) => match Placeholder::load(std::path::Path::new(&path)).await {
+            Ok(p) => Some(p),
+            Err(e) => {
+                warn!(?e, %path, "failed to read PLACEHOLDER_IMAGE; /image will 404 until an image arrives");
+                None
+            }
+        },
+        Err(_) => None,
+    };
+    let sequential_max_candidates = env::var("SEQUENTIAL_MAX_CANDIDATES")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&n: &usize| n > 0);
+    let seq_order = SeqOrder::from_env_value(&env::var("SEQ_ORDER").unwrap_or_default());
+    let download_mode =
+        DownloadMode::from_env_value(&env::var("DOWNLOAD_MODE").unwrap_or_default());
+    let seq_shuffle_seed = env::var("SEQ_SHUFFLE_SEED")
+        .ok()
+        .and_then(|x| x.trim().parse().ok());
+    let fallback_delay = env::var("FALLBACK_DELAY_MS")
+        .ok()
+        .and_then(|x| x.trim().parse().ok())
+        .map(Duration::from_millis)
+        .unwrap_or(FALLBACK_DELAY);
+    let verify_content_type =
+        ContentTypeCheck::from_env_value(&env::var("VERIFY_CONTENT_TYPE").unwrap_or_default());
+    let sequential_completion = SequentialCompletion::from_env_values(
+        &env::var("SEQUENTIAL_COMPLETION").unwrap_or_default(),
+        env::var("SEQUENTIAL_CONFIRM_PROVIDERS").ok().as_deref(),
+    );
+    let default_content_type = env::var("DEFAULT_CONTENT_TYPE")
+        .ok()
+        .map(|s| s.trim().to_string())
+        .filter(|s| HeaderValue::from_str(s).is_ok() && !s.is_empty())
+        .unwrap_or_else(|| "application/octet-stream".to_string());
+    let store_max_bytes: Option<u64> = env::var("STORE_MAX_BYTES")
+        .ok()
+        .and_then(|x| x.parse().ok());
+    let blob_ttl = env::var("BLOB_TTL_MS")
+        .ok()
+        .and_then(|x| x.parse().ok())
+        .filter(|&ms: &u64| ms > 0)
+        .map(Duration::from_millis);
+    let pinned: HashSet<iroh_blobs::Hash> = env::var("PINNED_HASHES")
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|s| s.trim().parse().ok())
+        .collect();
+    let url_signer = env::var("URL_SIGNING_KEY")
+        .ok()
+        .filter(|k| !k.is_empty())
+        .map(UrlSigner::new);
+    if url_signer.is_some() {
+        info!(
+            "URL_SIGNING_KEY set: /image and /blob/{{hash}} need signed URLs, and our HTTP \
+             fetches from peers are signed with this key, so peers that sign must share it"
+        );
+    }
+    let distribution = Distribution::from_env_value(&env::var("DISTRIBUTION").unwrap_or_default());
+    // Default matches the docker-compose layout, where NODE_NAME is also the hostname.
+    let public_url =
+        env::var("PUBLIC_URL").unwrap_or_else(|_| format!("http://{}:{}", node_name, http_port));
 
     // Early stdout message to confirm the binary actually starts and to help diagnose container exits.
     println!(
@@ -133,726 +1034,10378 @@ async fn main() -> anyhow::Result<()> {
     );
 
     fs::create_dir_all(&data_dir).await.ok();
+    let provider_scores = ProviderScores::load(&data_dir.join("provider_scores.json")).await;
+    let clock = LogicalClock::load(&data_dir.join("clock.json")).await;
+    let auto_resume = env_flag("AUTO_RESUME", false);
+    // Without AUTO_RESUME nothing would ever finish the receives a previous
+    // process left behind, so /transfers and /cancel shouldn't see them
+    let inflight_path = data_dir.join("inflight.json");
+    let inflight = if auto_resume {
+        InFlightSet::load(&inflight_path).await
+    } else {
+        fs::remove_file(&inflight_path).await.ok();
+        InFlightSet::default()
+    };
 
-    // --- Build iroh endpoint ---
-    let mut builder = Endpoint::builder();
-    if enable_local {
-        builder = builder.discovery_local_network();
-    }
-    let endpoint = builder.bind().await?;
+    // --- Build iroh endpoint (skipped entirely in HTTP-only mode) ---
+    let endpoint = match distribution {
+        Distribution::P2p => {
+            let mut builder = Endpoint::builder();
+            if enable_local {
+                builder = builder.discovery_local_network();
+            }
+            if let Some(ip) = bind_addrs.as_ref().and_then(BindAddrs::v4) {
+                builder = builder.bind_addr_v4(std::net::SocketAddrV4::new(ip, 0));
+            }
+            if let Some(ip) = bind_addrs.as_ref().and_then(BindAddrs::v6) {
+                builder = builder.bind_addr_v6(std::net::SocketAddrV6::new(ip, 0, 0, 0));
+            }
+            let endpoint = builder.bind().await?;
+            if let Some(allowed) = &bind_addrs {
+                info!(bound = ?endpoint.bound_sockets(), ?allowed, "BIND_ADDRS: restricted iroh addresses");
+            }
+            Some(endpoint)
+        }
+        Distribution::Http => {
+            info!("DISTRIBUTION=http: iroh endpoint disabled");
+            None
+        }
+    };
 
     // --- iroh-blobs with FS store ---
-    let store = Arc::new(FsStore::load(data_dir.join("blobs")).await?);
-    let blobs = BlobsProtocol::new(&*store, endpoint.clone(), None);
+    // GC only runs for a bounded store; otherwise nothing is ever deleted.
+    let store_root = data_dir.join("blobs");
+    let mut store_opts = FsStoreOptions::new(&store_root);
+    if store_max_bytes.is_some() || blob_ttl.is_some() {
+        store_opts.gc = Some(GcConfig {
+            interval: STORE_GC_INTERVAL,
+            add_protected: None,
+        });
+    }
+    let store = Arc::new(FsStore::load_with_opts(store_root.join("blobs.db"), store_opts).await?);
+    store.tags().delete_prefix(THROUGHPUT_TAG_PREFIX).await?;
+    let blobs = endpoint
+        .as_ref()
+        .map(|ep| BlobsProtocol::new(&store, ep.clone(), None));
 
     // We expose our node id string in status (peers convert to NodeAddr via discovery)
-    let node_id = endpoint.node_id();
+    let node_id = endpoint.as_ref().map(|ep| ep.node_id());
 
+    let events = EventBus::default();
     let shared = Arc::new(NodeShared {
         endpoint: endpoint.clone(),
         blobs: blobs.clone(),
         store: store.clone(),
-        state: Arc::new(Mutex::new(NodeState {
+        distribution,
+        public_url,
+        state: Arc::new(StateCell::new(NodeState {
             node_name: node_name.clone(),
-            node_addr: Some(node_id.to_string()),
+            node_addr: node_id.map(|id| id.to_string()),
+            version: clock.version,
+            version_origin: clock.origin,
             ..Default::default()
         })),
         data_dir: data_dir.clone(),
         peers_http,
         peers_addrs: Arc::new(Mutex::new(HashMap::new())),
+        peer_images: Arc::new(Mutex::new(HashMap::new())),
+        peer_image_sizes: Arc::new(Mutex::new(HashMap::new())),
+        size_tolerance,
+        peer_addrs_file,
+        peer_addr_seen: Arc::new(Mutex::new(HashMap::new())),
+        peer_addr_ttl,
+        http_blob_fallback,
+        http_notify_retries,
+        notify_upgrade_window,
+        export_retries,
+        verify_upload,
+        ingest,
+        keep_image_during_receive,
+        block_active_content,
+        sequential_max_candidates,
+        prefer_lan,
+        single_provider_parallelism,
+        verify_content_type,
+        egress,
+        idempotency: Arc::default(),
+        adopt_lock: Arc::default(),
+        clock_file: Arc::default(),
+        seq_order,
+        seq_shuffle_seed,
+        fallback_delay,
+        active_search,
+        download_mode,
+        fs_layout,
+        max_upload_bytes,
+        peer_status_timeout,
+        peer_status_max_bytes,
+        notify_log: NotifyLog::new(notify_log::NOTIFY_LOG_ENTRIES, events.clone()),
+        sequential_completion,
+        paused: Arc::new(tokio::sync::watch::channel(false).0),
+        cancel: Arc::new(Mutex::new(CancellationToken::new())),
+        image_changed: Arc::new(tokio::sync::watch::channel(None).0),
+        receive_deadline,
+        split_stall,
+        rate_limit,
+        verify_slots: Arc::new(tokio::sync::Semaphore::new(verify_concurrency)),
+        upload_slots,
+        url_signer,
+        store_max_bytes,
+        blob_ttl,
+        pinned,
+        store_usage: Arc::new(Mutex::new(StoreUsage::default())),
+        provider_scores: Arc::new(Mutex::new(provider_scores)),
+        default_content_type,
+        image_sidecar,
+        placeholder,
+        events,
+        notify_acl,
+        bind_addrs,
+        inflight: Arc::new(Mutex::new(inflight)),
+        cluster_status: Arc::new(Mutex::new(None)),
+        http_client: reqwest::Client::new(),
+        throughput_slots: Arc::new(tokio::sync::Semaphore::new(THROUGHPUT_MAX_RUNS)),
+        #[cfg(feature = "mmap_serve")]
+        mapped_image: Arc::new(mmap_image::MappedImage::new(data_dir.join("current.img"))),
         latency_min,
         latency_max,
         stream_sleep_ms,
+        demo_mode,
+        diagnostics_token,
+        log_ring,
     });
 
     // Router: serve blobs + our custom notify protocol
     #[cfg(all(not(test), feature = "p2p_notify"))]
-    let _iroh_router = IrohRouter::builder(endpoint.clone())
-        .accept(iroh_blobs::ALPN, blobs.clone())
-        .accept(
-            notify::NOTIFY_ALPN,
-            Arc::new(notify::NotifyHandler {
-                shared: shared.clone(),
-            }),
-        )
-        .spawn();
+    let _iroh_router = match (endpoint.clone(), blobs.clone()) {
+        (Some(endpoint), Some(blobs)) => {
+            // Taken apart so a new entry in `ROUTER_ALPNS` (and `/protocols`)
+            // doesn't compile until it has a handler here
+            let [blobs_alpn, notify_alpn] = ROUTER_ALPNS;
+            Some(
+                IrohRouter::builder(endpoint)
+                    .accept(
+                        blobs_alpn,
+                        egress::ThrottledBlobs {
+                            blobs,
+                            limiter: shared.egress.clone(),
+                        },
+                    )
+                    .accept(
+                        notify_alpn,
+                        Arc::new(notify::NotifyHandler {
+                            shared: shared.clone(),
+                        }),
+                    )
+                    .spawn(),
+            )
+        }
+        _ => None,
+    };
+
+    if let Err(e) = shared.load_store_usage().await {
+        warn!(?e, "failed to scan store for usage accounting");
+    }
+    shared.restore_image_meta().await;
+    if shared.peer_addrs_file.is_some() {
+        if let Err(e) = shared.load_peer_addrs_file().await {
+            warn!(?e, "failed to load PEER_ADDRS_FILE");
+        }
+    }
 
     // Start peer discovery (learn NodeAddrs via peers' /status)
     tokio::spawn(peer_addr_refresher(shared.clone()));
+    if auto_resume {
+        let shared = shared.clone();
+        tokio::spawn(async move { shared.resume_inflight().await });
+    }
+    if let Some(every) = announce_interval {
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(every).await;
+                shared.announce().await;
+            }
+        });
+    }
+
+    if let Some(ttl) = shared.blob_ttl {
+        // Sweep at least as often as GC runs, so expiry is roughly on time
+        let every = ttl.min(STORE_GC_INTERVAL);
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(every).await;
+                shared.sweep_expired_blobs().await;
+            }
+        });
+    }
+    if let Some(every) = slideshow_interval {
+        let shared = shared.clone();
+        tokio::spawn(async move {
+            loop {
+                sleep(every).await;
+                // POST /pause holds the slideshow along with transfers
+                if *shared.paused.borrow() {
+                    continue;
+                }
+                if let Err(e) = shared.advance_slide().await {
+                    warn!(?e, "failed to advance slideshow");
+                }
+            }
+        });
+    }
+    if let Some(limiter) = shared.rate_limit.clone() {
+        tokio::spawn(async move {
+            loop {
+                sleep(Duration::from_secs(60)).await;
+                limiter.prune(std::time::Instant::now());
+            }
+        });
+    }
 
     // --- HTTP server ---
-    let app = Router::new()
-        .route("/status", get(status))
-        .route("/image", get(get_image))
-        .route("/image_stream", get(image_stream))
-        .route("/upload", post(upload))
-        .route("/receive", post(receive_http))
-        // Allow uploads up to 20 MiB (adjust as needed)
-        .layer(DefaultBodyLimit::max(20 * 1024 * 1024))
-        .layer(CorsLayer::permissive())
-        // Add PNA header for HTTPS->localhost CORS preflights
-        .layer(from_fn(add_pna_header))
-        .with_state(shared.clone());
+    let app = app(shared.clone());
 
     let addr = SocketAddr::from(([0, 0, 0, 0], http_port));
     info!(%addr, %node_name, "HTTP listening");
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     // Clean shutdown
     // iroh_router.shutdown().await.ok();
     Ok(())
 }
 
-async fn status(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
-    Json(shared.state.lock().await.clone())
+/// The node's HTTP API.
+fn app(shared: Arc<NodeShared>) -> Router {
+    let signed = from_fn_with_state(shared.clone(), require_signed_url);
+    Router::new()
+        .route("/", get(index))
+        .route("/status", get(status))
+        .route("/status/stream", get(status_stream))
+        .route("/whoami", get(whoami))
+        .route("/peers", get(peers))
+        // Cluster views grow with the peer count; compress them (br, then
+        // gzip, per the client's Accept-Encoding q-values)
+        .route(
+            "/cluster/status",
+            get(cluster_status).layer(CompressionLayer::new()),
+        )
+        .route("/similar", get(similar))
+        .route("/peers/reload", post(reload_peers))
+        .route("/announce", post(announce))
+        .route("/events", get(events))
+        .route(
+            "/image",
+            get(get_image)
+                .head(head_image)
+                .route_layer(signed.clone())
+                .delete(delete_image),
+        )
+        .route(
+            "/image/:hash",
+            get(get_held_image).route_layer(signed.clone()),
+        )
+        .route("/images", get(list_images))
+        .route(
+            "/image_stream",
+            get(image_stream)
+                .head(head_image_stream)
+                .route_layer(signed.clone()),
+        )
+        .route("/image_feed", get(image_feed).route_layer(signed.clone()))
+        .route("/sign", post(sign_url))
+        .route("/ticket/inspect", post(inspect_ticket))
+        .route("/compare_strategies", post(compare_strategies))
+        .route("/throughput", post(throughput))
+        .route("/throughput/fetch", post(throughput_fetch))
+        .route("/blob/:hash", get(get_blob).route_layer(signed))
+        .route("/store", get(store_info))
+        .route(
+            "/store/add",
+            post(store_add).layer(DefaultBodyLimit::max(shared.max_upload_bytes)),
+        )
+        .route("/blobs", get(list_blobs))
+        .route(
+            "/upload",
+            post(upload).layer(DefaultBodyLimit::max(shared.max_upload_bytes)),
+        )
+        .route(
+            "/upload_and_sync",
+            post(upload_and_sync).layer(DefaultBodyLimit::max(shared.max_upload_bytes)),
+        )
+        .route("/receive", post(receive_http))
+        .route("/transfers", get(transfers))
+        .route("/notifies", get(notifies))
+        .route("/protocols", get(protocols))
+        .route("/diagnostics", get(diagnostics))
+        .route("/pause", post(pause))
+        .route("/resume", post(resume))
+        .route("/cancel", post(cancel))
+        .fallback(not_found)
+        // Uploads get MAX_UPLOAD_BYTES above; JSON endpoints stay small
+        .layer(DefaultBodyLimit::max(JSON_BODY_LIMIT))
+        .layer(from_fn_with_state(shared.clone(), rate_limit))
+        .layer(CorsLayer::permissive())
+        // Add PNA header for HTTPS->localhost CORS preflights
+        .layer(from_fn(add_pna_header))
+        .with_state(shared)
 }
 
-async fn get_image(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
-    let mut resp = match fs::read(shared.data_dir.join("current.img")).await {
-        Ok(bytes) => Response::builder()
-            .status(StatusCode::OK)
-            .body(bytes.into())
-            .unwrap(),
-        Err(_) => Response::builder()
-            .status(StatusCode::NOT_FOUND)
-            .body(axum::body::Body::empty())
-            .unwrap(),
-    };
-    resp.headers_mut()
-        .insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
-    if let Some(ct) = &shared.state.lock().await.content_type {
-        resp.headers_mut().insert(
-            "Content-Type",
-            HeaderValue::from_str(ct)
-                .unwrap_or(HeaderValue::from_static("application/octet-stream")),
-        );
-    }
-    resp
+/// Any path without a route: a JSON 404 naming it, so a client with a typo
+/// sees what it asked for.
+async fn not_found(uri: axum::http::Uri) -> impl IntoResponse {
+    (
+        StatusCode::NOT_FOUND,
+        Json(serde_json::json!({ "error": "not_found", "path": uri.path() })),
+    )
 }
 
-/// Stream the image in chunks with tiny sleeps to encourage progressive rendering in browsers
-async fn image_stream(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
-    let path = shared.data_dir.join("current.img");
-    match tokio::fs::File::open(path).await {
-        Ok(file) => {
-            let delay = shared.stream_sleep_ms;
-            let stream = ReaderStream::new(file).then(move |res| {
-                let d = delay;
-                async move {
-                    if d > 0 {
-                        sleep(Duration::from_millis(d)).await;
-                    }
-                    res
-                }
-            });
-            let mut resp = Response::builder()
-                .status(StatusCode::OK)
-                .body(axum::body::Body::from_stream(stream))
-                .unwrap();
-            resp.headers_mut()
-                .insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
-            if let Some(ct) = &shared.state.lock().await.content_type {
-                resp.headers_mut().insert(
-                    "Content-Type",
-                    HeaderValue::from_str(ct)
-                        .unwrap_or(HeaderValue::from_static("application/octet-stream")),
-                );
-            }
-            resp
+/// Standalone demo UI: current image, status, and upload/receive forms.
+async fn index() -> impl IntoResponse {
+    axum::response::Html(include_str!("index.html"))
+}
+
+#[derive(Deserialize)]
+struct StatusQuery {
+    /// Only answer if this is the current (or last) transfer.
+    transfer: Option<String>,
+}
+
+/// `GET /status`. With `?transfer=<id>` (the id a notify ack or `/receive`
+/// returned) it answers 404 unless that transfer is the current or most
+/// recent one, so a provider can follow one specific download.
+async fn status(
+    State(shared): State<Arc<NodeShared>>,
+    Query(q): Query<StatusQuery>,
+    format: ApiFormat,
+) -> impl IntoResponse {
+    let state = shared.status_snapshot().await;
+    if let Some(id) = q.transfer {
+        if state.transfer_id.as_deref() != Some(id.as_str()) {
+            return (StatusCode::NOT_FOUND, "unknown or superseded transfer").into_response();
         }
-        Err(_) => StatusCode::NOT_FOUND.into_response(),
     }
+    format.render(&state)
 }
 
-/// Accepts a multipart file upload, writes it into the local blobs store and
-/// saves a copy to `current.img` for HTTP serving. On a provider node the
-/// upload is a one-shot write (not a P2P download), so we set `bytes_total`
-/// and `bytes_received` to the full size and mark `progress = 100`.
-///
-/// Also fans out a hash-only notify to peers so they can discover and download.
-async fn upload(State(shared): State<Arc<NodeShared>>, mut mp: Multipart) -> impl IntoResponse {
-    maybe_latency(&shared).await;
+/// The protocols `main` registers on the iroh router, in the order it does.
+const ROUTER_ALPNS: [&[u8]; 2] = [iroh_blobs::ALPN, notify::NOTIFY_ALPN];
 
-    let mut filename = "upload".to_string();
-    let mut content_type = "application/octet-stream".to_string();
-    let mut bytes = Vec::new();
+/// ALPNs the iroh router accepts: `ROUTER_ALPNS`, or none in HTTP-only mode
+/// and without the `p2p_notify` feature, which gates the router as a whole.
+fn accepted_alpns(shared: &NodeShared) -> Vec<&'static [u8]> {
+    if !cfg!(feature = "p2p_notify") || shared.endpoint.is_none() || shared.blobs.is_none() {
+        return Vec::new();
+    }
+    ROUTER_ALPNS.to_vec()
+}
 
-    info!("/upload: reading multipart fields");
-    while let Ok(Some(mut field)) = mp.next_field().await {
-        let field_name = field.name().map(|s| s.to_string());
-        let fname_dbg = field.file_name().map(|s| s.to_string());
-        info!(?field_name, ?fname_dbg, "multipart field");
-        // Prefer the 'file' part; if no name is provided, assume it's the file
-        if field_name.as_deref() == Some("file") || field_name.is_none() {
-            if let Some(name) = field.file_name().map(|s| s.to_string()) {
-                filename = name;
+/// `GET /protocols`: the ALPNs this node accepts and the notify protocol
+/// version, so a peer can check compatibility before dialing.
+async fn protocols(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let alpns = accepted_alpns(&shared);
+    let alpn_str = |alpn: &[u8]| String::from_utf8_lossy(alpn).into_owned();
+    Json(serde_json::json!({
+        "alpns": alpns.iter().map(|a| alpn_str(a)).collect::<Vec<_>>(),
+        "blobs": {
+            "alpn": alpn_str(iroh_blobs::ALPN),
+            "accepted": alpns.contains(&iroh_blobs::ALPN),
+        },
+        "notify": {
+            "alpn": alpn_str(notify::NOTIFY_ALPN),
+            "version": notify::NOTIFY_PROTOCOL_VERSION,
+            "accepted": alpns.contains(&notify::NOTIFY_ALPN),
+        },
+    }))
+}
+
+/// `GET /status/stream`: server-sent events, each the `/status` JSON; one on
+/// connect, then one whenever `NodeState` is written or transfers are paused
+/// or resumed. Bursts of writes coalesce into one event. Nothing is spawned
+/// per client, so a disconnect just drops the stream and its subscriptions.
+async fn status_stream(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let mut changes = shared.state.subscribe();
+    changes.mark_changed();
+    let paused = shared.paused.subscribe();
+    let events = futures_util::stream::unfold(
+        (shared, changes, paused),
+        |(shared, mut changes, mut paused)| async move {
+            tokio::select! {
+                changed = changes.changed() => changed.ok()?,
+                changed = paused.changed() => changed.ok()?,
             }
-            if let Some(ct) = field.content_type().map(|s| s.to_string()) {
-                content_type = ct;
+            changes.borrow_and_update();
+            paused.borrow_and_update();
+            let state = shared.status_snapshot().await;
+            let event = sse::Event::default().json_data(&state).ok()?;
+            Some((
+                Ok::<_, std::convert::Infallible>(event),
+                (shared, changes, paused),
+            ))
+        },
+    );
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+/// `GET /cluster/status`: our own `/status` plus every peer's, fetched
+/// concurrently. An unreachable peer gets an `error` entry instead of failing
+/// the response; results are reused for [`CLUSTER_STATUS_CACHE`].
+async fn cluster_status(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let mut cached = shared.cluster_status.lock().await;
+    if let Some((at, body)) = cached.as_ref() {
+        if at.elapsed() < CLUSTER_STATUS_CACHE {
+            return Json(body.clone());
+        }
+    }
+    let fetches = shared.peers_http.iter().map(|url| {
+        let client = shared.http_client.clone();
+        async move {
+            let fetched = async {
+                client
+                    .get(format!("{}/status", url))
+                    .timeout(CLUSTER_STATUS_TIMEOUT)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json::<serde_json::Value>()
+                    .await
             }
-            // Read the file in chunks to avoid surprises if a single read fails
-            while let Ok(Some(chunk)) = field.chunk().await {
-                bytes.extend_from_slice(&chunk);
-                // Safety guard: hard cap at ~50 MiB in this handler even if body limit is higher
-                if bytes.len() > 50 * 1024 * 1024 {
-                    return (StatusCode::PAYLOAD_TOO_LARGE, "file too large").into_response();
-                }
+            .await;
+            match fetched {
+                Ok(status) => serde_json::json!({ "url": url, "status": status }),
+                Err(e) => serde_json::json!({ "url": url, "error": e.to_string() }),
             }
-            break;
         }
-    }
+    });
+    let mut nodes = vec![serde_json::json!({
+        "url": shared.public_url,
+        "self": true,
+        "status": shared.status_snapshot().await,
+    })];
+    nodes.extend(futures_util::future::join_all(fetches).await);
+    let body = serde_json::Value::Array(nodes);
+    *cached = Some((std::time::Instant::now(), body.clone()));
+    Json(body)
+}
 
-    info!(
-        ?filename,
-        ?content_type,
-        size = bytes.len(),
-        "/upload: parsed file"
-    );
+#[derive(Deserialize)]
+struct SimilarQuery {
+    /// pHash to compare against, as `/status` reports it.
+    phash: String,
+    /// Most differing bits still counted as similar.
+    max_distance: Option<u32>,
+}
 
-    if bytes.is_empty() {
-        return (StatusCode::BAD_REQUEST, "no file").into_response();
+/// `GET /similar?phash=<hex>`: this node's and each peer's current-image
+/// pHash (peers' from their `/status`), with its distance to `phash` and
+/// whether that is within `max_distance`. Nodes with no pHash never match.
+async fn similar(
+    State(shared): State<Arc<NodeShared>>,
+    Query(q): Query<SimilarQuery>,
+) -> impl IntoResponse {
+    if !phash::is_valid(&q.phash) {
+        return (StatusCode::BAD_REQUEST, "phash must be 16 hex digits").into_response();
+    }
+    let max_distance = q.max_distance.unwrap_or(SIMILAR_MAX_DISTANCE);
+    let compare = |theirs: Option<&str>| {
+        let distance = theirs.and_then(|p| phash::distance(&q.phash, p));
+        serde_json::json!({
+            "phash": theirs,
+            "distance": distance,
+            "similar": distance.is_some_and(|d| d <= max_distance),
+        })
+    };
+    let client = &shared.http_client;
+    let fetches = shared.peers_http.iter().map(|url| async move {
+        let fetched = async {
+            client
+                .get(format!("{}/status", url))
+                .timeout(CLUSTER_STATUS_TIMEOUT)
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<NodeState>()
+                .await
+        }
+        .await;
+        (url, fetched)
+    });
+    let ours = shared.state.lock().await.phash.clone();
+    let mut nodes = vec![{
+        let mut node = compare(ours.as_deref());
+        node["url"] = shared.public_url.clone().into();
+        node["self"] = true.into();
+        node
+    }];
+    for (url, fetched) in futures_util::future::join_all(fetches).await {
+        nodes.push(match fetched {
+            Ok(status) => {
+                let mut node = compare(status.phash.as_deref());
+                node["url"] = url.clone().into();
+                node
+            }
+            Err(e) => serde_json::json!({ "url": url, "error": e.to_string() }),
+        });
     }
+    Json(serde_json::json!({
+        "phash": q.phash,
+        "max_distance": max_distance,
+        "nodes": nodes,
+    }))
+    .into_response()
+}
 
-    // Add to blobs store (track total bytes)
-    let total = bytes.len() as u64;
-    let tag = shared.blobs.add_slice(&bytes).await.unwrap();
-    let ticket = shared.blobs.ticket(tag).await.unwrap();
+/// Everything a peer or UI needs to connect to this node and fetch from it.
+#[derive(Debug, Serialize)]
+struct WhoamiResp {
+    node_id: Option<String>,
+    node_name: String,
+    direct_addresses: Vec<String>,
+    relay_url: Option<String>,
+    /// Only present once an image is fully received or uploaded.
+    ticket_for_current_image: Option<String>,
+}
 
-    // Save a local copy for HTTP serving
-    let path = shared.data_dir.join("current.img");
-    if let Err(e) = fs::write(&path, &bytes).await {
-        error!(?e, "write failed");
+/// `GET /events`: newline-delimited JSON audit events, live from subscription.
+///
+/// A subscriber that can't keep up loses the oldest buffered events and gets
+/// a `{"event":"gap","missed":n}` line in their place.
+async fn events(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let stream = shared.events.subscribe().into_ndjson();
+    (
+        [("Content-Type", "application/x-ndjson")],
+        axum::body::Body::from_stream(stream),
+    )
+}
+
+async fn whoami(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    Json(whoami_report(&shared).await)
+}
+
+async fn whoami_report(shared: &NodeShared) -> WhoamiResp {
+    let (node_name, current) = {
+        let s = shared.state.lock().await;
+        let current = match (s.has_image, &s.current_hash) {
+            (true, Some(h)) => h.parse::<iroh_blobs::Hash>().ok(),
+            _ => None,
+        };
+        (s.node_name.clone(), current)
+    };
+    // No endpoint in HTTP-only mode: nothing to dial, no ticket to hand out
+    let addr = shared.endpoint.as_ref().map(|ep| {
+        shared.advertised(
+            ep.node_addr()
+                .get()
+                .unwrap_or_else(|| NodeAddr::new(ep.node_id())),
+        )
+    });
+    let ticket = match (&addr, current) {
+        (Some(addr), Some(hash)) => Some(
+            iroh_blobs::ticket::BlobTicket::new(addr.clone(), hash, iroh_blobs::BlobFormat::Raw)
+                .to_string(),
+        ),
+        _ => None,
+    };
+    WhoamiResp {
+        node_id: addr.as_ref().map(|a| a.node_id.to_string()),
+        node_name,
+        direct_addresses: addr
+            .as_ref()
+            .map(|a| a.direct_addresses.iter().map(|d| d.to_string()).collect())
+            .unwrap_or_default(),
+        relay_url: addr
+            .as_ref()
+            .and_then(|a| a.relay_url.as_ref())
+            .map(|u| u.to_string()),
+        ticket_for_current_image: ticket,
     }
+}
 
-    let provider = shared.endpoint.node_id().to_string();
+/// Configured peers with what we know of their address (node id, direct
+/// addresses, relay, when their `/status` last resolved it), provider
+/// reputation and locality (`lan` when local discovery found them), plus the
+/// effective notify ACL and whether each peer's notifies would be acted on.
+async fn peers(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    Json(peers_report(&shared).await)
+}
 
-    {
-        let mut s = shared.state.lock().await;
-        s.has_image = true;
-        s.current_filename = Some(filename.clone());
-        s.content_type = Some(content_type.clone());
-        s.bytes_total = Some(total);
-        s.bytes_received = total; // uploader is complete
-        s.progress = 100.0;
-        s.current_hash = Some(ticket.hash().to_string());
-        s.stripe_providers = HashMap::from([(provider.clone(), vec!["all".to_string()])]);
+async fn peers_report(shared: &NodeShared) -> serde_json::Value {
+    let addrs = shared.peers_addrs.lock().await.clone();
+    let seen = shared.peer_addr_seen.lock().await.clone();
+    let lan = shared.endpoint.as_ref().map(lan_nodes).unwrap_or_default();
+    let scores = shared.provider_scores.lock().await.clone();
+    let peers: Vec<_> = shared
+        .peers_http
+        .iter()
+        .map(|url| {
+            let node_id = addrs.get(url).map(|a| a.node_id.to_string());
+            let score = node_id.as_deref().map(|id| {
+                let s = scores.get(id);
+                serde_json::json!({
+                    "successes": s.successes,
+                    "failures": s.failures,
+                    "throughput_bps": s.throughput_bps,
+                    "reliability": s.reliability(),
+                    "score": s.score(),
+                })
+            });
+            let notify_allowed = addrs
+                .get(url)
+                .map(|a| shared.notify_acl.permits(&a.node_id));
+            let locality = addrs.get(url).map(|a| {
+                if lan.contains(&a.node_id) {
+                    "lan"
+                } else {
+                    "remote"
+                }
+            });
+            let addr = addrs.get(url);
+            let direct_addresses: Vec<String> = addr
+                .map(|a| a.direct_addresses.iter().map(|s| s.to_string()).collect())
+                .unwrap_or_default();
+            serde_json::json!({
+                "url": url,
+                "node_id": node_id,
+                "resolved": addr.is_some(),
+                "direct_addresses": direct_addresses,
+                "relay_url": addr.and_then(|a| a.relay_url.as_ref()).map(|u| u.to_string()),
+                "refreshed_ms_ago": seen.get(url).map(|at| at.elapsed().as_millis() as u64),
+                "score": score,
+                "locality": locality,
+                "notify_allowed": notify_allowed,
+            })
+        })
+        .collect();
+    serde_json::json!({ "peers": peers, "notify_acl": shared.notify_acl })
+}
+
+/// Re-read `PEER_ADDRS_FILE`; 404 when it isn't configured.
+async fn reload_peers(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    if shared.peer_addrs_file.is_none() {
+        return (StatusCode::NOT_FOUND, "PEER_ADDRS_FILE is not configured").into_response();
+    }
+    match shared.load_peer_addrs_file().await {
+        Ok((loaded, skipped)) => {
+            Json(serde_json::json!({ "loaded": loaded, "skipped": skipped })).into_response()
+        }
+        Err(e) => (StatusCode::BAD_REQUEST, e.to_string()).into_response(),
     }
+}
 
-    // P2P notify peers over iroh (fallback to HTTP /receive if unknown) using hash-only model
-    let msg = NotifyMsg {
-        hash: ticket.hash().to_string(),
-        filename: filename.clone(),
-        content_type: content_type.clone(),
-        provider_node_id: Some(provider.clone()),
+/// Outcome of a manual or periodic announce.
+#[derive(Debug, Serialize)]
+struct AnnounceResp {
+    /// Whether our addresses were handed to a discovery service.
+    published: bool,
+    /// Peers whose node address is now known.
+    peers_resolved: usize,
+}
+
+/// Re-publish to discovery and re-resolve peers now instead of waiting a cycle.
+async fn announce(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    Json(shared.announce().await)
+}
+
+/// Store usage vs the configured `STORE_MAX_BYTES` cap.
+async fn store_info(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    Json(store_report(&shared).await)
+}
+
+async fn store_report(shared: &NodeShared) -> serde_json::Value {
+    let (usage_bytes, blobs) = {
+        let usage = shared.store_usage.lock().await;
+        (usage.total_bytes(), usage.len())
     };
-    tokio::spawn(notify_all_peers(shared.clone(), msg.clone()));
+    let pinned: Vec<String> = shared.pinned.iter().map(|h| h.to_string()).collect();
+    let uploads = shared
+        .upload_slots
+        .as_ref()
+        .map(|u| serde_json::json!({ "max": u.max, "available": u.slots.available_permits() }));
+    serde_json::json!({
+        "usage_bytes": usage_bytes,
+        "max_bytes": shared.store_max_bytes,
+        "blobs": blobs,
+        "pinned": pinned,
+        "uploads": uploads,
+    })
+}
 
-    Json(serde_json::json!({
-        "ticket": ticket.to_string(),
-        "hash": ticket.hash().to_string(),
-        "filename": filename,
-        "content_type": content_type,
-        "provider_node_id": provider,
-    }))
-    .into_response()
+/// `GET /blobs`: every blob the node keeps, with its size and, under
+/// `BLOB_TTL_MS`, the milliseconds left before it expires (0 once due).
+async fn list_blobs(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let now = std::time::Instant::now();
+    let usage = shared.store_usage.lock().await;
+    let mut blobs: Vec<_> = usage
+        .blobs()
+        .map(|(hash, size, expires_at)| {
+            serde_json::json!({
+                "hash": hash.to_string(),
+                "size": size,
+                "pinned": shared.pinned.contains(hash),
+                "ttl_remaining_ms": expires_at
+                    .map(|at| at.saturating_duration_since(now).as_millis() as u64),
+            })
+        })
+        .collect();
+    blobs.sort_by(|a, b| a["hash"].as_str().cmp(&b["hash"].as_str()));
+    Json(serde_json::json!({ "blobs": blobs }))
 }
 
-/// HTTP receive endpoint accepts either a full ticket or just a hash
-async fn receive_http(
+/// `GET /image`. An image stored gzip-encoded is passed through as such to
+/// clients that accept gzip and decompressed for those that don't.
+async fn get_image(
     State(shared): State<Arc<NodeShared>>,
-    Json(msg): Json<ReceiveBody>,
+    req_headers: HeaderMap,
 ) -> impl IntoResponse {
-    maybe_latency(&shared).await;
-    if let Some(tk) = msg.ticket {
-        match tk.parse::<iroh_blobs::ticket::BlobTicket>() {
-            Ok(ticket) => {
-                let hash = ticket.hash();
-                let fallback = ticket.node_addr().clone();
-                if let Err(e) = shared
-                    .receive_by_discovery(hash, msg.filename, msg.content_type, Some(fallback))
-                    .await
-                {
-                    error!(?e, "receive (ticket) error");
-                    return StatusCode::BAD_GATEWAY.into_response();
-                }
-                StatusCode::OK.into_response()
-            }
-            Err(_) => StatusCode::BAD_REQUEST.into_response(),
-        }
-    } else if let Some(hs) = msg.hash {
-        match hs.parse() {
-            Ok(hash) => {
-                let fallback = msg
-                    .provider_node_id
-                    .and_then(|s| s.parse::<PublicKey>().ok())
-                    .map(NodeAddr::from);
-                if let Err(e) = shared
-                    .receive_by_discovery(hash, msg.filename, msg.content_type, fallback)
-                    .await
-                {
-                    error!(?e, "receive (hash) error");
-                    return StatusCode::BAD_GATEWAY.into_response();
-                }
-                StatusCode::OK.into_response()
+    if let Some(resp) = shared.placeholder_response(false).await {
+        return resp;
+    }
+    shared.touch_current().await;
+    let accepts_gzip = accepts_gzip(&req_headers);
+    if let Some(range) = req_headers.get("Range").and_then(|v| v.to_str().ok()) {
+        let if_range = req_headers.get("If-Range").and_then(|v| v.to_str().ok());
+        if let Some(resp) = ranged_image(&shared, range, if_range, accepts_gzip).await {
+            return resp;
+        }
+    }
+    match shared.current_image_bytes().await {
+        Ok(bytes) => {
+            let mut headers = image_headers(&shared, Some(bytes.len() as u64)).await;
+            if must_gunzip(&headers, accepts_gzip) {
+                return match shared.gunzip_image(bytes).await {
+                    Ok(plain) => {
+                        decoded_headers(&mut headers, Some(plain.len() as u64));
+                        (headers, plain).into_response()
+                    }
+                    Err(resp) => resp,
+                };
             }
-            Err(_) => StatusCode::BAD_REQUEST.into_response(),
+            (headers, bytes).into_response()
+        }
+        Err(_) => {
+            let mut resp = StatusCode::NOT_FOUND.into_response();
+            resp.headers_mut()
+                .insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+            resp
         }
-    } else {
-        StatusCode::BAD_REQUEST.into_response()
     }
 }
 
-impl NodeShared {
-    /// Discover a provider for the given hash among known peers and download.
-    pub async fn receive_by_discovery(
-        &self,
-        hash: iroh_blobs::Hash,
-        filename: String,
-        content_type: String,
-        fallback: Option<NodeAddr>,
-    ) -> anyhow::Result<()> {
-        // Initialize state for this transfer
-        {
-            let mut s = self.state.lock().await;
-            s.current_filename = Some(filename.clone());
-            s.content_type = Some(content_type.clone());
-            s.current_hash = Some(hash.to_string());
-            s.has_image = false;
-            s.bytes_received = 0;
-            s.bytes_total = None;
-            s.progress = 0.0;
-            s.stripe_providers.clear();
+/// `GET /image/{hash}`: any held image, not just the current one.
+async fn get_held_image(
+    State(shared): State<Arc<NodeShared>>,
+    Path(hash): Path<String>,
+    req_headers: HeaderMap,
+) -> Response {
+    let meta = {
+        let s = shared.state.lock().await;
+        s.images.iter().find(|m| m.hash == hash).cloned()
+    };
+    let Some(meta) = meta else {
+        return (StatusCode::NOT_FOUND, "no such image").into_response();
+    };
+    let file = match fs::File::open(image_set::path(&shared.data_dir, &meta.hash)).await {
+        Ok(file) => file,
+        Err(e) => {
+            error!(?e, %hash, "held image is listed but unreadable");
+            return (StatusCode::NOT_FOUND, "no such image").into_response();
         }
+    };
+    let len = file.metadata().await.map(|m| m.len()).ok();
+    let image = ServedImage {
+        content_type: Some(meta.content_type),
+        content_encoding: meta.content_encoding,
+        filename: Some(meta.filename),
+        hash: Some(meta.hash),
+    };
+    let mut headers = served_image_headers(&image, len);
+    headers.insert("Accept-Ranges", HeaderValue::from_static("none"));
+    stream_image_file(&shared, file, headers, accepts_gzip(&req_headers), 0).await
+}
 
-        let downloader = self.store.downloader(&self.endpoint);
+/// `GET /images`: the held images, oldest first.
+async fn list_images(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let images = shared.state.lock().await.images.clone();
+    Json(serde_json::json!({ "images": images }))
+}
 
-        // Build candidate node list from known peers; include fallback if provided
-        let mut candidate_addrs: Vec<NodeAddr> = {
-            let map = self.peers_addrs.lock().await;
-            map.values().cloned().collect()
-        };
-        if let Some(na) = fallback.as_ref() {
-            if !candidate_addrs
-                .iter()
-                .any(|addr| addr.node_id == na.node_id)
-            {
-                candidate_addrs.push(na.clone());
+/// Answer a `Range` request on `GET /image` with 206 or 416, reading only the
+/// requested slice. `None` means serve the full image instead: the header
+/// asks for several ranges or doesn't parse, `If-Range` doesn't name the
+/// current ETag, the image has to be decompressed for this client, or there
+/// is no image.
+///
+/// The length, headers and slice are all taken under `adopting`, so an
+/// upload or slide can't swap the image between them and leave a 206 with
+/// new bytes under the old total and ETag.
+async fn ranged_image(
+    shared: &NodeShared,
+    range: &str,
+    if_range: Option<&str>,
+    accepts_gzip: bool,
+) -> Option<Response> {
+    let _adopting = shared.adopting().await;
+    let len = fs::metadata(shared.data_dir.join("current.img"))
+        .await
+        .ok()?
+        .len();
+    let mut headers = image_headers(shared, Some(len)).await;
+    if must_gunzip(&headers, accepts_gzip) {
+        return None;
+    }
+    // A resumed download only continues the image it started with. We send
+    // no Last-Modified, so a date never matches either
+    if let Some(validator) = if_range {
+        let etag = headers.get("ETag").and_then(|v| v.to_str().ok());
+        if etag != Some(validator.trim()) {
+            return None;
+        }
+    }
+    match byte_range::ByteRange::parse(range, len) {
+        byte_range::ByteRange::Full => None,
+        byte_range::ByteRange::Unsatisfiable => {
+            headers.remove("Content-Length");
+            if let Ok(v) = HeaderValue::from_str(&format!("bytes */{len}")) {
+                headers.insert("Content-Range", v);
+            }
+            Some((StatusCode::RANGE_NOT_SATISFIABLE, headers).into_response())
+        }
+        byte_range::ByteRange::Partial { start, end } => {
+            let slice = match shared.current_image_slice(start, end - start + 1).await {
+                Ok(slice) => slice,
+                Err(e) => {
+                    warn!(?e, "reading a range of current.img failed");
+                    return None;
+                }
+            };
+            headers.insert("Content-Length", HeaderValue::from(slice.len()));
+            if let Ok(v) = HeaderValue::from_str(&format!("bytes {start}-{end}/{len}")) {
+                headers.insert("Content-Range", v);
             }
+            Some((StatusCode::PARTIAL_CONTENT, headers, slice).into_response())
         }
+    }
+}
 
-        // Register addresses with the endpoint and extract node ids
-        let mut candidate_nodes: Vec<iroh_base::PublicKey> = Vec::new();
-        for addr in &candidate_addrs {
-            if let Err(e) = self.endpoint.add_node_addr(addr.clone()) {
-                warn!(?e, "failed to add node addr");
+/// Stream the image in chunks with tiny sleeps to encourage progressive rendering in browsers.
+/// Gzip-stored images are negotiated as on `GET /image`.
+async fn image_stream(
+    State(shared): State<Arc<NodeShared>>,
+    req_headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(resp) = shared.placeholder_response(false).await {
+        return resp;
+    }
+    shared.touch_current().await;
+    let path = shared.data_dir.join("current.img");
+    match tokio::fs::File::open(path).await {
+        Ok(file) => {
+            // No length rather than a wrong one if the size can't be read
+            let len = file.metadata().await.map(|m| m.len()).ok();
+            let mut headers = image_headers(&shared, len).await;
+            // Ranges are only honoured on `/image`
+            headers.insert("Accept-Ranges", HeaderValue::from_static("none"));
+            let delay = if shared.demo_mode {
+                shared.stream_sleep_ms
+            } else {
+                0
+            };
+            stream_image_file(&shared, file, headers, accepts_gzip(&req_headers), delay).await
+        }
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Stream an image `file` under `headers` (from `image_headers` or
+/// `served_image_headers`), sleeping `delay` ms between chunks. A gzip-stored
+/// image for a client that doesn't accept gzip is decompressed first,
+/// capped like `GET /image`.
+async fn stream_image_file(
+    shared: &NodeShared,
+    mut file: fs::File,
+    mut headers: HeaderMap,
+    accepts_gzip: bool,
+    delay: u64,
+) -> Response {
+    let reader: Box<dyn tokio::io::AsyncRead + Send + Unpin> =
+        if must_gunzip(&headers, accepts_gzip) {
+            use tokio::io::AsyncReadExt;
+            let mut bytes = Vec::new();
+            if let Err(e) = file.read_to_end(&mut bytes).await {
+                error!(?e, "failed to read image for decompression");
+                return StatusCode::NOT_FOUND.into_response();
             }
-            if !candidate_nodes.iter().any(|pk| *pk == addr.node_id) {
-                candidate_nodes.push(addr.node_id);
+            let plain = match shared.gunzip_image(bytes).await {
+                Ok(plain) => plain,
+                Err(resp) => return resp,
+            };
+            decoded_headers(&mut headers, Some(plain.len() as u64));
+            Box::new(std::io::Cursor::new(plain))
+        } else {
+            Box::new(file)
+        };
+    let stream = ReaderStream::new(reader).then(move |res| {
+        let d = delay;
+        async move {
+            if d > 0 {
+                sleep(Duration::from_millis(d)).await;
             }
+            res
         }
+    });
+    let mut resp = Response::new(axum::body::Body::from_stream(stream));
+    *resp.headers_mut() = headers;
+    resp
+}
 
-        if !candidate_nodes.is_empty() {
-            match self
-                .attempt_split_download(hash, &filename, &content_type, candidate_nodes.clone())
-                .await
-            {
-                Ok(_) => return Ok(()),
-                Err(err) => {
-                    warn!(
-                        ?err,
-                        "stripe download failed; falling back to sequential download"
-                    );
-                    let mut s = self.state.lock().await;
-                    s.bytes_received = 0;
-                    s.bytes_total = None;
-                    s.progress = 0.0;
-                    s.stripe_providers.clear();
+/// Part boundary of `GET /image_feed`.
+const FEED_BOUNDARY: &str = "frame";
+
+/// `GET /image_feed`: `multipart/x-mixed-replace` stream that pushes the
+/// current image, then each new one as it completes (upload or receive), so
+/// an `<img>` pointed at it updates without polling. Every frame carries its
+/// own content type. The stream ends when the client goes away.
+async fn image_feed(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let mut changes = shared.image_changed.subscribe();
+    if shared.state.lock().await.has_image {
+        changes.mark_changed();
+    }
+    let frames =
+        futures_util::stream::unfold((shared, changes), |(shared, mut changes)| async move {
+            loop {
+                changes.changed().await.ok()?;
+                changes.borrow_and_update();
+                match shared.feed_frame().await {
+                    Ok(frame) => return Some((Ok::<_, std::io::Error>(frame), (shared, changes))),
+                    Err(e) => warn!(?e, "image_feed: reading current.img failed; skipping frame"),
                 }
             }
+        });
+    (
+        [
+            (
+                axum::http::header::CONTENT_TYPE,
+                format!("multipart/x-mixed-replace; boundary={FEED_BOUNDARY}"),
+            ),
+            (axum::http::header::CACHE_CONTROL, "no-store".to_string()),
+            (
+                axum::http::header::X_CONTENT_TYPE_OPTIONS,
+                "nosniff".to_string(),
+            ),
+        ],
+        axum::body::Body::from_stream(frames),
+    )
+}
+
+/// `HEAD /image`: the GET headers without reading the file. A gzip-stored
+/// image announced to a client that doesn't accept gzip has no
+/// `Content-Encoding`, and its `Content-Length` is the decoded size the gzip
+/// trailer records.
+async fn head_image(
+    State(shared): State<Arc<NodeShared>>,
+    req_headers: HeaderMap,
+) -> impl IntoResponse {
+    if let Some(resp) = shared.placeholder_response(true).await {
+        return resp;
+    }
+    let path = shared.data_dir.join("current.img");
+    match fs::metadata(&path).await {
+        Ok(meta) => {
+            let mut headers = image_headers(&shared, Some(meta.len())).await;
+            if must_gunzip(&headers, accepts_gzip(&req_headers)) {
+                let len = content_encoding::decoded_len(&path).await.ok();
+                decoded_headers(&mut headers, len);
+            }
+            (headers, ()).into_response()
         }
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
 
-        let mut last_err: Option<anyhow::Error> = None;
-        for addr in candidate_addrs {
-            let node_id = addr.node_id;
-            let mut last_provider: Option<String> = None;
+/// `HEAD /image_stream`: as `HEAD /image`, but the stream ignores `Range`.
+async fn head_image_stream(shared: State<Arc<NodeShared>>, req_headers: HeaderMap) -> Response {
+    let mut resp = head_image(shared, req_headers).await.into_response();
+    if resp.headers().contains_key("Accept-Ranges") {
+        resp.headers_mut()
+            .insert("Accept-Ranges", HeaderValue::from_static("none"));
+    }
+    resp
+}
 
-            // Start the download and obtain a progress stream
-            let dl = downloader.download(hash, Some(node_id));
-            let mut stream = match dl.stream().await {
-                Ok(s) => s,
-                Err(e) => {
-                    last_err = Some(e.into());
-                    continue;
-                }
-            };
+/// `DELETE /image`: drop the current image so the node is back to having
+/// none, without a restart. 204, or 404 if there was no image.
+///
+/// With `URL_SIGNING_KEY` set, a client that may not even read the image
+/// must not delete it: the request needs `Authorization: Bearer <key>`, as
+/// `POST /sign` does, and gets 403 without it.
+async fn delete_image(State(shared): State<Arc<NodeShared>>, headers: HeaderMap) -> Response {
+    if let Some(signer) = &shared.url_signer {
+        if !bearer_token(&headers).is_some_and(|t| signer.is_key(t)) {
+            return StatusCode::FORBIDDEN.into_response();
+        }
+    }
+    match shared.clear_image().await {
+        Ok(true) => StatusCode::NO_CONTENT.into_response(),
+        Ok(false) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            error!(?e, "failed to clear current image");
+            (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+        }
+    }
+}
 
-            let mut failed = false;
-            while let Some(item) = stream.next().await {
-                match item {
-                    DownloadProgessItem::Progress(recvd) => {
-                        let mut s = self.state.lock().await;
-                        s.bytes_received = recvd;
-                        if let Some(t) = s.bytes_total {
-                            if t > 0 {
-                                s.progress = (recvd as f32 / t as f32) * 100.0;
-                            }
-                        }
-                    }
-                    DownloadProgessItem::TryProvider { id, .. } => {
-                        last_provider = Some(id.to_string());
-                    }
-                    DownloadProgessItem::ProviderFailed { .. } => {}
-                    DownloadProgessItem::PartComplete { .. } => {}
-                    DownloadProgessItem::Error(e) => {
-                        failed = true;
-                        last_err = Some(e);
-                        break;
-                    }
-                    DownloadProgessItem::DownloadError => {
-                        failed = true;
-                        last_err = Some(anyhow::anyhow!("download error"));
-                        break;
-                    }
-                }
-            }
+/// Whether the request's `Accept-Encoding` admits gzip.
+fn accepts_gzip(req_headers: &HeaderMap) -> bool {
+    req_headers
+        .get("Accept-Encoding")
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(content_encoding::accepts_gzip)
+}
 
-            if failed {
-                continue;
+/// Whether `headers` would send gzip to a client that can't take it, so the
+/// body has to be decompressed first.
+fn must_gunzip(headers: &HeaderMap, accepts_gzip: bool) -> bool {
+    !accepts_gzip && headers.get("Content-Encoding").is_some_and(|e| e == "gzip")
+}
+
+/// Turn `image_headers` into those of the decompressed body: no
+/// `Content-Encoding`, and its length when known.
+fn decoded_headers(headers: &mut HeaderMap, len: Option<u64>) {
+    headers.remove("Content-Encoding");
+    match len {
+        Some(len) => headers.insert("Content-Length", HeaderValue::from(len)),
+        None => headers.remove("Content-Length"),
+    };
+}
+
+/// `Content-Disposition` for a served image: `attachment` for active content,
+/// `inline` otherwise, naming `filename` when there is one. `None` when
+/// there's nothing to say (inline with no name).
+fn disposition_header(active: bool, filename: Option<&str>) -> Option<HeaderValue> {
+    let disposition = if active { "attachment" } else { "inline" };
+    match filename.filter(|name| !name.is_empty()) {
+        Some(name) => HeaderValue::from_str(&content_disposition::value(disposition, name)).ok(),
+        None => active.then(|| HeaderValue::from_static("attachment")),
+    }
+}
+
+/// Response headers for the current image, shared by the GET and HEAD handlers.
+/// `Content-Length` is left out when `len` is unknown.
+///
+/// The ETag is the blob hash, so it changes exactly when the content does.
+/// `Content-Disposition` carries the uploaded filename so saved copies keep
+/// it. HTML and SVG are always sent as attachments so they can't run script
+/// from our origin, and `nosniff` stops browsers second-guessing the type.
+async fn image_headers(shared: &NodeShared, len: Option<u64>) -> HeaderMap {
+    let hash = match shared.state.lock().await.current_hash.clone() {
+        Some(h) => Some(h),
+        None => image_meta::read(&shared.data_dir.join("current.img"))
+            .await
+            .map(|m| m.hash),
+    };
+    let image = ServedImage {
+        content_type: shared.served_content_type().await,
+        content_encoding: shared.served_content_encoding().await,
+        filename: shared.state.lock().await.current_filename.clone(),
+        hash,
+    };
+    served_image_headers(&image, len)
+}
+
+/// What the headers of an image response are built from: the current
+/// image's state, or a held image's metadata.
+struct ServedImage {
+    content_type: Option<String>,
+    content_encoding: Option<String>,
+    filename: Option<String>,
+    hash: Option<String>,
+}
+
+/// The headers `image_headers` describes, for any image.
+fn served_image_headers(image: &ServedImage, len: Option<u64>) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    headers.insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+    headers.insert(
+        "X-Content-Type-Options",
+        HeaderValue::from_static("nosniff"),
+    );
+    let active = image
+        .content_type
+        .as_deref()
+        .is_some_and(active_content::is_active_type);
+    if let Some(disposition) = disposition_header(active, image.filename.as_deref()) {
+        headers.insert("Content-Disposition", disposition);
+    }
+    if let Some(ct) = &image.content_type {
+        headers.insert(
+            "Content-Type",
+            HeaderValue::from_str(ct)
+                .unwrap_or(HeaderValue::from_static("application/octet-stream")),
+        );
+    }
+    if let Some(len) = len {
+        headers.insert("Content-Length", HeaderValue::from(len));
+    }
+    if let Some(enc) = image
+        .content_encoding
+        .as_deref()
+        .and_then(|e| HeaderValue::from_str(e).ok())
+    {
+        headers.insert("Content-Encoding", enc);
+        headers.insert("Vary", HeaderValue::from_static("Accept-Encoding"));
+    }
+    headers.insert("Accept-Ranges", HeaderValue::from_static("bytes"));
+    if let Some(etag) = image
+        .hash
+        .as_deref()
+        .and_then(|h| HeaderValue::from_str(&format!("\"{}\"", h)).ok())
+    {
+        headers.insert("ETag", etag);
+    }
+    headers
+}
+
+/// Plan a blob of `total_chunks` both sequentially and with the seeded
+/// randomized striping, and summarize the two side by side. Pure
+/// computation: no transfer happens.
+async fn compare_strategies(Json(body): Json<CompareBody>) -> impl IntoResponse {
+    let CompareBody {
+        total_chunks,
+        span,
+        seed,
+    } = body;
+    if total_chunks == 0 || total_chunks > MAX_COMPARE_CHUNKS {
+        let msg = format!("total_chunks must be between 1 and {MAX_COMPARE_CHUNKS}");
+        return (StatusCode::BAD_REQUEST, msg).into_response();
+    }
+    if span == 0 || span > total_chunks {
+        return (
+            StatusCode::BAD_REQUEST,
+            "span must be between 1 and total_chunks",
+        )
+            .into_response();
+    }
+    let hash = iroh_blobs::Hash::from_bytes([0; 32]);
+    let mut rng = <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(seed);
+    let sequential = chunk_strategy::sequential_get_requests(hash, total_chunks, span);
+    let randomized =
+        chunk_strategy::randomized_get_requests_with_rng(hash, total_chunks, span, &mut rng);
+    Json(serde_json::json!({
+        "total_chunks": total_chunks,
+        "span": span,
+        "seed": seed,
+        "sequential": chunk_strategy::summarize_plan(&sequential, total_chunks),
+        "randomized": chunk_strategy::summarize_plan(&randomized, total_chunks),
+    }))
+    .into_response()
+}
+
+/// Mint a time-limited URL for `/image` or `/image_stream`.
+///
+/// Only holders of the signing key may mint: the request must carry
+/// `Authorization: Bearer <URL_SIGNING_KEY>`. Returns 404 when signing is off.
+async fn sign_url(
+    State(shared): State<Arc<NodeShared>>,
+    headers: HeaderMap,
+    Json(body): Json<SignBody>,
+) -> impl IntoResponse {
+    let Some(signer) = &shared.url_signer else {
+        return (StatusCode::NOT_FOUND, "URL signing is not configured").into_response();
+    };
+    if !bearer_token(&headers).is_some_and(|t| signer.is_key(t)) {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let path = body.path.unwrap_or_else(|| "/image".to_string());
+    if !signable(&path) {
+        return (
+            StatusCode::BAD_REQUEST,
+            "only /image, /image_stream, /image_feed, /image/{hash} and /blob/{hash} can be signed",
+        )
+            .into_response();
+    }
+    let exp = unix_now().saturating_add(body.ttl_secs.unwrap_or(3600));
+    Json(serde_json::json!({
+        "url": signer.signed_url(&path, exp),
+        "exp": exp,
+    }))
+    .into_response()
+}
+
+/// The token of an `Authorization: Bearer <token>` header.
+fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get("Authorization")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+}
+
+/// Whether `POST /sign` mints URLs for `path`: the current-image endpoints,
+/// or one blob or held image by hash.
+fn signable(path: &str) -> bool {
+    if ["/image", "/image_stream", "/image_feed"].contains(&path) {
+        return true;
+    }
+    path.strip_prefix("/image/")
+        .or_else(|| path.strip_prefix("/blob/"))
+        .and_then(parse_hash)
+        .is_some()
+}
+
+/// A hash from a request, hex or base32. `Hash::from_str` panics on base32
+/// of the wrong length, so anything but the two exact lengths is refused
+/// first.
+fn parse_hash(s: &str) -> Option<iroh_blobs::Hash> {
+    matches!(s.len(), 52 | 64).then(|| s.parse().ok()).flatten()
+}
+
+/// Describe what a ticket points at without downloading it.
+///
+/// Hash, node id and kind come from the ticket itself. The size needs a
+/// last-chunk request to the provider (verified, so a few KiB at most); it is
+/// `null` when the provider can't be reached in time or there is no endpoint.
+async fn inspect_ticket(
+    State(shared): State<Arc<NodeShared>>,
+    Json(body): Json<InspectBody>,
+) -> impl IntoResponse {
+    let ticket = match body.ticket.parse::<iroh_blobs::ticket::BlobTicket>() {
+        Ok(t) => t,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("invalid ticket: {e}")).into_response(),
+    };
+    let hash = ticket.hash();
+    let size = match &shared.endpoint {
+        Some(ep) => {
+            let probe = async {
+                let conn = ep
+                    .connect(ticket.node_addr().clone(), iroh_blobs::ALPN)
+                    .await?;
+                let (size, _) = iroh_blobs::get::request::get_verified_size(&conn, &hash).await?;
+                anyhow::Ok(size)
+            };
+            match tokio::time::timeout(TICKET_INSPECT_TIMEOUT, probe).await {
+                Ok(Ok(size)) => Some(size),
+                Ok(Err(e)) => {
+                    warn!(?e, %hash, "ticket inspect: provider query failed");
+                    None
+                }
+                Err(_) => {
+                    warn!(%hash, "ticket inspect: provider query timed out");
+                    None
+                }
             }
+        }
+        None => None,
+    };
+    let kind = match ticket.format() {
+        iroh_blobs::BlobFormat::Raw => "raw",
+        iroh_blobs::BlobFormat::HashSeq => "hash_seq",
+    };
+    Json(serde_json::json!({
+        "hash": hash.to_string(),
+        "node_id": ticket.node_addr().node_id.to_string(),
+        "size": size,
+        "kind": kind,
+    }))
+    .into_response()
+}
 
-            // Export the downloaded blob to our HTTP-served location
-            let out_path = self.data_dir.join("current.img");
-            let _ = self.store.blobs().export(hash, &out_path).await;
+/// `POST /throughput`: measure P2P transfer speed to one peer.
+///
+/// Adds a random blob under a `throughput-` tag, has the peer fetch it
+/// through `/throughput/fetch`, and times the round trip. Only configured
+/// peers (`PEER_HTTP_URLS`) can be asked, so this can't be pointed at an
+/// arbitrary URL. The peer neither shows nor keeps the blob, and ours is
+/// untagged for store GC once the measurement is done.
+async fn throughput(
+    State(shared): State<Arc<NodeShared>>,
+    Json(body): Json<ThroughputBody>,
+) -> impl IntoResponse {
+    let Some(endpoint) = shared.endpoint.clone() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "P2P is disabled (DISTRIBUTION=http)",
+        )
+            .into_response();
+    };
+    let peer_url = match (body.peer_url, body.node_id) {
+        (Some(url), _) => {
+            let url = url.trim_end_matches('/');
+            match shared
+                .peers_http
+                .iter()
+                .find(|p| p.trim_end_matches('/') == url)
             {
-                let mut s = self.state.lock().await;
-                let recvd = s.bytes_received;
-                s.bytes_total = Some(recvd);
-                s.has_image = true;
-                s.current_filename = Some(filename.clone());
-                s.content_type = Some(content_type.clone());
-                s.progress = 100.0;
-                if let Some(provider) = last_provider {
-                    s.stripe_providers
-                        .entry(provider)
-                        .or_insert_with(|| vec!["all".to_string()]);
+                Some(peer) => peer.clone(),
+                None => {
+                    return (
+                        StatusCode::BAD_REQUEST,
+                        "peer_url must be one of PEER_HTTP_URLS",
+                    )
+                        .into_response()
                 }
-                let self_id = self.endpoint.node_id().to_string();
-                let entry = s.stripe_providers.entry(self_id).or_default();
-                if !entry.iter().any(|v| v == "all") {
-                    entry.push("all".to_string());
+            }
+        }
+        (None, Some(id)) => {
+            let addrs = shared.peers_addrs.lock().await;
+            match addrs.iter().find(|(_, a)| a.node_id.to_string() == id) {
+                Some((url, _)) => url.clone(),
+                None => {
+                    return (StatusCode::NOT_FOUND, "no known peer with that node id")
+                        .into_response()
                 }
             }
-            return Ok(());
         }
-
-        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no provider found for hash")))
+        (None, None) => {
+            return (StatusCode::BAD_REQUEST, "peer_url or node_id is required").into_response()
+        }
+    };
+    let size = body.bytes.unwrap_or(THROUGHPUT_DEFAULT_BYTES);
+    if size == 0 || size > THROUGHPUT_MAX_BYTES {
+        let msg = format!("bytes must be between 1 and {THROUGHPUT_MAX_BYTES}");
+        return (StatusCode::BAD_REQUEST, msg).into_response();
     }
+    let Ok(_slot) = shared.throughput_slots.try_acquire() else {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many throughput tests running",
+        )
+            .into_response();
+    };
 
-    async fn attempt_split_download(
-        &self,
-        hash: iroh_blobs::Hash,
-        filename: &str,
-        content_type: &str,
-        providers: Vec<iroh_base::PublicKey>,
-    ) -> anyhow::Result<()> {
-        if providers.is_empty() {
-            return Err(anyhow::anyhow!("no providers supplied for split download"));
+    let mut data = vec![0u8; size as usize];
+    thread_rng().fill(&mut data[..]);
+    let tag = format!("{THROUGHPUT_TAG_PREFIX}{:016x}", thread_rng().gen::<u64>());
+    let blob = match shared.store.add_slice(&data).with_named_tag(&tag).await {
+        Ok(b) => b,
+        Err(e) => {
+            error!(?e, "throughput: failed to store test blob");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "failed to store test blob",
+            )
+                .into_response();
         }
+    };
+    let addr = shared.advertised(endpoint.node_addr().initialized().await);
+    let ticket = iroh_blobs::ticket::BlobTicket::new(addr, blob.hash, blob.format);
 
-        let downloader = self.store.downloader(&self.endpoint);
-        let opts = DownloadRequest::new(hash, Shuffled::new(providers), SplitStrategy::Split);
-        let mut stream = downloader.download_with_opts(opts).stream().await?;
+    let started = std::time::Instant::now();
+    let fetched = async {
+        shared
+            .http_client
+            .post(format!("{}/throughput/fetch", peer_url))
+            .json(&serde_json::json!({ "ticket": ticket.to_string() }))
+            .timeout(THROUGHPUT_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<FetchTiming>()
+            .await
+    }
+    .await;
+    let elapsed = started.elapsed();
+    if let Err(e) = shared.store.tags().delete(&tag).await {
+        warn!(?e, %tag, "throughput: failed to untag test blob");
+    }
+    let timing = match fetched {
+        Ok(t) => t,
+        Err(e) => {
+            warn!(?e, %peer_url, "throughput: peer fetch failed");
+            return (StatusCode::BAD_GATEWAY, format!("peer fetch failed: {e}")).into_response();
+        }
+    };
+    let transport = timing
+        .node_id
+        .parse::<PublicKey>()
+        .ok()
+        .and_then(|id| endpoint.conn_type(id))
+        .map(|mut t| match t.get() {
+            iroh::endpoint::ConnectionType::Direct(_) => "direct",
+            iroh::endpoint::ConnectionType::Relay(_) => "relay",
+            iroh::endpoint::ConnectionType::Mixed(..) => "mixed",
+            iroh::endpoint::ConnectionType::None => "none",
+        });
+    let elapsed_ms = elapsed.as_millis() as u64;
+    info!(%peer_url, bytes = size, elapsed_ms, ?transport, "throughput measured");
+    Json(serde_json::json!({
+        "peer_url": peer_url,
+        "node_id": timing.node_id,
+        "bytes": size,
+        "elapsed_ms": elapsed_ms,
+        "throughput_bps": (size as f64 / elapsed.as_secs_f64().max(1e-6)) as u64,
+        "peer_elapsed_ms": timing.elapsed_ms,
+        "transport": transport,
+    }))
+    .into_response()
+}
 
-        let mut owner_for_request: HashMap<String, String> = HashMap::new();
-        let mut label_cache: HashMap<String, String> = HashMap::new();
+/// `POST /throughput/fetch`: the peer side of `POST /throughput`. Downloads
+/// the ticket's blob into a scratch in-memory store, so neither the served
+/// image nor the blob store is touched, and reports how long it took. Blobs
+/// over `THROUGHPUT_MAX_BYTES` are refused before any data moves, and the
+/// fetches share `throughput_slots`, bounding the memory they can hold.
+async fn throughput_fetch(
+    State(shared): State<Arc<NodeShared>>,
+    Json(body): Json<InspectBody>,
+) -> impl IntoResponse {
+    let Some(endpoint) = shared.endpoint.clone() else {
+        return (
+            StatusCode::BAD_REQUEST,
+            "P2P is disabled (DISTRIBUTION=http)",
+        )
+            .into_response();
+    };
+    let ticket = match body.ticket.parse::<iroh_blobs::ticket::BlobTicket>() {
+        Ok(t) => t,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("invalid ticket: {e}")).into_response(),
+    };
+    let Ok(_slot) = shared.throughput_slots.try_acquire() else {
+        return (
+            StatusCode::TOO_MANY_REQUESTS,
+            "too many throughput fetches running",
+        )
+            .into_response();
+    };
+    let hash = ticket.hash();
+    let provider = ticket.node_addr().node_id;
+    let fetch = async {
+        let conn = endpoint
+            .connect(ticket.node_addr().clone(), iroh_blobs::ALPN)
+            .await?;
+        // Checked up front since the scratch store holds the blob in memory
+        let (size, _) = iroh_blobs::get::request::get_verified_size(&conn, &hash).await?;
+        anyhow::ensure!(
+            size <= THROUGHPUT_MAX_BYTES,
+            "blob is {size} bytes; at most {THROUGHPUT_MAX_BYTES} are accepted"
+        );
+        let scratch = iroh_blobs::store::mem::MemStore::new();
+        let started = std::time::Instant::now();
+        scratch
+            .downloader(&endpoint)
+            .download(hash, Some(provider))
+            .await?;
+        anyhow::Ok((size, started.elapsed()))
+    };
+    match tokio::time::timeout(THROUGHPUT_TIMEOUT, fetch).await {
+        Ok(Ok((bytes, elapsed))) => Json(FetchTiming {
+            node_id: endpoint.node_id().to_string(),
+            bytes,
+            elapsed_ms: elapsed.as_millis() as u64,
+        })
+        .into_response(),
+        Ok(Err(e)) => {
+            warn!(?e, %hash, "throughput fetch failed");
+            (StatusCode::BAD_GATEWAY, format!("fetch failed: {e}")).into_response()
+        }
+        Err(_) => (StatusCode::GATEWAY_TIMEOUT, "fetch timed out").into_response(),
+    }
+}
 
-        while let Some(item) = stream.next().await {
-            match item {
-                DownloadProgessItem::Progress(recvd) => {
-                    let mut s = self.state.lock().await;
-                    s.bytes_received = recvd;
-                    if let Some(t) = s.bytes_total {
-                        if t > 0 {
-                            s.progress = (recvd as f32 / t as f32) * 100.0;
-                        }
-                    }
+/// Serve a complete blob from the local store by hash.
+///
+/// This is the HTTP plane's content endpoint; peers use it as a last resort
+/// when P2P delivery fails (see `receive_via_http`). Returns 404 if the blob is
+/// unknown or only partially stored.
+async fn get_blob(
+    State(shared): State<Arc<NodeShared>>,
+    Path(hash): Path<String>,
+) -> impl IntoResponse {
+    let Some(hash) = parse_hash(&hash) else {
+        return StatusCode::BAD_REQUEST.into_response();
+    };
+    match shared.store.blobs().get_bytes(hash).await {
+        Ok(bytes) => {
+            shared.store_usage.lock().await.touch(&hash);
+            let len = bytes.len() as u64;
+            let mut resp = Response::builder()
+                .status(StatusCode::OK)
+                .body(axum::body::Body::from(bytes))
+                .unwrap();
+            resp.headers_mut()
+                .insert("Content-Length", HeaderValue::from(len));
+            resp.headers_mut()
+                .insert("Access-Control-Allow-Origin", HeaderValue::from_static("*"));
+            resp.headers_mut().insert(
+                "Content-Type",
+                HeaderValue::from_static("application/octet-stream"),
+            );
+            resp
+        }
+        Err(_) => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// `POST /store/add`: put bytes (the raw body, or the `file` part of a
+/// multipart form) in the blob store and retain them, without touching
+/// `current.img`, `state` or peers. Pre-seeds content this node can serve at
+/// `/blob/{hash}` and to P2P fetches without making it the current image.
+///
+/// Takes an upload slot and applies `BLOCK_ACTIVE_CONTENT` and
+/// `VERIFY_UPLOAD` like `/upload`, judging content by the part's or the
+/// request's `Content-Type`.
+async fn store_add(State(shared): State<Arc<NodeShared>>, req: axum::extract::Request) -> Response {
+    let _slot = match upload_slot(&shared, "/store/add").await {
+        Ok(slot) => slot,
+        Err(resp) => return resp,
+    };
+    let declared_type = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    let multipart = declared_type
+        .as_deref()
+        .is_some_and(|ct| ct.starts_with("multipart/form-data"));
+    let (bytes, content_type) = if multipart {
+        let mut mp = match Multipart::from_request(req, &()).await {
+            Ok(mp) => mp,
+            Err(rejection) => return rejection.into_response(),
+        };
+        let mut file = None;
+        loop {
+            let field = match mp.next_field().await {
+                Ok(Some(field)) => field,
+                Ok(None) => break,
+                Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                    return upload_too_large(shared.max_upload_bytes);
                 }
-                DownloadProgessItem::TryProvider { id, request } => {
-                    let key = request_key(request.as_ref());
-                    owner_for_request.insert(key.clone(), id.to_string());
-                    label_cache
-                        .entry(key)
-                        .or_insert_with(|| describe_request(request.as_ref()));
+                Err(e) => return e.into_response(),
+            };
+            if field.name().is_none_or(|name| name == "file") {
+                let content_type = field
+                    .content_type()
+                    .filter(|s| !s.is_empty())
+                    .map(str::to_string);
+                match field.bytes().await {
+                    Ok(bytes) => file = Some((bytes, content_type)),
+                    Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                        return upload_too_large(shared.max_upload_bytes);
+                    }
+                    Err(e) => return e.into_response(),
                 }
-                DownloadProgessItem::ProviderFailed { request, .. } => {
-                    owner_for_request.remove(&request_key(request.as_ref()));
+                break;
+            }
+        }
+        file.unwrap_or_default()
+    } else {
+        match bytes::Bytes::from_request(req, &()).await {
+            Ok(bytes) => (bytes, declared_type),
+            Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return upload_too_large(shared.max_upload_bytes);
+            }
+            Err(rejection) => return rejection.into_response(),
+        }
+    };
+    if bytes.is_empty() {
+        return (StatusCode::BAD_REQUEST, "no content").into_response();
+    }
+    let content_type = content_type.unwrap_or_else(|| shared.default_content_type.clone());
+    if shared.block_active_content && active_content::is_active(&content_type, &bytes) {
+        warn!(?content_type, "/store/add: rejecting active content");
+        return (
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "HTML and scripted SVG uploads are not accepted",
+        )
+            .into_response();
+    }
+    let hash = match shared.store.add_bytes(bytes.clone()).await {
+        Ok(tag) => tag.hash,
+        Err(e) => {
+            error!(?e, "/store/add: store add failed");
+            return (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response();
+        }
+    };
+    if shared.verify_upload {
+        if let Err(e) = shared.verify_stored(hash, &bytes).await {
+            error!(?e, %hash, "/store/add: store round-trip check failed");
+            return (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("store verification failed: {e}"),
+            )
+                .into_response();
+        }
+    }
+    shared.retain_blob(hash).await;
+    info!(%hash, bytes = bytes.len(), "/store/add: stored without serving");
+    let ticket = shared.endpoint.as_ref().map(|ep| {
+        let addr = shared.advertised(
+            ep.node_addr()
+                .get()
+                .unwrap_or_else(|| NodeAddr::new(ep.node_id())),
+        );
+        iroh_blobs::ticket::BlobTicket::new(addr, hash, iroh_blobs::BlobFormat::Raw).to_string()
+    });
+    Json(serde_json::json!({
+        "hash": hash.to_string(),
+        "bytes": bytes.len(),
+        "ticket": ticket,
+    }))
+    .into_response()
+}
+
+/// Accepts a multipart file upload, writes it into the local blobs store and
+/// saves a copy to `current.img` for HTTP serving. On a provider node the
+/// upload is a one-shot write (not a P2P download), so we set `bytes_total`
+/// and `bytes_received` to the full size and mark `progress = 100`.
+///
+/// Also fans out a hash-only notify to peers so they can discover and download.
+///
+/// With `If-None-Match: <hash>` matching the current image, nothing is read or
+/// stored: the response is `304 Not Modified` with that hash as the `ETag`.
+///
+/// A repeat of an `Idempotency-Key` seen within `IDEMPOTENCY_TTL` with the
+/// same file gets the first response again (marked `Idempotent-Replayed:
+/// true`) without an ingest. The same key with a different file gets 422,
+/// and a repeat while the first is still running gets 409.
+async fn upload(
+    State(shared): State<Arc<NodeShared>>,
+    Query(q): Query<UploadQuery>,
+    headers: HeaderMap,
+    mp: Multipart,
+) -> impl IntoResponse {
+    maybe_latency(&shared).await;
+
+    if let Some(hash) = shared.current_image_matches(&headers).await {
+        info!(%hash, "/upload: If-None-Match matches current image; skipping ingest");
+        let mut resp = StatusCode::NOT_MODIFIED.into_response();
+        if let Ok(etag) = HeaderValue::from_str(&format!("\"{}\"", hash)) {
+            resp.headers_mut().insert("ETag", etag);
+        }
+        return resp;
+    }
+
+    let file = match read_upload(&shared, mp).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    let idempotency_key = headers
+        .get("Idempotency-Key")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string);
+    // The key's marker is held only while claiming, never across the ingest
+    let pending = match idempotency_key {
+        Some(key) => {
+            let digest = iroh_blobs::Hash::new(&file.bytes).to_string();
+            let claim =
+                shared
+                    .idempotency
+                    .lock()
+                    .await
+                    .claim(&key, &digest, std::time::Instant::now());
+            match claim {
+                idempotency::Claim::New => Some(idempotency::Pending::new(
+                    shared.idempotency.clone(),
+                    key,
+                    digest,
+                )),
+                idempotency::Claim::Replay(body) => {
+                    info!(
+                        key,
+                        "/upload: replaying response for repeated Idempotency-Key"
+                    );
+                    let mut resp = Json(body).into_response();
+                    resp.headers_mut()
+                        .insert("Idempotent-Replayed", HeaderValue::from_static("true"));
+                    return resp;
                 }
-                DownloadProgessItem::PartComplete { request } => {
-                    let key = request_key(request.as_ref());
-                    if let Some(provider) = owner_for_request.get(&key).cloned() {
-                        let label = label_cache
-                            .entry(key)
-                            .or_insert_with(|| describe_request(request.as_ref()))
-                            .clone();
-                        let mut s = self.state.lock().await;
-                        let entry = s.stripe_providers.entry(provider).or_default();
-                        if !entry.iter().any(|v| v == &label) {
-                            entry.push(label);
-                        }
-                    }
+                idempotency::Claim::InFlight => {
+                    return (
+                        StatusCode::CONFLICT,
+                        "an upload with this Idempotency-Key is still in progress",
+                    )
+                        .into_response();
                 }
-                DownloadProgessItem::Error(e) => return Err(e),
-                DownloadProgessItem::DownloadError => {
-                    return Err(anyhow::anyhow!("download error"));
+                idempotency::Claim::Mismatch => {
+                    warn!(key, "/upload: Idempotency-Key reused with a different file");
+                    return (
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "Idempotency-Key was already used with a different file",
+                    )
+                        .into_response();
                 }
             }
         }
+        None => None,
+    };
+
+    match ingest_upload(&shared, file).await {
+        Ok((_, mut body, notifying)) => {
+            if q.await_notify.as_deref().is_some_and(query_flag) {
+                body["notified"] = match notifying.await {
+                    Ok(outcomes) => serde_json::json!(outcomes),
+                    Err(e) => {
+                        error!(?e, "/upload: notify fan-out panicked");
+                        serde_json::Value::Null
+                    }
+                };
+            }
+            if let Some(pending) = pending {
+                pending.complete(body.clone()).await;
+            }
+            Json(body).into_response()
+        }
+        Err(resp) => resp,
+    }
+}
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    /// Wait for the peer notify fan-out and report it as `notified`.
+    await_notify: Option<String>,
+}
+
+/// A boolean query parameter: `1` or `true`, like `env_flag`.
+fn query_flag(v: &str) -> bool {
+    matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true")
+}
+
+#[derive(Deserialize)]
+struct SyncQuery {
+    timeout_ms: Option<u64>,
+}
+
+impl SyncQuery {
+    /// `timeout_ms`, capped at `MAX_SYNC_TIMEOUT` so one request can't keep
+    /// polling peers indefinitely.
+    fn timeout(&self) -> Duration {
+        self.timeout_ms
+            .map_or(SYNC_TIMEOUT, Duration::from_millis)
+            .min(MAX_SYNC_TIMEOUT)
+    }
+}
+
+/// `POST /upload_and_sync`: `/upload`, then wait until every configured peer
+/// reports the new hash as its current image, or `timeout_ms` (default
+/// `SYNC_TIMEOUT`, at most `MAX_SYNC_TIMEOUT`) passes.
+///
+/// Responds with the `/upload` body plus `sync`: per-peer outcome (with the
+/// time it took each peer) and whether all of them made it.
+async fn upload_and_sync(
+    State(shared): State<Arc<NodeShared>>,
+    Query(q): Query<SyncQuery>,
+    mp: Multipart,
+) -> impl IntoResponse {
+    maybe_latency(&shared).await;
+    let file = match read_upload(&shared, mp).await {
+        Ok(file) => file,
+        Err(resp) => return resp,
+    };
+    let (hash, mut body, _) = match ingest_upload(&shared, file).await {
+        Ok(uploaded) => uploaded,
+        Err(resp) => return resp,
+    };
+    body["sync"] = wait_for_peers(&shared, hash, q.timeout()).await;
+    Json(body).into_response()
+}
+
+/// Poll peers' `/status` (via `refresh_peer_addrs`) until each reports `hash`
+/// or `timeout` passes.
+async fn wait_for_peers(
+    shared: &NodeShared,
+    hash: iroh_blobs::Hash,
+    timeout: Duration,
+) -> serde_json::Value {
+    let wanted = hash.to_string();
+    let client = &shared.http_client;
+    let started = std::time::Instant::now();
+    let mut synced_after: HashMap<String, u64> = HashMap::new();
+    loop {
+        refresh_peer_addrs(shared, client).await;
+        let elapsed = started.elapsed();
+        {
+            let images = shared.peer_images.lock().await;
+            for url in &shared.peers_http {
+                if images.get(url) == Some(&wanted) {
+                    synced_after
+                        .entry(url.clone())
+                        .or_insert(elapsed.as_millis() as u64);
+                }
+            }
+        }
+        if synced_after.len() == shared.peers_http.len() || elapsed >= timeout {
+            break;
+        }
+        sleep(SYNC_POLL_INTERVAL.min(timeout - elapsed)).await;
+    }
+    let peers: Vec<_> = shared
+        .peers_http
+        .iter()
+        .map(|url| {
+            let after = synced_after.get(url);
+            serde_json::json!({ "url": url, "synced": after.is_some(), "synced_after_ms": after })
+        })
+        .collect();
+    serde_json::json!({
+        "all_synced": synced_after.len() == shared.peers_http.len(),
+        "elapsed_ms": started.elapsed().as_millis() as u64,
+        "peers": peers,
+    })
+}
+
+/// 413 for an upload over `MAX_UPLOAD_BYTES`, naming the limit.
+fn upload_too_large(limit: usize) -> Response {
+    (
+        StatusCode::PAYLOAD_TOO_LARGE,
+        format!("file too large: the limit is {limit} bytes"),
+    )
+        .into_response()
+}
+
+/// An `upload_slots` permit for `route`, held until it drops; 429 when all
+/// are taken and queueing is off.
+async fn upload_slot(
+    shared: &NodeShared,
+    route: &str,
+) -> Result<Option<tokio::sync::OwnedSemaphorePermit>, Response> {
+    let Some(slots) = &shared.upload_slots else {
+        return Ok(None);
+    };
+    match slots.acquire().await {
+        Some(permit) => Ok(Some(permit)),
+        None => {
+            warn!(route, "MAX_CONCURRENT_UPLOADS reached; rejecting");
+            Err((StatusCode::TOO_MANY_REQUESTS, "too many concurrent uploads").into_response())
+        }
+    }
+}
+
+/// 400 for a multipart body that failed or ended before the file did.
+fn upload_incomplete(e: axum::extract::multipart::MultipartError) -> Response {
+    warn!(%e, "/upload: multipart body incomplete; discarding upload");
+    (StatusCode::BAD_REQUEST, format!("upload incomplete: {e}")).into_response()
+}
+
+/// The `file` part of an `/upload`, read in full, with the upload slot it
+/// holds until ingest is done with it.
+struct UploadedFile {
+    filename: String,
+    content_type: String,
+    content_encoding: Option<String>,
+    bytes: Vec<u8>,
+    _slot: Option<tokio::sync::OwnedSemaphorePermit>,
+}
+
+/// Take an upload slot and read the multipart `file` part into memory.
+async fn read_upload(shared: &NodeShared, mut mp: Multipart) -> Result<UploadedFile, Response> {
+    let slot = upload_slot(shared, "/upload").await?;
+    let mut filename = "upload".to_string();
+    let mut content_type = shared.default_content_type.clone();
+    let mut content_encoding = None;
+    let mut bytes = Vec::new();
+
+    info!("/upload: reading multipart fields");
+    loop {
+        let mut field = match mp.next_field().await {
+            Ok(Some(field)) => field,
+            Ok(None) => break,
+            Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                return Err(upload_too_large(shared.max_upload_bytes));
+            }
+            Err(e) => return Err(upload_incomplete(e)),
+        };
+        let field_name = field.name().map(|s| s.to_string());
+        let fname_dbg = field.file_name().map(|s| s.to_string());
+        info!(?field_name, ?fname_dbg, "multipart field");
+        // Prefer the 'file' part; if no name is provided, assume it's the file
+        if field_name.as_deref() == Some("file") || field_name.is_none() {
+            if let Some(name) = field.file_name().map(|s| s.to_string()) {
+                filename = name;
+            }
+            if let Some(ct) = field.content_type().filter(|s| !s.is_empty()) {
+                content_type = ct.to_string();
+            }
+            let declared = field
+                .headers()
+                .get("Content-Encoding")
+                .map(|v| v.to_str().unwrap_or("?"))
+                .unwrap_or_default();
+            content_encoding = match content_encoding::normalize(declared) {
+                Ok(encoding) => encoding.map(str::to_string),
+                Err(e) => return Err((StatusCode::UNSUPPORTED_MEDIA_TYPE, e).into_response()),
+            };
+            // Read the file in chunks to avoid surprises if a single read fails
+            loop {
+                let chunk = match field.chunk().await {
+                    Ok(Some(chunk)) => chunk,
+                    Ok(None) => break,
+                    // The body limit cut the stream off
+                    Err(e) if e.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+                        return Err(upload_too_large(shared.max_upload_bytes));
+                    }
+                    // The client went away or the body ended mid-part: what
+                    // arrived is a prefix of the file, never the file
+                    Err(e) => return Err(upload_incomplete(e)),
+                };
+                bytes.extend_from_slice(&chunk);
+                if bytes.len() > shared.max_upload_bytes {
+                    return Err(upload_too_large(shared.max_upload_bytes));
+                }
+            }
+            break;
+        }
+    }
+
+    info!(
+        ?filename,
+        ?content_type,
+        size = bytes.len(),
+        "/upload: parsed file"
+    );
+
+    if bytes.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, "no file").into_response());
+    }
+    Ok(UploadedFile {
+        filename,
+        content_type,
+        content_encoding,
+        bytes,
+        _slot: slot,
+    })
+}
+
+/// The ingest behind `/upload`: store a read `file`, make it the current
+/// image and notify peers. Returns the hash with the JSON body for the
+/// client and the running notify fan-out, or the error response.
+async fn ingest_upload(
+    shared: &Arc<NodeShared>,
+    file: UploadedFile,
+) -> Result<(iroh_blobs::Hash, serde_json::Value, Notifying), Response> {
+    let UploadedFile {
+        filename,
+        mut content_type,
+        content_encoding,
+        mut bytes,
+        _slot,
+    } = file;
+    // Clients without gzip get the decoded image, so it has to decode
+    let decoded = match &content_encoding {
+        Some(_) => {
+            let (encoded, limit) = (bytes.clone(), shared.max_upload_bytes);
+            match tokio::task::spawn_blocking(move || content_encoding::gunzip(&encoded, limit))
+                .await
+            {
+                Ok(Ok(plain)) => Some(plain),
+                res => {
+                    warn!(?res, "/upload: gzip-encoded body doesn't decode");
+                    return Err((
+                        StatusCode::BAD_REQUEST,
+                        format!(
+                            "gzip-encoded upload must decode to at most {} bytes",
+                            shared.max_upload_bytes
+                        ),
+                    )
+                        .into_response());
+                }
+            }
+        }
+        None => None,
+    };
+    if shared.block_active_content
+        && active_content::is_active(&content_type, decoded.as_deref().unwrap_or(&bytes))
+    {
+        warn!(
+            ?filename,
+            ?content_type,
+            "/upload: rejecting active content"
+        );
+        return Err((
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "HTML and scripted SVG uploads are not accepted",
+        )
+            .into_response());
+    }
+    // Encoded bodies aren't the image itself, so transforms can't apply
+    if !shared.ingest.is_empty() && content_encoding.is_none() {
+        let ingest = shared.ingest.clone();
+        let before = bytes.len();
+        (bytes, content_type) =
+            tokio::task::spawn_blocking(move || ingest.apply(bytes, content_type))
+                .await
+                .map_err(|e| {
+                    error!(?e, "/upload: ingest transform panicked");
+                    (StatusCode::INTERNAL_SERVER_ERROR, "ingest transform failed").into_response()
+                })?;
+        info!(
+            before,
+            after = bytes.len(),
+            "/upload: applied ingest transforms"
+        );
+    }
+
+    // Add to blobs store (track total bytes)
+    let total = bytes.len() as u64;
+    let started = std::time::Instant::now();
+    let tag = shared.store.add_slice(&bytes).await.map_err(|e| {
+        error!(?e, "/upload: store add failed");
+        (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+    })?;
+    let hash = tag.hash;
+    // iroh-blobs hashes and writes the outboard on a single task; there is no
+    // parallel add to hand off to, so just record how long ingest took.
+    info!(%hash, bytes = total, elapsed_ms = started.elapsed().as_millis() as u64, "store add finished");
+    if shared.verify_upload {
+        if let Err(e) = shared.verify_stored(hash, &bytes).await {
+            error!(?e, %hash, "/upload: store round-trip check failed");
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("store verification failed: {e}"),
+            )
+                .into_response());
+        }
+    }
+    // Tickets embed our iroh address, so there is none in HTTP-only mode
+    let ticket = match &shared.blobs {
+        Some(blobs) => {
+            let ticket = blobs.ticket(tag).await.map_err(|e| {
+                error!(?e, %hash, "/upload: ticket creation failed");
+                (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+            })?;
+            Some(iroh_blobs::ticket::BlobTicket::new(
+                shared.advertised(ticket.node_addr().clone()),
+                ticket.hash(),
+                ticket.format(),
+            ))
+        }
+        None => None,
+    };
+
+    // Save a local copy for HTTP serving; without it there is no image to
+    // mark present or announce
+    let adopting = shared.adopting().await;
+    if let Err(e) = shared.write_current(&bytes).await {
+        error!(?e, %hash, "/upload: writing current.img failed");
+        return Err((
+            StatusCode::INTERNAL_SERVER_ERROR,
+            format!("failed to write current.img: {e}"),
+        )
+            .into_response());
+    }
+
+    let provider_node_id = shared.endpoint.as_ref().map(|ep| ep.node_id().to_string());
+    let provider = provider_node_id
+        .clone()
+        .unwrap_or_else(|| shared.public_url.clone());
+
+    let version = {
+        let mut s = shared.state.lock().await;
+        s.has_image = true;
+        s.current_filename = Some(filename.clone());
+        s.content_type = Some(content_type.clone());
+        s.content_encoding = content_encoding.clone();
+        s.bytes_total = Some(total);
+        s.bytes_received = total; // uploader is complete
+        s.progress = 100.0;
+        s.clear_rate();
+        s.current_hash = Some(hash.to_string());
+        s.stripe_providers = HashMap::from([(provider.clone(), vec!["all".to_string()])]);
+        s.transfer_id = None; // the image is ours, not the result of a receive
+        let mut clock = s.clock();
+        let version = clock.tick(&provider);
+        s.set_clock(clock);
+        version
+    };
+    shared.save_clock().await;
+    shared.complete_image(hash).await;
+    drop(adopting);
+
+    // P2P notify peers over iroh (fallback to HTTP /receive if unknown) using hash-only model
+    let msg = NotifyMsg {
+        hash: hash.to_string(),
+        filename: filename.clone(),
+        content_type: content_type.clone(),
+        provider_node_id: provider_node_id.clone(),
+        provider_url: Some(shared.public_url.clone()),
+        version: Some(version),
+        bytes_total: Some(total),
+        content_encoding,
+    };
+    let notifying = tokio::spawn(notify_all_peers(shared.clone(), msg.clone()));
+
+    let body = serde_json::json!({
+        "ticket": ticket.map(|t| t.to_string()),
+        "hash": hash.to_string(),
+        "filename": filename,
+        "content_type": content_type,
+        "provider_node_id": provider_node_id,
+        "provider_url": shared.public_url,
+    });
+    Ok((hash, body, notifying))
+}
+
+/// The background `notify_all_peers` of an upload; dropping it leaves the
+/// fan-out running.
+type Notifying = tokio::task::JoinHandle<Vec<NotifyOutcome>>;
+
+/// HTTP receive endpoint accepts either a full ticket or just a hash
+async fn receive_http(
+    State(shared): State<Arc<NodeShared>>,
+    client: Option<ConnectInfo<SocketAddr>>,
+    Json(msg): Json<ReceiveBody>,
+) -> impl IntoResponse {
+    let peer = client.map(|c| c.0.to_string()).unwrap_or_default();
+    shared
+        .notify_log
+        .record(Direction::Inbound, "http", peer, &msg);
+    maybe_latency(&shared).await;
+    if let Some(Err(e)) = msg.stripe_span.map(chunk_strategy::validate_stripe_span) {
+        return (StatusCode::BAD_REQUEST, e).into_response();
+    }
+    let content_encoding =
+        match content_encoding::normalize(msg.content_encoding.as_deref().unwrap_or("")) {
+            Ok(encoding) => encoding.map(str::to_string),
+            Err(e) => return (StatusCode::BAD_REQUEST, e).into_response(),
+        };
+    // Parsed up front so a malformed request is refused before it can move
+    // the logical clock
+    let ticket = match msg
+        .ticket
+        .as_deref()
+        .map(str::parse::<iroh_blobs::ticket::BlobTicket>)
+    {
+        Some(Ok(ticket)) => Some(ticket),
+        Some(Err(_)) => return StatusCode::BAD_REQUEST.into_response(),
+        None => None,
+    };
+    let hash = match (&ticket, msg.hash.as_deref()) {
+        (Some(ticket), _) => ticket.hash(),
+        (None, Some(hs)) => match hs.parse::<iroh_blobs::Hash>() {
+            Ok(hash) => hash,
+            Err(_) => return StatusCode::BAD_REQUEST.into_response(),
+        },
+        (None, None) => return StatusCode::BAD_REQUEST.into_response(),
+    };
+    if let Some(version) = msg.version {
+        let origin = msg
+            .provider_node_id
+            .clone()
+            .or_else(|| ticket.as_ref().map(|t| t.node_addr().node_id.to_string()))
+            .or_else(|| msg.provider_url.clone())
+            .unwrap_or_default();
+        if !shared.adopt_version(version, &origin).await {
+            info!(version, "/receive: ignoring stale notify");
+            return (StatusCode::CONFLICT, "a newer image is already current").into_response();
+        }
+    }
+    if shared.distribution == Distribution::Http {
+        return match shared
+            .receive_by_http(
+                hash,
+                msg.filename,
+                msg.content_type,
+                content_encoding,
+                msg.bytes_total,
+                msg.provider_url,
+            )
+            .await
+        {
+            Ok(transfer_id) => transfer_accepted(transfer_id),
+            Err(e) => {
+                error!(?e, "receive (http) error");
+                StatusCode::BAD_GATEWAY.into_response()
+            }
+        };
+    }
+    let provider = match &ticket {
+        Some(ticket) => Some(ticket.node_addr().clone()),
+        None => msg
+            .provider_node_id
+            .as_deref()
+            .and_then(|s| s.parse::<PublicKey>().ok())
+            .map(NodeAddr::from),
+    };
+    let transfer = InFlight {
+        stripe_span: msg.stripe_span,
+        content_encoding,
+        declared_size: msg.bytes_total,
+        ..InFlight::new(
+            new_transfer_id(),
+            hash.to_string(),
+            msg.filename,
+            msg.content_type,
+            hinted_providers(provider, &msg.provider_node_ids),
+        )
+    };
+    match shared.track_receive(transfer).await {
+        Ok(transfer_id) => transfer_accepted(transfer_id),
+        Err(e) => {
+            let via = if ticket.is_some() { "ticket" } else { "hash" };
+            error!(?e, via, "receive error");
+            receive_error_response(&e)
+        }
+    }
+}
+
+/// P2P receives started but not yet finished, including ones interrupted by
+/// a restart (`resumed: true` once `AUTO_RESUME` picked them up again).
+async fn transfers(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let inflight = shared.inflight.lock().await;
+    Json(serde_json::json!({ "transfers": inflight.transfers() }))
+}
+
+/// `GET /notifies`: the most recent notify messages this node sent or
+/// received, oldest first, with direction, transport and peer.
+async fn notifies(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    Json(serde_json::json!({ "notifies": shared.notify_log.records() }))
+}
+
+/// `GET /diagnostics`: one JSON bundle to attach to a bug report: version,
+/// download settings, `/status`, `/whoami`, `/peers`, `/store`, in-flight
+/// `/transfers` and the most recent log lines.
+///
+/// Needs `Authorization: Bearer <DIAGNOSTICS_TOKEN>`; 404 when that isn't
+/// configured. Secrets (signing key, tokens) are never included.
+async fn diagnostics(State(shared): State<Arc<NodeShared>>, headers: HeaderMap) -> Response {
+    let Some(expected) = &shared.diagnostics_token else {
+        return (StatusCode::NOT_FOUND, "DIAGNOSTICS_TOKEN is not configured").into_response();
+    };
+    if !bearer_token(&headers).is_some_and(|t| constant_time_eq(t.as_bytes(), expected.as_bytes()))
+    {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+    let transfers = shared.inflight.lock().await.transfers().to_vec();
+    Json(serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "generated_at": unix_now(),
+        "config": {
+            "distribution": shared.distribution,
+            "public_url": shared.public_url,
+            "http_blob_fallback": shared.http_blob_fallback,
+            "receive_deadline_ms": shared.receive_deadline.map(|d| d.as_millis() as u64),
+            "split_stall_ms": shared.split_stall.map(|d| d.as_millis() as u64),
+            "sequential_max_candidates": shared.sequential_max_candidates,
+            "sequential_completion": shared.sequential_completion,
+            "single_provider_parallelism": shared.single_provider_parallelism,
+            "download_mode": shared.download_mode,
+            "fs_layout": shared.fs_layout,
+            "max_upload_bytes": shared.max_upload_bytes,
+            "prefer_lan": shared.prefer_lan,
+            "export_retries": shared.export_retries,
+            "keep_image_during_receive": shared.keep_image_during_receive,
+            "demo_mode": shared.demo_mode,
+            "verify_content_type": shared.verify_content_type,
+            "url_signing": shared.url_signer.is_some(),
+        },
+        "status": shared.status_snapshot().await,
+        "whoami": whoami_report(&shared).await,
+        "peers": peers_report(&shared).await,
+        "store": store_report(&shared).await,
+        "transfers": transfers,
+        "logs": shared.log_ring.lines(),
+    }))
+    .into_response()
+}
+
+/// Compare secrets without leaking how long a matching prefix is.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// A received blob's size differs from its declared size by more than
+/// `SIZE_MISMATCH_TOLERANCE_PCT`.
+#[derive(Debug)]
+pub struct SizeMismatch {
+    pub declared: u64,
+    pub actual: u64,
+}
+
+impl std::fmt::Display for SizeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "received {} bytes but {} were declared; blob discarded",
+            self.actual, self.declared
+        )
+    }
+}
+
+impl std::error::Error for SizeMismatch {}
+
+/// `POST /pause`: hold transfers for a demo. New receives queue instead of
+/// starting, and running ones stop reading progress, which stalls them
+/// where they are until `POST /resume`.
+async fn pause(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    shared.paused.send_replace(true);
+    info!("transfers paused");
+    Json(serde_json::json!({ "paused": true }))
+}
+
+/// `POST /resume`: let paused and queued transfers carry on.
+async fn resume(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    shared.paused.send_replace(false);
+    info!("transfers resumed");
+    Json(serde_json::json!({ "paused": false }))
+}
+
+/// `POST /cancel`: abort every receive that is running or queued behind
+/// `POST /pause`. Their downloads are dropped and the transfer fields reset;
+/// receives started afterwards are unaffected.
+async fn cancel(State(shared): State<Arc<NodeShared>>) -> impl IntoResponse {
+    let in_flight = shared.inflight.lock().await.transfers().len();
+    std::mem::take(&mut *shared.cancel.lock().await).cancel();
+    info!(in_flight, "receives cancelled");
+    Json(serde_json::json!({ "cancelled": in_flight }))
+}
+
+/// A receive was stopped by `POST /cancel`.
+#[derive(Debug)]
+pub struct TransferCancelled;
+
+impl std::fmt::Display for TransferCancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("transfer cancelled")
+    }
+}
+
+impl std::error::Error for TransferCancelled {}
+
+/// `receive_by_discovery` ran past `RECEIVE_DEADLINE_MS`.
+#[derive(Debug)]
+pub struct ReceiveTimeout(pub Duration);
+
+impl std::fmt::Display for ReceiveTimeout {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "receive deadline of {} ms exceeded", self.0.as_millis())
+    }
+}
+
+impl std::error::Error for ReceiveTimeout {}
+
+/// `receive_by_discovery` had nobody to ask: no peer addresses resolved, no
+/// provider named by the request, and no HTTP fallback to turn to. Carries
+/// how many peers are configured, since the fix differs: with none, configure
+/// some; with some, they just haven't resolved and `HTTP_BLOB_FALLBACK` is off.
+#[derive(Debug)]
+pub struct NoCandidates {
+    pub configured_peers: usize,
+}
+
+impl std::fmt::Display for NoCandidates {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.configured_peers {
+            0 => f.write_str(
+                "no peers known and no provider hint supplied; \
+                 configure PEER_HTTP_URLS or pass a ticket/provider_node_id",
+            ),
+            n => write!(
+                f,
+                "none of the {n} configured peers has a resolved address and no provider \
+                 hint supplied; HTTP_BLOB_FALLBACK is off, so pass a ticket/provider_node_id \
+                 or enable it"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for NoCandidates {}
+
+/// Response for a failed `/receive`: a misconfiguration ([`NoCandidates`])
+/// is a 503 that says so, a `POST /cancel` ([`TransferCancelled`]) a 409,
+/// anything else a bare 502.
+fn receive_error_response(e: &anyhow::Error) -> Response {
+    if e.downcast_ref::<NoCandidates>().is_some() {
+        (StatusCode::SERVICE_UNAVAILABLE, e.to_string()).into_response()
+    } else if e.downcast_ref::<TransferCancelled>().is_some() {
+        (StatusCode::CONFLICT, e.to_string()).into_response()
+    } else {
+        StatusCode::BAD_GATEWAY.into_response()
+    }
+}
+
+/// `/receive` success body.
+fn transfer_accepted(transfer_id: String) -> Response {
+    Json(serde_json::json!({ "transfer_id": transfer_id })).into_response()
+}
+
+/// A fresh id for one receive attempt.
+fn new_transfer_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
+
+impl NodeShared {
+    /// Discover a provider for the given hash among known peers and download.
+    ///
+    /// With `RECEIVE_DEADLINE_MS` set, the whole attempt (every provider,
+    /// round and fallback) is abandoned once the deadline passes, failing with
+    /// [`ReceiveTimeout`] and resetting the transfer fields. `POST /cancel`
+    /// does the same at any point, failing with [`TransferCancelled`].
+    ///
+    /// Returns the transfer id, which is also published in `/status`.
+    pub async fn receive_by_discovery(
+        &self,
+        hash: iroh_blobs::Hash,
+        filename: String,
+        content_type: String,
+        fallback: Option<NodeAddr>,
+    ) -> anyhow::Result<String> {
+        self.receive_from_providers(hash, filename, content_type, fallback.into_iter().collect())
+            .await
+    }
+
+    /// [`Self::receive_by_discovery`] with any number of providers the caller
+    /// already knows of, all tried alongside the known peers.
+    pub async fn receive_from_providers(
+        &self,
+        hash: iroh_blobs::Hash,
+        filename: String,
+        content_type: String,
+        providers: Vec<NodeAddr>,
+    ) -> anyhow::Result<String> {
+        self.track_receive(InFlight::new(
+            new_transfer_id(),
+            hash.to_string(),
+            filename,
+            content_type,
+            providers,
+        ))
+        .await
+    }
+
+    /// [`Self::receive_by_discovery`] in the background. Returns the transfer
+    /// id at once, so the caller can follow it at `/status?transfer=<id>`; it
+    /// is already the current transfer there, even while queued behind a pause.
+    #[cfg_attr(not(feature = "p2p_notify"), allow(dead_code))]
+    pub async fn spawn_receive(
+        self: &Arc<Self>,
+        hash: iroh_blobs::Hash,
+        filename: String,
+        content_type: String,
+        content_encoding: Option<String>,
+        declared_size: Option<u64>,
+        fallback: Option<NodeAddr>,
+    ) -> String {
+        let transfer = InFlight {
+            content_encoding,
+            declared_size,
+            ..InFlight::new(
+                new_transfer_id(),
+                hash.to_string(),
+                filename,
+                content_type,
+                fallback.into_iter().collect(),
+            )
+        };
+        let transfer_id = transfer.transfer_id.clone();
+        self.state.lock().await.transfer_id = Some(transfer_id.clone());
+        let shared = self.clone();
+        tokio::spawn(async move {
+            if let Err(e) = shared.track_receive(transfer).await {
+                error!(?e, %hash, "background receive failed");
+            }
+        });
+        transfer_id
+    }
+
+    /// Re-run every receive a previous process left in `inflight.json`, one
+    /// at a time. Chunks already in the store are not fetched again.
+    pub async fn resume_inflight(&self) {
+        let pending = self.inflight.lock().await.transfers().to_vec();
+        // Each waits its turn below; keep all their partials until then
+        let mut protected = Vec::new();
+        for transfer in &pending {
+            if let Ok(hash) = transfer.hash.parse::<iroh_blobs::Hash>() {
+                protected.extend(self.protect_blob(hash).await);
+            }
+        }
+        for transfer in pending {
+            info!(hash = %transfer.hash, "resuming interrupted receive");
+            // Tracked again below under its new transfer id
+            self.untrack_inflight(&transfer.transfer_id).await;
+            let res = self
+                .track_receive(InFlight {
+                    transfer_id: new_transfer_id(),
+                    resumed: true,
+                    ..transfer
+                })
+                .await;
+            if let Err(e) = res {
+                warn!(?e, "resumed receive failed");
+            }
+        }
+    }
+
+    /// Run a discovery receive while it is recorded in `inflight.json`.
+    async fn track_receive(&self, transfer: InFlight) -> anyhow::Result<String> {
+        let hash = match transfer.hash.parse::<iroh_blobs::Hash>() {
+            Ok(hash) => hash,
+            Err(e) => {
+                self.untrack_inflight(&transfer.transfer_id).await;
+                return Err(e.into());
+            }
+        };
+        let _protected = self.protect_blob(hash).await;
+        self.track_inflight(transfer.clone()).await;
+        let res = self.receive_tracked(&transfer, hash).await;
+        self.untrack_inflight(&transfer.transfer_id).await;
+        res.map(|()| transfer.transfer_id)
+    }
+
+    async fn receive_tracked(
+        &self,
+        transfer: &InFlight,
+        hash: iroh_blobs::Hash,
+    ) -> anyhow::Result<()> {
+        let transfer_id = transfer.transfer_id.as_str();
+        let cancelled = self.cancel.lock().await.child_token();
+        let started = std::sync::atomic::AtomicBool::new(false);
+        let run = async {
+            // Queued while paused; the deadline only starts once we run, and
+            // stands still while paused again
+            self.wait_unpaused().await;
+            started.store(true, std::sync::atomic::Ordering::Relaxed);
+            info!(%transfer_id, %hash, resumed = transfer.resumed, "receive started");
+            let attempt = self.discover_and_download(transfer, hash);
+            match self.receive_deadline {
+                Some(deadline) => match PausableBudget::new(self.paused.subscribe(), deadline)
+                    .run(attempt)
+                    .await
+                {
+                    Some(res) => res,
+                    None => {
+                        self.reset_transfer().await;
+                        Err(ReceiveTimeout(deadline).into())
+                    }
+                },
+                None => attempt.await,
+            }
+        };
+        // Dropping `run` drops the download streams with it
+        let res = tokio::select! {
+            res = run => res,
+            _ = cancelled.cancelled() => {
+                info!(%transfer_id, %hash, "receive cancelled");
+                // A receive still queued never made it into `state`
+                if started.load(std::sync::atomic::Ordering::Relaxed)
+                    && self.transfer_id().await.as_deref() == Some(transfer_id)
+                {
+                    self.reset_transfer().await;
+                }
+                Err(TransferCancelled.into())
+            }
+        };
+        if let Err(e) = &res {
+            self.fail_transfer(transfer_id, e).await;
+        }
+        res
+    }
+
+    async fn track_inflight(&self, transfer: InFlight) {
+        let mut inflight = self.inflight.lock().await;
+        inflight.insert(transfer);
+        self.save_inflight(&inflight).await;
+    }
+
+    async fn untrack_inflight(&self, transfer_id: &str) {
+        let mut inflight = self.inflight.lock().await;
+        inflight.remove(transfer_id);
+        self.save_inflight(&inflight).await;
+    }
+
+    async fn save_inflight(&self, inflight: &InFlightSet) {
+        if let Err(e) = inflight.save(&self.data_dir.join("inflight.json")).await {
+            warn!(?e, "failed to persist in-flight receives");
+        }
+    }
+
+    async fn discover_and_download(
+        &self,
+        transfer: &InFlight,
+        hash: iroh_blobs::Hash,
+    ) -> anyhow::Result<()> {
+        let InFlight {
+            transfer_id,
+            filename,
+            content_type,
+            content_encoding,
+            resumed,
+            stripe_span,
+            declared_size,
+            ..
+        } = transfer.clone();
+        let providers = transfer.providers();
+        let transfer_id = transfer_id.as_str();
+        let endpoint = self.endpoint()?;
+        self.begin_transfer(transfer_id, hash, &filename, &content_type)
+            .await;
+        {
+            let mut s = self.state.lock().await;
+            s.resumed = resumed;
+            s.incoming_encoding = content_encoding;
+            s.declared_size = declared_size.map(|size| (hash.to_string(), size));
+        }
+
+        let downloader = self.store.downloader(endpoint);
+        let mut round = 0;
+
+        // Build candidate node list from known peers plus any providers named
+        let mut candidate_addrs = self.known_peer_addrs().await;
+        for na in &providers {
+            if !candidate_addrs
+                .iter()
+                .any(|addr| addr.node_id == na.node_id)
+            {
+                candidate_addrs.push(na.clone());
+            }
+        }
+        if providers.is_empty() && self.active_search && self.peers_holding(hash).await.is_empty() {
+            if let Some((url, addr)) = self.search_peers(hash).await {
+                info!(%hash, %url, "active search found a provider");
+                self.record_peer_addr(&url, addr.clone(), "search").await;
+                candidate_addrs = vec![addr];
+            }
+        }
+
+        // Register addresses with the endpoint and extract node ids
+        let mut candidate_nodes: Vec<iroh_base::PublicKey> = Vec::new();
+        for addr in &candidate_addrs {
+            if let Err(e) = endpoint.add_node_addr(addr.clone()) {
+                warn!(?e, "failed to add node addr");
+            }
+            if !candidate_nodes.contains(&addr.node_id) {
+                candidate_nodes.push(addr.node_id);
+            }
+        }
+
+        let http_fallback = self.http_blob_fallback && !self.peers_http.is_empty();
+        if candidate_addrs.is_empty() && !http_fallback {
+            self.state.lock().await.download_strategy = None;
+            return Err(NoCandidates {
+                configured_peers: self.peers_http.len(),
+            }
+            .into());
+        }
+        if !candidate_nodes.is_empty() {
+            self.prefetch_size(&downloader, hash, candidate_nodes.clone())
+                .await;
+        }
+
+        if let ([provider], true) = (
+            candidate_nodes.as_slice(),
+            self.single_provider_parallelism > 1,
+        ) {
+            round += 1;
+            self.set_strategy("parallel", 1, round, Some(provider.to_string()))
+                .await;
+            match self
+                .attempt_parallel_download(hash, &filename, &content_type, *provider)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "parallel range download failed; falling back to sequential download"
+                    );
+                    self.restart_progress().await;
+                    self.pause_before_fallback().await;
+                }
+            }
+        } else if !candidate_nodes.is_empty() {
+            round += 1;
+            self.set_strategy(
+                self.download_mode.as_str(),
+                candidate_nodes.len(),
+                round,
+                None,
+            )
+            .await;
+            match self
+                .attempt_split_download(
+                    hash,
+                    &filename,
+                    &content_type,
+                    candidate_nodes.clone(),
+                    stripe_span,
+                )
+                .await
+            {
+                Ok(_) => return Ok(()),
+                Err(err) => {
+                    warn!(
+                        ?err,
+                        "stripe download failed; falling back to sequential download"
+                    );
+                    self.restart_progress().await;
+                    self.pause_before_fallback().await;
+                }
+            }
+        }
+
+        let mut last_err: Option<anyhow::Error> = None;
+        {
+            let scores = self.provider_scores.lock().await;
+            let latency = |node: &PublicKey| endpoint.remote_info(*node).and_then(|r| r.latency);
+            match self.seq_shuffle_seed {
+                Some(seed) => order_candidates(
+                    self.seq_order,
+                    &mut candidate_addrs,
+                    &scores,
+                    latency,
+                    &mut <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(seed),
+                ),
+                None => order_candidates(
+                    self.seq_order,
+                    &mut candidate_addrs,
+                    &scores,
+                    latency,
+                    &mut thread_rng(),
+                ),
+            }
+        }
+        if self.prefer_lan {
+            lan_first(&mut candidate_addrs, &lan_nodes(endpoint));
+        }
+        if let Some(max) = self.sequential_max_candidates {
+            if candidate_addrs.len() > max {
+                let skipped: Vec<String> = candidate_addrs
+                    .drain(max..)
+                    .map(|a| a.node_id.fmt_short().to_string())
+                    .collect();
+                info!(
+                    max,
+                    ?skipped,
+                    "SEQUENTIAL_MAX_CANDIDATES reached; not trying the rest"
+                );
+            }
+        }
+        let candidate_count = candidate_addrs.len();
+        for (i, addr) in candidate_addrs.iter().enumerate() {
+            let node_id = addr.node_id;
+            let mut last_provider: Option<String> = None;
+            round += 1;
+            self.set_strategy(
+                "sequential",
+                candidate_count,
+                round,
+                Some(node_id.to_string()),
+            )
+            .await;
+
+            // Start the download and obtain a progress stream
+            let started = std::time::Instant::now();
+            let dl = downloader.download(hash, Some(node_id));
+            let mut stream = match dl.stream().await {
+                Ok(s) => s,
+                Err(e) => {
+                    last_err = Some(e.into());
+                    self.note_provider_failure(node_id).await;
+                    continue;
+                }
+            };
+
+            let mut failed = false;
+            while let Some(item) = stream.next().await {
+                // Not polling holds the download where it is until resumed
+                self.wait_unpaused().await;
+                match item {
+                    DownloadProgessItem::Progress(recvd) => self.record_progress(recvd).await,
+                    DownloadProgessItem::TryProvider { id, .. } => {
+                        self.emit_provider_tried(id).await;
+                        last_provider = Some(id.to_string());
+                    }
+                    DownloadProgessItem::ProviderFailed { .. } => {}
+                    DownloadProgessItem::PartComplete { request } => {
+                        self.emit_part_complete(last_provider.clone(), request.as_ref())
+                            .await;
+                    }
+                    DownloadProgessItem::Error(e) => {
+                        failed = true;
+                        last_err = Some(e);
+                        break;
+                    }
+                    DownloadProgessItem::DownloadError => {
+                        failed = true;
+                        last_err = Some(anyhow::anyhow!("download error"));
+                        break;
+                    }
+                }
+            }
+
+            if failed {
+                self.note_provider_failure(node_id).await;
+                continue;
+            }
+            let recvd = self.state.lock().await.bytes_received;
+            self.note_provider_success(node_id, Some((recvd, started.elapsed())))
+                .await;
+            if let SequentialCompletion::AllComplete { providers } = self.sequential_completion {
+                let others: Vec<_> = candidate_addrs[i + 1..]
+                    .iter()
+                    .map(|a| a.node_id)
+                    .take(providers - 1)
+                    .collect();
+                self.confirm_with_providers(hash, &others).await?;
+            }
+
+            // Export the downloaded blob to our HTTP-served location
+            let _adopting = self.export_current(hash).await?;
+            let content_type = self.checked_content_type(&content_type).await;
+            {
+                let mut s = self.state.lock().await;
+                let total = s.bytes_total.unwrap_or(s.bytes_received);
+                s.bytes_total = Some(total);
+                s.bytes_received = total;
+                s.has_image = true;
+                s.current_filename = Some(filename.clone());
+                s.current_hash = Some(hash.to_string());
+                s.content_encoding = s.incoming_encoding.take();
+                s.set_content_type(&content_type);
+                s.progress = 100.0;
+                s.clear_rate();
+                s.download_strategy = None;
+                if let Some(provider) = last_provider {
+                    s.stripe_providers
+                        .entry(provider)
+                        .or_insert_with(|| vec!["all".to_string()]);
+                }
+                let self_id = endpoint.node_id().to_string();
+                let entry = s.stripe_providers.entry(self_id).or_default();
+                if !entry.iter().any(|v| v == "all") {
+                    entry.push("all".to_string());
+                }
+            }
+            self.complete_image(hash).await;
+            return Ok(());
+        }
+
+        if self.http_blob_fallback {
+            round += 1;
+            let urls = self.peers_holding(hash).await;
+            match self
+                .receive_via_http(hash, &filename, &content_type, urls, round)
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(err) => warn!(?err, "HTTP blob fallback failed"),
+            }
+        }
+
+        self.state.lock().await.download_strategy = None;
+        Err(last_err.unwrap_or_else(|| anyhow::anyhow!("no provider found for hash")))
+    }
+
+    /// Download `hash` purely over the HTTP plane (`DISTRIBUTION=http`).
+    ///
+    /// The announcing provider's URL is tried first, then any peer whose
+    /// `/status` reports the same hash.
+    pub async fn receive_by_http(
+        &self,
+        hash: iroh_blobs::Hash,
+        filename: String,
+        content_type: String,
+        content_encoding: Option<String>,
+        declared_size: Option<u64>,
+        provider_url: Option<String>,
+    ) -> anyhow::Result<String> {
+        self.wait_unpaused().await;
+        let transfer_id = new_transfer_id();
+        self.begin_transfer(&transfer_id, hash, &filename, &content_type)
+            .await;
+        {
+            let mut s = self.state.lock().await;
+            s.incoming_encoding = content_encoding;
+            s.declared_size = declared_size.map(|size| (hash.to_string(), size));
+        }
+        let mut urls: Vec<String> = provider_url.into_iter().collect();
+        for url in self.peers_holding(hash).await {
+            if !urls.contains(&url) {
+                urls.push(url);
+            }
+        }
+        let res = self
+            .receive_via_http(hash, &filename, &content_type, urls, 1)
+            .await;
+        if let Err(e) = &res {
+            self.state.lock().await.download_strategy = None;
+            self.fail_transfer(&transfer_id, e).await;
+        }
+        res.map(|()| transfer_id)
+    }
+
+    /// Peer URLs whose last `/status` reported holding `hash` (per `peer_images`).
+    async fn peers_holding(&self, hash: iroh_blobs::Hash) -> Vec<String> {
+        let wanted = hash.to_string();
+        let images = self.peer_images.lock().await;
+        images
+            .iter()
+            .filter(|(_, h)| **h == wanted)
+            .map(|(url, _)| url.clone())
+            .collect()
+    }
+
+    /// `ACTIVE_SEARCH`: ask every peer (`ACTIVE_SEARCH_FANOUT` at a time,
+    /// for at most `ACTIVE_SEARCH_TIMEOUT` overall) whether it holds `hash`,
+    /// as its current image (`/status`) or anywhere in its store (`/blobs`).
+    /// Returns the first holder that also reports a node id.
+    async fn search_peers(&self, hash: iroh_blobs::Hash) -> Option<(String, NodeAddr)> {
+        let probes: Vec<_> = self
+            .peers_http
+            .iter()
+            .map(|url| {
+                probe_peer(
+                    self.http_client.clone(),
+                    url.clone(),
+                    hash,
+                    self.peer_status_max_bytes,
+                )
+            })
+            .collect();
+        let mut found = futures_util::stream::iter(probes)
+            .buffer_unordered(ACTIVE_SEARCH_FANOUT)
+            .filter_map(futures_util::future::ready);
+        match tokio::time::timeout(ACTIVE_SEARCH_TIMEOUT, found.next()).await {
+            Ok(found) => found,
+            Err(_) => {
+                warn!(%hash, "active search timed out");
+                None
+            }
+        }
+    }
+
+    /// `path` on the peer at `url`. With `URL_SIGNING_KEY` set it is signed
+    /// for a minute with our key, which a peer that signs only accepts if it
+    /// has the same key; a peer without one ignores the signature.
+    fn peer_url(&self, url: &str, path: &str) -> String {
+        match &self.url_signer {
+            Some(signer) => format!("{url}{}", signer.signed_url(path, unix_now() + 60)),
+            None => format!("{url}{path}"),
+        }
+    }
+
+    /// Fetch `hash` over HTTP from `urls`: the HTTP-only distribution path and
+    /// the last resort when every P2P provider failed.
+    ///
+    /// Each URL is asked for `/blob/{hash}`, then `/image`, and only bytes that
+    /// hash to the requested value are accepted. Accepted bytes are added to the
+    /// store and exported just like a P2P download, so `has_image` keeps its
+    /// usual meaning.
+    async fn receive_via_http(
+        &self,
+        hash: iroh_blobs::Hash,
+        filename: &str,
+        content_type: &str,
+        urls: Vec<String>,
+        round: u32,
+    ) -> anyhow::Result<()> {
+        let wanted = hash.to_string();
+        if urls.is_empty() {
+            return Err(anyhow::anyhow!("no HTTP peer known to hold {}", wanted));
+        }
+
+        let client = &self.http_client;
+        let url_count = urls.len();
+        for url in urls {
+            self.set_strategy("http", url_count, round, Some(url.clone()))
+                .await;
+            self.events.emit(Event::ProviderTried {
+                transfer_id: self.transfer_id().await,
+                provider: url.clone(),
+            });
+            for target in [
+                self.peer_url(&url, &format!("/blob/{wanted}")),
+                self.peer_url(&url, "/image"),
+            ] {
+                let bytes = match fetch_http_bytes(client, &target).await {
+                    Ok(b) => b,
+                    Err(e) => {
+                        let refused = e
+                            .downcast_ref::<reqwest::Error>()
+                            .and_then(reqwest::Error::status)
+                            == Some(reqwest::StatusCode::FORBIDDEN);
+                        if refused {
+                            warn!(
+                                %target,
+                                "peer refused the HTTP blob fetch; it needs a signed URL, so both \
+                                 nodes must have the same URL_SIGNING_KEY"
+                            );
+                        } else {
+                            warn!(?e, %target, "HTTP blob fetch failed");
+                        }
+                        continue;
+                    }
+                };
+                if !self.verify_hash(bytes.clone(), hash).await? {
+                    warn!(%target, "HTTP blob fetch returned bytes with a different hash");
+                    continue;
+                }
+
+                self.store.add_slice(&bytes).await?;
+                let _adopting = self.export_current(hash).await?;
+                let content_type = self.checked_content_type(content_type).await;
+
+                let provider = match self.peers_addrs.lock().await.get(&url) {
+                    Some(addr) => addr.node_id.to_string(),
+                    None => url.clone(),
+                };
+                let total = bytes.len() as u64;
+                {
+                    let mut s = self.state.lock().await;
+                    s.bytes_received = total;
+                    s.bytes_total = Some(total);
+                    s.has_image = true;
+                    s.current_filename = Some(filename.to_string());
+                    s.current_hash = Some(hash.to_string());
+                    s.content_encoding = s.incoming_encoding.take();
+                    s.set_content_type(&content_type);
+                    s.progress = 100.0;
+                    s.clear_rate();
+                    s.download_strategy = None;
+                    s.stripe_providers
+                        .entry(provider)
+                        .or_insert_with(|| vec!["http".to_string()]);
+                }
+                self.events.emit(Event::Progress {
+                    transfer_id: self.transfer_id().await,
+                    bytes_received: total,
+                    bytes_total: Some(total),
+                });
+                info!(%target, size = total, "received blob via HTTP fallback");
+                self.complete_image(hash).await;
+                return Ok(());
+            }
+        }
+
+        Err(anyhow::anyhow!("no HTTP peer delivered {}", wanted))
+    }
+
+    async fn attempt_split_download(
+        &self,
+        hash: iroh_blobs::Hash,
+        filename: &str,
+        content_type: &str,
+        providers: Vec<iroh_base::PublicKey>,
+        stripe_span: Option<u64>,
+    ) -> anyhow::Result<()> {
+        if providers.is_empty() {
+            return Err(anyhow::anyhow!("no providers supplied for split download"));
+        }
+
+        let endpoint = self.endpoint()?;
+        let downloader = self.store.downloader(endpoint);
+        // Stripes go to providers in this order, so the best-scoring ones lead
+        let mut providers = providers;
+        providers.shuffle(&mut thread_rng());
+        self.provider_scores
+            .lock()
+            .await
+            .rank(&mut providers, |p| p.to_string());
+        let stream = match self.download_mode {
+            DownloadMode::Split => {
+                let opts = DownloadRequest::new(hash, providers.clone(), SplitStrategy::Split);
+                downloader.download_with_opts(opts).stream().await?.boxed()
+            }
+            DownloadMode::ChunkStripe => self
+                .chunk_stripe_stream(&downloader, hash, &providers, stripe_span)
+                .await?
+                .boxed(),
+        };
+        let mut stream = std::pin::pin!(stall_watchdog(
+            stream,
+            self.split_stall,
+            self.paused.subscribe(),
+        ));
+
+        let mut coverage = Coverage::default();
+        let mut contributors: HashSet<iroh_base::PublicKey> = HashSet::new();
+        let mut owner_for_request: HashMap<String, String> = HashMap::new();
+        let mut label_cache: HashMap<String, String> = HashMap::new();
+
+        while let Some(item) = stream.next().await {
+            // Not polling holds the download where it is until resumed
+            self.wait_unpaused().await;
+            match item {
+                DownloadProgessItem::Progress(recvd) => self.record_progress(recvd).await,
+                DownloadProgessItem::TryProvider { id, request } => {
+                    self.emit_provider_tried(id).await;
+                    if let Some(strategy) = self.state.lock().await.download_strategy.as_mut() {
+                        strategy.current_provider = Some(id.to_string());
+                    }
+                    contributors.insert(id);
+                    let key = request_key(request.as_ref());
+                    owner_for_request.insert(key.clone(), id.to_string());
+                    label_cache
+                        .entry(key)
+                        .or_insert_with(|| describe_request(request.as_ref()));
+                }
+                DownloadProgessItem::ProviderFailed { id, request } => {
+                    contributors.remove(&id);
+                    self.note_provider_failure(id).await;
+                    owner_for_request.remove(&request_key(request.as_ref()));
+                }
+                DownloadProgessItem::PartComplete { request } => {
+                    coverage.record(request.as_ref());
+                    let key = request_key(request.as_ref());
+                    self.emit_part_complete(owner_for_request.get(&key).cloned(), request.as_ref())
+                        .await;
+                    if let Some(provider) = owner_for_request.get(&key).cloned() {
+                        let label = label_cache
+                            .entry(key)
+                            .or_insert_with(|| describe_request(request.as_ref()))
+                            .clone();
+                        let mut s = self.state.lock().await;
+                        let entry = s.stripe_providers.entry(provider).or_default();
+                        if !entry.iter().any(|v| v == &label) {
+                            entry.push(label);
+                        }
+                    }
+                }
+                DownloadProgessItem::Error(e) => return Err(e),
+                DownloadProgessItem::DownloadError => {
+                    return Err(anyhow::anyhow!("download error"));
+                }
+            }
+        }
+
+        self.fill_coverage_gaps(&downloader, hash, &coverage, providers)
+            .await?;
+
+        // Stripes share the wall clock, so only reliability is credited here
+        for id in contributors {
+            self.note_provider_success(id, None).await;
+        }
+
+        self.adopt_striped(hash, filename, content_type).await
+    }
+
+    /// Start `DOWNLOAD_MODE=chunk_stripe`: the blob, sized up front, as
+    /// shuffled stripes dealt round-robin to `providers`. Each stripe is its
+    /// own request led by its assigned provider, with the others behind it
+    /// should that one fail. Stripes are `stripe_span` chunks when the
+    /// request named a size.
+    async fn chunk_stripe_stream(
+        &self,
+        downloader: &iroh_blobs::api::downloader::Downloader,
+        hash: iroh_blobs::Hash,
+        providers: &[iroh_base::PublicKey],
+        stripe_span: Option<u64>,
+    ) -> anyhow::Result<impl futures_util::Stream<Item = DownloadProgessItem>> {
+        let size = self
+            .prefetch_size(downloader, hash, providers.to_vec())
+            .await
+            .ok_or_else(|| anyhow::anyhow!("blob size unknown; can't plan stripes"))?;
+        let total_chunks = size.div_ceil(1024);
+        let plan = chunk_strategy::stripe_plan(
+            hash,
+            total_chunks,
+            providers.len(),
+            stripe_span,
+            &mut thread_rng(),
+        );
+
+        let mut parts = Vec::with_capacity(plan.len());
+        for (i, req) in plan.into_iter().enumerate() {
+            let mut order = providers.to_vec();
+            order.rotate_left(i % providers.len());
+            let opts = DownloadRequest::new(req, order, SplitStrategy::None);
+            parts.push(downloader.download_with_opts(opts).stream().await?);
+        }
+        Ok(merged_progress(parts))
+    }
+
+    /// Fetch `hash` from a single provider as `SINGLE_PROVIDER_PARALLELISM`
+    /// concurrent range requests, each on its own stream of the connection,
+    /// so a high-latency link has several requests in flight at once.
+    async fn attempt_parallel_download(
+        &self,
+        hash: iroh_blobs::Hash,
+        filename: &str,
+        content_type: &str,
+        provider: iroh_base::PublicKey,
+    ) -> anyhow::Result<()> {
+        let endpoint = self.endpoint()?;
+        let downloader = self.store.downloader(endpoint);
+        let request = |req: GetRequest| {
+            downloader.download_with_opts(DownloadRequest::new(
+                req,
+                vec![provider],
+                SplitStrategy::None,
+            ))
+        };
+
+        let size = self
+            .prefetch_size(&downloader, hash, vec![provider])
+            .await
+            .ok_or_else(|| anyhow::anyhow!("blob size unknown; can't plan ranges"))?;
+        let total_chunks = size.div_ceil(1024);
+        let span = total_chunks.div_ceil(self.single_provider_parallelism as u64);
+        let plan = chunk_strategy::sequential_get_requests(hash, total_chunks, span);
+
+        let mut parts = Vec::with_capacity(plan.len());
+        for (i, req) in plan.into_iter().enumerate() {
+            let stream = request(req).stream().await?;
+            parts.push(stream.map(move |item| (i, item)));
+        }
+        let mut received = vec![0; parts.len()];
+        let mut stream = futures_util::stream::select_all(parts);
+        let mut coverage = Coverage::default();
+        while let Some((i, item)) = stream.next().await {
+            // Not polling holds the download where it is until resumed
+            self.wait_unpaused().await;
+            match item {
+                DownloadProgessItem::Progress(recvd) => {
+                    received[i] = recvd;
+                    self.record_progress(received.iter().sum()).await;
+                }
+                DownloadProgessItem::TryProvider { id, .. } => self.emit_provider_tried(id).await,
+                DownloadProgessItem::ProviderFailed { .. } => {}
+                DownloadProgessItem::PartComplete { request } => {
+                    coverage.record(request.as_ref());
+                    self.emit_part_complete(Some(provider.to_string()), request.as_ref())
+                        .await;
+                    let label = describe_request(request.as_ref());
+                    self.state
+                        .lock()
+                        .await
+                        .stripe_providers
+                        .entry(provider.to_string())
+                        .or_default()
+                        .push(label);
+                }
+                DownloadProgessItem::Error(e) => return Err(e),
+                DownloadProgessItem::DownloadError => {
+                    return Err(anyhow::anyhow!("download error"));
+                }
+            }
+        }
+
+        self.fill_coverage_gaps(&downloader, hash, &coverage, vec![provider])
+            .await?;
+        self.note_provider_success(provider, None).await;
+        self.adopt_striped(hash, filename, content_type).await
+    }
+
+    /// Export a blob a split or parallel download completed and make it the
+    /// current image.
+    async fn adopt_striped(
+        &self,
+        hash: iroh_blobs::Hash,
+        filename: &str,
+        content_type: &str,
+    ) -> anyhow::Result<()> {
+        let self_id = self.endpoint()?.node_id().to_string();
+        let _adopting = self.export_current(hash).await?;
+        let content_type = self.checked_content_type(content_type).await;
+        {
+            let mut s = self.state.lock().await;
+            let total = s.bytes_total.unwrap_or(s.bytes_received);
+            s.bytes_total = Some(total);
+            s.bytes_received = total;
+            s.has_image = true;
+            s.current_filename = Some(filename.to_string());
+            s.current_hash = Some(hash.to_string());
+            s.content_encoding = s.incoming_encoding.take();
+            s.set_content_type(&content_type);
+            s.progress = 100.0;
+            s.clear_rate();
+            s.download_strategy = None;
+            let entry = s.stripe_providers.entry(self_id).or_default();
+            if !entry.iter().any(|v| v == "all") {
+                entry.push("all".to_string());
+            }
+        }
+        self.complete_image(hash).await;
+        Ok(())
+    }
+
+    /// Learn the size of `hash` before its body arrives by fetching the last
+    /// chunk, which proves the size, and publish it as `bytes_total` so
+    /// progress is a real percentage from the first byte. Best effort.
+    async fn prefetch_size(
+        &self,
+        downloader: &iroh_blobs::api::downloader::Downloader,
+        hash: iroh_blobs::Hash,
+        providers: Vec<iroh_base::PublicKey>,
+    ) -> Option<u64> {
+        let request = GetRequest::blob_ranges(hash, ChunkRanges::last_chunk());
+        if let Err(e) = downloader
+            .download_with_opts(DownloadRequest::new(
+                request,
+                providers,
+                SplitStrategy::None,
+            ))
+            .await
+        {
+            warn!(?e, %hash, "could not learn blob size up front");
+        }
+        let size = match self.store.blobs().status(hash).await.ok()? {
+            BlobStatus::Complete { size } | BlobStatus::Partial { size: Some(size) } => size,
+            _ => return None,
+        };
+        self.state.lock().await.bytes_total = Some(size);
+        Some(size)
+    }
+
+    /// Forget the progress of a strategy that failed, before trying the next.
+    /// A `bytes_total` already learned is verified, so it stays.
+    async fn restart_progress(&self) {
+        let mut s = self.state.lock().await;
+        s.bytes_received = 0;
+        s.progress = 0.0;
+        s.clear_rate();
+        s.stripe_providers.clear();
+    }
+
+    /// Make sure a split download really delivered the whole blob.
+    ///
+    /// If the store still has the blob as partial, request the ranges no
+    /// completed part covered from the same providers, then require the blob
+    /// to be complete.
+    async fn fill_coverage_gaps(
+        &self,
+        downloader: &iroh_blobs::api::downloader::Downloader,
+        hash: iroh_blobs::Hash,
+        coverage: &Coverage,
+        providers: Vec<iroh_base::PublicKey>,
+    ) -> anyhow::Result<()> {
+        let missing = match self.store.blobs().status(hash).await? {
+            BlobStatus::Complete { .. } => return Ok(()),
+            BlobStatus::Partial { size: Some(size) } => Some(coverage.missing(size.div_ceil(1024))),
+            // Size not verified yet, so the gaps can't be computed
+            _ => None,
+        };
+        warn!(%hash, ?missing, "split download left gaps; fetching missing ranges");
+        // Without known gaps (or with full coverage on paper) ask for the whole
+        // blob; the downloader skips whatever is already local
+        let request = missing
+            .and_then(|m| chunk_strategy::follow_up_request(hash, m))
+            .unwrap_or_else(|| GetRequest::blob(hash));
+        downloader
+            .download_with_opts(DownloadRequest::new(
+                request,
+                providers,
+                SplitStrategy::None,
+            ))
+            .await?;
+        match self.store.blobs().status(hash).await? {
+            BlobStatus::Complete { .. } => Ok(()),
+            other => anyhow::bail!("blob still incomplete after gap fill ({other:?})"),
+        }
+    }
+
+    async fn note_provider_success(
+        &self,
+        provider: iroh_base::PublicKey,
+        timed: Option<(u64, Duration)>,
+    ) {
+        let mut scores = self.provider_scores.lock().await;
+        scores.record_success(&provider.to_string(), timed);
+        self.save_provider_scores(&scores).await;
+    }
+
+    async fn note_provider_failure(&self, provider: iroh_base::PublicKey) {
+        let mut scores = self.provider_scores.lock().await;
+        scores.record_failure(&provider.to_string());
+        self.save_provider_scores(&scores).await;
+    }
+
+    async fn save_provider_scores(&self, scores: &ProviderScores) {
+        if let Err(e) = scores
+            .save(&self.data_dir.join("provider_scores.json"))
+            .await
+        {
+            warn!(?e, "failed to persist provider scores");
+        }
+    }
+
+    /// Check that `bytes` hash to `expected`.
+    ///
+    /// Hashing a large blob is CPU-bound, so it runs on the blocking pool to
+    /// keep the reactor (and `/status`) responsive, with at most
+    /// `VERIFY_CONCURRENCY` verifications in flight.
+    async fn verify_hash(
+        &self,
+        bytes: bytes::Bytes,
+        expected: iroh_blobs::Hash,
+    ) -> anyhow::Result<bool> {
+        let _permit = self.verify_slots.acquire().await?;
+        let started = std::time::Instant::now();
+        let len = bytes.len();
+        let actual = tokio::task::spawn_blocking(move || iroh_blobs::Hash::new(&bytes)).await?;
+        info!(
+            %expected,
+            bytes = len,
+            elapsed_ms = started.elapsed().as_millis() as u64,
+            "verified blob hash"
+        );
+        Ok(actual == expected)
+    }
+
+    /// Read `hash` back from the store and check it is exactly `original`
+    /// and still hashes to `hash`, catching store or disk faults at ingest.
+    async fn verify_stored(&self, hash: iroh_blobs::Hash, original: &[u8]) -> anyhow::Result<()> {
+        let stored = self.store.blobs().get_bytes(hash).await?;
+        anyhow::ensure!(
+            stored[..] == *original,
+            "store returned {} bytes that differ from the {} uploaded",
+            stored.len(),
+            original.len()
+        );
+        anyhow::ensure!(
+            self.verify_hash(stored, hash).await?,
+            "stored bytes don't hash to {hash}"
+        );
+        Ok(())
+    }
+
+    /// The iroh endpoint, or an error when running with `DISTRIBUTION=http`.
+    pub fn endpoint(&self) -> anyhow::Result<&Endpoint> {
+        self.endpoint
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("P2P is disabled (DISTRIBUTION=http)"))
+    }
+
+    /// `SEQUENTIAL_COMPLETION=all`: fetch `hash` again from each of
+    /// `providers` into a scratch in-memory store and check what arrived.
+    ///
+    /// Transfers are verified against the hash as they stream, so a provider
+    /// holding different bytes fails its fetch; any provider that can't
+    /// deliver the blob is reported as a discrepancy.
+    async fn confirm_with_providers(
+        &self,
+        hash: iroh_blobs::Hash,
+        providers: &[iroh_base::PublicKey],
+    ) -> anyhow::Result<()> {
+        let endpoint = self.endpoint()?;
+        if providers.is_empty() {
+            warn!(%hash, "SEQUENTIAL_COMPLETION=all but no other provider left to confirm with");
+        }
+        for &provider in providers {
+            let scratch = iroh_blobs::store::mem::MemStore::new();
+            let delivered = async {
+                scratch
+                    .downloader(endpoint)
+                    .download(hash, Some(provider))
+                    .await?;
+                let bytes = scratch.blobs().get_bytes(hash).await?;
+                anyhow::Ok(iroh_blobs::Hash::new(&bytes))
+            }
+            .await;
+            match delivered {
+                Ok(got) if got == hash => {
+                    info!(%hash, provider = %provider.fmt_short(), "provider confirmed content")
+                }
+                Ok(got) => anyhow::bail!(
+                    "provider {} delivered {got} for {hash}",
+                    provider.fmt_short()
+                ),
+                Err(e) => anyhow::bail!(
+                    "provider {} did not deliver matching content for {hash}: {e}",
+                    provider.fmt_short()
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reset the HTTP-visible transfer fields for a new download of `hash`.
+    ///
+    /// With `KEEP_IMAGE_DURING_RECEIVE`, an image we already have keeps being
+    /// served, along with its metadata, until the new one is exported over it.
+    async fn begin_transfer(
+        &self,
+        transfer_id: &str,
+        hash: iroh_blobs::Hash,
+        filename: &str,
+        content_type: &str,
+    ) {
+        let mut s = self.state.lock().await;
+        s.transfer_id = Some(transfer_id.to_string());
+        s.incoming_encoding = None;
+        s.declared_size = None;
+        if !(self.keep_image_during_receive && s.has_image) {
+            s.current_filename = Some(filename.to_string());
+            s.content_type = Some(content_type.to_string());
+            s.content_encoding = None;
+            s.current_hash = Some(hash.to_string());
+            s.has_image = false;
+            s.phash = None;
+        }
+        s.bytes_received = 0;
+        s.bytes_total = None;
+        s.progress = 0.0;
+        s.clear_rate();
+        s.stripe_providers.clear();
+        s.download_strategy = None;
+        s.last_error = None;
+        s.resumed = false;
+        s.progress_estimated = false;
+        drop(s);
+        self.events.emit(Event::TransferStarted {
+            transfer_id: transfer_id.to_string(),
+            hash: hash.to_string(),
+            filename: filename.to_string(),
+        });
+    }
+
+    /// Record a failed receive in `/status` and on the event stream.
+    async fn fail_transfer(&self, transfer_id: &str, err: &anyhow::Error) {
+        {
+            let mut s = self.state.lock().await;
+            s.last_error = Some(err.to_string());
+            s.declared_size = None;
+            s.clear_rate();
+        }
+        self.events.emit(Event::Error {
+            transfer_id: Some(transfer_id.to_string()),
+            message: err.to_string(),
+        });
+    }
+
+    /// Id of the receive currently reflected in `state`, if any.
+    async fn transfer_id(&self) -> Option<String> {
+        self.state.lock().await.transfer_id.clone()
+    }
+
+    /// Apply a downloader progress report to `state` and publish it.
+    ///
+    /// Before the exact size is known, a size peers reported for the same
+    /// hash stands in so progress moves; see [`estimated_progress`]. Speed and
+    /// ETA come from the recent reports in `rate_window`.
+    async fn record_progress(&self, recvd: u64) {
+        let now = std::time::Instant::now();
+        let event = {
+            let mut s = self.state.lock().await;
+            s.bytes_received = recvd;
+            let mut expected = None;
+            if let Some(t) = s.bytes_total {
+                if t > 0 {
+                    s.progress = (recvd as f32 / t as f32) * 100.0;
+                    s.progress_estimated = false;
+                }
+                expected = Some(t);
+            } else if let Some(hash) = s.current_hash.clone() {
+                let declared = s.declared_size.clone().filter(|(h, _)| *h == hash);
+                let estimate = match declared {
+                    Some((_, size)) => Some(size),
+                    None => self.peer_image_size(&hash).await,
+                };
+                if let Some(estimate) = estimate {
+                    s.progress = estimated_progress(recvd, estimate);
+                    s.progress_estimated = true;
+                    expected = Some(estimate);
+                }
+            }
+            s.bytes_per_sec = s.rate_window.record(now, recvd);
+            s.eta_seconds = expected
+                .and_then(|t| transfer_rate::eta_seconds(t.saturating_sub(recvd), s.bytes_per_sec));
+            Event::Progress {
+                transfer_id: s.transfer_id.clone(),
+                bytes_received: recvd,
+                bytes_total: s.bytes_total,
+            }
+        };
+        self.events.emit(event);
+    }
+
+    async fn emit_provider_tried(&self, provider: iroh_base::PublicKey) {
+        self.events.emit(Event::ProviderTried {
+            transfer_id: self.transfer_id().await,
+            provider: provider.to_string(),
+        });
+    }
+
+    async fn emit_part_complete(&self, provider: Option<String>, request: &GetRequest) {
+        self.events.emit(Event::PartComplete {
+            transfer_id: self.transfer_id().await,
+            provider,
+            range: describe_request(request),
+        });
+    }
+
+    /// Drop the progress of an abandoned transfer.
+    async fn reset_transfer(&self) {
+        let mut s = self.state.lock().await;
+        if !self.keep_image_during_receive {
+            s.has_image = false;
+        }
+        s.bytes_received = 0;
+        s.bytes_total = None;
+        s.progress = 0.0;
+        s.clear_rate();
+        s.stripe_providers.clear();
+        s.download_strategy = None;
+    }
+
+    /// Publish the strategy the current transfer is using to `/status`.
+    async fn set_strategy(
+        &self,
+        mode: &str,
+        providers: usize,
+        round: u32,
+        current_provider: Option<String>,
+    ) {
+        self.state.lock().await.download_strategy = Some(DownloadStrategy {
+            mode: mode.to_string(),
+            providers,
+            round,
+            current_provider,
+        });
+    }
+
+    pub async fn finish_download(
+        &self,
+        bytes: Vec<u8>,
+        filename: &str,
+        content_type: &str,
+    ) -> anyhow::Result<()> {
+        let _adopting = self.adopting().await;
+        self.write_current(&bytes).await?;
+        {
+            let mut s = self.state.lock().await;
+            s.has_image = true;
+            s.current_filename = Some(filename.to_string());
+            s.progress = 100.0;
+            s.clear_rate();
+        }
+        self.set_content_type(content_type).await;
+        Ok(())
+    }
+
+    /// Download using the ticket and update progress fields as chunks arrive
+    pub async fn receive_with_progress(
+        &self,
+        ticket: iroh_blobs::ticket::BlobTicket,
+        filename: String,
+        content_type: String,
+    ) -> anyhow::Result<()> {
+        self.wait_unpaused().await;
+        let endpoint = self.endpoint()?;
+        let hash = ticket.hash();
+        let node_addr: NodeAddr = ticket.node_addr().clone();
+        let _protected = self.protect_blob(hash).await;
+
+        {
+            let mut s = self.state.lock().await;
+            s.current_filename = Some(filename.clone());
+            s.content_type = Some(content_type.clone());
+            s.content_encoding = None;
+            s.current_hash = Some(hash.to_string());
+            s.has_image = false;
+            s.bytes_received = 0;
+            s.bytes_total = None; // unknown until we know
+            s.progress = 0.0;
+            s.clear_rate();
+            s.stripe_providers.clear();
+            s.download_strategy = Some(DownloadStrategy {
+                mode: "sequential".to_string(),
+                providers: 1,
+                round: 1,
+                current_provider: Some(node_addr.node_id.to_string()),
+            });
+        }
+
+        // Start the download via the store downloader (iroh-blobs 0.93) and stream progress updates
+        let downloader = self.store.downloader(endpoint);
+        self.prefetch_size(&downloader, hash, vec![node_addr.node_id])
+            .await;
+        let dl = downloader.download(hash, Some(node_addr.node_id));
+        let mut stream = match dl.stream().await {
+            Ok(s) => s,
+            Err(e) => {
+                return Err(e.into());
+            }
+        };
+
+        while let Some(item) = stream.next().await {
+            // Not polling holds the download where it is until resumed
+            self.wait_unpaused().await;
+            match item {
+                DownloadProgessItem::Progress(recvd) => self.record_progress(recvd).await,
+                DownloadProgessItem::TryProvider { .. } => {}
+                DownloadProgessItem::ProviderFailed { .. } => {}
+                DownloadProgessItem::PartComplete { .. } => {}
+                DownloadProgessItem::Error(e) => {
+                    return Err(e);
+                }
+                DownloadProgessItem::DownloadError => {
+                    return Err(anyhow::anyhow!("download error"));
+                }
+            }
+        }
+
+        // Export the downloaded blob to our HTTP-served location
+        let _adopting = match self.export_current(hash).await {
+            Ok(adopting) => adopting,
+            Err(e) => {
+                self.state.lock().await.last_error = Some(format!("{e:#}"));
+                return Err(e);
+            }
+        };
+        let content_type = self.checked_content_type(&content_type).await;
+        // Mark as complete in state
+        {
+            let mut s = self.state.lock().await;
+            let total = s.bytes_total.unwrap_or(s.bytes_received);
+            s.bytes_total = Some(total);
+            s.bytes_received = total;
+            s.has_image = true;
+            s.current_filename = Some(filename);
+            s.current_hash = Some(hash.to_string());
+            s.content_encoding = None;
+            s.set_content_type(&content_type);
+            s.progress = 100.0;
+            s.clear_rate();
+            s.download_strategy = None;
+            s.stripe_providers
+                .entry(node_addr.node_id.to_string())
+                .or_insert_with(|| vec!["all".to_string()]);
+            let self_id = endpoint.node_id().to_string();
+            let entry = s.stripe_providers.entry(self_id).or_default();
+            if !entry.iter().any(|v| v == "all") {
+                entry.push("all".to_string());
+            }
+        }
+        self.complete_image(hash).await;
+        Ok(())
+    }
+
+    /// Seed `peers_addrs` (and the endpoint's address book) from the static
+    /// `PEER_ADDRS_FILE`, for topologies without working discovery.
+    ///
+    /// Returns `(loaded, skipped)` entry counts.
+    async fn load_peer_addrs_file(&self) -> anyhow::Result<(usize, usize)> {
+        let path = self
+            .peer_addrs_file
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("PEER_ADDRS_FILE is not configured"))?;
+        let (entries, skipped) = peer_file::parse(&fs::read(path).await?)?;
+        for entry in &entries {
+            let addr = entry.node_addr();
+            if let Some(ep) = &self.endpoint {
+                if let Err(e) = ep.add_node_addr(addr.clone()) {
+                    warn!(?e, node_id = %addr.node_id, "failed to add static peer addr");
+                }
+            }
+            self.record_peer_addr(&entry.key(), addr, "peer_file").await;
+        }
+        info!(path = %path.display(), loaded = entries.len(), skipped, "loaded static peer addresses");
+        Ok((entries.len(), skipped))
+    }
+
+    /// What `GET /status` reports.
+    async fn status_snapshot(&self) -> NodeState {
+        let mut state = self.state.lock().await.clone();
+        state.sequential_completion = self.sequential_completion;
+        state.paused = *self.paused.borrow();
+        state.demo_mode = self.demo_mode;
+        state.signed_urls = self.url_signer.is_some();
+        state.seq_order = self.seq_order;
+        state.egress_bps = self.egress.as_ref().map(|limiter| limiter.rate());
+        state.node_addr_full = self
+            .endpoint
+            .as_ref()
+            .and_then(|ep| ep.node_addr().get())
+            .map(|a| self.advertised(a));
+        state
+    }
+
+    /// Lamport rule for an incoming notify: adopt it, raising our clock to
+    /// `version` by `origin`, only if it is newer than the image we have
+    /// (see [`LogicalClock::is_newer`]). Call it once the notify is accepted,
+    /// so a rejected one can't move the clock.
+    async fn adopt_version(&self, version: u64, origin: &str) -> bool {
+        let mut s = self.state.lock().await;
+        if !s.clock().is_newer(version, origin) {
+            return false;
+        }
+        s.set_clock(LogicalClock {
+            version,
+            origin: origin.to_string(),
+        });
+        drop(s);
+        self.save_clock().await;
+        true
+    }
+
+    /// Write the current clock to `clock.json`. The state lock is only held
+    /// to read it, and the read happens under `clock_file`, so racing saves
+    /// can't leave an older clock on disk than the last one set.
+    async fn save_clock(&self) {
+        let _file = self.clock_file.lock().await;
+        let clock = self.state.lock().await.clock();
+        if let Err(e) = clock.save(&self.data_dir.join("clock.json")).await {
+            warn!(?e, "failed to persist the logical clock");
+        }
+    }
+
+    /// Wait out `FALLBACK_DELAY_MS`, jittered, before the sequential
+    /// fallback, so a transient network problem has a moment to clear. The
+    /// jitter follows `SEQ_SHUFFLE_SEED` when set.
+    async fn pause_before_fallback(&self) {
+        let delay = match self.seq_shuffle_seed {
+            Some(seed) => jittered(
+                self.fallback_delay,
+                &mut <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(seed),
+            ),
+            None => jittered(self.fallback_delay, &mut thread_rng()),
+        };
+        if delay.is_zero() {
+            return;
+        }
+        info!(
+            delay_ms = delay.as_millis() as u64,
+            "pausing before sequential fallback"
+        );
+        sleep(delay).await;
+    }
+
+    /// Return once transfers aren't paused (at once if they aren't).
+    async fn wait_unpaused(&self) {
+        let mut rx = self.paused.subscribe();
+        if *rx.borrow() {
+            info!("transfers paused; waiting for POST /resume");
+        }
+        // Err only if the sender is gone, which it can't be while we hold self
+        let _ = rx.wait_for(|paused| !paused).await;
+    }
+
+    /// Our address as handed to peers: with `BIND_ADDRS` set, only the listed
+    /// IPs remain among the direct addresses.
+    fn advertised(&self, addr: NodeAddr) -> NodeAddr {
+        match &self.bind_addrs {
+            Some(allowed) => allowed.filter(addr),
+            None => addr,
+        }
+    }
+
+    /// Store `addr` as the address of the peer at `key`, reporting it on the
+    /// event feed when it is new or names a different node.
+    async fn record_peer_addr(&self, key: &str, addr: NodeAddr, source: &str) {
+        let node_id = addr.node_id;
+        let previous = self.peers_addrs.lock().await.insert(key.to_string(), addr);
+        if previous.is_some_and(|p| p.node_id == node_id) {
+            return;
+        }
+        info!(url = %key, %node_id, source, "peer resolved");
+        self.events.emit(Event::PeerResolved {
+            url: key.to_string(),
+            node_id: node_id.to_string(),
+            source: source.to_string(),
+        });
+    }
+
+    /// Drop addresses learned from a peer's `/status` that haven't been
+    /// re-resolved within `PEER_ADDR_TTL_SECS`. Static entries never expire.
+    async fn expire_peer_addrs(&self, ttl: Duration) {
+        let stale: Vec<String> = {
+            let mut seen = self.peer_addr_seen.lock().await;
+            let stale: Vec<String> = seen
+                .iter()
+                .filter(|(_, at)| at.elapsed() > ttl)
+                .map(|(url, _)| url.clone())
+                .collect();
+            for url in &stale {
+                seen.remove(url);
+            }
+            stale
+        };
+        for url in stale {
+            let Some(addr) = self.peers_addrs.lock().await.remove(&url) else {
+                continue;
+            };
+            info!(%url, node_id = %addr.node_id, "peer lost: address expired");
+            self.events.emit(Event::PeerLost {
+                url,
+                node_id: addr.node_id.to_string(),
+                reason: "ttl".to_string(),
+            });
+        }
+    }
+
+    /// Publish our current addresses to the endpoint's discovery services and
+    /// refresh peer addresses from their `/status`.
+    async fn announce(&self) -> AnnounceResp {
+        let mut published = false;
+        if let Some(ep) = &self.endpoint {
+            match (
+                ep.discovery(),
+                ep.node_addr().get().map(|a| self.advertised(a)),
+            ) {
+                (Some(discovery), Some(addr)) => {
+                    let data = iroh::discovery::NodeData::new(
+                        addr.relay_url.clone(),
+                        addr.direct_addresses.clone(),
+                    );
+                    discovery.publish(&data);
+                    published = true;
+                }
+                (None, _) => warn!("announce: endpoint has no discovery service"),
+                (_, None) => warn!("announce: no local addresses known yet"),
+            }
+        }
+        let peers_resolved = refresh_peer_addrs(self, &self.http_client).await;
+        info!(published, peers_resolved, "announced");
+        AnnounceResp {
+            published,
+            peers_resolved,
+        }
+    }
+
+    /// Take `adopt_lock`: no slide can replace `current.img` or its state
+    /// until the guard drops. Whoever changes the current image holds it
+    /// from writing the file until `complete_image` is done.
+    async fn adopting(&self) -> tokio::sync::OwnedMutexGuard<()> {
+        self.adopt_lock.clone().lock_owned().await
+    }
+
+    /// Export `hash` to `current.img`, retrying transient failures up to
+    /// `export_retries` extra times. Callers only mark the image as present
+    /// once this succeeds, and hold the returned `adopting` guard until
+    /// they have.
+    async fn export_current(
+        &self,
+        hash: iroh_blobs::Hash,
+    ) -> anyhow::Result<tokio::sync::OwnedMutexGuard<()>> {
+        self.check_declared_size(hash).await?;
+        let adopting = self.adopting().await;
+        let out_path = self.data_dir.join("current.img");
+        let staged = self.data_dir.join("current.img.part");
+        let res = with_retries(self.export_retries, EXPORT_BACKOFF, || async {
+            self.store.blobs().export(hash, &staged).await?;
+            // An export that stopped early must not replace current.img
+            self.verify_file(&staged, hash).await?;
+            fs::rename(&staged, &out_path).await?;
+            Ok(())
+        })
+        .await;
+        if let Err(e) = &res {
+            error!(?e, %hash, "exporting received blob failed; image stays unset");
+            let _ = fs::remove_file(&staged).await;
+        }
+        res.map(|()| adopting)
+            .map_err(|e| e.context(format!("exporting {hash} to {}", out_path.display())))
+    }
+
+    /// Check that the file at `path` hashes to `expected`.
+    async fn verify_file(
+        &self,
+        path: &std::path::Path,
+        expected: iroh_blobs::Hash,
+    ) -> anyhow::Result<()> {
+        let bytes = bytes::Bytes::from(fs::read(path).await?);
+        let len = bytes.len();
+        anyhow::ensure!(
+            self.verify_hash(bytes, expected).await?,
+            "{} ({len} bytes) doesn't hash to {expected}",
+            path.display()
+        );
+        Ok(())
+    }
+
+    /// The size some peer last reported for `hash`.
+    async fn peer_image_size(&self, hash: &str) -> Option<u64> {
+        self.peer_image_sizes
+            .lock()
+            .await
+            .values()
+            .find(|(h, _)| h == hash)
+            .map(|&(_, size)| size)
+    }
+
+    /// With `SIZE_MISMATCH_TOLERANCE_PCT` set, fail a received blob whose
+    /// size is further from the size declared for it (by the transfer's
+    /// notify, else a peer's `/status`) than the tolerance, and drop it from
+    /// the store unless it is the current image or pinned.
+    ///
+    /// Content is verified against the hash either way; a mismatch means the
+    /// declaration was wrong, which points at a misbehaving peer.
+    async fn check_declared_size(&self, hash: iroh_blobs::Hash) -> anyhow::Result<()> {
+        let hash_str = hash.to_string();
+        // The transfer's declaration is used up here, whatever the outcome
+        let declared = {
+            let mut s = self.state.lock().await;
+            match s.declared_size.take() {
+                Some((h, size)) if h == hash_str => Some(size),
+                other => {
+                    s.declared_size = other;
+                    None
+                }
+            }
+        };
+        let Some(tolerance) = self.size_tolerance else {
+            return Ok(());
+        };
+        let declared = match declared {
+            Some(size) => size,
+            None => match self.peer_image_size(&hash_str).await {
+                Some(size) => size,
+                None => return Ok(()),
+            },
+        };
+        let BlobStatus::Complete { size: actual } = self.store.blobs().status(hash).await? else {
+            return Ok(());
+        };
+        if actual.abs_diff(declared) as f64 <= declared as f64 * tolerance / 100.0 {
+            return Ok(());
+        }
+        warn!(%hash, declared, actual, "received blob doesn't match its declared size");
+        // `image_changed` still names the image being served while a receive
+        // without KEEP_IMAGE_DURING_RECEIVE has cleared `current_hash`
+        let served = self.image_changed.borrow().as_deref() == Some(hash_str.as_str());
+        let current = served || {
+            let s = self.state.lock().await;
+            s.has_image && s.current_hash.as_deref() == Some(hash_str.as_str())
+        };
+        if current || self.pinned.contains(&hash) {
+            info!(%hash, "keeping mismatched blob: it is the current image or pinned");
+        } else if let Err(e) = self.untag_blob(hash).await {
+            warn!(?e, %hash, "failed to discard mismatched blob");
+        }
+        Err(SizeMismatch { declared, actual }.into())
+    }
+
+    /// Replace `current.img` with `bytes` via a rename, so a concurrent reader
+    /// (or mapping) sees either the old image or the new one, never a mix.
+    async fn write_current(&self, bytes: &[u8]) -> std::io::Result<()> {
+        let staged = self.data_dir.join("current.img.part");
+        fs::write(&staged, bytes).await?;
+        fs::rename(&staged, self.data_dir.join("current.img")).await
+    }
+
+    /// Bytes of `current.img`. With `mmap_serve` they come from the shared
+    /// mapping, falling back to a plain read if mapping fails.
+    async fn current_image_bytes(&self) -> std::io::Result<bytes::Bytes> {
+        #[cfg(feature = "mmap_serve")]
+        {
+            let hash = self.state.lock().await.current_hash.clone();
+            let image = self.mapped_image.clone();
+            match tokio::task::spawn_blocking(move || image.load(hash)).await {
+                Ok(Ok(bytes)) => return Ok(bytes),
+                Ok(Err(e)) if e.kind() == std::io::ErrorKind::NotFound => return Err(e),
+                res => warn!(?res, "mapping current.img failed; reading it instead"),
+            }
+        }
+        fs::read(self.data_dir.join("current.img"))
+            .await
+            .map(bytes::Bytes::from)
+    }
+
+    /// `len` bytes of `current.img` from `start`, reading only that slice
+    /// (or slicing the shared mapping with `mmap_serve`).
+    async fn current_image_slice(&self, start: u64, len: u64) -> std::io::Result<bytes::Bytes> {
+        #[cfg(feature = "mmap_serve")]
+        {
+            let bytes = self.current_image_bytes().await?;
+            let start = (start as usize).min(bytes.len());
+            let end = start.saturating_add(len as usize).min(bytes.len());
+            Ok(bytes.slice(start..end))
+        }
+        #[cfg(not(feature = "mmap_serve"))]
+        {
+            use tokio::io::{AsyncReadExt, AsyncSeekExt};
+            let mut file = fs::File::open(self.data_dir.join("current.img")).await?;
+            file.seek(std::io::SeekFrom::Start(start)).await?;
+            let mut slice = Vec::with_capacity(len as usize);
+            file.take(len).read_to_end(&mut slice).await?;
+            Ok(slice.into())
+        }
+    }
+
+    /// `current.img` as one `GET /image_feed` part, headers included.
+    ///
+    /// Parts get the same guards as `/image`: a type that isn't a valid
+    /// header value becomes `application/octet-stream`, active content is
+    /// sent as an `attachment`, and a gzip-stored image is decompressed,
+    /// since clients don't decode per-part encodings.
+    async fn feed_frame(&self) -> std::io::Result<bytes::Bytes> {
+        let mut image = self.current_image_bytes().await?;
+        if self.served_content_encoding().await.as_deref() == Some("gzip") {
+            let limit = self.max_upload_bytes;
+            image = tokio::task::spawn_blocking(move || content_encoding::gunzip(&image, limit))
+                .await
+                .map_err(std::io::Error::other)??
+                .into();
+        }
+        let content_type = self
+            .served_content_type()
+            .await
+            .filter(|ct| HeaderValue::from_str(ct).is_ok())
+            .unwrap_or_else(|| "application/octet-stream".to_string());
+        let mut frame = format!(
+            "--{FEED_BOUNDARY}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n",
+            image.len()
+        );
+        if active_content::is_active_type(&content_type) {
+            frame.push_str("Content-Disposition: attachment\r\n");
+        }
+        frame.push_str("\r\n");
+        let mut frame = frame.into_bytes();
+        frame.extend_from_slice(&image);
+        frame.extend_from_slice(b"\r\n");
+        Ok(frame.into())
+    }
+
+    /// Decompress a gzip-stored image for a client that doesn't accept gzip.
+    /// More than `MAX_UPLOAD_BYTES` of output, or invalid gzip, is a 500.
+    async fn gunzip_image(
+        &self,
+        bytes: impl AsRef<[u8]> + Send + 'static,
+    ) -> Result<Vec<u8>, Response> {
+        let limit = self.max_upload_bytes;
+        match tokio::task::spawn_blocking(move || content_encoding::gunzip(bytes.as_ref(), limit))
+            .await
+        {
+            Ok(Ok(plain)) => Ok(plain),
+            res => {
+                error!(?res, "failed to decompress gzip-encoded image");
+                Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "stored image can't be decompressed",
+                )
+                    .into_response())
+            }
+        }
+    }
+
+    /// Bookkeeping once `hash` has been exported to `current.img` and `state`
+    /// describes it: compute its pHash, write the metadata sidecar, retain the
+    /// blob, and report the receive (if this was one) as completed.
+    async fn complete_image(&self, hash: iroh_blobs::Hash) {
+        let phash = self.image_phash().await;
+        self.state.lock().await.phash = phash;
+        if self.image_sidecar {
+            if let Err(e) = self.write_image_meta(hash).await {
+                warn!(?e, %hash, "failed to write image sidecar");
+            }
+        }
+        if let Err(e) = self.hold_image(hash).await {
+            warn!(?e, %hash, "failed to keep image under images/");
+        }
+        if self.fs_layout == FsLayout::Tree {
+            let filename = self.state.lock().await.current_filename.clone();
+            let image = self.data_dir.join("current.img");
+            match fs_layout::publish(
+                &self.data_dir,
+                &image,
+                &hash.to_string(),
+                filename.as_deref().unwrap_or_default(),
+            )
+            .await
+            {
+                Ok(path) => debug!(%hash, path = %path.display(), "published image to tree"),
+                Err(e) => warn!(?e, %hash, "failed to publish image to by-hash/by-name"),
+            }
+        }
+        self.retain_blob(hash).await;
+        let (transfer_id, bytes) = {
+            let mut s = self.state.lock().await;
+            s.progress_estimated = false;
+            (s.transfer_id.clone(), s.bytes_total)
+        };
+        if let Some(transfer_id) = transfer_id {
+            self.events.emit(Event::TransferCompleted {
+                transfer_id,
+                hash: hash.to_string(),
+                bytes,
+            });
+        }
+        self.image_changed.send_replace(Some(hash.to_string()));
+    }
+
+    /// pHash of `current.img`, computed on the blocking pool. `None` without
+    /// the `phash` feature, for content stored encoded, and for anything
+    /// that isn't a decodable image.
+    async fn image_phash(&self) -> Option<String> {
+        #[cfg(feature = "phash")]
+        {
+            let is_image = {
+                let s = self.state.lock().await;
+                s.content_encoding.is_none()
+                    && s.content_type
+                        .as_deref()
+                        .is_none_or(|ct| ct.starts_with("image/"))
+            };
+            if !is_image {
+                return None;
+            }
+            let image = self.current_image_bytes().await.ok()?;
+            tokio::task::spawn_blocking(move || phash::compute(&image))
+                .await
+                .ok()
+                .flatten()
+        }
+        #[cfg(not(feature = "phash"))]
+        None
+    }
+
+    /// Forget the current image: reset its `NodeState` fields, remove
+    /// `current.img` and its sidecar, and untag its blob (unless pinned) so
+    /// the store GC can reclaim it. `false` if there was no image to clear.
+    async fn clear_image(&self) -> anyhow::Result<bool> {
+        let _adopting = self.adopting().await;
+        let image = self.data_dir.join("current.img");
+        let hash = {
+            let mut s = self.state.lock().await;
+            if !s.has_image && !fs::try_exists(&image).await.unwrap_or(false) {
+                return Ok(false);
+            }
+            s.current_filename = None;
+            s.content_type = None;
+            s.content_encoding = None;
+            s.bytes_total = None;
+            s.bytes_received = 0;
+            s.progress = 0.0;
+            s.clear_rate();
+            s.has_image = false;
+            s.phash = None;
+            s.stripe_providers.clear();
+            s.current_hash.take().and_then(|h| h.parse().ok())
+        };
+        for path in [image_meta::sidecar_path(&image), image] {
+            match fs::remove_file(&path).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        if let Some(hash) = hash.filter(|h| !self.pinned.contains(h)) {
+            self.untag_blob(hash).await?;
+            self.store_usage.lock().await.remove(&hash);
+        }
+        info!("current image cleared");
+        Ok(true)
+    }
+
+    async fn write_image_meta(&self, hash: iroh_blobs::Hash) -> anyhow::Result<()> {
+        let meta = self.current_image_meta(hash).await?;
+        image_meta::write(&self.data_dir.join("current.img"), &meta).await?;
+        let held = image_set::path(&self.data_dir, &meta.hash);
+        if fs::try_exists(&held).await.unwrap_or(false) {
+            image_meta::write(&held, &meta).await?;
+        }
+        Ok(())
+    }
+
+    /// Serving metadata for `current.img`, which holds `hash`.
+    async fn current_image_meta(&self, hash: iroh_blobs::Hash) -> anyhow::Result<ImageMeta> {
+        let bytes = fs::metadata(self.data_dir.join("current.img")).await?.len();
+        let s = self.state.lock().await;
+        Ok(ImageMeta {
+            filename: s.current_filename.clone().unwrap_or_default(),
+            content_type: s
+                .content_type
+                .clone()
+                .unwrap_or_else(|| "application/octet-stream".to_string()),
+            content_encoding: s.content_encoding.clone(),
+            hash: hash.to_string(),
+            bytes,
+            phash: s.phash.clone(),
+        })
+    }
+
+    /// Keep the just-completed `current.img` under `images/` and list it as
+    /// the newest held image.
+    async fn hold_image(&self, hash: iroh_blobs::Hash) -> anyhow::Result<()> {
+        let meta = self.current_image_meta(hash).await?;
+        image_set::keep(&self.data_dir, &self.data_dir.join("current.img"), &meta).await?;
+        let evicted = image_set::push(&mut self.state.lock().await.images, meta);
+        self.drop_held(evicted).await;
+        Ok(())
+    }
+
+    /// Delete the copies of held images that fell out of `MAX_HELD_IMAGES`.
+    async fn drop_held(&self, evicted: Vec<ImageMeta>) {
+        for meta in evicted {
+            match image_set::remove(&self.data_dir, &meta.hash).await {
+                Ok(()) => info!(hash = %meta.hash, "dropped oldest held image"),
+                Err(e) => warn!(?e, hash = %meta.hash, "failed to drop held image"),
+            }
+        }
+    }
+
+    /// Put the next held image on `current.img` (`SLIDESHOW_MS`), wrapping
+    /// around. Held content that isn't `image/*` is skipped, and nothing
+    /// changes while a transfer is in flight. Runs under `adopt_lock`, and
+    /// gives up if the current image changed while the slide was read.
+    async fn advance_slide(&self) -> anyhow::Result<()> {
+        let _adopting = self.adopting().await;
+        let (index, meta, shown) = {
+            let s = self.state.lock().await;
+            if s.download_strategy.is_some() {
+                return Ok(());
+            }
+            let slides: Vec<&ImageMeta> = s
+                .images
+                .iter()
+                .filter(|m| m.content_type.starts_with("image/"))
+                .collect();
+            if slides.is_empty() {
+                return Ok(());
+            }
+            let index = s.slide_index.map_or(0, |i| (i + 1) % slides.len());
+            (index, slides[index].clone(), s.current_hash.clone())
+        };
+        let bytes = fs::read(image_set::path(&self.data_dir, &meta.hash)).await?;
+        // A receive may have started (and cleared the image) meanwhile
+        {
+            let s = self.state.lock().await;
+            if s.current_hash != shown || s.download_strategy.is_some() {
+                debug!("current image changed; skipping slide");
+                return Ok(());
+            }
+        }
+        self.write_current(&bytes).await?;
+        if self.image_sidecar {
+            image_meta::write(&self.data_dir.join("current.img"), &meta).await?;
+        }
+        {
+            let mut s = self.state.lock().await;
+            s.has_image = true;
+            s.current_filename = Some(meta.filename.clone());
+            s.content_type = Some(meta.content_type.clone());
+            s.content_encoding = meta.content_encoding.clone();
+            s.current_hash = Some(meta.hash.clone());
+            s.phash = meta.phash.clone();
+            s.bytes_total = Some(meta.bytes);
+            s.bytes_received = meta.bytes;
+            s.progress = 100.0;
+            s.slide_index = Some(index);
+        }
+        debug!(index, hash = %meta.hash, "slideshow advanced");
+        self.image_changed.send_replace(Some(meta.hash));
+        Ok(())
+    }
+
+    /// The content type to serve a just-exported receive with. With
+    /// `VERIFY_CONTENT_TYPE`, the start of `current.img` is sniffed; when it
+    /// contradicts `declared`, the sniffed type replaces it (`correct`) or
+    /// the mismatch goes to `last_error` and `declared` stands (`flag`).
+    async fn checked_content_type(&self, declared: &str) -> String {
+        if self.verify_content_type == ContentTypeCheck::Off {
+            return declared.to_string();
+        }
+        let head = match fs::File::open(self.data_dir.join("current.img")).await {
+            Ok(file) => {
+                use tokio::io::AsyncReadExt;
+                let mut head = Vec::with_capacity(active_content::SNIFF_LEN);
+                let _ = file
+                    .take(active_content::SNIFF_LEN as u64)
+                    .read_to_end(&mut head)
+                    .await;
+                head
+            }
+            Err(_) => return declared.to_string(),
+        };
+        let Some(sniffed) = active_content::sniff(&head)
+            .filter(|sniffed| content_sniff::contradicts(declared, sniffed))
+        else {
+            return declared.to_string();
+        };
+        warn!(
+            declared,
+            sniffed, "received content doesn't match its declared type"
+        );
+        match self.verify_content_type {
+            ContentTypeCheck::Correct => sniffed.to_string(),
+            _ => {
+                self.state.lock().await.last_error = Some(format!(
+                    "declared content type {declared} but the content is {sniffed}"
+                ));
+                declared.to_string()
+            }
+        }
+    }
+
+    /// Change the content type `current.img` is served with. Takes effect on
+    /// the next `/image` request, and the sidecar is rewritten so a restart
+    /// keeps it.
+    async fn set_content_type(&self, content_type: &str) {
+        let hash = {
+            let mut s = self.state.lock().await;
+            s.set_content_type(content_type);
+            s.current_hash
+                .as_deref()
+                .filter(|_| s.has_image)
+                .and_then(|h| h.parse::<iroh_blobs::Hash>().ok())
+        };
+        if let Some(hash) = hash.filter(|_| self.image_sidecar) {
+            if let Err(e) = self.write_image_meta(hash).await {
+                warn!(?e, %hash, "failed to rewrite image sidecar");
+            }
+        }
+    }
+
+    /// Restore serving metadata for the images left on disk by a previous run.
+    async fn restore_image_meta(&self) {
+        let mut images = image_set::load(&self.data_dir).await;
+        let evicted = image_set::trim(&mut images);
+        self.drop_held(evicted).await;
+        if !images.is_empty() {
+            info!(count = images.len(), "restored held images from images/");
+            self.state.lock().await.images = images;
+        }
+        let image = self.data_dir.join("current.img");
+        let Some(meta) = image_meta::read(&image).await else {
+            return;
+        };
+        // Ignore a sidecar that doesn't describe the image actually on disk
+        match fs::metadata(&image).await {
+            Ok(m) if m.len() == meta.bytes => {}
+            _ => return,
+        }
+        info!(hash = %meta.hash, filename = %meta.filename, "restored image metadata from sidecar");
+        let mut s = self.state.lock().await;
+        s.has_image = true;
+        s.current_filename = Some(meta.filename);
+        s.content_type = Some(meta.content_type);
+        s.content_encoding = meta.content_encoding;
+        s.current_hash = Some(meta.hash);
+        s.phash = meta.phash;
+        s.bytes_total = Some(meta.bytes);
+        s.bytes_received = meta.bytes;
+        s.progress = 100.0;
+    }
+
+    /// Content type for `current.img`: from state, else from its sidecar.
+    async fn served_content_type(&self) -> Option<String> {
+        if let Some(ct) = self.state.lock().await.content_type.clone() {
+            return Some(ct);
+        }
+        image_meta::read(&self.data_dir.join("current.img"))
+            .await
+            .map(|m| m.content_type)
+    }
+
+    /// Encoding `current.img` is stored in: from state, else from its sidecar.
+    async fn served_content_encoding(&self) -> Option<String> {
+        let s = self.state.lock().await;
+        if s.content_type.is_some() {
+            return s.content_encoding.clone();
+        }
+        drop(s);
+        image_meta::read(&self.data_dir.join("current.img"))
+            .await
+            .and_then(|m| m.content_encoding)
+    }
+
+    /// Shield `hash` from store GC until the returned tag is dropped.
+    ///
+    /// A receive's blob stays untagged until [`Self::retain_blob`], both while
+    /// it is partial and while the finished blob is exported and checked. With
+    /// `STORE_MAX_BYTES` or `BLOB_TTL_MS` the GC deletes untagged blobs, so a
+    /// receive that outlives a GC run (held by `POST /pause`, say) holds this
+    /// from start to finish.
+    async fn protect_blob(&self, hash: iroh_blobs::Hash) -> Option<iroh_blobs::api::TempTag> {
+        match self.store.tags().temp_tag(hash).await {
+            Ok(tag) => Some(tag),
+            Err(e) => {
+                warn!(?e, %hash, "failed to protect blob from store GC");
+                None
+            }
+        }
+    }
+
+    /// Keep a freshly stored blob: tag it so store GC leaves it alone, account
+    /// for it as most recently used (restarting its `BLOB_TTL_MS`), then evict
+    /// down to `STORE_MAX_BYTES`.
+    ///
+    /// Failures are logged rather than returned; the transfer itself succeeded.
+    async fn retain_blob(&self, hash: iroh_blobs::Hash) {
+        if let Err(e) = self.store.tags().set(blob_tag(&hash), hash).await {
+            warn!(?e, %hash, "failed to tag blob");
+        }
+        match self.store.blobs().status(hash).await {
+            Ok(BlobStatus::Complete { size }) => {
+                let mut usage = self.store_usage.lock().await;
+                usage.record(hash, size);
+                if let Some(ttl) = self.blob_ttl {
+                    usage.expire_at(&hash, std::time::Instant::now() + ttl);
+                }
+            }
+            other => warn!(?other, %hash, "blob not complete after ingest"),
+        }
+        self.enforce_store_cap().await;
+    }
+
+    /// Evict least-recently-used blobs until usage is under `STORE_MAX_BYTES`.
+    ///
+    /// The current image and `PINNED_HASHES` are never evicted. Eviction drops
+    /// every tag pointing at the blob; the store GC reclaims the bytes.
+    async fn enforce_store_cap(&self) {
+        let Some(cap) = self.store_max_bytes else {
+            return;
+        };
+        let mut protected = self.pinned.clone();
+        if let Some(current) = self.current_hash().await {
+            protected.insert(current);
+        }
+        let plan = self.store_usage.lock().await.eviction_plan(cap, &protected);
+        if plan.is_empty() {
+            return;
+        }
+
+        let mut reclaimed = 0;
+        for (hash, size) in plan {
+            if let Err(e) = self.untag_blob(hash).await {
+                warn!(?e, %hash, "failed to evict blob");
+                continue;
+            }
+            self.store_usage.lock().await.remove(&hash);
+            reclaimed += size;
+            info!(%hash, bytes = size, "evicted blob from store");
+        }
+        let usage = self.store_usage.lock().await.total_bytes();
+        info!(reclaimed, usage, cap, "store eviction finished");
+    }
+
+    /// Untag blobs whose `BLOB_TTL_MS` has run out. Like eviction, this spares
+    /// the current image and `PINNED_HASHES`; the store GC reclaims the bytes.
+    async fn sweep_expired_blobs(&self) {
+        let mut protected = self.pinned.clone();
+        if let Some(current) = self.current_hash().await {
+            protected.insert(current);
+        }
+        let expired = self
+            .store_usage
+            .lock()
+            .await
+            .expired(std::time::Instant::now(), &protected);
+        for (hash, size) in expired {
+            if let Err(e) = self.untag_blob(hash).await {
+                warn!(?e, %hash, "failed to untag expired blob");
+                continue;
+            }
+            self.store_usage.lock().await.remove(&hash);
+            info!(%hash, bytes = size, "expired blob from store");
+        }
+    }
+
+    /// Delete every tag that points at `hash`, its copy under `images/`, and
+    /// with `FS_LAYOUT=tree` its `by-hash`/`by-name` entries.
+    async fn untag_blob(&self, hash: iroh_blobs::Hash) -> anyhow::Result<()> {
+        let mut names = Vec::new();
+        let mut tags = self.store.tags().list().await?;
+        while let Some(tag) = tags.next().await {
+            let tag = tag?;
+            if tag.hash == hash {
+                names.push(tag.name);
+            }
+        }
+        for name in names {
+            self.store.tags().delete(name).await?;
+        }
+        if self.fs_layout == FsLayout::Tree {
+            fs_layout::remove(&self.data_dir, &hash.to_string()).await?;
+        }
+        let hash = hash.to_string();
+        image_set::remove(&self.data_dir, &hash).await?;
+        self.state.lock().await.images.retain(|m| m.hash != hash);
+        Ok(())
+    }
+
+    /// Seed `store_usage` from the tagged blobs already on disk (at startup).
+    async fn load_store_usage(&self) -> anyhow::Result<()> {
+        let mut hashes = HashSet::new();
+        let mut tags = self.store.tags().list().await?;
+        while let Some(tag) = tags.next().await {
+            hashes.insert(tag?.hash);
+        }
+        let mut usage = self.store_usage.lock().await;
+        // Blobs found at startup get a fresh TTL; expiries aren't persisted
+        let expires_at = self.blob_ttl.map(|ttl| std::time::Instant::now() + ttl);
+        for hash in hashes {
+            if let BlobStatus::Complete { size } = self.store.blobs().status(hash).await? {
+                usage.record(hash, size);
+                if let Some(at) = expires_at {
+                    usage.expire_at(&hash, at);
+                }
+            }
+        }
+        info!(
+            blobs = usage.len(),
+            bytes = usage.total_bytes(),
+            "store usage loaded"
+        );
+        Ok(())
+    }
+
+    /// Resolved peer addresses in insertion order: `PEERS` order first, then
+    /// peers learned at runtime, by URL.
+    async fn known_peer_addrs(&self) -> Vec<NodeAddr> {
+        let map = self.peers_addrs.lock().await;
+        let mut extra: Vec<(&String, &NodeAddr)> = map
+            .iter()
+            .filter(|(url, _)| !self.peers_http.contains(url))
+            .collect();
+        extra.sort_by_key(|(url, _)| *url);
+        self.peers_http
+            .iter()
+            .filter_map(|url| map.get(url))
+            .chain(extra.into_iter().map(|(_, addr)| addr))
+            .cloned()
+            .collect()
+    }
+
+    async fn current_hash(&self) -> Option<iroh_blobs::Hash> {
+        let s = self.state.lock().await;
+        s.current_hash.as_deref().and_then(|h| h.parse().ok())
+    }
+
+    /// The current image's hash if it is complete, still in the store, and named
+    /// by the request's `If-None-Match` (bare or quoted; `W/` tolerated).
+    async fn current_image_matches(&self, headers: &HeaderMap) -> Option<iroh_blobs::Hash> {
+        let wanted = headers.get("If-None-Match")?.to_str().ok()?;
+        let current = {
+            let s = self.state.lock().await;
+            if !s.has_image {
+                return None;
+            }
+            s.current_hash
+                .as_deref()?
+                .parse::<iroh_blobs::Hash>()
+                .ok()?
+        };
+        let matches = wanted.split(',').any(|tag| {
+            let tag = tag.trim();
+            let tag = tag.strip_prefix("W/").unwrap_or(tag);
+            tag.trim_matches('"') == current.to_string()
+        });
+        if matches && self.store.has(current).await.unwrap_or(false) {
+            Some(current)
+        } else {
+            None
+        }
+    }
+
+    /// The placeholder response, if one is configured and there is no image.
+    async fn placeholder_response(&self, head: bool) -> Option<Response> {
+        let placeholder = self.placeholder.as_ref()?;
+        if self.state.lock().await.has_image {
+            return None;
+        }
+        Some(placeholder.response(head))
+    }
+
+    /// Bump the current image's recency when it is served over HTTP.
+    async fn touch_current(&self) {
+        if let Some(hash) = self.current_hash().await {
+            self.store_usage.lock().await.touch(&hash);
+        }
+    }
+}
+
+/// Run `op`, retrying failures up to `retries` more times with doubling
+/// `backoff` between attempts. Returns the last error when every attempt fails.
+async fn with_retries<T, F, Fut>(retries: u32, backoff: Duration, mut op: F) -> anyhow::Result<T>
+where
+    F: FnMut() -> Fut,
+    Fut: std::future::Future<Output = anyhow::Result<T>>,
+{
+    let mut delay = backoff;
+    for attempt in 1.. {
+        match op().await {
+            Ok(v) => return Ok(v),
+            Err(e) if attempt > retries => return Err(e),
+            Err(e) => {
+                warn!(?e, attempt, "attempt failed; retrying");
+                sleep(delay).await;
+                delay *= 2;
+            }
+        }
+    }
+    unreachable!("the loop only exits by returning")
+}
+
+/// Nodes the endpoint has addresses for from local-network discovery.
+fn lan_nodes(endpoint: &Endpoint) -> HashSet<PublicKey> {
+    endpoint
+        .remote_info_iter()
+        .filter(|remote| {
+            remote.sources().iter().any(|(source, _)| {
+                matches!(source, Source::Discovery { name } if name == iroh::discovery::mdns::NAME)
+            })
+        })
+        .map(|remote| remote.node_id)
+        .collect()
+}
+
+/// Put the sequential fallback's candidates (in insertion order) into
+/// `order`. `latency` is the measured round-trip time to a node, if any.
+fn order_candidates(
+    order: SeqOrder,
+    addrs: &mut [NodeAddr],
+    scores: &ProviderScores,
+    latency: impl Fn(&PublicKey) -> Option<Duration>,
+    rng: &mut impl rand::Rng,
+) {
+    match order {
+        SeqOrder::Insertion => {}
+        SeqOrder::Shuffle => addrs.shuffle(rng),
+        SeqOrder::Scored => scores.rank(addrs, |a| a.node_id.to_string()),
+        SeqOrder::Latency => addrs.sort_by_key(|a| latency(&a.node_id).unwrap_or(Duration::MAX)),
+    }
+}
+
+/// Move providers on our LAN ahead of the rest (`PREFER_LAN_PROVIDERS`).
+/// Stable, so each group keeps its `SEQ_ORDER` order.
+fn lan_first(addrs: &mut [NodeAddr], lan: &HashSet<PublicKey>) {
+    addrs.sort_by_key(|a| !lan.contains(&a.node_id));
+}
+
+/// Percent done against a peer-reported size. Capped below 100, since only
+/// completion confirms the size (and the estimate may be wrong).
+fn estimated_progress(received: u64, estimate: u64) -> f32 {
+    if estimate == 0 {
+        return 0.0;
+    }
+    ((received as f32 / estimate as f32) * 100.0).min(99.0)
+}
+
+/// Name of the tag that keeps a retained blob alive in the store.
+fn blob_tag(hash: &iroh_blobs::Hash) -> String {
+    format!("blob-{}", hash)
+}
+
+/// Interleave the items of concurrent range downloads, with each `Progress`
+/// rewritten to the total received across all of them.
+fn merged_progress<S>(parts: Vec<S>) -> impl futures_util::Stream<Item = DownloadProgessItem>
+where
+    S: futures_util::Stream<Item = DownloadProgessItem> + Unpin,
+{
+    let received = vec![0u64; parts.len()];
+    let parts = parts
+        .into_iter()
+        .enumerate()
+        .map(|(i, part)| part.map(move |item| (i, item)));
+    futures_util::stream::select_all(parts).scan(received, |received, (i, item)| {
+        let item = match item {
+            DownloadProgessItem::Progress(recvd) => {
+                received[i] = recvd;
+                DownloadProgessItem::Progress(received.iter().sum())
+            }
+            other => other,
+        };
+        futures_util::future::ready(Some(item))
+    })
+}
+
+/// Pass a download's progress items through, but end it with an `Error` once
+/// `window` goes by without a `Progress` or `PartComplete` (`SPLIT_STALL_MS`).
+///
+/// Catches providers that accept a request and then never deliver, which
+/// would otherwise hold a split download open without ever failing it.
+fn stall_watchdog(
+    items: impl futures_util::Stream<Item = DownloadProgessItem>,
+    window: Option<Duration>,
+    paused: tokio::sync::watch::Receiver<bool>,
+) -> impl futures_util::Stream<Item = DownloadProgessItem> {
+    let budget = window.map(|w| PausableBudget::new(paused, w));
+    futures_util::stream::unfold(
+        (Box::pin(items), budget, false),
+        move |(mut items, mut budget, stalled)| async move {
+            if stalled {
+                return None;
+            }
+            let next = match budget.as_mut() {
+                Some(budget) => match budget.run(items.next()).await {
+                    Some(next) => next,
+                    None => {
+                        let ms = window.unwrap_or_default().as_millis();
+                        let err = anyhow::anyhow!("split download made no progress for {ms} ms");
+                        return Some((DownloadProgessItem::Error(err), (items, None, true)));
+                    }
+                },
+                None => items.next().await,
+            };
+            let item = next?;
+            if let (Some(budget), Some(window)) = (budget.as_mut(), window) {
+                if matches!(
+                    item,
+                    DownloadProgessItem::Progress(_) | DownloadProgessItem::PartComplete { .. }
+                ) {
+                    budget.left = window;
+                }
+            }
+            Some((item, (items, budget, false)))
+        },
+    )
+}
+
+/// A time budget that only runs down while transfers aren't paused, so
+/// `RECEIVE_DEADLINE_MS` and `SPLIT_STALL_MS` don't fire on a transfer held
+/// by `POST /pause`.
+struct PausableBudget {
+    paused: tokio::sync::watch::Receiver<bool>,
+    left: Duration,
+}
+
+impl PausableBudget {
+    fn new(paused: tokio::sync::watch::Receiver<bool>, budget: Duration) -> Self {
+        Self {
+            paused,
+            left: budget,
+        }
+    }
+
+    /// Drive `fut` against what's left of the budget: its output, or `None`
+    /// if the budget ran out first.
+    async fn run<F: std::future::Future>(&mut self, fut: F) -> Option<F::Output> {
+        let mut fut = std::pin::pin!(fut);
+        loop {
+            let paused = *self.paused.borrow_and_update();
+            let started = tokio::time::Instant::now();
+            tokio::select! {
+                biased;
+                out = &mut fut => {
+                    if !paused {
+                        self.left = self.left.saturating_sub(started.elapsed());
+                    }
+                    return Some(out);
+                }
+                _ = sleep(self.left), if !paused => {
+                    self.left = Duration::ZERO;
+                    return None;
+                }
+                changed = self.paused.changed() => {
+                    if !paused {
+                        self.left = self.left.saturating_sub(started.elapsed());
+                    }
+                    // The sender lives in `NodeShared`; without it nothing pauses
+                    if changed.is_err() {
+                        return Some(fut.await);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn request_key(req: &GetRequest) -> String {
+    format!("{}::{:?}", req.hash, req.ranges)
+}
+
+fn describe_request(req: &GetRequest) -> String {
+    if let Some((offset, ranges)) = req.ranges.as_single() {
+        format!("offset={} ranges={:?}", offset, ranges)
+    } else {
+        format!("ranges={:?}", req.ranges)
+    }
+}
+
+/// GET `url` and return the body, treating non-2xx statuses as errors.
+async fn fetch_http_bytes(client: &reqwest::Client, url: &str) -> anyhow::Result<bytes::Bytes> {
+    let resp = client.get(url).send().await?.error_for_status()?;
+    Ok(resp.bytes().await?)
+}
+
+/// Read a JSON response body of at most `max` bytes. A longer body is
+/// rejected as soon as it is known to be too long, without buffering it.
+async fn read_json_capped<T: serde::de::DeserializeOwned>(
+    mut resp: reqwest::Response,
+    max: usize,
+) -> anyhow::Result<T> {
+    if resp.content_length().is_some_and(|len| len > max as u64) {
+        anyhow::bail!("body over {max} bytes");
+    }
+    let mut body = Vec::new();
+    while let Some(chunk) = resp.chunk().await? {
+        if body.len() + chunk.len() > max {
+            anyhow::bail!("body over {max} bytes");
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(serde_json::from_slice(&body)?)
+}
+
+/// Best‑effort extractor for bytes from a generic progress event (MVP; tolerant to API changes)
+#[allow(dead_code)]
+fn progress_bytes(evt: &impl core::fmt::Debug) -> Option<(u64, Option<u64>)> {
+    let s = format!("{:?}", evt);
+    let nums: Vec<u64> = s
+        .split(|c: char| !c.is_ascii_digit())
+        .filter_map(|t| t.parse().ok())
+        .collect();
+    let recvd = nums.first().copied();
+    let total = nums.get(1).copied();
+    recvd.map(|r| (r, total))
+}
+
+/// Best-effort fan-out to peers about a new blob hash.
+///
+/// First attempts P2P notify via iroh using any known `NodeAddr`s. If the
+/// address book is empty or a send fails, falls back to HTTP `/receive`.
+/// Why: ensures reliability during early boot or partial discovery.
+///
+/// Returns one outcome per attempt, in the order they were made.
+async fn notify_all_peers(shared: Arc<NodeShared>, msg: NotifyMsg) -> Vec<NotifyOutcome> {
+    maybe_latency(&shared).await;
+    let client = shared.http_client.clone();
+    let body = serde_json::json!({
+        "hash": &msg.hash,
+        "filename": &msg.filename,
+        "content_type": &msg.content_type,
+        "provider_node_id": &msg.provider_node_id,
+        "provider_url": &msg.provider_url,
+        "version": &msg.version,
+        "bytes_total": &msg.bytes_total,
+        "content_encoding": &msg.content_encoding,
+    })
+    .to_string();
+    // Peers built like us have no notify handler to accept a P2P notify
+    if !cfg!(feature = "p2p_notify") {
+        info!(
+            "P2P notify is unavailable in this build (no p2p_notify feature); notifying over HTTP"
+        );
+        return notify_peers_over_http(&shared, &client, &msg, &body).await;
+    }
+    let addrs = shared.peers_addrs.lock().await.clone();
+    let endpoint = match shared.endpoint.as_ref() {
+        Some(endpoint) if !addrs.is_empty() => endpoint,
+        Some(endpoint) => {
+            warn!("no peer NodeAddrs known yet; using HTTP fallback");
+            let mut outcomes = notify_peers_over_http(&shared, &client, &msg, &body).await;
+            if let Some(window) = shared.notify_upgrade_window {
+                let missed = outcomes
+                    .iter()
+                    .filter(|o| !o.ok)
+                    .map(|o| o.url.clone())
+                    .collect();
+                outcomes.extend(upgrade_to_p2p(&shared, endpoint, missed, &msg, window).await);
+            }
+            return outcomes;
+        }
+        None => return notify_peers_over_http(&shared, &client, &msg, &body).await,
+    };
+    let mut outcomes = Vec::new();
+    for (url, addr) in addrs {
+        maybe_latency(&shared).await;
+        let (via, delivered, transfer_id) = match send_notify(
+            endpoint,
+            addr,
+            &msg,
+            &shared.notify_log,
+        )
+        .await
+        {
+            Ok(transfer_id) => ("p2p", true, transfer_id),
+            Err(e) if !peer_file::is_http_key(&url) => {
+                warn!(?e, node_id = %url, "p2p notify failed; peer has no HTTP URL to fall back to");
+                ("p2p", false, None)
+            }
+            Err(e) => {
+                warn!(?e, %url, "p2p notify failed; attempting HTTP fallback");
+                shared
+                    .notify_log
+                    .record(Direction::Outbound, "http", url.clone(), &msg);
+                let delivered =
+                    post_receive(&client, &url, &body, shared.http_notify_retries).await;
+                ("http", delivered, None)
+            }
+        };
+        outcomes.push(notify_sent(&shared, url, via, delivered, transfer_id));
+    }
+    outcomes
+}
+
+/// How one notify attempt to a peer went, as reported by `/upload?await_notify=1`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+struct NotifyOutcome {
+    url: String,
+    /// `p2p` or `http`.
+    method: String,
+    ok: bool,
+}
+
+/// Publish a `notify_sent` event and return the matching outcome.
+fn notify_sent(
+    shared: &NodeShared,
+    url: String,
+    via: &str,
+    delivered: bool,
+    transfer_id: Option<String>,
+) -> NotifyOutcome {
+    shared.events.emit(Event::NotifySent {
+        peer: url.clone(),
+        via: via.to_string(),
+        delivered,
+        transfer_id,
+    });
+    NotifyOutcome {
+        url,
+        method: via.to_string(),
+        ok: delivered,
+    }
+}
+
+/// Send the notify `body` (`msg` as JSON) to every configured peer's HTTP
+/// `/receive`.
+async fn notify_peers_over_http(
+    shared: &NodeShared,
+    client: &reqwest::Client,
+    msg: &NotifyMsg,
+    body: &str,
+) -> Vec<NotifyOutcome> {
+    let mut outcomes = Vec::new();
+    for url in &shared.peers_http {
+        shared
+            .notify_log
+            .record(Direction::Outbound, "http", url.clone(), msg);
+        let delivered = post_receive(client, url, body, shared.http_notify_retries).await;
+        outcomes.push(notify_sent(shared, url.clone(), "http", delivered, None));
+    }
+    outcomes
+}
+
+/// Cold-start follow-up to an HTTP fallback: for `window`, notify each peer in
+/// `pending` over P2P as soon as discovery resolves its address. Peers the
+/// HTTP notify reached aren't in `pending`, so nobody is notified twice.
+async fn upgrade_to_p2p(
+    shared: &NodeShared,
+    endpoint: &Endpoint,
+    mut pending: Vec<String>,
+    msg: &NotifyMsg,
+    window: Duration,
+) -> Vec<NotifyOutcome> {
+    let mut outcomes = Vec::new();
+    let deadline = tokio::time::Instant::now() + window;
+    while !pending.is_empty() && tokio::time::Instant::now() < deadline {
+        sleep(NOTIFY_UPGRADE_POLL).await;
+        let resolved: Vec<(String, NodeAddr)> = {
+            let addrs = shared.peers_addrs.lock().await;
+            pending
+                .iter()
+                .filter_map(|url| addrs.get(url).map(|a| (url.clone(), a.clone())))
+                .collect()
+        };
+        for (url, addr) in resolved {
+            match send_notify(endpoint, addr, msg, &shared.notify_log).await {
+                Ok(transfer_id) => {
+                    info!(%url, "delivered notify over P2P after HTTP fallback failed");
+                    pending.retain(|p| *p != url);
+                    outcomes.push(notify_sent(shared, url, "p2p", true, transfer_id));
+                }
+                Err(e) => warn!(?e, %url, "p2p notify upgrade failed; will retry"),
+            }
+        }
+    }
+    if !pending.is_empty() {
+        warn!(
+            ?pending,
+            "peers still not notified after NOTIFY_P2P_UPGRADE_MS"
+        );
+    }
+    outcomes
+}
+
+/// A random delay in `[base/2, base]`.
+fn jittered(base: Duration, rng: &mut impl Rng) -> Duration {
+    let half = base.as_millis() as u64 / 2;
+    Duration::from_millis(half + rng.gen_range(0..=half))
+}
+
+/// POST a notify to a peer's `/receive`, retrying transient failures.
+///
+/// Connection errors and 5xx responses are retried up to `retries` times with
+/// jittered exponential backoff, bounded by `HTTP_NOTIFY_MAX_WAIT` in total.
+/// Returns whether the peer accepted the notify.
+async fn post_receive(client: &reqwest::Client, url: &str, body: &str, retries: u32) -> bool {
+    let started = std::time::Instant::now();
+    let mut backoff = HTTP_NOTIFY_BACKOFF;
+    for attempt in 1..=retries + 1 {
+        let res = client
+            .post(format!("{}/receive", url))
+            .header("Content-Type", "application/json")
+            .body(body.to_string())
+            .send()
+            .await;
+        let transient = match res {
+            Ok(resp) if resp.status().is_success() => {
+                info!(%url, attempt, "HTTP notify delivered");
+                return true;
+            }
+            Ok(resp) => {
+                warn!(%url, attempt, status = %resp.status(), "HTTP notify rejected");
+                resp.status().is_server_error()
+            }
+            Err(e) => {
+                warn!(?e, %url, attempt, "HTTP notify failed");
+                true
+            }
+        };
+        if !transient || attempt > retries {
+            break;
+        }
+        let delay = jittered(backoff, &mut thread_rng());
+        if started.elapsed() + delay > HTTP_NOTIFY_MAX_WAIT {
+            warn!(%url, attempt, "HTTP notify retry budget exhausted");
+            break;
+        }
+        sleep(delay).await;
+        backoff *= 2;
+    }
+    false
+}
+
+async fn peer_addr_refresher(shared: Arc<NodeShared>) {
+    loop {
+        refresh_peer_addrs(&shared, &shared.http_client).await;
+        sleep(Duration::from_millis(1000)).await;
+    }
+}
+
+/// One pass over `PEER_HTTP_URLS`; returns how many peers have a known address.
+///
+/// Each `/status` fetch is bounded by `PEER_STATUS_TIMEOUT_MS` and
+/// `PEER_STATUS_MAX_BYTES`, so a hostile or broken peer can't stall or
+/// exhaust the discovery loop.
+async fn refresh_peer_addrs(shared: &NodeShared, client: &reqwest::Client) -> usize {
+    for url in &shared.peers_http {
+        let resp = client
+            .get(format!("{}/status", url))
+            .timeout(shared.peer_status_timeout)
+            .send()
+            .await;
+        if let Ok(resp) = resp {
+            let peer = read_json_capped::<StatusPeerResp>(resp, shared.peer_status_max_bytes).await;
+            if let Err(e) = &peer {
+                debug!(%url, error = %e, "ignoring peer /status");
+            }
+            if let Ok(peer) = peer {
+                if let Some(na) = peer.addr() {
+                    shared.record_peer_addr(url, na, "status").await;
+                    shared
+                        .peer_addr_seen
+                        .lock()
+                        .await
+                        .insert(url.clone(), std::time::Instant::now());
+                }
+                let mut images = shared.peer_images.lock().await;
+                // One size per peer: a peer moving on drops its old size
+                let mut sizes = shared.peer_image_sizes.lock().await;
+                match peer.current_hash.filter(|_| peer.has_image) {
+                    Some(hash) => {
+                        match peer.bytes_total {
+                            Some(size) => sizes.insert(url.clone(), (hash.clone(), size)),
+                            None => sizes.remove(url),
+                        };
+                        images.insert(url.clone(), hash);
+                    }
+                    None => {
+                        images.remove(url);
+                        sizes.remove(url);
+                    }
+                }
+            }
+        }
+    }
+    if let Some(ttl) = shared.peer_addr_ttl {
+        shared.expire_peer_addrs(ttl).await;
+    }
+    shared.peers_addrs.lock().await.len()
+}
+
+/// One `ACTIVE_SEARCH` probe: the peer at `url` and its node id, if it holds
+/// `hash` as its current image or in its store. Its `/status` and `/blobs`
+/// bodies are read up to `max_bytes` each.
+async fn probe_peer(
+    client: reqwest::Client,
+    url: String,
+    hash: iroh_blobs::Hash,
+    max_bytes: usize,
+) -> Option<(String, NodeAddr)> {
+    let wanted = hash.to_string();
+    let resp = client
+        .get(format!("{}/status", url))
+        .timeout(CLUSTER_STATUS_TIMEOUT)
+        .send()
+        .await
+        .ok()?;
+    let status: StatusPeerResp = read_json_capped(resp, max_bytes).await.ok()?;
+    let addr = status.addr()?;
+    if !(status.has_image && status.current_hash.as_deref() == Some(wanted.as_str())) {
+        let resp = client
+            .get(format!("{}/blobs", url))
+            .timeout(CLUSTER_STATUS_TIMEOUT)
+            .send()
+            .await
+            .ok()?;
+        let blobs: serde_json::Value = read_json_capped(resp, max_bytes).await.ok()?;
+        let listed = blobs["blobs"]
+            .as_array()
+            .is_some_and(|b| b.iter().any(|blob| blob["hash"] == wanted.as_str()));
+        if !listed {
+            return None;
+        }
+    }
+    Some((url, addr))
+}
+
+/// Seconds since the unix epoch.
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Read a boolean env flag; accepts `1`/`true` (case-insensitive) as enabled.
+fn env_flag(key: &str, default: bool) -> bool {
+    match env::var(key) {
+        Ok(v) => matches!(v.trim().to_ascii_lowercase().as_str(), "1" | "true"),
+        Err(_) => default,
+    }
+}
+
+async fn maybe_latency(shared: &NodeShared) {
+    let min = shared.latency_min;
+    let max = shared.latency_max.max(min);
+    if max == 0 || !shared.demo_mode {
+        return;
+    }
+    let dur = {
+        let mut rng = thread_rng();
+        rng.gen_range(min..=max)
+    };
+    sleep(Duration::from_millis(dur)).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Build a `NodeShared` backed by a store under `dir`, with relays and
+    /// discovery disabled so tests never leave the machine.
+    async fn test_shared(dir: &std::path::Path) -> Arc<NodeShared> {
+        let endpoint = Endpoint::builder()
+            .relay_mode(iroh::RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let store = Arc::new(FsStore::load(dir.join("blobs")).await.unwrap());
+        let blobs = BlobsProtocol::new(&store, endpoint.clone(), None);
+        let events = EventBus::default();
+        Arc::new(NodeShared {
+            endpoint: Some(endpoint),
+            blobs: Some(blobs),
+            store,
+            distribution: Distribution::P2p,
+            public_url: "http://127.0.0.1:0".to_string(),
+            state: Arc::new(StateCell::new(NodeState::default())),
+            data_dir: dir.to_path_buf(),
+            peers_http: Vec::new(),
+            peers_addrs: Arc::new(Mutex::new(HashMap::new())),
+            peer_images: Arc::new(Mutex::new(HashMap::new())),
+            peer_image_sizes: Arc::new(Mutex::new(HashMap::new())),
+            size_tolerance: None,
+            peer_addrs_file: None,
+            peer_addr_seen: Arc::new(Mutex::new(HashMap::new())),
+            peer_addr_ttl: None,
+            http_blob_fallback: false,
+            http_notify_retries: 0,
+            notify_upgrade_window: None,
+            export_retries: 0,
+            verify_upload: false,
+            ingest: IngestPipeline::default(),
+            keep_image_during_receive: false,
+            block_active_content: false,
+            sequential_max_candidates: None,
+            prefer_lan: true,
+            single_provider_parallelism: 1,
+            verify_content_type: ContentTypeCheck::Off,
+            egress: None,
+            idempotency: Arc::default(),
+            adopt_lock: Arc::default(),
+            clock_file: Arc::default(),
+            seq_order: SeqOrder::Scored,
+            seq_shuffle_seed: None,
+            fallback_delay: FALLBACK_DELAY,
+            active_search: false,
+            download_mode: DownloadMode::Split,
+            fs_layout: FsLayout::Flat,
+            max_upload_bytes: DEFAULT_MAX_UPLOAD_BYTES,
+            peer_status_timeout: PEER_STATUS_TIMEOUT,
+            peer_status_max_bytes: PEER_STATUS_MAX_BYTES,
+            notify_log: NotifyLog::new(notify_log::NOTIFY_LOG_ENTRIES, events.clone()),
+            sequential_completion: SequentialCompletion::FirstComplete,
+            paused: Arc::new(tokio::sync::watch::channel(false).0),
+            cancel: Arc::new(Mutex::new(CancellationToken::new())),
+            image_changed: Arc::new(tokio::sync::watch::channel(None).0),
+            receive_deadline: None,
+            split_stall: None,
+            rate_limit: None,
+            verify_slots: Arc::new(tokio::sync::Semaphore::new(1)),
+            upload_slots: None,
+            url_signer: None,
+            store_max_bytes: None,
+            blob_ttl: None,
+            pinned: HashSet::new(),
+            store_usage: Arc::new(Mutex::new(StoreUsage::default())),
+            provider_scores: Arc::new(Mutex::new(ProviderScores::default())),
+            default_content_type: "application/octet-stream".to_string(),
+            image_sidecar: true,
+            placeholder: None,
+            events,
+            notify_acl: NotifyAcl::default(),
+            bind_addrs: None,
+            inflight: Arc::new(Mutex::new(InFlightSet::default())),
+            cluster_status: Arc::new(Mutex::new(None)),
+            http_client: reqwest::Client::new(),
+            throughput_slots: Arc::new(tokio::sync::Semaphore::new(THROUGHPUT_MAX_RUNS)),
+            #[cfg(feature = "mmap_serve")]
+            mapped_image: Arc::new(mmap_image::MappedImage::new(dir.join("current.img"))),
+            latency_min: 0,
+            latency_max: 0,
+            stream_sleep_ms: 0,
+            demo_mode: true,
+            diagnostics_token: None,
+            log_ring: LogRing::new(0),
+        })
+    }
+
+    /// Bind an ephemeral localhost port and return it with its base URL.
+    async fn bind_local() -> (tokio::net::TcpListener, String) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        (listener, url)
+    }
+
+    /// Serve `app` on an ephemeral localhost port and return its base URL.
+    async fn spawn_stub(app: Router) -> String {
+        let (listener, url) = bind_local().await;
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    /// Random bytes a little over 300 KiB: enough chunks to split or stripe,
+    /// with a partial last chunk.
+    fn random_blob() -> Vec<u8> {
+        let mut data = vec![0u8; 300 * 1024 + 17];
+        thread_rng().fill(&mut data[..]);
+        data
+    }
+
+    /// Nodes serving blobs over iroh, kept alive as long as this is.
+    struct BlobProviders {
+        addrs: Vec<NodeAddr>,
+        _routers: Vec<iroh::protocol::Router>,
+        _dirs: Vec<tempfile::TempDir>,
+    }
+
+    impl BlobProviders {
+        /// Make the providers dialable from `receiver`'s endpoint, without
+        /// making them peers.
+        fn introduce_to(&self, receiver: &NodeShared) {
+            for addr in &self.addrs {
+                receiver
+                    .endpoint
+                    .as_ref()
+                    .unwrap()
+                    .add_node_addr(addr.clone())
+                    .unwrap();
+            }
+        }
+
+        fn node_ids(&self) -> Vec<PublicKey> {
+            self.addrs.iter().map(|a| a.node_id).collect()
+        }
+    }
+
+    /// `n` providers, each holding `data` unless it is empty.
+    async fn spawn_blob_providers(n: usize, data: &[u8]) -> BlobProviders {
+        let mut providers = BlobProviders {
+            addrs: Vec::new(),
+            _routers: Vec::new(),
+            _dirs: Vec::new(),
+        };
+        for _ in 0..n {
+            let dir = tempfile::tempdir().unwrap();
+            let provider = test_shared(dir.path()).await;
+            providers._routers.push(
+                iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+                    .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+                    .spawn(),
+            );
+            if !data.is_empty() {
+                provider.store.add_slice(data).await.unwrap();
+            }
+            providers.addrs.push(
+                provider
+                    .endpoint
+                    .as_ref()
+                    .unwrap()
+                    .node_addr()
+                    .initialized()
+                    .await,
+            );
+            providers._dirs.push(dir);
+        }
+        providers
+    }
+
+    #[test]
+    fn test_progress_bytes_with_total() {
+        let dbg = "DownloadProgress { received: 50, total: 200 }";
+        let out = progress_bytes(&dbg).unwrap();
+        assert_eq!(out.0, 50);
+        assert_eq!(out.1, Some(200));
+    }
+
+    #[test]
+    fn test_progress_bytes_only_received() {
+        let dbg = "Ev { received: 123 }";
+        let out = progress_bytes(&dbg).unwrap();
+        assert_eq!(out.0, 123);
+        assert_eq!(out.1, None);
+    }
+
+    #[tokio::test]
+    async fn test_demo_mode_off_injects_no_delay() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            latency_min: 5_000,
+            latency_max: 5_000,
+            stream_sleep_ms: 5_000,
+            demo_mode: false,
+            ..(*shared).clone()
+        });
+        fs::write(dir.path().join("current.img"), b"12345")
+            .await
+            .unwrap();
+        shared.state.lock().await.has_image = true;
+
+        let started = std::time::Instant::now();
+        maybe_latency(&shared).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let resp = client
+            .get(format!("{}/image_stream", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"12345");
+        let status: serde_json::Value = client
+            .get(format!("{}/status", url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status["demo_mode"], false);
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_store_add_serves_blob_without_current_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+
+        let added: serde_json::Value = client
+            .post(format!("{}/store/add", url))
+            .body("pre-seeded")
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let hash = iroh_blobs::Hash::new(b"pre-seeded").to_string();
+        assert_eq!(added["hash"], hash.as_str());
+        assert!(added["ticket"].is_string());
+
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(b"from a form".to_vec()).file_name("f.bin"),
+        );
+        let resp = client
+            .post(format!("{}/store/add", url))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        let form_hash = resp.json::<serde_json::Value>().await.unwrap()["hash"].clone();
+        assert_eq!(
+            form_hash,
+            iroh_blobs::Hash::new(b"from a form").to_string().as_str()
+        );
+
+        let blob = client
+            .get(format!("{}/blob/{}", url, hash))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(blob.status().as_u16(), 200);
+        assert_eq!(&blob.bytes().await.unwrap()[..], b"pre-seeded");
+        let s = shared.state.lock().await;
+        assert!(!s.has_image);
+        assert_eq!(s.current_hash, None);
+        assert!(!dir.path().join("current.img").exists());
+    }
+
+    #[tokio::test]
+    async fn test_upload_store_failure_is_500() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        shared.store.shutdown().await.unwrap();
+        let url = spawn_stub(app(shared.clone())).await;
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(b"pixels".to_vec()).file_name("a.png"),
+        );
+        let resp = reqwest::Client::new()
+            .post(format!("{}/upload", url))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 500);
+        assert!(!shared.state.lock().await.has_image);
+    }
+
+    #[tokio::test]
+    async fn test_upload_write_failure_leaves_no_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        // current.img is staged here first; a directory makes the write fail
+        fs::create_dir(dir.path().join("current.img.part"))
+            .await
+            .unwrap();
+        let url = spawn_stub(app(shared.clone())).await;
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(b"pixels".to_vec()).file_name("a.png"),
+        );
+        let resp = reqwest::Client::new()
+            .post(format!("{}/upload", url))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 500);
+        let s = shared.state.lock().await.clone();
+        assert!(!s.has_image);
+        assert_eq!(s.current_hash, None);
+        // No version was spent on it, so nothing can have been announced
+        assert_eq!(s.version, 0);
+    }
+
+    #[tokio::test]
+    async fn test_store_add_applies_upload_checks() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            block_active_content: true,
+            max_upload_bytes: 64,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let add = |body: &'static [u8], content_type: &'static str| {
+            client
+                .post(format!("{}/store/add", url))
+                .header("Content-Type", content_type)
+                .body(body)
+                .send()
+        };
+
+        let resp = add(b"<html><script>1</script></html>", "text/html")
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 415);
+        let resp = add(&[0; 65], "application/octet-stream").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 413);
+        assert_eq!(
+            resp.text().await.unwrap(),
+            "file too large: the limit is 64 bytes"
+        );
+        assert_eq!(shared.store_usage.lock().await.len(), 0);
+
+        // All upload slots taken: /store/add is turned away like /upload
+        let slots = UploadSlots::new(1, false);
+        let _held = slots.acquire().await.unwrap();
+        let busy = Arc::new(NodeShared {
+            upload_slots: Some(slots),
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(busy)).await;
+        let resp = client
+            .post(format!("{}/store/add", url))
+            .body("queued")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 429);
+    }
+
+    #[test]
+    fn test_lan_discovered_provider_ordered_before_relay_only() {
+        let relay_only = NodeAddr::new(iroh::SecretKey::generate(thread_rng()).public())
+            .with_relay_url("https://relay.example".parse().unwrap());
+        let lan = NodeAddr::new(iroh::SecretKey::generate(thread_rng()).public())
+            .with_direct_addresses(["192.168.1.20:4433".parse().unwrap()]);
+        // The relay-only provider has the better record, so it ranks first
+        let mut scores = ProviderScores::default();
+        scores.record_success(
+            &relay_only.node_id.to_string(),
+            Some((1 << 20, Duration::from_millis(100))),
+        );
+        let mut candidates = vec![lan.clone(), relay_only.clone()];
+        scores.rank(&mut candidates, |a| a.node_id.to_string());
+        assert_eq!(candidates[0].node_id, relay_only.node_id);
+
+        lan_first(&mut candidates, &HashSet::from([lan.node_id]));
+        assert_eq!(candidates[0].node_id, lan.node_id);
+        assert_eq!(candidates[1].node_id, relay_only.node_id);
+    }
+
+    #[tokio::test]
+    async fn test_insertion_order_follows_peers_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec!["http://b:3000".into(), "http://a:3000".into()],
+            ..(*shared).clone()
+        });
+        let mut nodes = HashMap::new();
+        for url in [
+            "http://z:3000",
+            "http://a:3000",
+            "http://c:3000",
+            "http://b:3000",
+        ] {
+            let addr = NodeAddr::new(iroh::SecretKey::generate(thread_rng()).public());
+            nodes.insert(url, addr.node_id);
+            shared.peers_addrs.lock().await.insert(url.into(), addr);
+        }
+        let mut candidates = shared.known_peer_addrs().await;
+        order_candidates(
+            SeqOrder::Insertion,
+            &mut candidates,
+            &ProviderScores::default(),
+            |_| None,
+            &mut thread_rng(),
+        );
+        let order: Vec<PublicKey> = candidates.iter().map(|a| a.node_id).collect();
+        let expected: Vec<PublicKey> = [
+            "http://b:3000",
+            "http://a:3000",
+            "http://c:3000",
+            "http://z:3000",
+        ]
+        .iter()
+        .map(|url| nodes[url])
+        .collect();
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn test_seeded_shuffle_and_latency_orders() {
+        use rand::SeedableRng;
+        let addrs: Vec<NodeAddr> = (0..8)
+            .map(|_| NodeAddr::new(iroh::SecretKey::generate(thread_rng()).public()))
+            .collect();
+        let ids = |addrs: &[NodeAddr]| addrs.iter().map(|a| a.node_id).collect::<Vec<_>>();
+        let shuffled = |seed| {
+            let mut candidates = addrs.clone();
+            order_candidates(
+                SeqOrder::Shuffle,
+                &mut candidates,
+                &ProviderScores::default(),
+                |_| None,
+                &mut rand::rngs::StdRng::seed_from_u64(seed),
+            );
+            ids(&candidates)
+        };
+        let mut expected = ids(&addrs);
+        expected.shuffle(&mut rand::rngs::StdRng::seed_from_u64(7));
+        assert_eq!(shuffled(7), expected);
+        assert_eq!(shuffled(7), shuffled(7));
+        assert_ne!(shuffled(7), ids(&addrs));
+
+        // Measured round trips ascending, unmeasured ones last in their order
+        let rtt: HashMap<PublicKey, Duration> = [(addrs[5].node_id, 30), (addrs[2].node_id, 10)]
+            .into_iter()
+            .map(|(id, ms)| (id, Duration::from_millis(ms)))
+            .collect();
+        let mut candidates = addrs.clone();
+        order_candidates(
+            SeqOrder::Latency,
+            &mut candidates,
+            &ProviderScores::default(),
+            |id| rtt.get(id).copied(),
+            &mut thread_rng(),
+        );
+        let order = ids(&candidates);
+        assert_eq!(
+            &order[..3],
+            &[addrs[2].node_id, addrs[5].node_id, addrs[0].node_id]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_estimated_progress_stays_below_100_until_confirmed() {
+        assert_eq!(estimated_progress(50, 200), 25.0);
+        assert_eq!(estimated_progress(500, 200), 99.0);
+        assert_eq!(estimated_progress(10, 0), 0.0);
+
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let hash = iroh_blobs::Hash::new(b"estimated");
+        shared
+            .peer_image_sizes
+            .lock()
+            .await
+            .insert("http://peer".into(), (hash.to_string(), 1000));
+        shared
+            .begin_transfer("t", hash, "e.bin", "application/octet-stream")
+            .await;
+
+        shared.record_progress(500).await;
+        {
+            let s = shared.state.lock().await;
+            assert_eq!((s.progress, s.progress_estimated), (50.0, true));
+        }
+        // The peer under-reported: still not done
+        shared.record_progress(1500).await;
+        {
+            let s = shared.state.lock().await;
+            assert_eq!((s.progress, s.progress_estimated), (99.0, true));
+        }
+        // Exact size known: real progress replaces the estimate
+        shared.state.lock().await.bytes_total = Some(2000);
+        shared.record_progress(2000).await;
+        let s = shared.state.lock().await;
+        assert_eq!((s.progress, s.progress_estimated), (100.0, false));
+    }
+
+    #[tokio::test]
+    async fn test_status_reports_speed_and_eta() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let hash = iroh_blobs::Hash::new(b"speed");
+        shared
+            .begin_transfer("t", hash, "s.bin", "application/octet-stream")
+            .await;
+        shared.state.lock().await.bytes_total = Some(1_000_000);
+
+        shared.record_progress(0).await;
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        shared.record_progress(100_000).await;
+        {
+            let s = shared.state.lock().await;
+            assert!(
+                s.bytes_per_sec > 0.0 && s.bytes_per_sec <= 500_000.0,
+                "{}",
+                s.bytes_per_sec
+            );
+            // 900 KB left at no more than 500 KB/s
+            assert!(
+                s.eta_seconds.is_some_and(|eta| eta >= 2),
+                "{:?}",
+                s.eta_seconds
+            );
+            let json = serde_json::to_value(&*s).unwrap();
+            assert!(json.get("bytes_per_sec").is_some());
+            assert!(json.get("rate_window").is_none());
+        }
+
+        shared
+            .fail_transfer("t", &anyhow::anyhow!("provider went away"))
+            .await;
+        let s = shared.state.lock().await;
+        assert_eq!((s.bytes_per_sec, s.eta_seconds), (0.0, None));
+    }
+
+    #[test]
+    fn test_status_peer_resp_serde() {
+        let v: StatusPeerResp = serde_json::from_str("{\"node_addr\":null}").unwrap();
+        assert!(v.node_addr.is_none());
+        let v2: StatusPeerResp = serde_json::from_str("{\"node_addr\":\"abc\"}").unwrap();
+        assert_eq!(v2.node_addr, Some("abc".to_string()));
+        assert!(v2.addr().is_none());
+
+        let id = iroh::SecretKey::generate(thread_rng()).public();
+        let other = iroh::SecretKey::generate(thread_rng()).public();
+        let direct: std::net::SocketAddr = "192.0.2.7:4433".parse().unwrap();
+        let full = NodeAddr::new(id).with_direct_addresses([direct]);
+        let parse = |full: serde_json::Value| {
+            serde_json::from_value::<StatusPeerResp>(serde_json::json!({
+                "node_addr": id.to_string(),
+                "node_addr_full": full,
+            }))
+            .unwrap()
+            .addr()
+            .unwrap()
+        };
+        assert_eq!(parse(serde_json::to_value(&full).unwrap()), full);
+        // A full address for some other node, or garbage, leaves the bare key
+        let mismatched = NodeAddr::new(other).with_direct_addresses([direct]);
+        assert_eq!(
+            parse(serde_json::to_value(&mismatched).unwrap()),
+            NodeAddr::new(id)
+        );
+        assert_eq!(parse(serde_json::json!("nonsense")), NodeAddr::new(id));
+        assert_eq!(parse(serde_json::Value::Null), NodeAddr::new(id));
+    }
+
+    #[tokio::test]
+    async fn test_peer_status_carries_direct_addresses() {
+        let dir_a = tempfile::tempdir().unwrap();
+        let dir_b = tempfile::tempdir().unwrap();
+        let provider = test_shared(dir_a.path()).await;
+        let expected = provider
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .node_addr()
+            .initialized()
+            .await;
+        assert!(!expected.direct_addresses.is_empty());
+        provider.state.lock().await.node_addr = Some(expected.node_id.to_string());
+        let peer_url = spawn_stub(app(provider.clone())).await;
+
+        let receiver = test_shared(dir_b.path()).await;
+        let receiver = Arc::new(NodeShared {
+            peers_http: vec![peer_url.clone()],
+            ..(*receiver).clone()
+        });
+        assert_eq!(
+            refresh_peer_addrs(&receiver, &reqwest::Client::new()).await,
+            1
+        );
+        let resolved = receiver.peers_addrs.lock().await[&peer_url].clone();
+        assert_eq!(resolved, expected);
+    }
+
+    #[tokio::test]
+    async fn test_http_blob_fallback_after_p2p_failure() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            http_blob_fallback: true,
+            ..(*shared).clone()
+        });
+
+        let payload: &'static [u8] = b"not really a png, but bytes all the same";
+        let hash = iroh_blobs::Hash::new(payload);
+        // The stub peer has no /blob route, so this also exercises the /image retry.
+        let url =
+            spawn_stub(Router::new().route("/image", get(move || async move { payload }))).await;
+        shared
+            .peer_images
+            .lock()
+            .await
+            .insert(url, hash.to_string());
+
+        // A provider that cannot be reached: no addresses, no relay, no discovery.
+        let dead = iroh::SecretKey::generate(thread_rng()).public();
+        shared
+            .receive_by_discovery(
+                hash,
+                "f.bin".into(),
+                "application/octet-stream".into(),
+                Some(NodeAddr::from(dead)),
+            )
+            .await
+            .unwrap();
+
+        let s = shared.state.lock().await.clone();
+        assert!(s.has_image);
+        assert_eq!(s.bytes_total, Some(payload.len() as u64));
+        assert_eq!(
+            fs::read(dir.path().join("current.img")).await.unwrap(),
+            payload
+        );
+        assert!(shared.store.has(hash).await.unwrap());
+        assert_eq!(s.download_strategy, None);
+    }
+
+    #[tokio::test]
+    async fn test_http_distribution_transfers_between_nodes() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let (listener_a, url_a) = bind_local().await;
+        let (listener_b, url_b) = bind_local().await;
+        let http_only = |shared: Arc<NodeShared>, url: &str, peers: Vec<String>| {
+            Arc::new(NodeShared {
+                endpoint: None,
+                blobs: None,
+                distribution: Distribution::Http,
+                public_url: url.to_string(),
+                peers_http: peers,
+                ..(*shared).clone()
+            })
+        };
+        let a = http_only(test_shared(dir_a.path()).await, &url_a, vec![url_b.clone()]);
+        let b = http_only(test_shared(dir_b.path()).await, &url_b, Vec::new());
+        let (app_a, app_b) = (app(a.clone()), app(b.clone()));
+        tokio::spawn(async move { axum::serve(listener_a, app_a).await.unwrap() });
+        tokio::spawn(async move { axum::serve(listener_b, app_b).await.unwrap() });
+
+        let payload = b"\x89PNG pretend image bytes".to_vec();
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(payload.clone())
+                .file_name("pic.png")
+                .mime_str("image/png")
+                .unwrap(),
+        );
+        let resp: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/upload", url_a))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(resp["ticket"].is_null());
+        assert_eq!(resp["provider_url"], url_a.as_str());
+
+        let mut received = false;
+        for _ in 0..100 {
+            if b.state.lock().await.has_image {
+                received = true;
+                break;
+            }
+            sleep(Duration::from_millis(50)).await;
+        }
+        assert!(received, "node B never received the blob over HTTP");
+        let s = b.state.lock().await.clone();
+        assert_eq!(s.current_hash, resp["hash"].as_str().map(String::from));
+        assert_eq!(s.content_type.as_deref(), Some("image/png"));
+        assert!(s.stripe_providers.contains_key(&url_a));
+        assert_eq!(
+            fs::read(dir_b.path().join("current.img")).await.unwrap(),
+            payload
+        );
+    }
+
+    #[tokio::test]
+    async fn test_image_sidecar_restores_serving_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let hash = shared.store.add_slice(b"gif bytes").await.unwrap().hash;
+        fs::write(dir.path().join("current.img"), b"gif bytes")
+            .await
+            .unwrap();
+        {
+            let mut s = shared.state.lock().await;
+            s.current_filename = Some("anim.gif".into());
+            s.content_type = Some("image/gif".into());
+        }
+        shared.complete_image(hash).await;
+        let meta = image_meta::read(&dir.path().join("current.img"))
+            .await
+            .unwrap();
+        assert_eq!(meta.hash, hash.to_string());
+        assert_eq!(meta.bytes, 9);
+
+        // A restarted node has empty state: /image falls back to the sidecar
+        let restarted = Arc::new(NodeShared {
+            state: Arc::new(StateCell::new(NodeState::default())),
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(restarted.clone())).await;
+        let resp = reqwest::get(format!("{}/image", url)).await.unwrap();
+        assert_eq!(resp.headers()["content-type"], "image/gif");
+
+        restarted.restore_image_meta().await;
+        let s = restarted.state.lock().await.clone();
+        assert!(s.has_image);
+        assert_eq!(s.current_filename.as_deref(), Some("anim.gif"));
+        assert_eq!(s.current_hash, Some(hash.to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_content_type_change_reaches_image_and_sidecar() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let upload = |ct: &'static str| {
+            let part = reqwest::multipart::Part::bytes(b"same bytes".to_vec())
+                .file_name("pic")
+                .mime_str(ct)
+                .unwrap();
+            client
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+        };
+        let served_type = || async {
+            let resp = reqwest::get(format!("{}/image", url)).await.unwrap();
+            resp.headers()["content-type"].to_str().unwrap().to_string()
+        };
+        let sidecar_type = || async {
+            image_meta::read(&dir.path().join("current.img"))
+                .await
+                .unwrap()
+                .content_type
+        };
+
+        assert!(upload("image/png").await.unwrap().status().is_success());
+        assert_eq!(served_type().await, "image/png");
+
+        // Same content re-uploaded under a corrected type
+        assert!(upload("image/webp").await.unwrap().status().is_success());
+        assert_eq!(served_type().await, "image/webp");
+        assert_eq!(sidecar_type().await, "image/webp");
+
+        shared.set_content_type("image/avif").await;
+        assert_eq!(served_type().await, "image/avif");
+        assert_eq!(sidecar_type().await, "image/avif");
+    }
+
+    #[tokio::test]
+    async fn test_peer_resolved_then_lost_events() {
+        let peer_id = iroh::SecretKey::generate(thread_rng()).public();
+        let up = Arc::new(std::sync::atomic::AtomicBool::new(true));
+        let stub = Router::new().route(
+            "/status",
+            get({
+                let up = up.clone();
+                move || async move {
+                    if up.load(std::sync::atomic::Ordering::SeqCst) {
+                        Json(serde_json::json!({ "node_addr": peer_id.to_string() }))
+                            .into_response()
+                    } else {
+                        StatusCode::SERVICE_UNAVAILABLE.into_response()
+                    }
+                }
+            }),
+        );
+        let peer_url = spawn_stub(stub).await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![peer_url.clone()],
+            peer_addr_ttl: Some(Duration::from_millis(50)),
+            ..(*shared).clone()
+        });
+        let mut events = shared.events.subscribe();
+        let client = reqwest::Client::new();
+
+        assert_eq!(refresh_peer_addrs(&shared, &client).await, 1);
+        // Re-resolving the same node is not news
+        refresh_peer_addrs(&shared, &client).await;
+        up.store(false, std::sync::atomic::Ordering::SeqCst);
+        sleep(Duration::from_millis(100)).await;
+        assert_eq!(refresh_peer_addrs(&shared, &client).await, 0);
+
+        assert_eq!(
+            events.next().await,
+            Some(Event::PeerResolved {
+                url: peer_url.clone(),
+                node_id: peer_id.to_string(),
+                source: "status".into(),
+            })
+        );
+        assert_eq!(
+            events.next().await,
+            Some(Event::PeerLost {
+                url: peer_url,
+                node_id: peer_id.to_string(),
+                reason: "ttl".into(),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_oversized_peer_status_is_rejected() {
+        let peer_id = iroh::SecretKey::generate(thread_rng()).public();
+        // A /status that never ends, with no Content-Length to go by
+        let endless = Router::new().route(
+            "/status",
+            get(move || async move {
+                let head = format!("{{\"node_addr\":\"{peer_id}\",\"pad\":\"");
+                let pad = futures_util::stream::repeat(bytes::Bytes::from(vec![b'x'; 64 * 1024]));
+                let body = futures_util::stream::once(async move { bytes::Bytes::from(head) })
+                    .chain(pad)
+                    .map(Ok::<_, std::io::Error>);
+                axum::body::Body::from_stream(body)
+            }),
+        );
+        // Declared too large up front
+        let padded = Router::new().route(
+            "/status",
+            get(move || async move {
+                Json(serde_json::json!({
+                    "node_addr": peer_id.to_string(),
+                    "pad": "x".repeat(8 * 1024),
+                }))
+            }),
+        );
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![spawn_stub(endless).await, spawn_stub(padded).await],
+            peer_status_max_bytes: 4096,
+            ..(*shared).clone()
+        });
+        let client = reqwest::Client::new();
+
+        let started = std::time::Instant::now();
+        assert_eq!(refresh_peer_addrs(&shared, &client).await, 0);
+        assert!(
+            started.elapsed() < PEER_STATUS_TIMEOUT,
+            "{:?}",
+            started.elapsed()
+        );
+
+        // The same peer within the limit resolves
+        let shared = Arc::new(NodeShared {
+            peer_status_max_bytes: 64 * 1024,
+            ..(*shared).clone()
+        });
+        assert_eq!(refresh_peer_addrs(&shared, &client).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_active_search_probe_caps_peer_bodies() {
+        let peer_id = iroh::SecretKey::generate(thread_rng()).public();
+        let hash = iroh_blobs::Hash::new(b"wanted");
+        let endless = || {
+            let pad = futures_util::stream::repeat(bytes::Bytes::from(vec![b' '; 64 * 1024]));
+            axum::body::Body::from_stream(pad.map(Ok::<_, std::io::Error>))
+        };
+        let status =
+            move || async move { Json(serde_json::json!({ "node_addr": peer_id.to_string() })) };
+        // A /status that never ends, and one that's fine but a /blobs that isn't
+        let endless_status = Router::new().route("/status", get(move || async move { endless() }));
+        let endless_blobs = Router::new()
+            .route("/status", get(status))
+            .route("/blobs", get(move || async move { endless() }));
+        let listed = Router::new().route("/status", get(status)).route(
+            "/blobs",
+            get(move || async move {
+                Json(serde_json::json!({ "blobs": [{ "hash": hash.to_string() }] }))
+            }),
+        );
+        let client = reqwest::Client::new();
+
+        let started = std::time::Instant::now();
+        for stub in [endless_status, endless_blobs] {
+            let url = spawn_stub(stub).await;
+            assert!(probe_peer(client.clone(), url, hash, 4096).await.is_none());
+        }
+        assert!(
+            started.elapsed() < CLUSTER_STATUS_TIMEOUT,
+            "{:?}",
+            started.elapsed()
+        );
+        let url = spawn_stub(listed).await;
+        let (found, addr) = probe_peer(client, url.clone(), hash, 4096).await.unwrap();
+        assert_eq!((found, addr.node_id), (url, peer_id));
+    }
+
+    #[tokio::test]
+    async fn test_whoami_lists_only_bind_addrs() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            bind_addrs: BindAddrs::parse("192.0.2.1").0,
+            ..(*shared).clone()
+        });
+        let local = shared
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .node_addr()
+            .initialized()
+            .await;
+        assert!(!local.direct_addresses.is_empty());
+        let url = spawn_stub(app(shared)).await;
+        let v: serde_json::Value = reqwest::get(format!("{}/whoami", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        // The endpoint's own addresses are all outside the list
+        assert_eq!(v["direct_addresses"], serde_json::json!([]));
+    }
+
+    #[tokio::test]
+    async fn test_cluster_status_reports_unreachable_peers() {
+        let reachable = spawn_stub(Router::new().route(
+            "/status",
+            get(|| async { Json(serde_json::json!({ "node_name": "up" })) }),
+        ))
+        .await;
+        let (listener, unreachable) = bind_local().await;
+        drop(listener);
+
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        shared.state.lock().await.node_name = "me".into();
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![reachable.clone(), unreachable.clone()],
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let fetch = || async {
+            reqwest::get(format!("{}/cluster/status", url))
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        };
+
+        let nodes = fetch().await;
+        assert_eq!(nodes.as_array().unwrap().len(), 3);
+        assert_eq!(nodes[0]["self"], true);
+        assert_eq!(nodes[0]["status"]["node_name"], "me");
+        assert_eq!(nodes[1]["url"], reachable);
+        assert_eq!(nodes[1]["status"]["node_name"], "up");
+        assert_eq!(nodes[2]["url"], unreachable);
+        assert!(nodes[2]["error"].is_string());
+
+        // Served from cache within the window
+        shared.state.lock().await.node_name = "renamed".into();
+        assert_eq!(fetch().await[0]["status"]["node_name"], "me");
+    }
+
+    #[tokio::test]
+    async fn test_cluster_status_negotiates_brotli() {
+        use std::io::Read;
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared)).await;
+        let fetch = |accept: &'static str| {
+            reqwest::Client::new()
+                .get(format!("{}/cluster/status", url))
+                .header("Accept-Encoding", accept)
+                .send()
+        };
+
+        let resp = fetch("gzip, br").await.unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "br");
+        let compressed = resp.bytes().await.unwrap();
+        let mut json = Vec::new();
+        brotli_decompressor::Decompressor::new(&compressed[..], 4096)
+            .read_to_end(&mut json)
+            .unwrap();
+        let nodes: serde_json::Value = serde_json::from_slice(&json).unwrap();
+        assert_eq!(nodes[0]["self"], true);
+
+        // q-values win over the br preference
+        let resp = fetch("br;q=0.5, gzip").await.unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        let resp = fetch("identity").await.unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_answers_429_with_retry_after() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            rate_limit: Some(Arc::new(RateLimiter::new(1.0))),
+            ..(*shared).clone()
+        });
+        let (listener, url) = bind_local().await;
+        let service = app(shared).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+        let client = reqwest::Client::new();
+
+        let resp = client
+            .post(format!("{}/peers/reload", url))
+            .send()
+            .await
+            .unwrap();
+        assert_ne!(resp.status().as_u16(), 429);
+        let resp = client
+            .post(format!("{}/peers/reload", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 429);
+        assert_eq!(resp.headers()["retry-after"], "1");
+        // Reads are never limited
+        for _ in 0..3 {
+            let resp = client.get(format!("{}/status", url)).send().await.unwrap();
+            assert!(resp.status().is_success());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_block_active_content_rejects_html() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            block_active_content: true,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let upload = |body: &'static [u8], ct: &'static str| {
+            let part = reqwest::multipart::Part::bytes(body)
+                .file_name("f")
+                .mime_str(ct)
+                .unwrap();
+            reqwest::Client::new()
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+        };
+
+        let resp = upload(b"<html><script>steal()</script></html>", "text/html")
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 415);
+        // Disguised as an image it is still sniffed out
+        let resp = upload(b"<!doctype html><p>hi", "image/png").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 415);
+        assert!(!shared.state.lock().await.has_image);
+
+        let resp = upload(b"\x89PNG\r\n\x1a\n", "image/png").await.unwrap();
+        assert!(resp.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_svg_is_served_as_attachment() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared)).await;
+        let part = reqwest::multipart::Part::bytes(&b"<svg onload=\"alert(1)\"/>"[..])
+            .file_name("x.svg")
+            .mime_str("image/svg+xml")
+            .unwrap();
+        // Permissive mode: accepted, but never rendered inline
+        let resp = reqwest::Client::new()
+            .post(format!("{}/upload", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+
+        let resp = reqwest::get(format!("{}/image", url)).await.unwrap();
+        assert_eq!(resp.headers()["content-type"], "image/svg+xml");
+        assert_eq!(
+            resp.headers()["content-disposition"],
+            "attachment; filename=\"x.svg\""
+        );
+        assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+    }
+
+    #[tokio::test]
+    async fn test_gzip_image_passthrough_or_decompressed() {
+        use std::io::Write;
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(&[42u8; 1000]).unwrap();
+        let gz = enc.finish().unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared)).await;
+        let mut part_headers = reqwest::header::HeaderMap::new();
+        part_headers.insert("Content-Encoding", "gzip".parse().unwrap());
+        let part = reqwest::multipart::Part::bytes(gz.clone())
+            .file_name("big.bmp")
+            .mime_str("image/bmp")
+            .unwrap()
+            .headers(part_headers);
+        // No automatic decompression: the raw response is what's checked
+        let client = reqwest::Client::new();
+        let resp = client
+            .post(format!("{}/upload", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        let meta = image_meta::read(&dir.path().join("current.img"))
+            .await
+            .unwrap();
+        assert_eq!(meta.content_encoding.as_deref(), Some("gzip"));
+
+        let resp = client
+            .get(format!("{}/image", url))
+            .header("Accept-Encoding", "br, gzip")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+        assert_eq!(resp.bytes().await.unwrap(), gz);
+
+        let resp = client
+            .get(format!("{}/image", url))
+            .header("Accept-Encoding", "identity")
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.headers()["content-length"], "1000");
+        assert_eq!(resp.headers()["content-type"], "image/bmp");
+        assert_eq!(resp.bytes().await.unwrap(), vec![42u8; 1000]);
+    }
+
+    /// `data` gzip-compressed.
+    fn gzipped(data: &[u8]) -> Vec<u8> {
+        use std::io::Write;
+        let mut enc = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        enc.write_all(data).unwrap();
+        enc.finish().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_gzip_negotiated_on_stream_and_head() {
+        let gz = gzipped(&[42u8; 1000]);
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared)).await;
+        let client = reqwest::Client::new();
+        let upload = |body: Vec<u8>, encoding: &'static str| {
+            let mut part_headers = reqwest::header::HeaderMap::new();
+            part_headers.insert("Content-Encoding", encoding.parse().unwrap());
+            let part = reqwest::multipart::Part::bytes(body)
+                .file_name("big.bmp")
+                .headers(part_headers);
+            client
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+        };
+        // Only encodings we can decode for other clients are stored
+        assert_eq!(upload(gz.clone(), "br").await.unwrap().status(), 415);
+        assert_eq!(
+            upload(b"not gzip".to_vec(), "gzip").await.unwrap().status(),
+            400
+        );
+        assert!(upload(gz.clone(), "gzip")
+            .await
+            .unwrap()
+            .status()
+            .is_success());
+
+        let request = |method: reqwest::Method, path: &str, accept: &str| {
+            client
+                .request(method, format!("{}{}", url, path))
+                .header("Accept-Encoding", accept)
+                .send()
+        };
+        let resp = request(reqwest::Method::GET, "/image_stream", "gzip")
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+        assert_eq!(resp.bytes().await.unwrap(), gz);
+        let resp = request(reqwest::Method::GET, "/image_stream", "identity")
+            .await
+            .unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.headers()["content-length"], "1000");
+        assert_eq!(resp.bytes().await.unwrap(), vec![42u8; 1000]);
+
+        for path in ["/image", "/image_stream"] {
+            let resp = request(reqwest::Method::HEAD, path, "gzip").await.unwrap();
+            assert_eq!(resp.headers()["content-encoding"], "gzip", "{path}");
+            assert_eq!(
+                resp.headers()["content-length"],
+                gz.len().to_string().as_str()
+            );
+            let resp = request(reqwest::Method::HEAD, path, "identity")
+                .await
+                .unwrap();
+            assert!(resp.headers().get("content-encoding").is_none(), "{path}");
+            assert_eq!(resp.headers()["content-length"], "1000", "{path}");
+            assert_eq!(resp.headers()["vary"], "Accept-Encoding");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_receive_keeps_announced_content_encoding() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let gz = gzipped(&[7u8; 4000]);
+        let tag = provider.store.add_slice(&gz).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        receiver
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .add_node_addr(ticket.node_addr().clone())
+            .unwrap();
+        let url = spawn_stub(app(receiver.clone())).await;
+        let receive = |encoding: &str| {
+            reqwest::Client::new()
+                .post(format!("{}/receive", url))
+                .json(&serde_json::json!({
+                    "hash": ticket.hash().to_string(),
+                    "filename": "r.bmp",
+                    "content_type": "image/bmp",
+                    "provider_node_id": ticket.node_addr().node_id.to_string(),
+                    "content_encoding": encoding,
+                }))
+                .send()
+        };
+        assert_eq!(receive("compress").await.unwrap().status(), 400);
+        assert!(receive("gzip").await.unwrap().status().is_success());
+
+        let s = receiver.state.lock().await.clone();
+        assert!(s.has_image);
+        assert_eq!(s.content_encoding.as_deref(), Some("gzip"));
+        let resp = reqwest::Client::new()
+            .get(format!("{}/image", url))
+            .header("Accept-Encoding", "identity")
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(resp.bytes().await.unwrap(), vec![7u8; 4000]);
+    }
+
+    #[tokio::test]
+    async fn test_throughput_to_peer() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let sender = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(sender.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, sender.blobs.clone().unwrap())
+            .spawn();
+        let peer = test_shared(dir_b.path()).await;
+        let peer_url = spawn_stub(app(peer.clone())).await;
+        let sender = Arc::new(NodeShared {
+            peers_http: vec![peer_url.clone()],
+            ..(*sender).clone()
+        });
+        let url = spawn_stub(app(sender.clone())).await;
+
+        // Only configured peers can be measured against
+        let resp = reqwest::Client::new()
+            .post(format!("{}/throughput", url))
+            .json(&serde_json::json!({ "peer_url": "http://127.0.0.1:1", "bytes": 1024 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 400);
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/throughput", url))
+            .json(&serde_json::json!({ "peer_url": format!("{peer_url}/"), "bytes": 256 * 1024 }))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+        let v: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(v["bytes"], 256 * 1024);
+        assert!(v["throughput_bps"].as_u64().unwrap() > 0);
+        assert_eq!(
+            v["node_id"],
+            peer.endpoint.as_ref().unwrap().node_id().to_string()
+        );
+        assert!(["direct", "relay", "mixed", "none"].contains(&v["transport"].as_str().unwrap()));
+        // The peer's image and store are untouched, and our test blob is untagged
+        assert!(!peer.state.lock().await.has_image);
+        assert_eq!(peer.store.tags().list().await.unwrap().count().await, 0);
+        assert_eq!(sender.store.tags().list().await.unwrap().count().await, 0);
+
+        // Runs are capped; with every slot taken the next one is turned away
+        let held = sender
+            .throughput_slots
+            .try_acquire_many(THROUGHPUT_MAX_RUNS as u32)
+            .unwrap();
+        let resp = reqwest::Client::new()
+            .post(format!("{}/throughput", url))
+            .json(&serde_json::json!({ "peer_url": peer_url, "bytes": 1024 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 429);
+        drop(held);
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/throughput", url))
+            .json(&serde_json::json!({ "peer_url": peer_url, "bytes": 0 }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_single_provider_parallel_ranges_download_whole_blob() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let data = random_blob();
+        let tag = provider.store.add_slice(&data).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        let receiver = Arc::new(NodeShared {
+            single_provider_parallelism: 4,
+            ..(*receiver).clone()
+        });
+        let mut events = receiver.events.subscribe();
+        receiver
+            .receive_by_discovery(
+                ticket.hash(),
+                "big.bin".into(),
+                "application/octet-stream".into(),
+                Some(ticket.node_addr().clone()),
+            )
+            .await
+            .unwrap();
+
+        let exported = fs::read(dir_b.path().join("current.img")).await.unwrap();
+        assert_eq!(exported, data);
+        let s = receiver.state.lock().await.clone();
+        assert_eq!(s.current_hash, Some(ticket.hash().to_string()));
+        assert_eq!(s.bytes_total, Some(data.len() as u64));
+        assert_eq!(
+            s.stripe_providers[&provider.endpoint.as_ref().unwrap().node_id().to_string()].len(),
+            4
+        );
+        let mut parts = 0;
+        while let Ok(Some(event)) =
+            tokio::time::timeout(Duration::from_millis(200), events.next()).await
+        {
+            parts += matches!(event, Event::PartComplete { .. }) as usize;
+        }
+        assert_eq!(parts, 4);
+    }
+
+    #[tokio::test]
+    async fn test_receive_splits_across_all_hinted_providers() {
+        let data = random_blob();
+        let seeded = spawn_blob_providers(2, &data).await;
+        let providers = &seeded.addrs;
+        let hash = iroh_blobs::Hash::new(&data);
+
+        // Known to the endpoint, but not as peers: only the hints name them
+        let dir = tempfile::tempdir().unwrap();
+        let receiver = test_shared(dir.path()).await;
+        seeded.introduce_to(&receiver);
+        let mut changes = receiver.state.subscribe();
+        let watched = receiver.clone();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let strategies = tokio::spawn({
+            let seen = seen.clone();
+            async move {
+                while changes.changed().await.is_ok() {
+                    if let Some(s) = watched.state.lock().await.download_strategy.clone() {
+                        seen.lock().unwrap().push((s.mode, s.providers));
+                    }
+                }
+            }
+        });
+        let url = spawn_stub(app(receiver.clone())).await;
+        let ids = format!(
+            "{}, {},not-a-node-id",
+            providers[0].node_id, providers[1].node_id
+        );
+        let resp = reqwest::Client::new()
+            .post(format!("{}/receive", url))
+            .json(&serde_json::json!({
+                "hash": hash.to_string(),
+                "filename": "seeded.bin",
+                "content_type": "application/octet-stream",
+                "provider_node_ids": ids,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            fs::read(dir.path().join("current.img")).await.unwrap(),
+            data
+        );
+        strategies.abort();
+        let seen = seen.lock().unwrap().clone();
+        assert!(seen.contains(&("split".to_string(), 2)), "{seen:?}");
+
+        // The array form, repeating provider_node_id, names the same two
+        let body: ReceiveBody = serde_json::from_value(serde_json::json!({
+            "hash": hash.to_string(),
+            "filename": "x",
+            "content_type": "x",
+            "provider_node_id": providers[0].node_id.to_string(),
+            "provider_node_ids": [providers[0].node_id.to_string(), providers[1].node_id.to_string()],
+        }))
+        .unwrap();
+        let first = body
+            .provider_node_id
+            .as_deref()
+            .and_then(|s| s.parse::<PublicKey>().ok())
+            .map(NodeAddr::from);
+        let hinted: Vec<_> = hinted_providers(first, &body.provider_node_ids)
+            .into_iter()
+            .map(|a| a.node_id)
+            .collect();
+        assert_eq!(hinted, [providers[0].node_id, providers[1].node_id]);
+
+        // A flood of hints is cut down to the first few
+        let many: Vec<String> = (0..40)
+            .map(|_| iroh::SecretKey::generate(thread_rng()).public().to_string())
+            .collect();
+        let hinted = hinted_providers(Some(providers[0].clone()), &many);
+        assert_eq!(hinted.len(), MAX_HINTED_PROVIDERS);
+        assert_eq!(hinted[0].node_id, providers[0].node_id);
+        assert_eq!(hinted[1].node_id.to_string(), many[0]);
+    }
+
+    #[tokio::test]
+    async fn test_failed_split_pauses_before_sequential() {
+        // Reachable, but neither holds the blob: the split fails at once
+        let empty = spawn_blob_providers(2, &[]).await;
+        let dir = tempfile::tempdir().unwrap();
+        let receiver = test_shared(dir.path()).await;
+        let receiver = Arc::new(NodeShared {
+            fallback_delay: Duration::from_millis(400),
+            seq_shuffle_seed: Some(11),
+            ..(*receiver).clone()
+        });
+        let expected = jittered(
+            Duration::from_millis(400),
+            &mut <rand::rngs::StdRng as rand::SeedableRng>::seed_from_u64(11),
+        );
+        assert!(expected >= Duration::from_millis(200));
+
+        let mut changes = receiver.state.subscribe();
+        let watched = receiver.clone();
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let strategies = tokio::spawn({
+            let seen = seen.clone();
+            async move {
+                while changes.changed().await.is_ok() {
+                    if let Some(s) = watched.state.lock().await.download_strategy.clone() {
+                        seen.lock()
+                            .unwrap()
+                            .push((s.mode, std::time::Instant::now()));
+                    }
+                }
+            }
+        });
+        let hash = iroh_blobs::Hash::new(b"nobody has this");
+        assert!(receiver
+            .receive_from_providers(
+                hash,
+                "x.bin".into(),
+                "application/octet-stream".into(),
+                empty.addrs.clone()
+            )
+            .await
+            .is_err());
+        strategies.abort();
+
+        let seen = seen.lock().unwrap().clone();
+        let split = seen.iter().rev().find(|(mode, _)| mode == "split");
+        let sequential = seen.iter().find(|(mode, _)| mode == "sequential");
+        let (Some((_, split_at)), Some((_, sequential_at))) = (split, sequential) else {
+            panic!("expected a split then a sequential attempt: {seen:?}");
+        };
+        assert!(
+            sequential_at.duration_since(*split_at) >= expected,
+            "{:?} < {expected:?}",
+            sequential_at.duration_since(*split_at)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_chunk_stripe_mode_spreads_stripes_across_providers() {
+        let data = random_blob();
+        let seeded = spawn_blob_providers(2, &data).await;
+        let hash = iroh_blobs::Hash::new(&data);
+
+        let dir = tempfile::tempdir().unwrap();
+        let receiver = test_shared(dir.path()).await;
+        let receiver = Arc::new(NodeShared {
+            download_mode: DownloadMode::ChunkStripe,
+            ..(*receiver).clone()
+        });
+        seeded.introduce_to(&receiver);
+        receiver
+            .begin_transfer("t", hash, "striped.bin", "application/octet-stream")
+            .await;
+        // Counted as they come; progress events would crowd them out of the bus
+        let mut events = receiver.events.subscribe();
+        let tried: Arc<std::sync::Mutex<HashMap<String, usize>>> = Arc::default();
+        let counter = tried.clone();
+        let counting = tokio::spawn(async move {
+            while let Some(event) = events.next().await {
+                if let Event::ProviderTried { provider, .. } = event {
+                    *counter.lock().unwrap().entry(provider).or_default() += 1;
+                }
+            }
+        });
+        receiver
+            .attempt_split_download(
+                hash,
+                "striped.bin",
+                "application/octet-stream",
+                seeded.node_ids(),
+                None,
+            )
+            .await
+            .unwrap();
+
+        let exported = fs::read(dir.path().join("current.img")).await.unwrap();
+        assert_eq!(exported, data);
+        let s = receiver.state.lock().await.clone();
+        assert_eq!(s.bytes_received, data.len() as u64);
+        // 301 chunks in stripes of 38, each led by the two providers in turn
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        counting.abort();
+        let tried = tried.lock().unwrap().clone();
+        let mut stripes = 0;
+        for node_id in seeded.node_ids() {
+            let id = node_id.to_string();
+            assert!(tried.get(&id).is_some_and(|&n| n >= 4), "{tried:?}");
+            let labels = s.stripe_providers.get(&id).cloned().unwrap_or_default();
+            assert!(labels.iter().all(|l| l.starts_with("offset=0 ranges=")));
+            stripes += labels.len();
+        }
+        assert_eq!(stripes, 8);
+    }
+
+    #[tokio::test]
+    async fn test_receive_stripe_span_sizes_the_stripes() {
+        let data = random_blob();
+        let seeded = spawn_blob_providers(2, &data).await;
+        let hash = iroh_blobs::Hash::new(&data);
+
+        let dir = tempfile::tempdir().unwrap();
+        let receiver = test_shared(dir.path()).await;
+        let receiver = Arc::new(NodeShared {
+            download_mode: DownloadMode::ChunkStripe,
+            ..(*receiver).clone()
+        });
+        seeded.introduce_to(&receiver);
+        let url = spawn_stub(app(receiver.clone())).await;
+        let receive = |stripe_span: u64| {
+            reqwest::Client::new()
+                .post(format!("{}/receive", url))
+                .json(&serde_json::json!({
+                    "hash": hash.to_string(),
+                    "filename": "s.bin",
+                    "content_type": "application/octet-stream",
+                    "provider_node_ids": seeded
+                        .node_ids()
+                        .iter()
+                        .map(|id| id.to_string())
+                        .collect::<Vec<_>>(),
+                    "stripe_span": stripe_span,
+                }))
+                .send()
+        };
+
+        for bad in [0, chunk_strategy::MAX_STRIPE_SPAN + 1] {
+            let resp = receive(bad).await.unwrap();
+            assert_eq!(resp.status(), 400);
+            assert!(resp.text().await.unwrap().contains("stripe_span"));
+        }
+        assert_eq!(receive(100).await.unwrap().status(), 200);
+
+        // 301 chunks in stripes of 100: three full ones and a 1-chunk tail
+        let s = receiver.state.lock().await.clone();
+        assert!(s.has_image);
+        let stripes: usize = s.stripe_providers.values().map(Vec::len).sum();
+        assert_eq!(stripes, 4, "{:?}", s.stripe_providers);
+    }
+
+    #[tokio::test]
+    async fn test_bytes_total_known_before_first_progress() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let data = vec![7u8; 200 * 1024 + 5];
+        let tag = provider.store.add_slice(&data).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        receiver
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .add_node_addr(ticket.node_addr().clone())
+            .unwrap();
+        let mut events = receiver.events.subscribe();
+        receiver
+            .receive_with_progress(ticket, "big.bin".into(), "application/octet-stream".into())
+            .await
+            .unwrap();
+
+        let total = data.len() as u64;
+        let mut progress = Vec::new();
+        while let Ok(Some(event)) =
+            tokio::time::timeout(Duration::from_millis(200), events.next()).await
+        {
+            if let Event::Progress {
+                bytes_received,
+                bytes_total,
+                ..
+            } = event
+            {
+                progress.push((bytes_received, bytes_total));
+            }
+        }
+        // Every report mid-transfer already carried the real total
+        assert!(progress.iter().any(|(recvd, _)| *recvd < total));
+        assert!(
+            progress.iter().all(|(_, t)| *t == Some(total)),
+            "{progress:?}"
+        );
+        let s = receiver.state.lock().await.clone();
+        assert_eq!((s.bytes_total, s.bytes_received), (Some(total), total));
+        assert_eq!(s.progress, 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_active_search_finds_the_one_peer_holding_hash() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider
+            .store
+            .add_slice(b"only one peer has this")
+            .await
+            .unwrap();
+        let hash = tag.hash;
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        // Two peers that don't hold it, and one that keeps it in its store
+        let peer = |node_id: String, blobs: Vec<String>| {
+            Router::new()
+                .route(
+                    "/status",
+                    get(move || async move {
+                        Json(serde_json::json!({ "node_addr": node_id, "has_image": false }))
+                    }),
+                )
+                .route(
+                    "/blobs",
+                    get(move || async move {
+                        let blobs: Vec<_> = blobs
+                            .iter()
+                            .map(|hash| serde_json::json!({ "hash": hash }))
+                            .collect();
+                        Json(serde_json::json!({ "blobs": blobs }))
+                    }),
+                )
+        };
+        let other = || iroh::SecretKey::generate(thread_rng()).public().to_string();
+        let holder = spawn_stub(peer(
+            ticket.node_addr().node_id.to_string(),
+            vec![hash.to_string()],
+        ))
+        .await;
+        let peers_http = vec![
+            spawn_stub(peer(other(), vec![])).await,
+            holder.clone(),
+            spawn_stub(peer(other(), vec!["ff".repeat(32)])).await,
+        ];
+
+        let receiver = test_shared(dir_b.path()).await;
+        receiver
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .add_node_addr(ticket.node_addr().clone())
+            .unwrap();
+        let passive = Arc::new(NodeShared {
+            peers_http: peers_http.clone(),
+            ..(*receiver).clone()
+        });
+        let err = passive
+            .receive_by_discovery(hash, "f.bin".into(), "text/plain".into(), None)
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<NoCandidates>().is_some(), "{err}");
+        // The peers are configured; only the fallback is missing
+        let msg = err.to_string();
+        assert!(msg.contains("none of the 3 configured peers"), "{msg}");
+        assert!(msg.contains("HTTP_BLOB_FALLBACK is off"), "{msg}");
+        assert!(!msg.contains("configure PEER_HTTP_URLS"), "{msg}");
+
+        let searching = Arc::new(NodeShared {
+            peers_http,
+            active_search: true,
+            ..(*receiver).clone()
+        });
+        searching
+            .receive_by_discovery(hash, "f.bin".into(), "text/plain".into(), None)
+            .await
+            .unwrap();
+        assert_eq!(
+            fs::read(dir_b.path().join("current.img")).await.unwrap(),
+            b"only one peer has this"
+        );
+        let known = searching.peers_addrs.lock().await.clone();
+        assert_eq!(known[&holder].node_id, ticket.node_addr().node_id);
+    }
+
+    #[tokio::test]
+    async fn test_provider_egress_is_throttled() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let provider = Arc::new(NodeShared {
+            egress: Some(Arc::new(egress::EgressLimiter::new(512 * 1024))),
+            ..(*provider).clone()
+        });
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(
+                iroh_blobs::ALPN,
+                egress::ThrottledBlobs {
+                    blobs: provider.blobs.clone().unwrap(),
+                    limiter: provider.egress.clone(),
+                },
+            )
+            .spawn();
+        let data: Vec<u8> = (0..768 * 1024).map(|i| (i % 251) as u8).collect();
+        let tag = provider.store.add_slice(&data).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        receiver
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .add_node_addr(ticket.node_addr().clone())
+            .unwrap();
+        let start = std::time::Instant::now();
+        receiver
+            .receive_with_progress(ticket, "big.bin".into(), "application/octet-stream".into())
+            .await
+            .unwrap();
+        // 768 KiB at 512 KiB/s, less the 0.1 s burst
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(1300), "{elapsed:?}");
+        let served = fs::read(dir_b.path().join("current.img")).await.unwrap();
+        assert_eq!(served, data);
+        let egress_bps = provider.status_snapshot().await.egress_bps.unwrap();
+        assert!(egress_bps > 0);
+    }
+
+    #[tokio::test]
+    async fn test_sniffed_content_type_overrides_declared() {
+        let dir_a = tempfile::tempdir().unwrap();
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&[0u8; 64]);
+        let tag = provider.store.add_slice(&png).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        for (check, served, flagged) in [
+            (ContentTypeCheck::Correct, "image/png", false),
+            (ContentTypeCheck::Flag, "text/plain", true),
+        ] {
+            let dir = tempfile::tempdir().unwrap();
+            let receiver = test_shared(dir.path()).await;
+            let receiver = Arc::new(NodeShared {
+                verify_content_type: check,
+                ..(*receiver).clone()
+            });
+            receiver
+                .endpoint
+                .as_ref()
+                .unwrap()
+                .add_node_addr(ticket.node_addr().clone())
+                .unwrap();
+            receiver
+                .receive_with_progress(ticket.clone(), "a.png".into(), "text/plain".into())
+                .await
+                .unwrap();
+            let s = receiver.state.lock().await.clone();
+            assert_eq!(s.content_type.as_deref(), Some(served), "{check:?}");
+            assert_eq!(s.last_error.is_some(), flagged, "{check:?}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_discovered_receive_types_held_copy_and_sidecar() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+        png.extend_from_slice(&[0u8; 64]);
+        let tag = provider.store.add_slice(&png).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        let receiver = Arc::new(NodeShared {
+            verify_content_type: ContentTypeCheck::Correct,
+            image_sidecar: true,
+            ..(*receiver).clone()
+        });
+        receiver
+            .receive_by_discovery(
+                ticket.hash(),
+                "a.png".into(),
+                "text/plain".into(),
+                Some(ticket.node_addr().clone()),
+            )
+            .await
+            .unwrap();
+
+        // The sequential path serves, holds and records the corrected type
+        let s = receiver.state.lock().await.clone();
+        assert_eq!(s.content_type.as_deref(), Some("image/png"));
+        let held = s
+            .images
+            .iter()
+            .find(|m| m.hash == ticket.hash().to_string());
+        assert_eq!(held.unwrap().content_type, "image/png");
+        let sidecar = image_meta::read(&dir_b.path().join("current.img"))
+            .await
+            .unwrap();
+        assert_eq!(sidecar.content_type, "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_peers_reports_known_addresses_and_refresh() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let (resolved, pending) = ("http://a:3000".to_string(), "http://b:3000".to_string());
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![resolved.clone(), pending.clone()],
+            ..(*shared).clone()
+        });
+        let peer_id = iroh::SecretKey::generate(thread_rng()).public();
+        let addr = NodeAddr::new(peer_id).with_direct_addresses(["10.0.0.7:4433".parse().unwrap()]);
+        shared.record_peer_addr(&resolved, addr, "status").await;
+        shared
+            .peer_addr_seen
+            .lock()
+            .await
+            .insert(resolved.clone(), std::time::Instant::now());
+
+        let url = spawn_stub(app(shared)).await;
+        let body: serde_json::Value = reqwest::get(format!("{}/peers", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let peers = body["peers"].as_array().unwrap();
+        assert_eq!(peers[0]["url"], resolved.as_str());
+        assert_eq!(peers[0]["resolved"], true);
+        assert_eq!(peers[0]["node_id"], peer_id.to_string().as_str());
+        assert_eq!(
+            peers[0]["direct_addresses"],
+            serde_json::json!(["10.0.0.7:4433"])
+        );
+        assert!(peers[0]["refreshed_ms_ago"].as_u64().unwrap() < 60_000);
+        assert_eq!(peers[1]["resolved"], false);
+        assert_eq!(peers[1]["node_id"], serde_json::Value::Null);
+        assert_eq!(peers[1]["direct_addresses"], serde_json::json!([]));
+        assert_eq!(peers[1]["refreshed_ms_ago"], serde_json::Value::Null);
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics_bundle_requires_token() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let resp = reqwest::get(format!("{}/diagnostics", url)).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 404);
+
+        let log_ring = LogRing::new(10);
+        let shared = Arc::new(NodeShared {
+            diagnostics_token: Some("t0ken".into()),
+            log_ring: log_ring.clone(),
+            ..(*shared).clone()
+        });
+        {
+            use tracing_subscriber::layer::SubscriberExt;
+            let subscriber = tracing_subscriber::registry().with(log_ring);
+            tracing::subscriber::with_default(subscriber, || info!("before the report"));
+        }
+        let url = spawn_stub(app(shared)).await;
+        let client = reqwest::Client::new();
+        let get = |token: &'static str| {
+            client
+                .get(format!("{}/diagnostics", url))
+                .bearer_auth(token)
+                .send()
+        };
+        assert_eq!(get("wrong").await.unwrap().status().as_u16(), 401);
+        let bundle: serde_json::Value = get("t0ken").await.unwrap().json().await.unwrap();
+        for section in [
+            "version",
+            "config",
+            "status",
+            "whoami",
+            "peers",
+            "store",
+            "transfers",
+        ] {
+            assert!(!bundle[section].is_null(), "missing {section}");
+        }
+        assert_eq!(bundle["config"]["demo_mode"], true);
+        let logs = bundle["logs"].as_array().unwrap();
+        assert!(logs[0].as_str().unwrap().ends_with("before the report"));
+        assert!(!bundle.to_string().contains("t0ken"));
+    }
+
+    #[tokio::test]
+    async fn test_keep_image_during_receive_serves_old_image() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider.store.add_slice(b"new image").await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        let receiver = Arc::new(NodeShared {
+            keep_image_during_receive: true,
+            ..(*receiver).clone()
+        });
+        let url = spawn_stub(app(receiver.clone())).await;
+        let part = reqwest::multipart::Part::bytes(&b"old image"[..]).file_name("old.bin");
+        reqwest::Client::new()
+            .post(format!("{}/upload", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap();
+        let old_hash = iroh_blobs::Hash::new(b"old image").to_string();
+        let image = || async {
+            let resp = reqwest::get(format!("{}/image", url)).await.unwrap();
+            let etag = resp.headers()["etag"].to_str().unwrap().to_string();
+            (etag, resp.bytes().await.unwrap())
+        };
+
+        // The new transfer has started but not finished
+        receiver
+            .begin_transfer("t", ticket.hash(), "new.bin", "image/png")
+            .await;
+        let s = receiver.state.lock().await.clone();
+        assert!(s.has_image);
+        assert_eq!(s.current_hash.as_ref(), Some(&old_hash));
+        assert_eq!(
+            image().await,
+            (format!("\"{old_hash}\""), "old image".into())
+        );
+
+        receiver
+            .receive_by_discovery(
+                ticket.hash(),
+                "new.bin".into(),
+                "image/png".into(),
+                Some(ticket.node_addr().clone()),
+            )
+            .await
+            .unwrap();
+        let s = receiver.state.lock().await.clone();
+        assert_eq!(s.current_hash, Some(ticket.hash().to_string()));
+        assert_eq!(s.current_filename.as_deref(), Some("new.bin"));
+        assert_eq!(
+            image().await,
+            (format!("\"{}\"", ticket.hash()), "new image".into())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_declared_size_mismatch_rejects_receive() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider.store.add_slice(vec![8u8; 1000]).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        let receiver = Arc::new(NodeShared {
+            size_tolerance: Some(1.0),
+            ..(*receiver).clone()
+        });
+        let receive = |declared_size: u64| {
+            receiver.track_receive(InFlight {
+                declared_size: Some(declared_size),
+                ..InFlight::new(
+                    new_transfer_id(),
+                    ticket.hash().to_string(),
+                    "p.bin".into(),
+                    "application/octet-stream".into(),
+                    vec![ticket.node_addr().clone()],
+                )
+            })
+        };
+
+        // The notify claimed twice the size that arrived
+        let err = receive(2000).await.unwrap_err();
+        assert!(err.downcast_ref::<SizeMismatch>().is_some(), "{err:?}");
+        let s = receiver.state.lock().await.clone();
+        assert!(!s.has_image);
+        assert!(s.last_error.unwrap().contains("2000 were declared"));
+        assert_eq!(s.declared_size, None);
+        assert!(!dir_b.path().join("current.img").exists());
+
+        // Within 1% is accepted, and the declaration ends with the transfer
+        receive(1005).await.unwrap();
+        let s = receiver.state.lock().await.clone();
+        assert!(s.has_image);
+        assert_eq!(s.declared_size, None);
+
+        // A bad declaration for the blob we already serve doesn't discard it
+        receive(2000).await.unwrap_err();
+        let mut tags = receiver.store.tags().list().await.unwrap();
+        let mut tagged = false;
+        while let Some(tag) = tags.next().await {
+            tagged |= tag.unwrap().hash == ticket.hash();
+        }
+        assert!(tagged, "current image was untagged");
+    }
+
+    #[tokio::test]
+    async fn test_declared_size_is_recorded_only_for_accepted_notifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        assert!(shared.adopt_version(5, "z").await);
+        let url = spawn_stub(app(shared.clone())).await;
+        let hash = iroh_blobs::Hash::new(b"declared");
+        let resp = reqwest::Client::new()
+            .post(format!("{}/receive", url))
+            .json(&serde_json::json!({
+                "hash": hash.to_string(),
+                "filename": "x.png",
+                "content_type": "image/png",
+                "version": 1,
+                "bytes_total": 1234,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 409);
+        // A stale notify leaves no size behind to skew later progress
+        assert_eq!(shared.state.lock().await.declared_size, None);
+        assert_eq!(shared.peer_image_size(&hash.to_string()).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_status_resolves_transfer_by_id() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider
+            .store
+            .add_slice(vec![5u8; 32 * 1024])
+            .await
+            .unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        let url = spawn_stub(app(receiver.clone())).await;
+        let mut changed = receiver.image_changed.subscribe();
+        // What the notify handler does: start the download, ack with the id
+        let transfer_id = receiver
+            .spawn_receive(
+                ticket.hash(),
+                "p.bin".into(),
+                "application/octet-stream".into(),
+                None,
+                None,
+                Some(ticket.node_addr().clone()),
+            )
+            .await;
+
+        let status_of = |id: String| {
+            let url = url.clone();
+            async move {
+                reqwest::get(format!("{}/status?transfer={}", url, id))
+                    .await
+                    .unwrap()
+            }
+        };
+        // Resolvable as soon as the ack would be sent
+        let resp = status_of(transfer_id.clone()).await;
+        assert!(resp.status().is_success());
+        let s: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(s["transfer_id"], transfer_id);
+
+        tokio::time::timeout(Duration::from_secs(10), changed.changed())
+            .await
+            .expect("transfer never completed")
+            .unwrap();
+        let done: serde_json::Value = status_of(transfer_id.clone()).await.json().await.unwrap();
+        assert_eq!(done["transfer_id"], transfer_id);
+        assert_eq!(done["has_image"], true);
+        assert_eq!(done["current_hash"], ticket.hash().to_string());
+
+        assert_eq!(
+            status_of("someone-else".into()).await.status().as_u16(),
+            404
+        );
+    }
+
+    #[tokio::test]
+    async fn test_pause_holds_receive_until_resume() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider
+            .store
+            .add_slice(vec![3u8; 16 * 1024])
+            .await
+            .unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let receiver = test_shared(dir_b.path()).await;
+        let url = spawn_stub(app(receiver.clone())).await;
+        let client = reqwest::Client::new();
+        let status = || async {
+            reqwest::get(format!("{}/status", url))
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        };
+
+        let resp = client.post(format!("{}/pause", url)).send().await.unwrap();
+        assert!(resp.status().is_success());
+        assert_eq!(status().await["paused"], true);
+        receiver
+            .spawn_receive(
+                ticket.hash(),
+                "p.bin".into(),
+                "application/octet-stream".into(),
+                None,
+                None,
+                Some(ticket.node_addr().clone()),
+            )
+            .await;
+        sleep(Duration::from_millis(300)).await;
+        let s = status().await;
+        assert_eq!(s["has_image"], false);
+        assert_eq!(s["bytes_received"], 0);
+        assert_eq!(receiver.inflight.lock().await.transfers().len(), 1);
+
+        client.post(format!("{}/resume", url)).send().await.unwrap();
+        let started = std::time::Instant::now();
+        loop {
+            let s = status().await;
+            if s["has_image"] == true {
+                assert_eq!(s["paused"], false);
+                assert_eq!(s["current_hash"], ticket.hash().to_string());
+                break;
+            }
+            assert!(started.elapsed() < Duration::from_secs(10), "never resumed");
+            sleep(Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_whoami_shape() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        shared.state.lock().await.node_name = "alpha".into();
+        let url = spawn_stub(app(shared.clone())).await;
+        let whoami = || async {
+            reqwest::get(format!("{}/whoami", url))
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()
+        };
+
+        let v = whoami().await;
+        let node_id = shared.endpoint.as_ref().unwrap().node_id();
+        assert_eq!(v["node_id"], node_id.to_string());
+        assert_eq!(v["node_name"], "alpha");
+        assert!(v["direct_addresses"].is_array());
+        assert!(v["relay_url"].is_null());
+        assert!(v["ticket_for_current_image"].is_null());
+
+        let hash = shared.store.add_slice(b"img").await.unwrap().hash;
+        {
+            let mut s = shared.state.lock().await;
+            s.has_image = true;
+            s.current_hash = Some(hash.to_string());
+        }
+        let v = whoami().await;
+        let ticket: iroh_blobs::ticket::BlobTicket = v["ticket_for_current_image"]
+            .as_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(ticket.hash(), hash);
+        assert_eq!(ticket.node_addr().node_id, node_id);
+    }
+
+    #[tokio::test]
+    async fn test_announce_resolves_peers_immediately() {
+        let dir = tempfile::tempdir().unwrap();
+        let peer_id = iroh::SecretKey::generate(thread_rng()).public();
+        let peer =
+            spawn_stub(Router::new().route(
+                "/status",
+                get(move || async move {
+                    Json(serde_json::json!({ "node_addr": peer_id.to_string() }))
+                }),
+            ))
+            .await;
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![peer.clone()],
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+
+        let v: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/announce", url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(v["peers_resolved"], 1);
+        assert!(v["published"].is_boolean());
+        assert_eq!(shared.peers_addrs.lock().await[&peer].node_id, peer_id);
+    }
+
+    #[tokio::test]
+    async fn test_placeholder_served_until_an_image_arrives() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("waiting.png");
+        fs::write(&path, b"placeholder png").await.unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            placeholder: Some(Placeholder::load(&path).await.unwrap()),
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+
+        for route in ["/image", "/image_stream"] {
+            let resp = reqwest::get(format!("{}{}", url, route)).await.unwrap();
+            assert_eq!(resp.status(), 200);
+            assert_eq!(resp.headers()["content-type"], "image/png");
+            assert!(resp.headers().get("etag").is_none());
+            assert_eq!(&resp.bytes().await.unwrap()[..], b"placeholder png");
+        }
+        assert!(!shared.state.lock().await.has_image);
+
+        // A real image replaces it
+        let part = reqwest::multipart::Part::bytes(b"real".to_vec()).file_name("r.gif");
+        reqwest::Client::new()
+            .post(format!("{}/upload", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap();
+        let resp = reqwest::get(format!("{}/image", url)).await.unwrap();
+        assert!(resp.headers().get("etag").is_some());
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"real");
+    }
+
+    #[tokio::test]
+    async fn test_unknown_path_gets_json_404() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = spawn_stub(app(test_shared(dir.path()).await)).await;
+        let resp = reqwest::get(format!("{}/staus?x=1", url)).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 404);
+        let body: serde_json::Value = resp.json().await.unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({ "error": "not_found", "path": "/staus" })
+        );
+        // Real routes, the UI included, are untouched
+        let index = reqwest::get(format!("{}/", url)).await.unwrap();
+        assert_eq!(index.status().as_u16(), 200);
+        assert!(index.text().await.unwrap().contains("<html"));
+    }
+
+    #[tokio::test]
+    async fn test_head_image_headers() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let head = |path: &str| client.head(format!("{}{}", url, path)).send();
+        assert_eq!(head("/image").await.unwrap().status(), 404);
+
+        fs::write(dir.path().join("current.img"), b"12345")
+            .await
+            .unwrap();
+        {
+            let mut s = shared.state.lock().await;
+            s.content_type = Some("image/jpeg".into());
+            s.current_hash = Some(iroh_blobs::Hash::new(b"12345").to_string());
+        }
+        let etag = format!("\"{}\"", iroh_blobs::Hash::new(b"12345"));
+        for path in ["/image", "/image_stream"] {
+            let resp = head(path).await.unwrap();
+            assert_eq!(resp.status(), 200);
+            let h = resp.headers();
+            assert_eq!(h["content-type"], "image/jpeg");
+            assert_eq!(h["content-length"], "5");
+            assert_eq!(h["etag"], etag.as_str());
+            let ranges = if path == "/image" { "bytes" } else { "none" };
+            assert_eq!(h["accept-ranges"], ranges);
+            // No filename to give
+            assert!(h.get("content-disposition").is_none());
+            assert!(resp.bytes().await.unwrap().is_empty());
+        }
+        // GET carries the same headers
+        let resp = client.get(format!("{}/image", url)).send().await.unwrap();
+        assert_eq!(resp.headers()["etag"], etag.as_str());
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"12345");
+        // An unknown length is left out, not sent as 0
+        let headers = image_headers(&shared, None).await;
+        assert!(headers.get("content-length").is_none());
+        assert_eq!(headers["etag"], etag.as_str());
+    }
+
+    #[tokio::test]
+    async fn test_image_content_disposition_names_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        fs::write(dir.path().join("current.img"), b"12345")
+            .await
+            .unwrap();
+        {
+            let mut s = shared.state.lock().await;
+            s.content_type = Some("image/jpeg".into());
+            s.current_filename = Some("Zürich \"1\".jpg".into());
+        }
+        for path in ["/image", "/image_stream"] {
+            let resp = reqwest::get(format!("{}{}", url, path)).await.unwrap();
+            assert_eq!(
+                resp.headers()["content-disposition"],
+                "inline; filename=\"Z_rich \\\"1\\\".jpg\"; \
+                 filename*=UTF-8''Z%C3%BCrich%20%221%22.jpg",
+                "{path}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_upload_idempotency_key_replays_response() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let upload = |key: &'static str| {
+            let form = reqwest::multipart::Form::new().part(
+                "file",
+                reqwest::multipart::Part::bytes(b"retried".to_vec()).file_name("r.bin"),
+            );
+            client
+                .post(format!("{}/upload", url))
+                .header("Idempotency-Key", key)
+                .multipart(form)
+                .send()
+        };
+
+        let first = upload("k1").await.unwrap();
+        assert!(first.headers().get("idempotent-replayed").is_none());
+        let first: serde_json::Value = first.json().await.unwrap();
+        let second = upload("k1").await.unwrap();
+        assert_eq!(second.headers()["idempotent-replayed"], "true");
+        assert_eq!(second.json::<serde_json::Value>().await.unwrap(), first);
+        // One ingest: the logical clock moved once
+        assert_eq!(shared.state.lock().await.version, 1);
+
+        upload("k2").await.unwrap();
+        assert_eq!(shared.state.lock().await.version, 2);
+
+        // Same key, different file: refused, not replayed
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(b"something else".to_vec()).file_name("r.bin"),
+        );
+        let reused = client
+            .post(format!("{}/upload", url))
+            .header("Idempotency-Key", "k1")
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(reused.status().as_u16(), 422);
+        assert_eq!(shared.state.lock().await.version, 2);
+    }
+
+    #[tokio::test]
+    async fn test_upload_idempotency_key_in_flight_and_abandoned() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            block_active_content: true,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let upload = |key: &'static str, bytes: &'static [u8]| {
+            let form = reqwest::multipart::Form::new().part(
+                "file",
+                reqwest::multipart::Part::bytes(bytes.to_vec()).file_name("f.html"),
+            );
+            client
+                .post(format!("{}/upload", url))
+                .header("Idempotency-Key", key)
+                .multipart(form)
+                .send()
+        };
+
+        // Another request holds the key: the retry is told so, and the cache
+        // lock isn't held meanwhile, so other keys go ahead
+        let digest = iroh_blobs::Hash::new(b"slow").to_string();
+        let claim =
+            shared
+                .idempotency
+                .lock()
+                .await
+                .claim("busy", &digest, std::time::Instant::now());
+        assert_eq!(claim, idempotency::Claim::New);
+        assert_eq!(
+            upload("busy", b"slow").await.unwrap().status().as_u16(),
+            409
+        );
+        assert_eq!(
+            upload("other", b"fast").await.unwrap().status().as_u16(),
+            200
+        );
+
+        // A failed upload frees its key, so the retry runs instead of 409
+        let html: &[u8] = b"<html><script>1</script></html>";
+        assert_eq!(upload("failed", html).await.unwrap().status().as_u16(), 415);
+        assert_eq!(upload("failed", html).await.unwrap().status().as_u16(), 415);
+    }
+
+    #[tokio::test]
+    async fn test_tree_layout_links_name_and_hash_to_upload() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            fs_layout: FsLayout::Tree,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(b"tree bytes".to_vec()).file_name("t.png"),
+        );
+        let resp = reqwest::Client::new()
+            .post(format!("{}/upload", url))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+
+        let hash = iroh_blobs::Hash::new(b"tree bytes").to_string();
+        let by_hash = dir.path().join(fs_layout::BY_HASH).join(&hash);
+        let by_name = dir.path().join(fs_layout::BY_NAME).join("t.png");
+        assert_eq!(fs::read(&by_hash).await.unwrap(), b"tree bytes");
+        assert_eq!(fs::read(&by_name).await.unwrap(), b"tree bytes");
+        assert_eq!(
+            fs::canonicalize(&by_name).await.unwrap(),
+            fs::canonicalize(&by_hash).await.unwrap()
+        );
+
+        // Deleting the image takes its tree entries with it
+        let resp = reqwest::Client::new()
+            .delete(format!("{}/image", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 204);
+        assert!(fs::symlink_metadata(&by_name).await.is_err());
+        assert!(fs::metadata(&by_hash).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_status_stream_pushes_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let mut resp = reqwest::get(format!("{}/status/stream", url))
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["content-type"], "text/event-stream");
+        async fn next_event(resp: &mut reqwest::Response, buf: &mut String) -> serde_json::Value {
+            loop {
+                if let Some(end) = buf.find("\n\n") {
+                    let event: String = buf.drain(..end + 2).collect();
+                    if let Some(data) = event.strip_prefix("data: ") {
+                        return serde_json::from_str(data.trim()).unwrap();
+                    }
+                    continue;
+                }
+                let chunk = tokio::time::timeout(Duration::from_secs(5), resp.chunk())
+                    .await
+                    .expect("no status event")
+                    .unwrap()
+                    .expect("stream ended");
+                buf.push_str(std::str::from_utf8(&chunk).unwrap());
+            }
+        }
+        let mut buf = String::new();
+
+        let first = next_event(&mut resp, &mut buf).await;
+        assert_eq!(first["progress"], 0.0);
+        assert_eq!(first["paused"], false);
+        shared.state.lock().await.progress = 42.0;
+        assert_eq!(next_event(&mut resp, &mut buf).await["progress"], 42.0);
+        // Reads alone don't produce events; pausing does
+        let _ = shared.state.lock().await.progress;
+        shared.paused.send_replace(true);
+        let paused = next_event(&mut resp, &mut buf).await;
+        assert_eq!(paused["paused"], true);
+        assert_eq!(paused["progress"], 42.0);
+
+        // The subscriptions go away with the client
+        assert_eq!(shared.paused.receiver_count(), 1);
+        drop(resp);
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while shared.paused.receiver_count() > 0 {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("status stream outlived its client");
+    }
+
+    #[tokio::test]
+    async fn test_images_are_held_and_served_by_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        for (bytes, name, mime) in [
+            (&b"first image"[..], "a.png", "image/png"),
+            (&b"second image"[..], "b.gif", "image/gif"),
+        ] {
+            let form = reqwest::multipart::Form::new().part(
+                "file",
+                reqwest::multipart::Part::bytes(bytes.to_vec())
+                    .file_name(name)
+                    .mime_str(mime)
+                    .unwrap(),
+            );
+            let resp = client
+                .post(format!("{}/upload", url))
+                .multipart(form)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+        }
+        let first = iroh_blobs::Hash::new(b"first image").to_string();
+        let second = iroh_blobs::Hash::new(b"second image").to_string();
+
+        let listed: serde_json::Value = reqwest::get(format!("{}/images", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let images = listed["images"].as_array().unwrap();
+        assert_eq!(images.len(), 2);
+        assert_eq!(images[0]["hash"], first.as_str());
+        assert_eq!(images[0]["filename"], "a.png");
+        assert_eq!(images[1]["hash"], second.as_str());
+        assert_eq!(images[1]["content_type"], "image/gif");
+        // Only /images lists them; /status stays small
+        let status: serde_json::Value = reqwest::get(format!("{}/status", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(status.get("images").is_none());
+
+        let resp = reqwest::get(format!("{}/image/{}", url, first))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(resp.headers()["content-type"], "image/png");
+        assert_eq!(resp.headers()["content-length"], "11");
+        assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"first image");
+        // /image is still the most recent one
+        let resp = reqwest::get(format!("{}/image", url)).await.unwrap();
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"second image");
+        let unknown = iroh_blobs::Hash::new(b"never uploaded");
+        let resp = reqwest::get(format!("{}/image/{}", url, unknown))
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 404);
+
+        // A restart finds both again
+        let restarted = Arc::new(NodeShared {
+            state: Arc::new(StateCell::new(NodeState::default())),
+            ..(*shared).clone()
+        });
+        restarted.restore_image_meta().await;
+        let hashes: Vec<_> = restarted
+            .state
+            .lock()
+            .await
+            .images
+            .iter()
+            .map(|m| m.hash.clone())
+            .collect();
+        assert_eq!(hashes.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_held_images_are_capped_and_gzip_negotiated() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let plain = b"held and gzipped".to_vec();
+        let mut part_headers = reqwest::header::HeaderMap::new();
+        part_headers.insert("Content-Encoding", "gzip".parse().unwrap());
+        let form = reqwest::multipart::Form::new().part(
+            "file",
+            reqwest::multipart::Part::bytes(gzipped(&plain))
+                .file_name("g.png")
+                .mime_str("image/png")
+                .unwrap()
+                .headers(part_headers),
+        );
+        let resp = client
+            .post(format!("{}/upload", url))
+            .multipart(form)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        let gz: serde_json::Value = resp.json().await.unwrap();
+        let gz_hash = gz["hash"].as_str().unwrap().to_string();
+
+        // A client without gzip gets it decoded, with the decoded length
+        let resp = client
+            .get(format!("{}/image/{}", url, gz_hash))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.headers().get("content-encoding").is_none());
+        assert_eq!(
+            resp.headers()["content-length"],
+            plain.len().to_string().as_str()
+        );
+        assert_eq!(&resp.bytes().await.unwrap()[..], &plain[..]);
+        let resp = client
+            .get(format!("{}/image/{}", url, gz_hash))
+            .header("Accept-Encoding", "gzip")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["content-encoding"], "gzip");
+
+        // Past the cap the oldest copy goes, file and all
+        for i in 0..image_set::MAX_HELD_IMAGES {
+            let form = reqwest::multipart::Form::new().part(
+                "file",
+                reqwest::multipart::Part::bytes(format!("image {i}").into_bytes())
+                    .file_name("n.png"),
+            );
+            client
+                .post(format!("{}/upload", url))
+                .multipart(form)
+                .send()
+                .await
+                .unwrap();
+        }
+        let images = shared.state.lock().await.images.clone();
+        assert_eq!(images.len(), image_set::MAX_HELD_IMAGES);
+        assert!(images.iter().all(|m| m.hash != gz_hash));
+        assert!(!image_set::path(dir.path(), &gz_hash).exists());
+        let resp = client
+            .get(format!("{}/image/{}", url, gz_hash))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_slideshow_cycles_through_held_images() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        for (bytes, name, mime) in [
+            (&b"slide one"[..], "one.png", "image/png"),
+            (&b"just text"[..], "notes.txt", "text/plain"),
+            (&b"slide two"[..], "two.jpg", "image/jpeg"),
+        ] {
+            let form = reqwest::multipart::Form::new().part(
+                "file",
+                reqwest::multipart::Part::bytes(bytes.to_vec())
+                    .file_name(name)
+                    .mime_str(mime)
+                    .unwrap(),
+            );
+            let resp = client
+                .post(format!("{}/upload", url))
+                .multipart(form)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(resp.status().as_u16(), 200);
+        }
+
+        // The text upload is never shown, and the slides wrap around
+        for (index, bytes, name) in [
+            (0, &b"slide one"[..], "one.png"),
+            (1, &b"slide two"[..], "two.jpg"),
+            (0, &b"slide one"[..], "one.png"),
+        ] {
+            shared.advance_slide().await.unwrap();
+            let status: serde_json::Value = reqwest::get(format!("{}/status", url))
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            assert_eq!(status["slide_index"], index);
+            assert_eq!(status["current_filename"], name);
+            assert_eq!(
+                status["current_hash"],
+                iroh_blobs::Hash::new(bytes).to_string()
+            );
+            let resp = reqwest::get(format!("{}/image", url)).await.unwrap();
+            assert_eq!(&resp.bytes().await.unwrap()[..], bytes);
+        }
+
+        // A slide waits for an image being adopted, rather than writing
+        // over it halfway
+        let adopting = shared.adopting().await;
+        let slide = tokio::spawn({
+            let shared = shared.clone();
+            async move { shared.advance_slide().await }
+        });
+        sleep(Duration::from_millis(100)).await;
+        assert!(!slide.is_finished());
+        assert_eq!(shared.state.lock().await.slide_index, Some(0));
+        drop(adopting);
+        slide.await.unwrap().unwrap();
+        assert_eq!(shared.state.lock().await.slide_index, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_export_verifies_content_against_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let path = dir.path().join("check.bin");
+        fs::write(&path, b"original").await.unwrap();
+        let expected = iroh_blobs::Hash::new(b"original");
+        shared.verify_file(&path, expected).await.unwrap();
+        // A truncated export is caught
+        fs::write(&path, b"orig").await.unwrap();
+        let err = shared.verify_file(&path, expected).await.unwrap_err();
+        assert!(err.to_string().contains("doesn't hash to"), "{err}");
+
+        let tag = shared.store.add_slice(b"original").await.unwrap();
+        shared.export_current(tag.hash).await.unwrap();
+        assert_eq!(
+            fs::read(dir.path().join("current.img")).await.unwrap(),
+            b"original"
+        );
+        assert!(!dir.path().join("current.img.part").exists());
+    }
+
+    #[tokio::test]
+    async fn test_delete_image_resets_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let delete = || client.delete(format!("{}/image", url)).send();
+        assert_eq!(delete().await.unwrap().status().as_u16(), 404);
+
+        let tag = shared.store.add_slice(b"pixels").await.unwrap();
+        shared.retain_blob(tag.hash).await;
+        shared.export_current(tag.hash).await.unwrap();
+        {
+            let mut s = shared.state.lock().await;
+            s.current_filename = Some("a.png".into());
+            s.content_type = Some("image/png".into());
+            s.current_hash = Some(tag.hash.to_string());
+            s.bytes_total = Some(6);
+            s.bytes_received = 6;
+            s.progress = 100.0;
+            s.has_image = true;
+            s.stripe_providers.insert("p1".into(), vec!["0-3".into()]);
+        }
+        shared.write_image_meta(tag.hash).await.unwrap();
+
+        assert_eq!(delete().await.unwrap().status().as_u16(), 204);
+        let s = shared.state.lock().await.clone();
+        assert!(!s.has_image);
+        assert_eq!(s.current_filename, None);
+        assert_eq!(s.content_type, None);
+        assert_eq!(s.current_hash, None);
+        assert_eq!(
+            (s.bytes_total, s.bytes_received, s.progress),
+            (None, 0, 0.0)
+        );
+        assert!(s.stripe_providers.is_empty());
+        let image = dir.path().join("current.img");
+        assert!(!image.exists());
+        assert!(!image_meta::sidecar_path(&image).exists());
+        assert_eq!(shared.store.tags().list().await.unwrap().count().await, 0);
+        let resp = client.get(format!("{}/image", url)).send().await.unwrap();
+        assert_eq!(resp.status().as_u16(), 404);
+        assert_eq!(delete().await.unwrap().status().as_u16(), 404);
+    }
+
+    #[tokio::test]
+    async fn test_delete_image_needs_signing_key() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            url_signer: Some(UrlSigner::new("k")),
+            ..(*shared).clone()
+        });
+        let tag = shared.store.add_slice(b"pixels").await.unwrap();
+        shared.retain_blob(tag.hash).await;
+        shared.export_current(tag.hash).await.unwrap();
+        {
+            let mut s = shared.state.lock().await;
+            s.current_hash = Some(tag.hash.to_string());
+            s.has_image = true;
+        }
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let delete = || client.delete(format!("{}/image", url));
+
+        assert_eq!(delete().send().await.unwrap().status(), 403);
+        assert_eq!(
+            delete().bearer_auth("wrong").send().await.unwrap().status(),
+            403
+        );
+        // A signed read URL doesn't unlock deletion either
+        let signed = UrlSigner::new("k").signed_url("/image", unix_now() + 60);
+        let resp = client
+            .delete(format!("{}{}", url, signed))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 403);
+        assert!(shared.state.lock().await.has_image);
+        assert!(dir.path().join("current.img").exists());
+        assert_ne!(shared.store.tags().list().await.unwrap().count().await, 0);
+
+        assert_eq!(
+            delete().bearer_auth("k").send().await.unwrap().status(),
+            204
+        );
+        assert!(!shared.state.lock().await.has_image);
+    }
+
+    #[tokio::test]
+    async fn test_get_image_serves_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        fs::write(dir.path().join("current.img"), b"0123456789")
+            .await
+            .unwrap();
+        shared.state.lock().await.content_type = Some("video/mp4".into());
+        let url = spawn_stub(app(shared)).await;
+        let client = reqwest::Client::new();
+        let get = |range: &'static str| {
+            client
+                .get(format!("{}/image", url))
+                .header("Range", range)
+                .send()
+        };
+
+        let resp = get("bytes=2-5").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 2-5/10");
+        assert_eq!(resp.headers()["content-length"], "4");
+        assert_eq!(resp.headers()["content-type"], "video/mp4");
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"2345");
+
+        let resp = get("bytes=-3").await.unwrap();
+        assert_eq!(resp.headers()["content-range"], "bytes 7-9/10");
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"789");
+
+        // Several ranges get the whole image
+        let resp = get("bytes=0-1,4-5").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"0123456789");
+
+        let resp = get("bytes=10-").await.unwrap();
+        assert_eq!(resp.status().as_u16(), 416);
+        assert_eq!(resp.headers()["content-range"], "bytes */10");
+    }
+
+    #[tokio::test]
+    async fn test_ranged_image_honours_if_range_and_image_swaps() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let set_image = |data: &'static [u8]| {
+            let shared = shared.clone();
+            async move {
+                shared.write_current(data).await.unwrap();
+                shared.state.lock().await.current_hash =
+                    Some(iroh_blobs::Hash::new(data).to_string());
+            }
+        };
+        set_image(b"0123456789").await;
+        let old_etag = format!("\"{}\"", iroh_blobs::Hash::new(b"0123456789"));
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let get = |if_range: String| {
+            client
+                .get(format!("{}/image", url))
+                .header("Range", "bytes=2-5")
+                .header("If-Range", if_range)
+                .send()
+        };
+
+        let resp = get(old_etag.clone()).await.unwrap();
+        assert_eq!(resp.status(), 206);
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"2345");
+        // Another validator, or a date, gets the whole image
+        let resp = get("\"other\"".into()).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"0123456789");
+        let resp = get("Wed, 21 Oct 2015 07:28:00 GMT".into()).await.unwrap();
+        assert_eq!(resp.status(), 200);
+
+        // A range asked for while a new image is being adopted is answered
+        // from the new image alone
+        let adopting = shared.adopting().await;
+        let pending = tokio::spawn(
+            client
+                .get(format!("{}/image", url))
+                .header("Range", "bytes=2-5")
+                .send(),
+        );
+        sleep(Duration::from_millis(100)).await;
+        set_image(b"abcdefghijklmnop").await;
+        drop(adopting);
+        let resp = pending.await.unwrap().unwrap();
+        assert_eq!(resp.status(), 206);
+        assert_eq!(resp.headers()["content-range"], "bytes 2-5/16");
+        assert_eq!(
+            resp.headers()["etag"],
+            format!("\"{}\"", iroh_blobs::Hash::new(b"abcdefghijklmnop")).as_str()
+        );
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"cdef");
+        // The old ETag no longer resumes anything
+        assert_eq!(get(old_etag).await.unwrap().status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_status_msgpack_roundtrip() {
+        let state = NodeState {
+            node_name: "n1".into(),
+            has_image: true,
+            current_hash: Some("abc".into()),
+            bytes_total: Some(42),
+            progress: 50.0,
+            stripe_providers: HashMap::from([("p".into(), vec!["0-3".into()])]),
+            download_strategy: Some(DownloadStrategy {
+                mode: "split".into(),
+                providers: 2,
+                round: 1,
+                current_provider: None,
+            }),
+            ..Default::default()
+        };
+        let packed = rmp_serde::to_vec_named(&state).unwrap();
+        assert!(packed.len() < serde_json::to_vec(&state).unwrap().len());
+        let back: NodeState = rmp_serde::from_slice(&packed).unwrap();
+        assert_eq!(back.node_name, "n1");
+        assert_eq!(back.bytes_total, Some(42));
+        assert_eq!(back.stripe_providers, state.stripe_providers);
+        assert_eq!(back.download_strategy, state.download_strategy);
+
+        // Served by /status when asked for
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        *shared.state.lock().await = state;
+        let url = spawn_stub(app(shared)).await;
+        let resp = reqwest::Client::new()
+            .get(format!("{}/status", url))
+            .header("Accept", "application/msgpack")
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.headers()["content-type"], "application/msgpack");
+        let back: NodeState = rmp_serde::from_slice(&resp.bytes().await.unwrap()).unwrap();
+        assert_eq!(back.current_hash.as_deref(), Some("abc"));
+    }
+
+    #[tokio::test]
+    async fn test_compare_strategies() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = spawn_stub(app(test_shared(dir.path()).await)).await;
+        let compare = |body: serde_json::Value| {
+            let url = url.clone();
+            async move {
+                reqwest::Client::new()
+                    .post(format!("{}/compare_strategies", url))
+                    .json(&body)
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let body = serde_json::json!({ "total_chunks": 64, "span": 16, "seed": 3 });
+        let first: serde_json::Value = compare(body.clone()).await.json().await.unwrap();
+        assert_eq!(
+            (first["span"].as_u64(), first["seed"].as_u64()),
+            (Some(16), Some(3))
+        );
+        for plan in ["sequential", "randomized"] {
+            assert_eq!(first[plan]["stripes"], 4);
+            assert_eq!(first[plan]["coverage"], 1.0);
+            assert_eq!(first[plan]["max_stripes_per_offset"], 1);
+        }
+        assert_eq!(
+            first["sequential"]["order"],
+            serde_json::json!([0, 16, 32, 48])
+        );
+        // Same seed, same plan
+        let again: serde_json::Value = compare(body).await.json().await.unwrap();
+        assert_eq!(first, again);
+
+        for bad in [
+            serde_json::json!({ "total_chunks": 0, "span": 1, "seed": 0 }),
+            serde_json::json!({ "total_chunks": MAX_COMPARE_CHUNKS + 1, "span": 1, "seed": 0 }),
+            serde_json::json!({ "total_chunks": 8, "span": 0, "seed": 0 }),
+            serde_json::json!({ "total_chunks": 8, "span": 9, "seed": 0 }),
+        ] {
+            assert_eq!(compare(bad).await.status(), 400);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ticket_inspect() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider.store.add_slice(vec![7u8; 5000]).await.unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        let url = spawn_stub(app(test_shared(dir_b.path()).await)).await;
+        let inspect = |ticket: String| {
+            let url = url.clone();
+            async move {
+                reqwest::Client::new()
+                    .post(format!("{}/ticket/inspect", url))
+                    .json(&serde_json::json!({ "ticket": ticket }))
+                    .send()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let v: serde_json::Value = inspect(ticket.to_string()).await.json().await.unwrap();
+        assert_eq!(v["hash"], ticket.hash().to_string());
+        assert_eq!(v["node_id"], ticket.node_addr().node_id.to_string());
+        assert_eq!(v["kind"], "raw");
+        assert_eq!(v["size"], 5000);
+
+        // Unreachable provider: static fields only
+        let dead = iroh::SecretKey::generate(thread_rng()).public();
+        let orphan =
+            iroh_blobs::ticket::BlobTicket::new(dead.into(), ticket.hash(), ticket.format());
+        let v: serde_json::Value = inspect(orphan.to_string()).await.json().await.unwrap();
+        assert_eq!(v["node_id"], dead.to_string());
+        assert!(v["size"].is_null());
+
+        assert_eq!(inspect("garbage".into()).await.status(), 400);
+    }
+
+    #[tokio::test]
+    async fn test_with_retries_recovers_from_transient_failure() {
+        let calls = std::sync::atomic::AtomicU32::new(0);
+        let flaky_export = || async {
+            match calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst) {
+                0 => Err(anyhow::anyhow!("disk hiccup")),
+                _ => Ok("exported"),
+            }
+        };
+        assert_eq!(
+            with_retries(2, Duration::from_millis(1), flaky_export)
+                .await
+                .unwrap(),
+            "exported"
+        );
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+
+        // Out of retries: the last error surfaces
+        let res: anyhow::Result<()> = with_retries(1, Duration::from_millis(1), || async {
+            Err(anyhow::anyhow!("disk gone"))
+        })
+        .await;
+        assert_eq!(res.unwrap_err().to_string(), "disk gone");
+    }
+
+    #[tokio::test]
+    async fn test_export_failure_leaves_image_unset() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = b"unexportable".to_vec();
+        let hash = iroh_blobs::Hash::new(&payload);
+        let provider = spawn_stub(Router::new().route(
+            "/blob/:hash",
+            get(move || {
+                let payload = payload.clone();
+                async move { payload }
+            }),
+        ))
+        .await;
+        // A directory where the file should go makes every export fail
+        std::fs::create_dir(dir.path().join("current.img")).unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            export_retries: 1,
+            ..(*shared).clone()
+        });
+
+        let res = shared
+            .receive_by_http(hash, "x".into(), "x".into(), None, None, Some(provider))
+            .await;
+        assert!(res.unwrap_err().to_string().contains("exporting"));
+        assert!(!shared.state.lock().await.has_image);
+    }
+
+    #[tokio::test]
+    async fn test_p2p_export_failure_fails_the_transfer() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider
+            .store
+            .add_slice(b"nowhere to put it")
+            .await
+            .unwrap();
+        let hash = tag.hash;
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+
+        // Directories where the exported file should go: the data dir is
+        // effectively unwritable for it
+        std::fs::create_dir(dir_b.path().join("current.img")).unwrap();
+        std::fs::create_dir(dir_b.path().join("current.img.part")).unwrap();
+        let receiver = test_shared(dir_b.path()).await;
+        receiver
+            .endpoint
+            .as_ref()
+            .unwrap()
+            .add_node_addr(ticket.node_addr().clone())
+            .unwrap();
+
+        let err = receiver
+            .receive_with_progress(ticket.clone(), "x".into(), "x".into())
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("exporting"), "{err}");
+        let s = receiver.state.lock().await.clone();
+        assert!(!s.has_image);
+        assert!(s.last_error.is_some_and(|e| e.contains("exporting")));
+
+        receiver.state.lock().await.last_error = None;
+        let err = receiver
+            .receive_by_discovery(
+                hash,
+                "x".into(),
+                "x".into(),
+                Some(ticket.node_addr().clone()),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("exporting"), "{err}");
+        let s = receiver.state.lock().await.clone();
+        assert!(!s.has_image);
+        assert!(s.last_error.is_some_and(|e| e.contains("exporting")));
+    }
+
+    #[tokio::test]
+    async fn test_http_notify_retries_transient_failure() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let peer = spawn_stub(Router::new().route(
+            "/receive",
+            post(move || {
+                let n = counter.fetch_add(1, Ordering::SeqCst);
+                async move {
+                    if n == 0 {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::OK
+                    }
+                }
+            }),
+        ))
+        .await;
+        let client = reqwest::Client::new();
+
+        assert!(post_receive(&client, &peer, "{}", 2).await);
+        assert_eq!(hits.load(Ordering::SeqCst), 2);
+
+        // Without retries the first 503 is final
+        hits.store(0, Ordering::SeqCst);
+        assert!(!post_receive(&client, &peer, "{}", 0).await);
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "p2p_notify")]
+    #[tokio::test]
+    async fn test_failed_http_notify_upgrades_to_p2p_once_resolved() {
+        use iroh::protocol::{AcceptError, ProtocolHandler};
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        /// Stands in for a peer's notify handler: counts and acks.
+        #[derive(Debug, Clone)]
+        struct CountingNotify(Arc<AtomicUsize>);
+        impl ProtocolHandler for CountingNotify {
+            async fn accept(&self, conn: iroh::endpoint::Connection) -> Result<(), AcceptError> {
+                let (mut send, mut recv) = conn.accept_bi().await?;
+                recv.read_to_end(256 * 1024)
+                    .await
+                    .map_err(AcceptError::from_err)?;
+                self.0.fetch_add(1, Ordering::SeqCst);
+                let _ = send.write_all(br#"{"transfer_id":"t-1"}"#).await;
+                let _ = send.finish();
+                conn.closed().await;
+                Ok(())
+            }
+        }
+
+        let notifies = Arc::new(AtomicUsize::new(0));
+        let peer_ep = Endpoint::builder()
+            .relay_mode(iroh::RelayMode::Disabled)
+            .bind()
+            .await
+            .unwrap();
+        let _router = iroh::protocol::Router::builder(peer_ep.clone())
+            .accept(notify::NOTIFY_ALPN, CountingNotify(notifies.clone()))
+            .spawn();
+        let peer_addr = peer_ep.node_addr().initialized().await;
+
+        // One peer's HTTP /receive is down, the other's works
+        let down = spawn_stub(Router::new().route(
+            "/receive",
+            post(|| async { StatusCode::INTERNAL_SERVER_ERROR }),
+        ))
+        .await;
+        let up =
+            spawn_stub(Router::new().route("/receive", post(|| async { StatusCode::OK }))).await;
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![down.clone(), up.clone()],
+            http_notify_retries: 0,
+            notify_upgrade_window: Some(Duration::from_secs(5)),
+            ..(*shared).clone()
+        });
+        let mut events = shared.events.subscribe();
+        // The notify log's own events aren't what this is about
+        async fn next_sent(events: &mut events::Subscription) -> Option<Event> {
+            loop {
+                match events.next().await {
+                    Some(Event::NotifyMessage(_)) => continue,
+                    other => return other,
+                }
+            }
+        }
+        let msg = NotifyMsg {
+            hash: iroh_blobs::Hash::new(b"x").to_string(),
+            filename: "x".into(),
+            content_type: "x".into(),
+            provider_node_id: None,
+            provider_url: None,
+            version: None,
+            bytes_total: None,
+            content_encoding: None,
+        };
+        let notifying = tokio::spawn(notify_all_peers(shared.clone(), msg));
+
+        for (peer, delivered) in [(&down, false), (&up, true)] {
+            assert_eq!(
+                next_sent(&mut events).await,
+                Some(Event::NotifySent {
+                    peer: peer.clone(),
+                    via: "http".into(),
+                    delivered,
+                    transfer_id: None,
+                })
+            );
+        }
+        // Both peers resolve after the fallback
+        {
+            let mut addrs = shared.peers_addrs.lock().await;
+            addrs.insert(down.clone(), peer_addr.clone());
+            addrs.insert(up.clone(), peer_addr);
+        }
+        assert_eq!(
+            next_sent(&mut events).await,
+            Some(Event::NotifySent {
+                peer: down,
+                via: "p2p".into(),
+                delivered: true,
+                transfer_id: Some("t-1".into()),
+            })
+        );
+        notifying.await.unwrap();
+        // The peer HTTP already reached isn't notified again
+        assert_eq!(notifies.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_notify_log_records_sent_and_received() {
+        let dir_a = tempfile::tempdir().unwrap();
+        let dir_b = tempfile::tempdir().unwrap();
+        let b = test_shared(dir_b.path()).await;
+        let (listener, url_b) = bind_local().await;
+        let service = app(b.clone()).into_make_service_with_connect_info::<SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, service).await.unwrap() });
+        let a = test_shared(dir_a.path()).await;
+        let a = Arc::new(NodeShared {
+            peers_http: vec![url_b.clone()],
+            http_notify_retries: 0,
+            ..(*a).clone()
+        });
+        let mut events = a.events.subscribe();
+        let hash = iroh_blobs::Hash::new(b"logged").to_string();
+        let msg = NotifyMsg {
+            hash: hash.clone(),
+            filename: "l.png".into(),
+            content_type: "image/png".into(),
+            provider_node_id: None,
+            provider_url: Some("http://user:pw@a.local:8080/?sig=s".into()),
+            version: None,
+            bytes_total: None,
+            content_encoding: None,
+        };
+        notify_all_peers(a.clone(), msg).await;
+
+        let sent = a.notify_log.records();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(
+            (sent[0].direction, sent[0].transport.as_str(), &sent[0].peer),
+            (Direction::Outbound, "http", &url_b)
+        );
+        assert_eq!(sent[0].message["hash"], hash.as_str());
+        assert_eq!(sent[0].message["provider_url"], "http://a.local:8080/");
+        assert_eq!(
+            events.next().await,
+            Some(Event::NotifyMessage(sent[0].clone()))
+        );
+
+        let body: serde_json::Value = reqwest::get(format!("{url_b}/notifies"))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let received = &body["notifies"][0];
+        assert_eq!(received["direction"], "inbound");
+        assert_eq!(received["transport"], "http");
+        assert!(received["peer"].as_str().unwrap().starts_with("127.0.0.1:"));
+        assert_eq!(received["message"]["hash"], hash.as_str());
+        assert_eq!(received["message"]["filename"], "l.png");
+    }
+
+    #[cfg(not(feature = "p2p_notify"))]
+    #[tokio::test]
+    async fn test_notify_goes_straight_to_http_without_p2p_notify() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let hits = Arc::new(AtomicUsize::new(0));
+        let counter = hits.clone();
+        let peer = spawn_stub(Router::new().route(
+            "/receive",
+            post(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { StatusCode::OK }
+            }),
+        ))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![peer.clone()],
+            ..(*shared).clone()
+        });
+        // A known NodeAddr would normally route this peer over P2P
+        let dead = iroh::SecretKey::generate(thread_rng()).public();
+        shared
+            .peers_addrs
+            .lock()
+            .await
+            .insert(peer.clone(), NodeAddr::new(dead));
+        let mut events = shared.events.subscribe();
+
+        let msg = NotifyMsg {
+            hash: iroh_blobs::Hash::new(b"x").to_string(),
+            filename: "x".into(),
+            content_type: "x".into(),
+            provider_node_id: None,
+            provider_url: None,
+            version: None,
+            bytes_total: None,
+            content_encoding: None,
+        };
+        notify_all_peers(shared.clone(), msg).await;
+        assert_eq!(hits.load(Ordering::SeqCst), 1);
+        assert!(matches!(
+            events.next().await,
+            Some(Event::NotifyMessage(notify_log::NotifyRecord { ref transport, .. })) if transport == "http"
+        ));
+        assert_eq!(
+            events.next().await,
+            Some(Event::NotifySent {
+                peer,
+                via: "http".into(),
+                delivered: true,
+                transfer_id: None,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_content_length_matches_payload() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let payload = vec![9u8; 200_000];
+        let hash = shared.store.add_slice(&payload).await.unwrap().hash;
+        fs::write(dir.path().join("current.img"), &payload)
+            .await
+            .unwrap();
+        let url = spawn_stub(app(shared)).await;
+        for path in [
+            "/image".to_string(),
+            "/image_stream".into(),
+            format!("/blob/{hash}"),
+        ] {
+            let resp = reqwest::get(format!("{}{}", url, path)).await.unwrap();
+            assert_eq!(resp.headers()["content-length"], "200000", "{path}");
+            assert_eq!(resp.bytes().await.unwrap().len(), payload.len(), "{path}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ingest_transforms_decide_stored_hash() {
+        // SOI, APP1 (Exif) and a scan: strip_exif leaves SOI and the scan
+        let jpeg: &[u8] = &[
+            0xFF, 0xD8, 0xFF, 0xE1, 0x00, 0x06, b'E', b'x', b'i', b'f', 0xFF, 0xDA, 0x00, 0x02,
+            0x12, 0x34, 0xFF, 0xD9,
+        ];
+        let stripped: &[u8] = &[0xFF, 0xD8, 0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0xFF, 0xD9];
+        for (transforms, expected) in [("noop", jpeg), ("noop,strip_exif", stripped)] {
+            let dir = tempfile::tempdir().unwrap();
+            let shared = test_shared(dir.path()).await;
+            let shared = Arc::new(NodeShared {
+                ingest: IngestPipeline::from_env_values(transforms, None).0,
+                ..(*shared).clone()
+            });
+            let url = spawn_stub(app(shared.clone())).await;
+            let part = reqwest::multipart::Part::bytes(jpeg)
+                .file_name("p.jpg")
+                .mime_str("image/jpeg")
+                .unwrap();
+            let body: serde_json::Value = reqwest::Client::new()
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+                .await
+                .unwrap()
+                .json()
+                .await
+                .unwrap();
+            let hash = iroh_blobs::Hash::new(expected);
+            assert_eq!(body["hash"], hash.to_string(), "{transforms}");
+            let stored = shared.store.get_bytes(hash).await.unwrap();
+            assert_eq!(&stored[..], expected, "{transforms}");
+            let served = reqwest::get(format!("{}/image", url)).await.unwrap();
+            assert_eq!(&served.bytes().await.unwrap()[..], expected, "{transforms}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_verify_upload_catches_corrupted_store() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        let notifies = Arc::new(AtomicUsize::new(0));
+        let counter = notifies.clone();
+        let peer = spawn_stub(Router::new().route(
+            "/receive",
+            post(move || {
+                counter.fetch_add(1, Ordering::SeqCst);
+                async { StatusCode::OK }
+            }),
+        ))
+        .await;
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            verify_upload: true,
+            peers_http: vec![peer],
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        // Large enough that the store keeps it in a data file, not inline
+        let payload = vec![5u8; 64 * 1024];
+        shared.store.add_slice(&payload).await.unwrap();
+
+        // The disk flips a byte under the store
+        let hash = iroh_blobs::Hash::new(&payload);
+        let data = find_file(
+            &dir.path().join("blobs"),
+            &format!("{}.data", hash.to_hex()),
+        )
+        .expect("blob data file");
+        let mut on_disk = std::fs::read(&data).unwrap();
+        on_disk[1000] ^= 0xff;
+        std::fs::write(&data, on_disk).unwrap();
+
+        assert!(shared.verify_stored(hash, &payload).await.is_err());
+
+        // Re-adding the bytes would move a fresh copy over a corrupted data
+        // file, so break the disk where the store can't repair it
+        std::fs::remove_file(&data).unwrap();
+        std::fs::create_dir(&data).unwrap();
+        let part = reqwest::multipart::Part::bytes(payload.clone()).file_name("v.bin");
+        let resp = reqwest::Client::new()
+            .post(format!("{}/upload", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 500);
+        assert!(resp
+            .text()
+            .await
+            .unwrap()
+            .contains("store verification failed"));
+
+        // Neither shared locally nor announced to peers
+        sleep(Duration::from_millis(200)).await;
+        assert_eq!(notifies.load(Ordering::SeqCst), 0);
+        assert!(!shared.state.lock().await.has_image);
+        let image = reqwest::get(format!("{}/image", url)).await.unwrap();
+        assert_eq!(image.status(), 404);
+    }
+
+    /// First file named `name` anywhere under `dir`.
+    fn find_file(dir: &std::path::Path, name: &str) -> Option<PathBuf> {
+        for entry in std::fs::read_dir(dir).ok()?.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                if let Some(found) = find_file(&path, name) {
+                    return Some(found);
+                }
+            } else if entry.file_name() == name {
+                return Some(path);
+            }
+        }
+        None
+    }
+
+    #[tokio::test]
+    async fn test_upload_applies_default_content_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            default_content_type: "image/jpeg".to_string(),
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let upload = |part: reqwest::multipart::Part| {
+            let url = url.clone();
+            async move {
+                reqwest::Client::new()
+                    .post(format!("{}/upload", url))
+                    .multipart(reqwest::multipart::Form::new().part("file", part))
+                    .send()
+                    .await
+                    .unwrap()
+                    .json::<serde_json::Value>()
+                    .await
+                    .unwrap()
+            }
+        };
+
+        let untyped = reqwest::multipart::Part::bytes(b"jpeg?".to_vec()).file_name("a");
+        assert_eq!(upload(untyped).await["content_type"], "image/jpeg");
+        assert_eq!(
+            shared.state.lock().await.content_type.as_deref(),
+            Some("image/jpeg")
+        );
+
+        // An explicit part type still wins
+        let typed = reqwest::multipart::Part::bytes(b"png".to_vec())
+            .file_name("b.png")
+            .mime_str("image/png")
+            .unwrap();
+        assert_eq!(upload(typed).await["content_type"], "image/png");
+    }
+
+    #[tokio::test]
+    async fn test_reload_static_peer_addrs() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("peers.json");
+        let peer = iroh::SecretKey::generate(thread_rng()).public();
+        fs::write(
+            &file,
+            format!(r#"[{{ "node_id": "{peer}", "direct_addresses": ["127.0.0.1:4"] }}, {{}}]"#),
+        )
+        .await
+        .unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peer_addrs_file: Some(file),
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let v: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/peers/reload", url))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(v["loaded"], 1);
+        assert_eq!(v["skipped"], 1);
+        let addrs = shared.peers_addrs.lock().await;
+        assert_eq!(addrs[&peer.to_string()].direct_addresses.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_adopt_version_persists_the_newest_clock_outside_the_state_lock() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+
+        // With clock.json's writer held up, an adopt still sets the clock and
+        // leaves the state lock free for everyone else
+        let file = shared.clock_file.clone().lock_owned().await;
+        let adopt = tokio::spawn({
+            let shared = shared.clone();
+            async move { shared.adopt_version(1, "a").await }
+        });
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while shared.state.lock().await.version != 1 {
+                tokio::task::yield_now().await;
+            }
+        })
+        .await
+        .unwrap();
+        drop(file);
+        assert!(adopt.await.unwrap());
+
+        let adopts: Vec<_> = (2..=20u64)
+            .map(|v| {
+                let shared = shared.clone();
+                tokio::spawn(async move { shared.adopt_version(v, "a").await })
+            })
+            .collect();
+        for adopt in adopts {
+            adopt.await.unwrap();
+        }
+        let clock = LogicalClock::load(&dir.path().join("clock.json")).await;
+        assert_eq!(clock.version, 20);
+        assert_eq!(clock.version, shared.state.lock().await.version);
+    }
+
+    #[tokio::test]
+    async fn test_notify_versions_adopt_newer_and_ignore_stale() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        assert!(shared.adopt_version(2, "b").await);
+        assert!(!shared.adopt_version(2, "b").await);
+        assert!(!shared.adopt_version(1, "z").await);
+        assert!(shared.adopt_version(3, "b").await);
+        // Equal versions from different nodes: the larger node id wins
+        assert!(!shared.adopt_version(3, "a").await);
+        assert!(shared.adopt_version(3, "c").await);
+
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let receive = |version: Option<u64>| {
+            client
+                .post(format!("{}/receive", url))
+                .json(&serde_json::json!({
+                    "hash": iroh_blobs::Hash::new(b"older").to_string(),
+                    "filename": "x.png",
+                    "content_type": "image/png",
+                    "version": version,
+                }))
+                .send()
+        };
+        assert_eq!(receive(Some(3)).await.unwrap().status().as_u16(), 409);
+        // Unversioned notifies are not subject to ordering
+        assert_ne!(receive(None).await.unwrap().status().as_u16(), 409);
+        // A notify refused as malformed leaves the clock alone
+        let resp = client
+            .post(format!("{}/receive", url))
+            .json(&serde_json::json!({
+                "hash": "zz".repeat(32),
+                "filename": "x.png",
+                "content_type": "image/png",
+                "version": 9,
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 400);
+        assert_eq!(shared.state.lock().await.version, 3);
+
+        // An upload moves past every version seen so far
+        let part = reqwest::multipart::Part::bytes(b"img".to_vec()).file_name("a.png");
+        let body: serde_json::Value = client
+            .post(format!("{}/upload", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert!(body["hash"].is_string());
+        let status: serde_json::Value = reqwest::get(format!("{}/status", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(status["version"], 4);
+        // Persisted, so a restarted node picks up where it left off
+        let clock = LogicalClock::load(&dir.path().join("clock.json")).await;
+        assert_eq!(clock.version, 4);
+        assert_eq!(
+            clock.origin,
+            shared.endpoint.as_ref().unwrap().node_id().to_string()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_image_feed_pushes_each_new_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared)).await;
+        let upload = |body: &'static [u8], ct: &'static str| {
+            let part = reqwest::multipart::Part::bytes(body)
+                .file_name("f")
+                .mime_str(ct)
+                .unwrap();
+            reqwest::Client::new()
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+        };
+        upload(b"first-frame", "image/png").await.unwrap();
+
+        let mut feed = reqwest::get(format!("{}/image_feed", url)).await.unwrap();
+        assert_eq!(
+            feed.headers()["content-type"],
+            "multipart/x-mixed-replace; boundary=frame"
+        );
+        async fn read_until(feed: &mut reqwest::Response, received: &mut Vec<u8>, needle: &[u8]) {
+            while !received.windows(needle.len()).any(|w| w == needle) {
+                let chunk = tokio::time::timeout(Duration::from_secs(5), feed.chunk())
+                    .await
+                    .expect("no frame within 5s")
+                    .unwrap()
+                    .expect("feed ended");
+                received.extend_from_slice(&chunk);
+            }
+        }
+        let mut received = Vec::new();
+        read_until(&mut feed, &mut received, b"first-frame\r\n").await;
+
+        upload(b"second-frame", "image/gif").await.unwrap();
+        read_until(&mut feed, &mut received, b"second-frame\r\n").await;
+        let text = String::from_utf8(received).unwrap();
+        assert_eq!(text.matches("--frame\r\n").count(), 2);
+        assert!(text.starts_with(
+            "--frame\r\nContent-Type: image/png\r\nContent-Length: 11\r\n\r\nfirst-frame\r\n"
+        ));
+        assert!(text.contains(
+            "--frame\r\nContent-Type: image/gif\r\nContent-Length: 12\r\n\r\nsecond-frame\r\n"
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_image_feed_parts_are_guarded_like_image() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let upload = |body: Vec<u8>, ct: &'static str, encoding: Option<&'static str>| {
+            let mut part = reqwest::multipart::Part::bytes(body)
+                .file_name("f")
+                .mime_str(ct)
+                .unwrap();
+            if let Some(encoding) = encoding {
+                let mut headers = reqwest::header::HeaderMap::new();
+                headers.insert("Content-Encoding", encoding.parse().unwrap());
+                part = part.headers(headers);
+            }
+            reqwest::Client::new()
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+        };
+        let resp = reqwest::get(format!("{}/image_feed", url)).await.unwrap();
+        assert_eq!(resp.headers()["x-content-type-options"], "nosniff");
+        drop(resp);
+
+        // Gzip-stored: the part carries the decoded bytes, no per-part encoding
+        upload(gzipped(b"plain-frame"), "image/png", Some("gzip"))
+            .await
+            .unwrap();
+        let frame = String::from_utf8(shared.feed_frame().await.unwrap().to_vec()).unwrap();
+        assert_eq!(
+            frame,
+            "--frame\r\nContent-Type: image/png\r\nContent-Length: 11\r\n\r\nplain-frame\r\n"
+        );
+
+        // Active content is an attachment, as on /image
+        upload(b"<svg/>".to_vec(), "image/svg+xml", None)
+            .await
+            .unwrap();
+        let frame = String::from_utf8(shared.feed_frame().await.unwrap().to_vec()).unwrap();
+        assert!(
+            frame.contains("Content-Disposition: attachment\r\n"),
+            "{frame}"
+        );
+
+        // A type that can't be a header value can't inject part headers
+        shared.state.lock().await.content_type = Some("image/png\r\nX-Injected: 1".into());
+        let frame = String::from_utf8(shared.feed_frame().await.unwrap().to_vec()).unwrap();
+        assert!(frame.starts_with("--frame\r\nContent-Type: application/octet-stream\r\n"));
+        assert!(!frame.contains("X-Injected"), "{frame}");
+    }
+
+    #[tokio::test]
+    async fn test_upload_await_notify_reports_outcomes() {
+        let ok_peer =
+            spawn_stub(Router::new().route("/receive", post(|| async { StatusCode::OK }))).await;
+        let (listener, dead_peer) = bind_local().await;
+        drop(listener);
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![ok_peer.clone(), dead_peer.clone()],
+            http_notify_retries: 0,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared)).await;
+        let upload = |query: &'static str| {
+            let part = reqwest::multipart::Part::bytes(b"img".to_vec()).file_name("a.png");
+            reqwest::Client::new()
+                .post(format!("{}/upload{}", url, query))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+        };
+
+        let body: serde_json::Value = upload("?await_notify=1")
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            body["notified"],
+            serde_json::json!([
+                { "url": ok_peer, "method": "http", "ok": true },
+                { "url": dead_peer, "method": "http", "ok": false },
+            ])
+        );
+        // Fire-and-forget by default
+        let body: serde_json::Value = upload("").await.unwrap().json().await.unwrap();
+        assert!(body.get("notified").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_upload_over_configured_limit_gets_413() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            max_upload_bytes: 4096,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let upload = |len: usize| {
+            let part = reqwest::multipart::Part::bytes(vec![3u8; len]).file_name("l.bin");
+            reqwest::Client::new()
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
+                .send()
+        };
+
+        let resp = upload(8192).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 413);
+        assert!(resp.text().await.unwrap().contains("4096 bytes"));
+        assert!(!shared.state.lock().await.has_image);
+
+        let resp = upload(2048).await.unwrap();
+        assert_eq!(resp.status().as_u16(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_upload_cut_off_mid_file_is_not_ingested() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let addr = url.trim_start_matches("http://");
+        // Promise a full part, send half of it, then hang up
+        let head = "--b\r\nContent-Disposition: form-data; name=\"file\"; \
+                    filename=\"cut.png\"\r\nContent-Type: image/png\r\n\r\n";
+        let body = format!("{head}{}\r\n--b--\r\n", "x".repeat(1000));
+        let sent = &body[..head.len() + 500];
+        let request = format!(
+            "POST /upload HTTP/1.1\r\nHost: {addr}\r\n\
+             Content-Type: multipart/form-data; boundary=b\r\n\
+             Content-Length: {}\r\n\r\n{sent}",
+            body.len()
+        );
+        let mut conn = tokio::net::TcpStream::connect(addr).await.unwrap();
+        conn.write_all(request.as_bytes()).await.unwrap();
+        conn.shutdown().await.unwrap();
+        let mut resp = String::new();
+        let _ = conn.read_to_string(&mut resp).await;
+        assert!(resp.starts_with("HTTP/1.1 400"), "{resp}");
+
+        assert!(!shared.state.lock().await.has_image);
+        assert!(shared.state.lock().await.images.is_empty());
+        assert!(!dir.path().join("current.img").exists());
+        let mut tags = shared.store.tags().list().await.unwrap();
+        assert!(tags.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_json_endpoints_have_small_body_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let oversize = serde_json::json!({
+            "hash": iroh_blobs::Hash::new(b"x").to_string(),
+            "filename": "x".repeat(JSON_BODY_LIMIT),
+            "content_type": "x",
+        });
+        let resp = client
+            .post(format!("{}/receive", url))
+            .json(&oversize)
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 413);
+
+        // The same size is fine as an upload
+        let part = reqwest::multipart::Part::bytes(vec![7u8; JSON_BODY_LIMIT * 2]).file_name("big");
+        let resp = client
+            .post(format!("{}/upload", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap();
+        assert!(resp.status().is_success());
+    }
+
+    #[tokio::test]
+    async fn test_receive_without_candidates_explains_misconfiguration() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        assert!(shared.peers_http.is_empty());
+        let url = spawn_stub(app(shared.clone())).await;
+        let hash = iroh_blobs::Hash::new(b"nobody has this");
+        let resp = reqwest::Client::new()
+            .post(format!("{}/receive", url))
+            .json(&serde_json::json!({
+                "hash": hash.to_string(),
+                "filename": "x.png",
+                "content_type": "image/png",
+            }))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status().as_u16(), 503);
+        let body = resp.text().await.unwrap();
+        assert!(body.contains("configure PEER_HTTP_URLS"), "{body}");
+        let s = shared.state.lock().await.clone();
+        assert_eq!(s.last_error.as_deref(), Some(body.as_str()));
+        assert_eq!(s.download_strategy, None);
+    }
+
+    #[tokio::test]
+    async fn test_receive_deadline_aborts_whole_attempt() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = iroh_blobs::Hash::new(b"never arrives");
+        // P2P candidate fails fast, then the HTTP fallback peer stalls
+        let stalled = spawn_stub(Router::new().route(
+            "/blob/:hash",
+            get(|| async {
+                sleep(Duration::from_secs(30)).await;
+                StatusCode::OK
+            }),
+        ))
+        .await;
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            http_blob_fallback: true,
+            receive_deadline: Some(Duration::from_millis(500)),
+            ..(*shared).clone()
+        });
+        shared
+            .peer_images
+            .lock()
+            .await
+            .insert(stalled, hash.to_string());
+        let dead = iroh::SecretKey::generate(thread_rng()).public();
+
+        let started = std::time::Instant::now();
+        let err = shared
+            .receive_by_discovery(
+                hash,
+                "x.png".into(),
+                "image/png".into(),
+                Some(NodeAddr::new(dead)),
+            )
+            .await
+            .unwrap_err();
+        assert!(err.downcast_ref::<ReceiveTimeout>().is_some(), "{err:?}");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let s = shared.state.lock().await.clone();
+        assert_eq!(s.download_strategy, None);
+        assert_eq!(s.bytes_received, 0);
+        assert!(!s.has_image);
+        assert_eq!(
+            s.last_error.as_deref(),
+            Some("receive deadline of 500 ms exceeded")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cancel_aborts_running_receive() {
+        let dir = tempfile::tempdir().unwrap();
+        let hash = iroh_blobs::Hash::new(b"cancel me");
+        let stalled = spawn_stub(Router::new().route(
+            "/blob/:hash",
+            get(|| async {
+                sleep(Duration::from_secs(30)).await;
+                StatusCode::OK
+            }),
+        ))
+        .await;
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            http_blob_fallback: true,
+            ..(*shared).clone()
+        });
+        shared
+            .peer_images
+            .lock()
+            .await
+            .insert(stalled, hash.to_string());
+        let url = spawn_stub(app(shared.clone())).await;
+        let dead = iroh::SecretKey::generate(thread_rng()).public();
+
+        let receiving = tokio::spawn({
+            let shared = shared.clone();
+            async move {
+                shared
+                    .receive_by_discovery(
+                        hash,
+                        "x.png".into(),
+                        "image/png".into(),
+                        Some(NodeAddr::new(dead)),
+                    )
+                    .await
+            }
+        });
+        // Wait for it to get stuck on the HTTP fallback
+        while shared
+            .state
+            .lock()
+            .await
+            .download_strategy
+            .as_ref()
+            .map(|d| d.mode.as_str())
+            != Some("http")
+        {
+            sleep(Duration::from_millis(20)).await;
+        }
+
+        let resp = reqwest::Client::new()
+            .post(format!("{}/cancel", url))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(
+            resp.json::<serde_json::Value>().await.unwrap()["cancelled"],
+            1
+        );
+        let err = tokio::time::timeout(Duration::from_secs(5), receiving)
+            .await
+            .expect("receive kept running after /cancel")
+            .unwrap()
+            .unwrap_err();
+        assert!(err.downcast_ref::<TransferCancelled>().is_some(), "{err:?}");
+        let s = shared.state.lock().await.clone();
+        assert_eq!(s.download_strategy, None);
+        assert_eq!(s.bytes_received, 0);
+        assert_eq!(s.last_error.as_deref(), Some("transfer cancelled"));
+        assert!(shared.inflight.lock().await.transfers().is_empty());
+        // The next receive gets a fresh token
+        assert!(!shared.cancel.lock().await.is_cancelled());
+    }
+
+    #[tokio::test]
+    async fn test_protocols_lists_accepted_alpns() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = spawn_stub(app(test_shared(dir.path()).await)).await;
+        let body: serde_json::Value = reqwest::get(format!("{}/protocols", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let notify_alpn = "/iroh-demo/image-notify/1";
+        assert_eq!(body["notify"]["alpn"], notify_alpn);
+        assert_eq!(body["notify"]["version"], 1);
+        let alpns = body["alpns"].as_array().unwrap();
+        let listed = alpns.iter().any(|a| a == notify_alpn);
+        assert_eq!(listed, cfg!(feature = "p2p_notify"));
+        assert_eq!(body["notify"]["accepted"], cfg!(feature = "p2p_notify"));
+        if cfg!(feature = "p2p_notify") {
+            assert!(alpns
+                .iter()
+                .any(|a| a.as_str() == std::str::from_utf8(iroh_blobs::ALPN).ok()));
+        }
+    }
 
-        let out_path = self.data_dir.join("current.img");
-        self.store.blobs().export(hash, &out_path).await?;
-        {
-            let mut s = self.state.lock().await;
-            let recvd = s.bytes_received;
-            s.bytes_total = Some(recvd);
-            s.has_image = true;
-            s.current_filename = Some(filename.to_string());
-            s.content_type = Some(content_type.to_string());
-            s.progress = 100.0;
-            let self_id = self.endpoint.node_id().to_string();
-            let entry = s.stripe_providers.entry(self_id).or_default();
-            if !entry.iter().any(|v| v == "all") {
-                entry.push("all".to_string());
-            }
+    #[tokio::test]
+    async fn test_index_page() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = spawn_stub(app(test_shared(dir.path()).await)).await;
+        let resp = reqwest::get(format!("{}/", url)).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert!(resp.headers()["content-type"]
+            .to_str()
+            .unwrap()
+            .starts_with("text/html"));
+        let body = resp.text().await.unwrap();
+        for wired in [
+            "\"/status\"",
+            "\"/upload\"",
+            "\"/receive\"",
+            "\"/sign\"",
+            "No image yet.",
+        ] {
+            assert!(body.contains(wired), "missing {wired}");
         }
-        Ok(())
     }
-    pub async fn finish_download(
-        &self,
-        bytes: Vec<u8>,
-        filename: &str,
-        content_type: &str,
-    ) -> anyhow::Result<()> {
-        let path = self.data_dir.join("current.img");
-        fs::write(&path, bytes).await?;
-        let mut s = self.state.lock().await;
-        s.has_image = true;
-        s.current_filename = Some(filename.to_string());
-        s.content_type = Some(content_type.to_string());
-        s.progress = 100.0;
-        Ok(())
+
+    // Single-threaded runtime: if hashing ran inline, the timer below could
+    // not fire until verification had finished.
+    #[tokio::test(flavor = "current_thread")]
+    async fn test_verify_hash_runs_off_the_reactor() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let bytes = bytes::Bytes::from(vec![5u8; 64 * 1024 * 1024]);
+        let expected = iroh_blobs::Hash::new(&bytes);
+
+        let verifier = shared.clone();
+        let task = tokio::spawn(async move { verifier.verify_hash(bytes, expected).await });
+        sleep(Duration::from_millis(1)).await;
+        assert!(!task.is_finished(), "reactor was blocked by verification");
+        assert!(task.await.unwrap().unwrap());
+
+        assert!(!shared
+            .verify_hash(bytes::Bytes::from_static(b"x"), expected)
+            .await
+            .unwrap());
     }
 
-    /// Download using the ticket and update progress fields as chunks arrive
-    pub async fn receive_with_progress(
-        &self,
-        ticket: iroh_blobs::ticket::BlobTicket,
-        filename: String,
-        content_type: String,
-    ) -> anyhow::Result<()> {
+    #[tokio::test]
+    async fn test_repeat_receives_get_distinct_transfer_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = b"same bytes every time".to_vec();
+        let hash = iroh_blobs::Hash::new(&payload);
+        let provider = spawn_stub(Router::new().route(
+            "/blob/:hash",
+            get(move || {
+                let payload = payload.clone();
+                async move { payload }
+            }),
+        ))
+        .await;
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            endpoint: None,
+            blobs: None,
+            distribution: Distribution::Http,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let receive = || async {
+            reqwest::Client::new()
+                .post(format!("{}/receive", url))
+                .json(&serde_json::json!({
+                    "hash": hash.to_string(),
+                    "filename": "a.txt",
+                    "content_type": "text/plain",
+                    "provider_url": provider,
+                }))
+                .send()
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()["transfer_id"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        };
+
+        let first = receive().await;
+        let second = receive().await;
+        assert_ne!(first, second);
+        assert_eq!(shared.state.lock().await.transfer_id, Some(second));
+    }
+
+    #[tokio::test]
+    async fn test_events_stream_transfer_in_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = b"an evented image".to_vec();
+        let hash = iroh_blobs::Hash::new(&payload);
+        let provider = spawn_stub(Router::new().route(
+            "/blob/:hash",
+            get(move || {
+                let payload = payload.clone();
+                async move { payload }
+            }),
+        ))
+        .await;
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            endpoint: None,
+            blobs: None,
+            distribution: Distribution::Http,
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+
+        // Subscribed once the response headers are back
+        let mut feed = reqwest::get(format!("{}/events", url)).await.unwrap();
+        assert_eq!(feed.headers()["content-type"], "application/x-ndjson");
+        let transfer_id = shared
+            .receive_by_http(
+                hash,
+                "e.bin".into(),
+                "image/png".into(),
+                None,
+                None,
+                Some(provider),
+            )
+            .await
+            .unwrap();
+
+        let mut buf = String::new();
+        let mut events: Vec<serde_json::Value> = Vec::new();
+        while !events.iter().any(|e| e["event"] == "transfer_completed") {
+            let chunk = feed.chunk().await.unwrap().expect("feed ended early");
+            buf.push_str(std::str::from_utf8(&chunk).unwrap());
+            while let Some(end) = buf.find('\n') {
+                events.push(serde_json::from_str(&buf[..end]).unwrap());
+                buf.drain(..=end);
+            }
+        }
+        let kinds: Vec<&str> = events
+            .iter()
+            .map(|e| e["event"].as_str().unwrap())
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                "transfer_started",
+                "provider_tried",
+                "progress",
+                "transfer_completed"
+            ]
+        );
+        assert!(events
+            .iter()
+            .all(|e| e["transfer_id"] == transfer_id.as_str()));
+        assert_eq!(events[3]["hash"], hash.to_string());
+    }
+
+    #[tokio::test]
+    async fn test_restart_resumes_persisted_inflight_receive() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider
+            .store
+            .add_slice(vec![7u8; 64 * 1024])
+            .await
+            .unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
         let hash = ticket.hash();
-        let node_addr: NodeAddr = ticket.node_addr().clone();
 
-        {
-            let mut s = self.state.lock().await;
-            s.current_filename = Some(filename.clone());
-            s.content_type = Some(content_type.clone());
-            s.current_hash = Some(hash.to_string());
-            s.has_image = false;
-            s.bytes_received = 0;
-            s.bytes_total = None; // unknown until we know
-            s.progress = 0.0;
-            s.stripe_providers.clear();
+        // Before the "restart": half the blob arrived and the receive was
+        // recorded as in flight
+        let receiver = test_shared(dir_b.path()).await;
+        let endpoint = receiver.endpoint.clone().unwrap();
+        endpoint.add_node_addr(ticket.node_addr().clone()).unwrap();
+        receiver
+            .store
+            .downloader(&endpoint)
+            .download_with_opts(DownloadRequest::new(
+                GetRequest::blob_ranges(hash, ChunkRanges::chunks(0..32)),
+                vec![ticket.node_addr().node_id],
+                SplitStrategy::None,
+            ))
+            .await
+            .unwrap();
+        let path = dir_b.path().join("inflight.json");
+        let mut persisted = InFlightSet::default();
+        persisted.insert(InFlight {
+            transfer_id: "before-restart".into(),
+            hash: hash.to_string(),
+            filename: "r.bin".into(),
+            content_type: "application/octet-stream".into(),
+            content_encoding: None,
+            provider: Some(ticket.node_addr().clone()),
+            more_providers: Vec::new(),
+            stripe_span: None,
+            declared_size: None,
+            resumed: false,
+        });
+        persisted.save(&path).await.unwrap();
+
+        // Startup: load the set, then resume it
+        *receiver.inflight.lock().await = InFlightSet::load(&path).await;
+        receiver.resume_inflight().await;
+
+        let state = receiver.state.lock().await.clone();
+        assert!(state.has_image);
+        assert!(state.resumed);
+        assert_ne!(state.transfer_id.as_deref(), Some("before-restart"));
+        assert!(matches!(
+            receiver.store.blobs().status(hash).await.unwrap(),
+            BlobStatus::Complete { size: 65536 }
+        ));
+        assert!(InFlightSet::load(&path).await.transfers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sequential_fallback_stops_at_candidate_cap() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            sequential_max_candidates: Some(2),
+            ..(*shared).clone()
+        });
+        for n in 0..5 {
+            let dead = iroh::SecretKey::generate(thread_rng()).public();
+            shared
+                .peers_addrs
+                .lock()
+                .await
+                .insert(format!("http://peer{n}"), NodeAddr::new(dead));
         }
+        let hash = iroh_blobs::Hash::new(b"nobody has this");
+        assert!(shared
+            .receive_by_discovery(hash, "x".into(), "x".into(), None)
+            .await
+            .is_err());
 
-        // Start the download via the store downloader (iroh-blobs 0.93) and stream progress updates
-        let downloader = self.store.downloader(&self.endpoint);
-        let dl = downloader.download(hash, Some(node_addr.node_id));
-        let mut stream = match dl.stream().await {
-            Ok(s) => s,
-            Err(e) => {
-                return Err(e.into());
-            }
+        // Each sequential attempt marks its provider failed once
+        let scores = shared.provider_scores.lock().await;
+        let attempted = shared
+            .peers_addrs
+            .lock()
+            .await
+            .values()
+            .filter(|a| scores.get(&a.node_id.to_string()).failures > 0)
+            .count();
+        assert_eq!(attempted, 2);
+    }
+
+    #[tokio::test]
+    async fn test_all_complete_flags_divergent_provider() {
+        let dirs: Vec<_> = (0..3).map(|_| tempfile::tempdir().unwrap()).collect();
+        let provider = |dir: PathBuf, content: Vec<u8>| async move {
+            let node = test_shared(&dir).await;
+            let router = iroh::protocol::Router::builder(node.endpoint.clone().unwrap())
+                .accept(iroh_blobs::ALPN, node.blobs.clone().unwrap())
+                .spawn();
+            let tag = node.store.add_slice(content).await.unwrap();
+            let ticket = node.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+            (router, ticket)
         };
+        let (_good_router, good) = provider(dirs[0].path().into(), vec![7u8; 4096]).await;
+        let (_bad_router, bad) = provider(dirs[1].path().into(), vec![8u8; 4096]).await;
+        let hash = good.hash();
 
-        while let Some(item) = stream.next().await {
-            match item {
-                DownloadProgessItem::Progress(recvd) => {
-                    let mut s = self.state.lock().await;
-                    s.bytes_received = recvd;
-                    if let Some(t) = s.bytes_total {
-                        if t > 0 {
-                            s.progress = (recvd as f32 / t as f32) * 100.0;
-                        }
-                    }
-                }
-                DownloadProgessItem::TryProvider { .. } => {}
-                DownloadProgessItem::ProviderFailed { .. } => {}
-                DownloadProgessItem::PartComplete { .. } => {}
-                DownloadProgessItem::Error(e) => {
-                    return Err(e.into());
-                }
-                DownloadProgessItem::DownloadError => {
-                    return Err(anyhow::anyhow!("download error"));
-                }
-            }
+        let receiver = test_shared(dirs[2].path()).await;
+        let receiver = Arc::new(NodeShared {
+            sequential_completion: SequentialCompletion::AllComplete { providers: 2 },
+            ..(*receiver).clone()
+        });
+        let endpoint = receiver.endpoint.clone().unwrap();
+        for ticket in [&good, &bad] {
+            endpoint.add_node_addr(ticket.node_addr().clone()).unwrap();
         }
 
-        // Export the downloaded blob to our HTTP-served location
-        let out_path = self.data_dir.join("current.img");
-        let _ = self.store.blobs().export(hash, &out_path).await;
-        // Mark as complete in state
-        {
-            let mut s = self.state.lock().await;
-            let recvd = s.bytes_received;
-            s.bytes_total = Some(recvd);
-            s.has_image = true;
-            s.current_filename = Some(filename);
-            s.content_type = Some(content_type);
-            s.progress = 100.0;
-            s.stripe_providers
-                .entry(node_addr.node_id.to_string())
-                .or_insert_with(|| vec!["all".to_string()]);
-            let self_id = self.endpoint.node_id().to_string();
-            let entry = s.stripe_providers.entry(self_id).or_default();
-            if !entry.iter().any(|v| v == "all") {
-                entry.push("all".to_string());
-            }
+        let agreeing = [good.node_addr().node_id];
+        assert!(receiver
+            .confirm_with_providers(hash, &agreeing)
+            .await
+            .is_ok());
+        let err = receiver
+            .confirm_with_providers(hash, &[good.node_addr().node_id, bad.node_addr().node_id])
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&bad.node_addr().node_id.fmt_short().to_string()));
+
+        let url = spawn_stub(app(receiver.clone())).await;
+        let status: serde_json::Value = reqwest::get(format!("{}/status", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(
+            status["sequential_completion"],
+            serde_json::json!({ "mode": "all_complete", "providers": 2 })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pausable_budget_stands_still_while_paused() {
+        let (pause, paused) = tokio::sync::watch::channel(true);
+        let mut budget = PausableBudget::new(paused, Duration::from_millis(100));
+        // 300 ms paused and 50 ms running fit a 100 ms budget
+        let work = async {
+            sleep(Duration::from_millis(350)).await;
+            7
+        };
+        let resume = async {
+            sleep(Duration::from_millis(300)).await;
+            pause.send(false).unwrap();
+        };
+        let (out, ()) = tokio::join!(budget.run(work), resume);
+        assert_eq!(out, Some(7));
+        assert_eq!(budget.left, Duration::from_millis(50));
+        // Unpaused, what's left of it still runs out
+        let started = tokio::time::Instant::now();
+        assert_eq!(budget.run(std::future::pending::<()>()).await, None);
+        assert_eq!(started.elapsed(), Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_stall_watchdog_ends_silent_split() {
+        let request = Arc::new(GetRequest::blob(iroh_blobs::Hash::new(b"x")));
+        let id = iroh::SecretKey::generate(thread_rng()).public();
+        // A provider is tried, sends some bytes, then goes quiet for good
+        let items = futures_util::stream::iter([
+            DownloadProgessItem::TryProvider {
+                id,
+                request: request.clone(),
+            },
+            DownloadProgessItem::Progress(10),
+        ])
+        .chain(futures_util::stream::pending());
+        let (_pause, paused) = tokio::sync::watch::channel(false);
+        let watched = stall_watchdog(items, Some(Duration::from_millis(50)), paused);
+        let seen: Vec<_> =
+            tokio::time::timeout(Duration::from_secs(5), watched.collect::<Vec<_>>())
+                .await
+                .expect("watchdog never fired");
+        assert!(matches!(seen[0], DownloadProgessItem::TryProvider { .. }));
+        assert!(matches!(seen[1], DownloadProgessItem::Progress(10)));
+        match &seen[2..] {
+            [DownloadProgessItem::Error(e)] => assert!(e.to_string().contains("no progress")),
+            other => panic!("expected a single stall error, got {other:?}"),
         }
-        Ok(())
+
+        // A stalled split falls back to a sequential download
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider
+            .store
+            .add_slice(vec![3u8; 64 * 1024])
+            .await
+            .unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+        let receiver = test_shared(dir_b.path()).await;
+        let receiver = Arc::new(NodeShared {
+            split_stall: Some(Duration::ZERO),
+            ..(*receiver).clone()
+        });
+        receiver
+            .receive_by_discovery(
+                ticket.hash(),
+                "f.bin".into(),
+                "application/octet-stream".into(),
+                Some(ticket.node_addr().clone()),
+            )
+            .await
+            .unwrap();
+        let s = receiver.state.lock().await.clone();
+        assert!(s.has_image);
+        // Only a sequential download times a provider on its own
+        let provider_id = ticket.node_addr().node_id.to_string();
+        let score = receiver.provider_scores.lock().await.get(&provider_id);
+        assert!(score.throughput_bps.is_some());
     }
-}
 
-fn request_key(req: &GetRequest) -> String {
-    format!("{}::{:?}", req.hash, req.ranges)
-}
+    #[tokio::test]
+    async fn test_split_gap_is_filled_by_follow_up_fetch() {
+        let (dir_a, dir_b) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        let provider = test_shared(dir_a.path()).await;
+        let _router = iroh::protocol::Router::builder(provider.endpoint.clone().unwrap())
+            .accept(iroh_blobs::ALPN, provider.blobs.clone().unwrap())
+            .spawn();
+        let tag = provider
+            .store
+            .add_slice(vec![1u8; 64 * 1024])
+            .await
+            .unwrap();
+        let ticket = provider.blobs.as_ref().unwrap().ticket(tag).await.unwrap();
+        let hash = ticket.hash();
 
-fn describe_request(req: &GetRequest) -> String {
-    if let Some((offset, ranges)) = req.ranges.as_single() {
-        format!("offset={} ranges={:?}", offset, ranges)
-    } else {
-        format!("ranges={:?}", req.ranges)
+        let receiver = test_shared(dir_b.path()).await;
+        let endpoint = receiver.endpoint.clone().unwrap();
+        endpoint.add_node_addr(ticket.node_addr().clone()).unwrap();
+        let downloader = receiver.store.downloader(&endpoint);
+        let providers = vec![ticket.node_addr().node_id];
+
+        // The stripe covering chunks 16..32 is "dropped"
+        let delivered = ChunkRanges::chunks(0..16) | ChunkRanges::chunks(32..64);
+        let first = GetRequest::blob_ranges(hash, delivered);
+        downloader
+            .download_with_opts(DownloadRequest::new(
+                first.clone(),
+                providers.clone(),
+                SplitStrategy::None,
+            ))
+            .await
+            .unwrap();
+        assert!(matches!(
+            receiver.store.blobs().status(hash).await.unwrap(),
+            BlobStatus::Partial { .. }
+        ));
+        let mut coverage = Coverage::default();
+        coverage.record(&first);
+        assert_eq!(coverage.missing(64), ChunkRanges::chunks(16..32));
+
+        receiver
+            .fill_coverage_gaps(&downloader, hash, &coverage, providers)
+            .await
+            .unwrap();
+        assert!(matches!(
+            receiver.store.blobs().status(hash).await.unwrap(),
+            BlobStatus::Complete { size: 65536 }
+        ));
     }
-}
 
-/// Best‑effort extractor for bytes from a generic progress event (MVP; tolerant to API changes)
-#[allow(dead_code)]
-fn progress_bytes(evt: &impl core::fmt::Debug) -> Option<(u64, Option<u64>)> {
-    let s = format!("{:?}", evt);
-    let nums: Vec<u64> = s
-        .split(|c: char| !c.is_ascii_digit())
-        .filter_map(|t| t.parse().ok())
-        .collect();
-    let recvd = nums.get(0).copied();
-    let total = nums.get(1).copied();
-    recvd.map(|r| (r, total))
-}
+    #[tokio::test]
+    async fn test_upload_and_sync_reports_partial_sync_on_timeout() {
+        let payload = b"cluster image".to_vec();
+        let hash = iroh_blobs::Hash::new(&payload).to_string();
+        // One peer picks the image up, the other never does
+        let stub_peer = |current: Option<String>| {
+            Router::new()
+                .route(
+                    "/status",
+                    get(move || {
+                        let current = current.clone();
+                        async move {
+                            Json(serde_json::json!({
+                                "has_image": current.is_some(),
+                                "current_hash": current,
+                            }))
+                        }
+                    }),
+                )
+                .route("/receive", post(|| async { StatusCode::OK }))
+        };
+        let synced = spawn_stub(stub_peer(Some(hash.clone()))).await;
+        let stuck = spawn_stub(stub_peer(None)).await;
 
-/// Best-effort fan-out to peers about a new blob hash.
-///
-/// First attempts P2P notify via iroh using any known `NodeAddr`s. If the
-/// address book is empty or a send fails, falls back to HTTP `/receive`.
-/// Why: ensures reliability during early boot or partial discovery.
-async fn notify_all_peers(shared: Arc<NodeShared>, msg: NotifyMsg) {
-    maybe_latency(&shared).await;
-    let addrs = shared.peers_addrs.lock().await.clone();
-    if addrs.is_empty() {
-        warn!("no peer NodeAddrs known yet; using HTTP fallback");
-        let body = serde_json::json!({
-            "hash": &msg.hash,
-            "filename": &msg.filename,
-            "content_type": &msg.content_type,
-            "provider_node_id": &msg.provider_node_id,
-        })
-        .to_string();
-        for url in &shared.peers_http {
-            let _ = reqwest::Client::new()
-                .post(format!("{}/receive", url))
-                .header("Content-Type", "application/json")
-                .body(body.clone())
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let shared = Arc::new(NodeShared {
+            peers_http: vec![synced.clone(), stuck.clone()],
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared)).await;
+
+        let part = reqwest::multipart::Part::bytes(payload).file_name("c.png");
+        let body: serde_json::Value = reqwest::Client::new()
+            .post(format!("{}/upload_and_sync?timeout_ms=300", url))
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        assert_eq!(body["hash"], hash.as_str());
+        let sync = &body["sync"];
+        assert_eq!(sync["all_synced"], false);
+        assert!(sync["elapsed_ms"].as_u64().unwrap() >= 300);
+        assert_eq!(sync["peers"][0]["url"], synced.as_str());
+        assert_eq!(sync["peers"][0]["synced"], true);
+        assert!(sync["peers"][0]["synced_after_ms"].is_u64());
+        assert_eq!(sync["peers"][1]["synced"], false);
+        assert!(sync["peers"][1]["synced_after_ms"].is_null());
+    }
+
+    #[test]
+    fn test_sync_timeout_is_capped() {
+        let timeout = |timeout_ms| SyncQuery { timeout_ms }.timeout();
+        assert_eq!(timeout(None), SYNC_TIMEOUT);
+        assert_eq!(timeout(Some(300)), Duration::from_millis(300));
+        assert_eq!(timeout(Some(u64::MAX)), MAX_SYNC_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn test_upload_limit_rejects_with_429() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let slots = UploadSlots::new(1, false);
+        let shared = Arc::new(NodeShared {
+            upload_slots: Some(slots.clone()),
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared)).await;
+        let upload = || async {
+            let part = reqwest::multipart::Part::bytes(b"busy".to_vec()).file_name("b.png");
+            reqwest::Client::new()
+                .post(format!("{}/upload", url))
+                .multipart(reqwest::multipart::Form::new().part("file", part))
                 .send()
-                .await;
-        }
-        return;
+                .await
+                .unwrap()
+                .status()
+        };
+        let store = || async {
+            reqwest::get(format!("{}/store", url))
+                .await
+                .unwrap()
+                .json::<serde_json::Value>()
+                .await
+                .unwrap()["uploads"]
+                .clone()
+        };
+
+        // Another upload holds the only slot
+        let held = slots.acquire().await.unwrap();
+        assert_eq!(
+            store().await,
+            serde_json::json!({ "max": 1, "available": 0 })
+        );
+        assert_eq!(upload().await, 429);
+
+        drop(held);
+        assert_eq!(upload().await, 200);
+        // Released again once the upload finished
+        assert_eq!(store().await["available"], 1);
     }
-    for (url, addr) in addrs {
-        maybe_latency(&shared).await;
-        if let Err(e) = send_notify(&shared.endpoint, addr, &msg).await {
-            warn!(?e, %url, "p2p notify failed; attempting HTTP fallback");
-            let body = serde_json::json!({
-                "hash": &msg.hash,
-                "filename": &msg.filename,
-                "content_type": &msg.content_type,
-                "provider_node_id": &msg.provider_node_id,
-            })
+
+    #[tokio::test]
+    async fn test_conditional_upload_skips_identical_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let url = spawn_stub(app(shared.clone())).await;
+        let upload = |if_none_match: Option<String>| {
+            let url = url.clone();
+            async move {
+                let part =
+                    reqwest::multipart::Part::bytes(b"seed image".to_vec()).file_name("s.png");
+                let mut req = reqwest::Client::new()
+                    .post(format!("{}/upload", url))
+                    .multipart(reqwest::multipart::Form::new().part("file", part));
+                if let Some(tag) = if_none_match {
+                    req = req.header("If-None-Match", tag);
+                }
+                req.send().await.unwrap()
+            }
+        };
+
+        let first = upload(None).await;
+        assert_eq!(first.status(), 200);
+        let hash = first.json::<serde_json::Value>().await.unwrap()["hash"]
+            .as_str()
+            .unwrap()
             .to_string();
-            let _ = reqwest::Client::new()
-                .post(format!("{}/receive", url))
-                .header("Content-Type", "application/json")
-                .body(body)
+
+        let second = upload(Some(format!("\"{hash}\""))).await;
+        assert_eq!(second.status(), 304);
+        assert_eq!(second.headers()["etag"], format!("\"{hash}\"").as_str());
+
+        // A different hash is not a match: the upload goes through
+        let other = iroh_blobs::Hash::new(b"other").to_string();
+        assert_eq!(upload(Some(other)).await.status(), 200);
+    }
+
+    #[tokio::test]
+    async fn test_signed_image_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("current.img"), b"img")
+            .await
+            .unwrap();
+        let shared = test_shared(dir.path()).await;
+        let signer = UrlSigner::new("k");
+        let shared = Arc::new(NodeShared {
+            url_signer: Some(signer.clone()),
+            ..(*shared).clone()
+        });
+        let url = spawn_stub(app(shared.clone())).await;
+        let client = reqwest::Client::new();
+        let get = |path: String| client.get(format!("{}{}", url, path)).send();
+
+        assert_eq!(get("/image".into()).await.unwrap().status(), 403);
+        // The demo page stays open and learns from /status that it must sign
+        assert_eq!(get("/".into()).await.unwrap().status(), 200);
+        let status: serde_json::Value = get("/status".into()).await.unwrap().json().await.unwrap();
+        assert_eq!(status["signed_urls"], true);
+        let good = signer.signed_url("/image", unix_now() + 60);
+        let resp = get(good.clone()).await.unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"img");
+        let expired = signer.signed_url("/image", unix_now() - 1);
+        assert_eq!(get(expired).await.unwrap().status(), 403);
+        // A signature for /image doesn't unlock /image_stream
+        let moved = good.replacen("/image", "/image_stream", 1);
+        assert_eq!(get(moved).await.unwrap().status(), 403);
+
+        let minted: serde_json::Value = client
+            .post(format!("{}/sign", url))
+            .bearer_auth("k")
+            .json(&serde_json::json!({ "path": "/image_stream", "ttl_secs": 60 }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let minted = minted["url"].as_str().unwrap().to_string();
+        assert_eq!(get(minted).await.unwrap().status(), 200);
+
+        // Blobs are signed per hash, like held images
+        let hash = shared.store.add_slice(b"blob").await.unwrap().hash;
+        assert_eq!(get(format!("/blob/{hash}")).await.unwrap().status(), 403);
+        let minted: serde_json::Value = client
+            .post(format!("{}/sign", url))
+            .bearer_auth("k")
+            .json(&serde_json::json!({ "path": format!("/blob/{hash}") }))
+            .send()
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let resp = get(minted["url"].as_str().unwrap().to_string())
+            .await
+            .unwrap();
+        assert_eq!(resp.status(), 200);
+        assert_eq!(&resp.bytes().await.unwrap()[..], b"blob");
+        for path in ["/blob/not-a-hash", "/status"] {
+            let refused = client
+                .post(format!("{}/sign", url))
+                .bearer_auth("k")
+                .json(&serde_json::json!({ "path": path }))
                 .send()
-                .await;
+                .await
+                .unwrap();
+            assert_eq!(refused.status(), 400);
         }
+        let denied = client
+            .post(format!("{}/sign", url))
+            .bearer_auth("wrong")
+            .json(&serde_json::json!({}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(denied.status(), 401);
     }
-}
 
-async fn peer_addr_refresher(shared: Arc<NodeShared>) {
-    let client = reqwest::Client::new();
-    loop {
-        for url in &shared.peers_http {
-            if let Ok(resp) = client.get(format!("{}/status", url)).send().await {
-                if let Ok(StatusPeerResp { node_addr }) = resp.json::<StatusPeerResp>().await {
-                    if let Some(na) = node_addr
-                        .and_then(|s| s.parse::<PublicKey>().ok())
-                        .map(NodeAddr::from)
-                    {
-                        shared.peers_addrs.lock().await.insert(url.clone(), na);
-                    }
-                }
-            }
+    #[tokio::test]
+    async fn test_store_cap_evicts_oldest_unpinned_blob() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let add = |data: &'static [u8]| {
+            let shared = shared.clone();
+            async move { shared.store.add_slice(data).await.unwrap().hash }
+        };
+        let pinned = add(&[1; 100]).await;
+        let oldest = add(&[2; 100]).await;
+        let newer = add(&[3; 100]).await;
+        let shared = Arc::new(NodeShared {
+            store_max_bytes: Some(300),
+            pinned: HashSet::from([pinned]),
+            ..(*shared).clone()
+        });
+        shared.retain_blob(pinned).await;
+        shared.retain_blob(oldest).await;
+        shared.retain_blob(newer).await;
+        assert_eq!(shared.store_usage.lock().await.total_bytes(), 300);
+
+        // The fourth blob pushes usage over the cap: the oldest unpinned blob goes
+        let newest = add(&[4; 100]).await;
+        shared.state.lock().await.current_hash = Some(newest.to_string());
+        shared.retain_blob(newest).await;
+
+        let mut tagged = HashSet::new();
+        let mut tags = shared.store.tags().list().await.unwrap();
+        while let Some(tag) = tags.next().await {
+            tagged.insert(tag.unwrap().hash);
         }
-        sleep(Duration::from_millis(1000)).await;
+        assert!(!tagged.contains(&oldest));
+        assert!(tagged.contains(&pinned));
+        assert!(tagged.contains(&newer));
+        assert!(tagged.contains(&newest));
+        assert_eq!(shared.store_usage.lock().await.total_bytes(), 300);
     }
-}
 
-async fn maybe_latency(shared: &NodeShared) {
-    let min = shared.latency_min;
-    let max = shared.latency_max.max(min);
-    if max == 0 {
-        return;
+    #[tokio::test]
+    async fn test_paused_receive_outlives_store_gc() {
+        let data = random_blob();
+        let seeded = spawn_blob_providers(1, &data).await;
+        let dir = tempfile::tempdir().unwrap();
+        let receiver = test_shared(dir.path()).await;
+        let mut opts = FsStoreOptions::new(&dir.path().join("gc"));
+        opts.gc = Some(GcConfig {
+            interval: Duration::from_millis(200),
+            add_protected: None,
+        });
+        let store = FsStore::load_with_opts(dir.path().join("gc/blobs.db"), opts)
+            .await
+            .unwrap();
+        let receiver = Arc::new(NodeShared {
+            store: Arc::new(store),
+            store_max_bytes: Some(1 << 30),
+            ..(*receiver).clone()
+        });
+        seeded.introduce_to(&receiver);
+        // Already in the store but untagged, like a download about to finish
+        let hash = receiver
+            .store
+            .add_slice(&data)
+            .temp_tag()
+            .await
+            .unwrap()
+            .hash()
+            .to_owned();
+
+        receiver.paused.send_replace(true);
+        receiver
+            .spawn_receive(
+                hash,
+                "gc.bin".into(),
+                "application/octet-stream".into(),
+                None,
+                None,
+                Some(seeded.addrs[0].clone()),
+            )
+            .await;
+        while receiver.inflight.lock().await.transfers().is_empty() {
+            sleep(Duration::from_millis(5)).await;
+        }
+        // Several GC runs while the receive is held
+        sleep(Duration::from_secs(1)).await;
+        assert!(matches!(
+            receiver.store.blobs().status(hash).await.unwrap(),
+            BlobStatus::Complete { .. }
+        ));
+
+        receiver.paused.send_replace(false);
+        let started = std::time::Instant::now();
+        while !receiver.state.lock().await.has_image {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "never finished"
+            );
+            sleep(Duration::from_millis(20)).await;
+        }
+        // Tagged now, so later GC runs keep it too
+        sleep(Duration::from_millis(500)).await;
+        assert!(matches!(
+            receiver.store.blobs().status(hash).await.unwrap(),
+            BlobStatus::Complete { .. }
+        ));
     }
-    let dur = {
-        let mut rng = thread_rng();
-        rng.gen_range(min..=max)
-    };
-    sleep(Duration::from_millis(dur)).await;
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[tokio::test]
+    async fn test_expired_blobs_are_untagged_by_sweeper() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let add = |data: &'static [u8]| {
+            let shared = shared.clone();
+            async move { shared.store.add_slice(data).await.unwrap().hash }
+        };
+        let (pinned, current, expiring) = (
+            add(&[1; 10]).await,
+            add(&[2; 10]).await,
+            add(&[3; 10]).await,
+        );
+        let shared = Arc::new(NodeShared {
+            blob_ttl: Some(Duration::from_millis(500)),
+            pinned: HashSet::from([pinned]),
+            ..(*shared).clone()
+        });
+        for hash in [pinned, current, expiring] {
+            shared.retain_blob(hash).await;
+        }
+        shared.state.lock().await.current_hash = Some(current.to_string());
+        let url = spawn_stub(app(shared.clone())).await;
+        let listed: serde_json::Value = reqwest::get(format!("{}/blobs", url))
+            .await
+            .unwrap()
+            .json()
+            .await
+            .unwrap();
+        let blobs = listed["blobs"].as_array().unwrap();
+        assert_eq!(blobs.len(), 3);
+        assert!(blobs
+            .iter()
+            .all(|b| b["ttl_remaining_ms"].as_u64().unwrap() <= 500));
 
-    #[test]
-    fn test_progress_bytes_with_total() {
-        let dbg = "DownloadProgress { received: 50, total: 200 }";
-        let out = progress_bytes(&dbg).unwrap();
-        assert_eq!(out.0, 50);
-        assert_eq!(out.1, Some(200));
+        // Nothing has expired yet
+        shared.sweep_expired_blobs().await;
+        assert_eq!(shared.store_usage.lock().await.len(), 3);
+
+        sleep(Duration::from_millis(500)).await;
+        shared.sweep_expired_blobs().await;
+        let mut tagged = HashSet::new();
+        let mut tags = shared.store.tags().list().await.unwrap();
+        while let Some(tag) = tags.next().await {
+            tagged.insert(tag.unwrap().hash);
+        }
+        assert_eq!(tagged, HashSet::from([pinned, current]));
+        assert_eq!(shared.store_usage.lock().await.len(), 2);
     }
 
     #[test]
-    fn test_progress_bytes_only_received() {
-        let dbg = "Ev { received: 123 }";
-        let out = progress_bytes(&dbg).unwrap();
-        assert_eq!(out.0, 123);
-        assert_eq!(out.1, None);
+    fn test_distribution_from_env_value() {
+        assert_eq!(Distribution::from_env_value("http"), Distribution::Http);
+        assert_eq!(Distribution::from_env_value(" HTTP "), Distribution::Http);
+        assert_eq!(Distribution::from_env_value("p2p"), Distribution::P2p);
+        assert_eq!(Distribution::from_env_value(""), Distribution::P2p);
+    }
+
+    #[tokio::test]
+    async fn test_download_strategy_cleared_after_failed_receive() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let dead = iroh::SecretKey::generate(thread_rng()).public();
+        let res = shared
+            .receive_by_discovery(
+                iroh_blobs::Hash::new(b"missing"),
+                "f".into(),
+                "text/plain".into(),
+                Some(NodeAddr::from(dead)),
+            )
+            .await;
+        assert!(res.is_err());
+        assert_eq!(shared.state.lock().await.download_strategy, None);
     }
 
     #[test]
-    fn test_status_peer_resp_serde() {
-        let v: StatusPeerResp = serde_json::from_str("{\"node_addr\":null}").unwrap();
-        assert!(v.node_addr.is_none());
-        let v2: StatusPeerResp = serde_json::from_str("{\"node_addr\":\"abc\"}").unwrap();
-        assert_eq!(v2.node_addr, Some("abc".to_string()));
+    fn test_download_strategy_serializes_in_status() {
+        let state = NodeState {
+            download_strategy: Some(DownloadStrategy {
+                mode: "split".into(),
+                providers: 3,
+                round: 1,
+                current_provider: Some("abc".into()),
+            }),
+            ..Default::default()
+        };
+        let v = serde_json::to_value(&state).unwrap();
+        assert_eq!(v["download_strategy"]["mode"], "split");
+        assert_eq!(v["download_strategy"]["providers"], 3);
+        assert_eq!(v["download_strategy"]["round"], 1);
+        assert_eq!(v["download_strategy"]["current_provider"], "abc");
+    }
+
+    #[tokio::test]
+    async fn test_http_blob_fallback_rejects_wrong_hash() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = test_shared(dir.path()).await;
+        let hash = iroh_blobs::Hash::new(b"expected");
+        let url = spawn_stub(Router::new().route("/image", get(|| async { "tampered" }))).await;
+        shared
+            .peer_images
+            .lock()
+            .await
+            .insert(url, hash.to_string());
+
+        let urls = shared.peers_holding(hash).await;
+        let res = shared
+            .receive_via_http(hash, "f", "text/plain", urls, 1)
+            .await;
+        assert!(res.is_err());
+        assert!(!shared.state.lock().await.has_image);
+    }
+
+    #[tokio::test]
+    async fn test_http_blob_fetch_needs_matching_signing_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let provider = test_shared(&dir.path().join("p")).await;
+        let hash = provider.store.add_slice(b"signed").await.unwrap().hash;
+        let signing = spawn_stub(app(Arc::new(NodeShared {
+            url_signer: Some(UrlSigner::new("a")),
+            ..(*provider).clone()
+        })))
+        .await;
+        let open = spawn_stub(app(provider)).await;
+
+        let fetch = |key: Option<&'static str>, url: String, name: &'static str| {
+            let dir = dir.path().join(name);
+            async move {
+                let receiver = test_shared(&dir).await;
+                let receiver = NodeShared {
+                    url_signer: key.map(UrlSigner::new),
+                    ..(*receiver).clone()
+                };
+                receiver
+                    .receive_via_http(hash, "f", "text/plain", vec![url], 1)
+                    .await
+                    .is_ok()
+            }
+        };
+        assert!(fetch(Some("a"), signing.clone(), "same").await);
+        assert!(!fetch(Some("b"), signing.clone(), "other").await);
+        assert!(!fetch(None, signing, "none").await);
+        // A peer that doesn't sign ignores the signature
+        assert!(fetch(Some("b"), open, "open").await);
     }
 }
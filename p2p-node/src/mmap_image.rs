@@ -0,0 +1,94 @@
+use std::{fs::File, path::PathBuf, sync::Mutex, time::SystemTime};
+
+use bytes::Bytes;
+use memmap2::Mmap;
+
+/// `current.img` mapped into memory for `GET /image` (`mmap_serve` feature).
+///
+/// The mapping is reused across requests and only redone when the file's
+/// identity changes: a different image hash, length or mtime. Responses hold
+/// a reference to the mapping, so an in-flight body keeps it alive after a
+/// remap.
+///
+/// Writers must replace `current.img` by rename rather than rewriting it in
+/// place; truncating a mapped file would fault readers of the old mapping.
+#[derive(Debug)]
+pub struct MappedImage {
+    path: PathBuf,
+    cached: Mutex<Option<Mapping>>,
+}
+
+#[derive(Debug)]
+struct Mapping {
+    key: (Option<String>, u64, SystemTime),
+    bytes: Bytes,
+}
+
+impl MappedImage {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            cached: Mutex::new(None),
+        }
+    }
+
+    /// The file's contents, mapping it afresh if it changed since the last
+    /// call. `hash` is the image's current hash, when known. Blocking.
+    pub fn load(&self, hash: Option<String>) -> std::io::Result<Bytes> {
+        let meta = std::fs::metadata(&self.path)?;
+        let key = (hash, meta.len(), meta.modified()?);
+        let mut cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(mapping) = cached.as_ref().filter(|m| m.key == key) {
+            return Ok(mapping.bytes.clone());
+        }
+        let file = File::open(&self.path)?;
+        // Zero-length files can't be mapped on every platform
+        let bytes = if key.1 == 0 {
+            Bytes::new()
+        } else {
+            // SAFETY: current.img is only ever replaced by rename, so the
+            // mapped inode is never truncated underneath us
+            Bytes::from_owner(unsafe { Mmap::map(&file)? })
+        };
+        *cached = Some(Mapping {
+            key,
+            bytes: bytes.clone(),
+        });
+        Ok(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serves_file_contents_and_remaps_on_replace() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("current.img");
+        std::fs::write(&path, b"first image").unwrap();
+        let image = MappedImage::new(path.clone());
+
+        let first = image.load(Some("a".into())).unwrap();
+        assert_eq!(&first[..], b"first image");
+        // Unchanged file: same mapping
+        assert_eq!(
+            image.load(Some("a".into())).unwrap().as_ptr(),
+            first.as_ptr()
+        );
+
+        let tmp = dir.path().join("next.img");
+        std::fs::write(&tmp, b"second image!").unwrap();
+        std::fs::rename(&tmp, &path).unwrap();
+        assert_eq!(&image.load(Some("b".into())).unwrap()[..], b"second image!");
+        // The earlier response body is still readable
+        assert_eq!(&first[..], b"first image");
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = MappedImage::new(dir.path().join("current.img"));
+        assert!(image.load(None).is_err());
+    }
+}
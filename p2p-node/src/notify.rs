@@ -1,3 +1,4 @@
+use crate::notify_log::{Direction, NotifyLog};
 #[cfg(all(not(test), feature = "p2p_notify"))]
 use crate::NodeShared;
 use iroh::Endpoint;
@@ -15,15 +16,59 @@ use {
 
 pub const NOTIFY_ALPN: &[u8] = b"/iroh-demo/image-notify/1";
 
+/// Version of the notify wire format; the suffix of [`NOTIFY_ALPN`], so
+/// peers speaking another version never get connected.
+pub const NOTIFY_PROTOCOL_VERSION: u32 = 1;
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NotifyMsg {
     pub hash: String,
     pub filename: String,
     pub content_type: String,
     pub provider_node_id: Option<String>,
+    /// Provider's HTTP base URL, used by HTTP-only receivers to pull `/blob/{hash}`.
+    #[serde(default)]
+    pub provider_url: Option<String>,
+    /// The sender's logical clock for this update. A receiver ignores a
+    /// notify that isn't newer than its own; `None` is always adopted.
+    #[serde(default)]
+    pub version: Option<u64>,
+    /// Size the sender declares for the blob; checked against what arrives
+    /// when `SIZE_MISMATCH_TOLERANCE_PCT` is set.
+    #[serde(default)]
+    pub bytes_total: Option<u64>,
+    /// `Content-Encoding` the blob is stored under (`gzip`), so the receiver
+    /// serves it the same way; `None` for plain content.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_encoding: Option<String>,
+}
+
+/// Reply to a notify whose sender `NotifyAcl` rejects.
+pub const NOTIFY_DENIED: &[u8] = b"denied";
+
+/// Reply to a notify whose `version` is not newer than the receiver's.
+pub const NOTIFY_STALE: &[u8] = b"stale";
+
+/// Reply to an accepted notify, sent as soon as the download is started.
+/// The sender can follow it at the receiver's `/status?transfer=<id>`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NotifyAck {
+    pub transfer_id: String,
+}
+
+/// The transfer id in a notify reply; `None` for a denial or a bare `ok`
+/// from an older receiver.
+pub fn ack_transfer_id(ack: &[u8]) -> Option<String> {
+    serde_json::from_slice::<NotifyAck>(ack)
+        .ok()
+        .map(|a| a.transfer_id)
 }
 
 /// Accept incoming notify messages (JSON) and kick off a download (only when p2p_notify feature is enabled)
+///
+/// The download runs in the background and the notify is acknowledged right
+/// away with a [`NotifyAck`]. Notifies from senders the node's `NotifyAcl`
+/// rejects are acknowledged with [`NOTIFY_DENIED`] and otherwise ignored.
 #[cfg(all(not(test), feature = "p2p_notify"))]
 #[derive(Debug)]
 pub struct NotifyHandler {
@@ -40,45 +85,82 @@ impl ProtocolHandler for NotifyHandler {
         async move {
             // In iroh 0.91, accept_bi yields (SendStream, RecvStream)
             let (mut send, mut recv) = conn.accept_bi().await?;
+            let remote = conn.remote_node_id().map_err(AcceptError::from_err)?;
+            if !shared.notify_acl.permits(&remote) {
+                tracing::info!(%remote, "ignoring notify from sender outside NOTIFY_ALLOW/NOTIFY_DENY");
+                let _ = send.write_all(NOTIFY_DENIED).await;
+                let _ = send.finish();
+                return Ok(());
+            }
             // Limit JSON message size to 256 KiB
             let body = recv
                 .read_to_end(256 * 1024)
                 .await
                 .map_err(AcceptError::from_err)?;
             let msg: NotifyMsg = serde_json::from_slice(&body).map_err(AcceptError::from_err)?;
+            shared
+                .notify_log
+                .record(Direction::Inbound, "p2p", remote.to_string(), &msg);
             let hash: iroh_blobs::Hash = msg.hash.parse().map_err(AcceptError::from_err)?;
+            let content_encoding =
+                crate::content_encoding::normalize(msg.content_encoding.as_deref().unwrap_or(""))
+                    .map_err(|e| AcceptError::from_err(std::io::Error::other(e)))?;
+            if let Some(version) = msg.version {
+                if !shared.adopt_version(version, &remote.to_string()).await {
+                    tracing::info!(%remote, version, "ignoring stale notify");
+                    let _ = send.write_all(NOTIFY_STALE).await;
+                    let _ = send.finish();
+                    return Ok(());
+                }
+            }
             let fallback: Option<NodeAddr> = match msg.provider_node_id.as_deref() {
                 Some(pk) => pk.parse::<PublicKey>().ok().map(NodeAddr::from),
                 None => None,
             };
-            if let Err(e) = shared
-                .receive_by_discovery(hash, msg.filename, msg.content_type, fallback)
-                .await
-            {
-                tracing::error!(?e, "notify receive_by_discovery failed");
-                // We still respond on the stream, but don't fail the accept
-            }
-            let _ = send.write_all(b"ok").await;
+            let transfer_id = shared
+                .spawn_receive(
+                    hash,
+                    msg.filename,
+                    msg.content_type,
+                    content_encoding.map(str::to_string),
+                    msg.bytes_total,
+                    fallback,
+                )
+                .await;
+            let ack =
+                serde_json::to_vec(&NotifyAck { transfer_id }).map_err(AcceptError::from_err)?;
+            let _ = send.write_all(&ack).await;
             let _ = send.finish();
             Ok(())
         }
     }
 }
 
-/// Helper to send a notify message to a peer
+/// Helper to send a notify message to a peer and record it in `log`.
+/// Returns the transfer id the peer acknowledged with, if any.
 pub async fn send_notify(
     endpoint: &Endpoint,
     node_addr: iroh_base::NodeAddr,
     msg: &NotifyMsg,
-) -> anyhow::Result<()> {
+    log: &NotifyLog,
+) -> anyhow::Result<Option<String>> {
+    let peer = node_addr.node_id.to_string();
     let conn = endpoint.connect(node_addr, NOTIFY_ALPN).await?;
     let (mut send, mut recv) = conn.open_bi().await?;
     let body = serde_json::to_vec(msg)?;
     send.write_all(&body).await?;
     send.finish()?;
+    log.record(Direction::Outbound, "p2p", peer, msg);
     // Wait briefly for an ACK from the peer to reduce benign close warnings
-    let _ = timeout(Duration::from_millis(1500), recv.read_to_end(64)).await;
-    Ok(())
+    if let Ok(Ok(ack)) = timeout(Duration::from_millis(1500), recv.read_to_end(256)).await {
+        if ack == NOTIFY_DENIED {
+            tracing::warn!(peer = %conn.remote_node_id()?, "peer declined our notify");
+        } else if ack == NOTIFY_STALE {
+            tracing::warn!(peer = %conn.remote_node_id()?, "peer already has a newer image");
+        }
+        return Ok(ack_transfer_id(&ack));
+    }
+    Ok(None)
 }
 
 #[cfg(test)]
@@ -92,6 +174,10 @@ mod tests {
             filename: "f.png".into(),
             content_type: "image/png".into(),
             provider_node_id: Some("prov".into()),
+            provider_url: None,
+            version: Some(3),
+            bytes_total: None,
+            content_encoding: Some("gzip".into()),
         };
         let s = serde_json::to_string(&msg).unwrap();
         let back: NotifyMsg = serde_json::from_str(&s).unwrap();
@@ -99,10 +185,32 @@ mod tests {
         assert_eq!(back.filename, "f.png");
         assert_eq!(back.content_type, "image/png");
         assert_eq!(back.provider_node_id.as_deref(), Some("prov"));
+        assert_eq!(back.version, Some(3));
+        assert_eq!(back.content_encoding.as_deref(), Some("gzip"));
+        // Senders that predate versions
+        let old: NotifyMsg = serde_json::from_str(
+            r#"{"hash":"h","filename":"f","content_type":"c","provider_node_id":null}"#,
+        )
+        .unwrap();
+        assert_eq!(old.version, None);
+        assert_eq!(old.content_encoding, None);
+    }
+
+    #[test]
+    fn test_ack_carries_transfer_id() {
+        let ack = serde_json::to_vec(&NotifyAck {
+            transfer_id: "t-1".into(),
+        })
+        .unwrap();
+        assert_eq!(ack_transfer_id(&ack).as_deref(), Some("t-1"));
+        assert_eq!(ack_transfer_id(b"ok"), None);
+        assert_eq!(ack_transfer_id(NOTIFY_DENIED), None);
+        assert_eq!(ack_transfer_id(NOTIFY_STALE), None);
     }
 
     #[test]
     fn test_notify_alpn_value() {
         assert_eq!(NOTIFY_ALPN, b"/iroh-demo/image-notify/1");
+        assert!(NOTIFY_ALPN.ends_with(format!("/{NOTIFY_PROTOCOL_VERSION}").as_bytes()));
     }
 }
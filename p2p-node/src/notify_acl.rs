@@ -0,0 +1,89 @@
+use std::collections::BTreeSet;
+
+use iroh_base::PublicKey;
+use serde::Serialize;
+
+/// Which remotes' P2P notifies this node acts on (`NOTIFY_ALLOW` / `NOTIFY_DENY`).
+///
+/// This is independent of who may connect: a denied node can still fetch
+/// blobs from us, its notifies just don't start a download. Deny wins over
+/// allow; with no allow list every node not denied is accepted.
+///
+/// Only P2P notifies are checked, since there the remote node id is
+/// authenticated by the connection. `POST /receive` is not covered: anything
+/// an HTTP body says about its sender can be made up.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct NotifyAcl {
+    /// `None` means open: any node not in `deny`.
+    allow: Option<BTreeSet<PublicKey>>,
+    deny: BTreeSet<PublicKey>,
+}
+
+impl NotifyAcl {
+    /// Build from comma-separated node id lists. Returns the ACL and the
+    /// entries that didn't parse as node ids.
+    pub fn from_lists(allow: Option<&str>, deny: Option<&str>) -> (Self, Vec<String>) {
+        let mut invalid = Vec::new();
+        let mut parse = |list: &str| -> BTreeSet<PublicKey> {
+            list.split(',')
+                .map(str::trim)
+                .filter(|s| !s.is_empty())
+                .filter_map(|s| match s.parse() {
+                    Ok(id) => Some(id),
+                    Err(_) => {
+                        invalid.push(s.to_string());
+                        None
+                    }
+                })
+                .collect()
+        };
+        // A set but unparseable allow list admits nobody rather than everybody
+        let allow = allow.filter(|s| !s.trim().is_empty()).map(&mut parse);
+        let deny = deny.map(&mut parse).unwrap_or_default();
+        (Self { allow, deny }, invalid)
+    }
+
+    pub fn permits(&self, node: &PublicKey) -> bool {
+        !self.deny.contains(node) && self.allow.as_ref().is_none_or(|a| a.contains(node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn node() -> PublicKey {
+        iroh::SecretKey::generate(rand::thread_rng()).public()
+    }
+
+    #[test]
+    fn open_by_default() {
+        let (acl, invalid) = NotifyAcl::from_lists(Some(" "), Some(""));
+        assert!(invalid.is_empty());
+        assert!(acl.permits(&node()));
+        let (acl, _) = NotifyAcl::from_lists(Some("typo"), None);
+        assert!(!acl.permits(&node()));
+    }
+
+    #[test]
+    fn allow_list_admits_only_listed_nodes() {
+        let (trusted, stranger) = (node(), node());
+        let (acl, invalid) =
+            NotifyAcl::from_lists(Some(&format!("{trusted}, not-a-node-id")), None);
+        assert_eq!(invalid, vec!["not-a-node-id"]);
+        assert!(acl.permits(&trusted));
+        assert!(!acl.permits(&stranger));
+    }
+
+    #[test]
+    fn deny_wins_over_allow() {
+        let (a, b) = (node(), node());
+        let (acl, _) = NotifyAcl::from_lists(Some(&format!("{a},{b}")), Some(&b.to_string()));
+        assert!(acl.permits(&a));
+        assert!(!acl.permits(&b));
+        // Deny alone leaves everyone else open
+        let (acl, _) = NotifyAcl::from_lists(None, Some(&b.to_string()));
+        assert!(acl.permits(&node()));
+        assert!(!acl.permits(&b));
+    }
+}
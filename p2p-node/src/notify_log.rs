@@ -0,0 +1,149 @@
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+};
+
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::events::{Event, EventBus};
+
+/// Notify messages kept for `GET /notifies`.
+pub const NOTIFY_LOG_ENTRIES: usize = 200;
+
+/// Longest string field recorded verbatim; longer ones are cut short.
+const MAX_FIELD_LEN: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Inbound,
+    Outbound,
+}
+
+/// One notify message as this node saw it.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct NotifyRecord {
+    /// Unix seconds.
+    pub at: u64,
+    pub direction: Direction,
+    /// `p2p` or `http`.
+    pub transport: String,
+    /// The other side: a node id over P2P, a base URL or client address
+    /// over HTTP.
+    pub peer: String,
+    /// The message, sanitized by [`sanitize`].
+    pub message: Value,
+}
+
+/// Every notify sent or received, for diagnosing why a peer did or didn't
+/// pick up a blob. Each record is also published on `/events`.
+///
+/// Clones share the buffer.
+#[derive(Debug, Clone)]
+pub struct NotifyLog {
+    cap: usize,
+    records: Arc<Mutex<VecDeque<NotifyRecord>>>,
+    events: EventBus,
+}
+
+impl NotifyLog {
+    pub fn new(cap: usize, events: EventBus) -> Self {
+        Self {
+            cap,
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(cap))),
+            events,
+        }
+    }
+
+    pub fn record(
+        &self,
+        direction: Direction,
+        transport: &str,
+        peer: impl Into<String>,
+        message: &impl Serialize,
+    ) {
+        let record = NotifyRecord {
+            at: crate::unix_now(),
+            direction,
+            transport: transport.to_string(),
+            peer: peer.into(),
+            message: sanitize(serde_json::to_value(message).unwrap_or_default()),
+        };
+        if self.cap > 0 {
+            let mut records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+            if records.len() == self.cap {
+                records.pop_front();
+            }
+            records.push_back(record.clone());
+        }
+        self.events.emit(Event::NotifyMessage(record));
+    }
+
+    /// Retained records, oldest first.
+    pub fn records(&self) -> Vec<NotifyRecord> {
+        let records = self.records.lock().unwrap_or_else(|e| e.into_inner());
+        records.iter().cloned().collect()
+    }
+}
+
+/// A notify message fit for a debugging log: credentials and query strings
+/// are dropped from `provider_url`, and overlong strings are truncated.
+pub fn sanitize(mut message: Value) -> Value {
+    if let Some(fields) = message.as_object_mut() {
+        for (name, value) in fields.iter_mut() {
+            let Value::String(s) = value else {
+                continue;
+            };
+            if name == "provider_url" {
+                if let Ok(mut url) = reqwest::Url::parse(s) {
+                    let _ = url.set_username("");
+                    let _ = url.set_password(None);
+                    url.set_query(None);
+                    *s = url.to_string();
+                }
+            }
+            if s.len() > MAX_FIELD_LEN {
+                let mut end = MAX_FIELD_LEN;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                s.truncate(end);
+                s.push('…');
+            }
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strips_url_credentials_and_truncates() {
+        let long = "x".repeat(MAX_FIELD_LEN + 10);
+        let clean = sanitize(json!({
+            "provider_url": "http://user:pw@node-a:8080/?sig=abc",
+            "filename": long,
+            "version": 3,
+        }));
+        assert_eq!(clean["provider_url"], "http://node-a:8080/");
+        assert_eq!(
+            clean["filename"].as_str().unwrap().chars().count(),
+            MAX_FIELD_LEN + 1
+        );
+        assert_eq!(clean["version"], 3);
+    }
+
+    #[test]
+    fn keeps_the_newest_records() {
+        let log = NotifyLog::new(2, EventBus::default());
+        for peer in ["a", "b", "c"] {
+            log.record(Direction::Outbound, "http", peer, &json!({}));
+        }
+        let peers: Vec<_> = log.records().into_iter().map(|r| r.peer).collect();
+        assert_eq!(peers, ["b", "c"]);
+    }
+}
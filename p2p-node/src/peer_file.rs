@@ -0,0 +1,92 @@
+use std::net::SocketAddr;
+
+use iroh_base::{NodeAddr, PublicKey, RelayUrl};
+use serde::Deserialize;
+
+/// One entry of a `PEER_ADDRS_FILE`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PeerEntry {
+    pub node_id: PublicKey,
+    #[serde(default)]
+    pub direct_addresses: Vec<SocketAddr>,
+    #[serde(default)]
+    pub relay_url: Option<RelayUrl>,
+    /// The peer's HTTP base URL, if it has one; used as the `peers_addrs` key
+    /// so HTTP notify fallback can reach it.
+    #[serde(default)]
+    pub url: Option<String>,
+}
+
+impl PeerEntry {
+    pub fn node_addr(&self) -> NodeAddr {
+        NodeAddr::from_parts(
+            self.node_id,
+            self.relay_url.clone(),
+            self.direct_addresses.iter().copied(),
+        )
+    }
+
+    /// Key for `peers_addrs`: the HTTP URL when known, else the node id.
+    pub fn key(&self) -> String {
+        self.url.clone().unwrap_or_else(|| self.node_id.to_string())
+    }
+}
+
+/// Whether a `peers_addrs` key is an HTTP base URL. Keys of entries without
+/// a `url` are bare node ids, which have no HTTP API to fall back to.
+pub fn is_http_key(key: &str) -> bool {
+    key.starts_with("http://") || key.starts_with("https://")
+}
+
+/// Parse a static peer list (a JSON array of [`PeerEntry`]).
+///
+/// Entries are validated one by one: malformed ones are skipped and counted
+/// rather than failing the whole file. Returns the valid entries and the
+/// number skipped; errors only if the file is not a JSON array.
+pub fn parse(json: &[u8]) -> anyhow::Result<(Vec<PeerEntry>, usize)> {
+    let raw: Vec<serde_json::Value> = serde_json::from_slice(json)?;
+    let total = raw.len();
+    let entries: Vec<PeerEntry> = raw
+        .into_iter()
+        .filter_map(|v| serde_json::from_value(v).ok())
+        .collect();
+    let skipped = total - entries.len();
+    Ok((entries, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sample_file() {
+        let id = iroh::SecretKey::from_bytes(&[1; 32]).public();
+        let json = format!(
+            r#"[
+                {{ "node_id": "{id}", "direct_addresses": ["10.0.0.2:11204"], "relay_url": "https://relay.example/" }},
+                {{ "node_id": "{id}", "url": "http://node2:8080" }},
+                {{ "node_id": "not-a-key" }},
+                {{ "direct_addresses": ["10.0.0.3:1"] }}
+            ]"#
+        );
+        let (entries, skipped) = parse(json.as_bytes()).unwrap();
+        assert_eq!(skipped, 2);
+        assert_eq!(entries.len(), 2);
+
+        let addr = entries[0].node_addr();
+        assert_eq!(addr.node_id, id);
+        assert!(addr
+            .direct_addresses
+            .contains(&"10.0.0.2:11204".parse().unwrap()));
+        assert_eq!(
+            addr.relay_url.unwrap().to_string(),
+            "https://relay.example/"
+        );
+        assert_eq!(entries[0].key(), id.to_string());
+        assert_eq!(entries[1].key(), "http://node2:8080");
+        assert!(!is_http_key(&entries[0].key()));
+        assert!(is_http_key(&entries[1].key()));
+
+        assert!(parse(b"{}").is_err());
+    }
+}
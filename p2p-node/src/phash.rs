@@ -0,0 +1,150 @@
+//! Perceptual hashes of image content, as 16 hex digits.
+//!
+//! Two images that look the same hash to nearby values even when their bytes
+//! differ (re-encoded, recompressed, metadata stripped), so the Hamming
+//! distance between hashes measures visual similarity. Computing one needs
+//! the `phash` feature; comparing them doesn't.
+
+/// Largest width or height `compute` will decode. A pHash only needs 32x32,
+/// so anything bigger is refused rather than allocated.
+#[cfg(feature = "phash")]
+const MAX_DECODE_DIMENSION: u32 = 8192;
+
+/// Most memory the decoder may allocate for one image.
+#[cfg(feature = "phash")]
+const MAX_DECODE_ALLOC: u64 = 256 * 1024 * 1024;
+
+/// Whether `s` is a well-formed pHash: exactly 16 hex digits.
+pub fn is_valid(s: &str) -> bool {
+    s.len() == 16 && s.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Hamming distance between two hex pHashes, or `None` if either is malformed.
+pub fn distance(a: &str, b: &str) -> Option<u32> {
+    if !is_valid(a) || !is_valid(b) {
+        return None;
+    }
+    let a = u64::from_str_radix(a, 16).ok()?;
+    let b = u64::from_str_radix(b, 16).ok()?;
+    Some((a ^ b).count_ones())
+}
+
+/// DCT pHash of an encoded image: downscale to 32x32 grayscale, take the
+/// lowest 8x8 frequencies without the DC term, and set one bit per
+/// coefficient above their median. `None` for content that doesn't decode
+/// as an image or is over the decode limits. Blocking.
+#[cfg(feature = "phash")]
+pub fn compute(bytes: &[u8]) -> Option<String> {
+    use image::imageops::FilterType;
+
+    const N: usize = 32;
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(MAX_DECODE_DIMENSION);
+    limits.max_image_height = Some(MAX_DECODE_DIMENSION);
+    limits.max_alloc = Some(MAX_DECODE_ALLOC);
+    let mut reader = image::ImageReader::new(std::io::Cursor::new(bytes))
+        .with_guessed_format()
+        .ok()?;
+    reader.limits(limits);
+    let img = reader.decode().ok()?;
+    let gray = img
+        .resize_exact(N as u32, N as u32, FilterType::Triangle)
+        .to_luma8();
+    let pixels: Vec<f64> = gray.pixels().map(|p| p.0[0] as f64).collect();
+
+    // 2-D DCT-II, only the 8x8 low-frequency corner is needed
+    let cos: Vec<Vec<f64>> = (0..8)
+        .map(|u| {
+            (0..N)
+                .map(|x| {
+                    ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * N) as f64).cos()
+                })
+                .collect()
+        })
+        .collect();
+    let mut coeffs = Vec::with_capacity(64);
+    for u in 0..8 {
+        for v in 0..8 {
+            let mut sum = 0.0;
+            for y in 0..N {
+                for x in 0..N {
+                    sum += pixels[y * N + x] * cos[u][y] * cos[v][x];
+                }
+            }
+            coeffs.push(sum);
+        }
+    }
+
+    let mut sorted = coeffs[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+    let bits = coeffs
+        .iter()
+        .enumerate()
+        .skip(1)
+        .filter(|(_, &c)| c > median)
+        .fold(0u64, |acc, (i, _)| acc | 1 << i);
+    Some(format!("{bits:016x}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance_counts_differing_bits() {
+        assert_eq!(distance("00000000000000ff", "000000000000000f"), Some(4));
+        assert_eq!(distance("ffffffffffffffff", "ffffffffffffffff"), Some(0));
+        assert_eq!(distance("not-hex", "00"), None);
+        // Short, long and sign-prefixed values all parse as u64 but aren't pHashes
+        assert_eq!(distance("ff", "ff"), None);
+        assert_eq!(distance("0000000000000000ff", "0000000000000000ff"), None);
+        assert_eq!(distance("+00000000000000f", "+00000000000000f"), None);
+        assert!(is_valid("0123456789abcdef"));
+    }
+
+    #[cfg(feature = "phash")]
+    #[test]
+    fn re_encoded_image_hashes_close() {
+        use image::{ImageFormat, RgbImage};
+        use std::io::Cursor;
+
+        let encode = |img: &RgbImage, format| {
+            let mut out = Cursor::new(Vec::new());
+            img.write_to(&mut out, format).unwrap();
+            out.into_inner()
+        };
+        // Broad light and shade plus finer texture, roughly like a photo
+        let scene = RgbImage::from_fn(256, 256, |x, y| {
+            let (x, y) = (x as f64 / 256.0, y as f64 / 256.0);
+            let v = 128.0
+                + 60.0 * (x * 5.0).sin() * (y * 3.0 + 1.0).cos()
+                + 50.0 * (x - y)
+                + 30.0 * (x * 17.0).sin() * (y * 23.0).sin();
+            image::Rgb([v as u8, (v * 0.8) as u8, (255.0 - v) as u8])
+        });
+        let png = encode(&scene, ImageFormat::Png);
+        let jpeg = encode(&scene, ImageFormat::Jpeg);
+        assert_ne!(png, jpeg);
+        let (a, b) = (compute(&png).unwrap(), compute(&jpeg).unwrap());
+        assert!(distance(&a, &b).unwrap() <= 4, "{a} vs {b}");
+
+        let checkers = RgbImage::from_fn(64, 64, |x, y| {
+            if (x / 8 + y / 8) % 2 == 0 {
+                image::Rgb([255, 255, 255])
+            } else {
+                image::Rgb([0, 0, 0])
+            }
+        });
+        let c = compute(&encode(&checkers, ImageFormat::Png)).unwrap();
+        assert!(distance(&a, &c).unwrap() > 10, "{a} vs {c}");
+
+        assert_eq!(compute(b"not an image"), None);
+
+        // Over the dimension limit: refused without decoding the pixels
+        let wide = image::GrayImage::new(MAX_DECODE_DIMENSION + 1, 1);
+        let mut out = Cursor::new(Vec::new());
+        wide.write_to(&mut out, ImageFormat::Png).unwrap();
+        assert_eq!(compute(&out.into_inner()), None);
+    }
+}
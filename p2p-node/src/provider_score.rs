@@ -0,0 +1,147 @@
+use std::{collections::HashMap, path::Path, time::Duration};
+
+use serde::{Deserialize, Serialize};
+use tokio::fs;
+
+/// Weight of the newest throughput sample in the moving average.
+const THROUGHPUT_ALPHA: f64 = 0.3;
+
+/// Rolling reputation of one provider.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProviderScore {
+    pub successes: u64,
+    pub failures: u64,
+    /// Exponential moving average of delivered bytes/sec; `None` until a
+    /// transfer from this provider alone has been timed.
+    pub throughput_bps: Option<f64>,
+}
+
+impl ProviderScore {
+    /// Success rate with one phantom success and failure (Laplace smoothing),
+    /// so an unknown provider sits at 0.5 and a single outcome can't pin it
+    /// to 0 or 1.
+    pub fn reliability(&self) -> f64 {
+        (self.successes as f64 + 1.0) / ((self.successes + self.failures) as f64 + 2.0)
+    }
+
+    /// Ranking value: reliability, boosted logarithmically by throughput so
+    /// speed breaks ties without a fast-but-flaky provider winning outright.
+    pub fn score(&self) -> f64 {
+        let mbps = self.throughput_bps.unwrap_or(0.0) / 1_000_000.0;
+        self.reliability() * (1.0 + mbps.ln_1p())
+    }
+}
+
+/// Per-provider scores keyed by node id, persisted as JSON in the data dir.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ProviderScores {
+    providers: HashMap<String, ProviderScore>,
+}
+
+impl ProviderScores {
+    /// Load from `path`, starting empty if it is missing or unreadable.
+    pub async fn load(path: &Path) -> Self {
+        match fs::read(path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).unwrap_or_default(),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub async fn save(&self, path: &Path) -> anyhow::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?).await?;
+        Ok(())
+    }
+
+    /// Record a delivered transfer. `timed` is `(bytes, elapsed)` when the
+    /// provider served the whole blob, so the rate is its own.
+    pub fn record_success(&mut self, provider: &str, timed: Option<(u64, Duration)>) {
+        let entry = self.providers.entry(provider.to_string()).or_default();
+        entry.successes += 1;
+        if let Some((bytes, elapsed)) = timed {
+            let secs = elapsed.as_secs_f64();
+            if secs > 0.0 {
+                let sample = bytes as f64 / secs;
+                entry.throughput_bps = Some(match entry.throughput_bps {
+                    Some(avg) => avg + THROUGHPUT_ALPHA * (sample - avg),
+                    None => sample,
+                });
+            }
+        }
+    }
+
+    pub fn record_failure(&mut self, provider: &str) {
+        self.providers
+            .entry(provider.to_string())
+            .or_default()
+            .failures += 1;
+    }
+
+    pub fn get(&self, provider: &str) -> ProviderScore {
+        self.providers.get(provider).cloned().unwrap_or_default()
+    }
+
+    /// Sort `items` best provider first. The sort is stable, so providers with
+    /// equal scores keep their incoming (e.g. shuffled) order.
+    pub fn rank<T>(&self, items: &mut [T], provider: impl Fn(&T) -> String) {
+        items.sort_by(|a, b| {
+            let (a, b) = (
+                self.get(&provider(a)).score(),
+                self.get(&provider(b)).score(),
+            );
+            b.total_cmp(&a)
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn score_update_math() {
+        let mut scores = ProviderScores::default();
+        assert_eq!(scores.get("a").reliability(), 0.5);
+
+        scores.record_success("a", Some((2_000_000, Duration::from_secs(1))));
+        let a = scores.get("a");
+        assert_eq!(a.reliability(), 2.0 / 3.0);
+        assert_eq!(a.throughput_bps, Some(2_000_000.0));
+
+        // Moving average moves 30% of the way to the new sample
+        scores.record_success("a", Some((1_000_000, Duration::from_secs(1))));
+        assert_eq!(scores.get("a").throughput_bps, Some(1_700_000.0));
+
+        scores.record_failure("a");
+        let a = scores.get("a");
+        assert_eq!((a.successes, a.failures), (2, 1));
+        assert_eq!(a.reliability(), 3.0 / 5.0);
+    }
+
+    #[test]
+    fn ranks_fast_reliable_first_and_flaky_last() {
+        let mut scores = ProviderScores::default();
+        scores.record_success("fast", Some((50_000_000, Duration::from_secs(1))));
+        scores.record_success("slow", Some((100_000, Duration::from_secs(1))));
+        scores.record_failure("flaky");
+        scores.record_failure("flaky");
+
+        let mut order = vec!["flaky", "new", "slow", "fast"];
+        scores.rank(&mut order, |p| p.to_string());
+        assert_eq!(order, vec!["fast", "slow", "new", "flaky"]);
+    }
+
+    #[tokio::test]
+    async fn persists_across_loads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("provider_scores.json");
+        assert_eq!(
+            ProviderScores::load(&path).await.get("a"),
+            ProviderScore::default()
+        );
+
+        let mut scores = ProviderScores::default();
+        scores.record_failure("a");
+        scores.save(&path).await.unwrap();
+        assert_eq!(ProviderScores::load(&path).await.get("a").failures, 1);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Most clients tracked at once; beyond this, new clients go unlimited
+/// until idle buckets are pruned.
+pub const MAX_TRACKED_CLIENTS: usize = 10_000;
+
+/// Per-client-IP token buckets for the mutating endpoints (`RATE_LIMIT_RPS`).
+///
+/// Each client may burst up to one second's worth of requests and then gets
+/// `rps` per second. Buckets that have refilled completely carry no state
+/// worth keeping and are dropped by [`RateLimiter::prune`].
+#[derive(Debug)]
+pub struct RateLimiter {
+    rps: f64,
+    burst: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(rps: f64) -> Self {
+        Self {
+            rps,
+            burst: rps.max(1.0),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Take a token for `client`, or return how long until one is available.
+    pub fn check(&self, client: IpAddr, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= MAX_TRACKED_CLIENTS && !buckets.contains_key(&client) {
+            self.prune_locked(&mut buckets, now);
+            if buckets.len() >= MAX_TRACKED_CLIENTS {
+                return Ok(());
+            }
+        }
+        let bucket = buckets.entry(client).or_insert(Bucket {
+            tokens: self.burst,
+            updated: now,
+        });
+        let elapsed = now.saturating_duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.rps).min(self.burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            // A tiny `rps` makes the wait too long for a `Duration`
+            Err(
+                Duration::try_from_secs_f64((1.0 - bucket.tokens) / self.rps)
+                    .unwrap_or(Duration::MAX),
+            )
+        }
+    }
+
+    /// Forget clients whose buckets would be full again by `now`.
+    pub fn prune(&self, now: Instant) {
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        self.prune_locked(&mut buckets, now);
+    }
+
+    fn prune_locked(&self, buckets: &mut HashMap<IpAddr, Bucket>, now: Instant) {
+        buckets.retain(|_, b| {
+            let elapsed = now.saturating_duration_since(b.updated).as_secs_f64();
+            b.tokens + elapsed * self.rps < self.burst
+        });
+    }
+
+    pub fn tracked(&self) -> usize {
+        self.buckets.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 1));
+    const B: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    #[test]
+    fn bursts_then_limits_per_client() {
+        let limiter = RateLimiter::new(2.0);
+        let now = Instant::now();
+        assert!(limiter.check(A, now).is_ok());
+        assert!(limiter.check(A, now).is_ok());
+        let wait = limiter.check(A, now).unwrap_err();
+        assert_eq!(wait, Duration::from_millis(500));
+        // Another client has its own bucket
+        assert!(limiter.check(B, now).is_ok());
+        // Refills at `rps`
+        assert!(limiter.check(A, now + Duration::from_millis(500)).is_ok());
+        assert!(limiter.check(A, now + Duration::from_millis(500)).is_err());
+    }
+
+    #[test]
+    fn tiny_rps_waits_forever_without_panicking() {
+        let limiter = RateLimiter::new(1e-20);
+        let now = Instant::now();
+        assert!(limiter.check(A, now).is_ok());
+        assert_eq!(limiter.check(A, now).unwrap_err(), Duration::MAX);
+    }
+
+    #[test]
+    fn prune_drops_refilled_buckets() {
+        let limiter = RateLimiter::new(1.0);
+        let now = Instant::now();
+        limiter.check(A, now).unwrap();
+        limiter.check(B, now + Duration::from_millis(900)).unwrap();
+        limiter.prune(now + Duration::from_secs(1));
+        assert_eq!(limiter.tracked(), 1);
+        limiter.prune(now + Duration::from_secs(2));
+        assert_eq!(limiter.tracked(), 0);
+    }
+}
@@ -0,0 +1,148 @@
+use hmac::{Hmac, Mac};
+use serde::Deserialize;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Query parameters carried by a signed URL (`?exp=<unix secs>&sig=<hex hmac>`).
+#[derive(Debug, Default, Deserialize)]
+pub struct SignedQuery {
+    pub exp: Option<u64>,
+    pub sig: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing,
+    Expired,
+    Invalid,
+}
+
+/// Mints and checks time-limited URLs for the image endpoints.
+///
+/// The signature is an HMAC-SHA256 (keyed by `URL_SIGNING_KEY`) over the
+/// request path and expiry, so a link can't be retargeted to another endpoint
+/// or extended without the key.
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+impl std::fmt::Debug for UrlSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UrlSigner").finish_non_exhaustive()
+    }
+}
+
+impl UrlSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// Whether `token` is the signing key itself (used to gate minting).
+    pub fn is_key(&self, token: &str) -> bool {
+        let mut mac = self.mac("key-check", 0);
+        mac.update(token.as_bytes());
+        let mut expected = self.mac("key-check", 0);
+        expected.update(&self.key);
+        mac.verify_slice(&expected.finalize().into_bytes()).is_ok()
+    }
+
+    /// Hex signature for `path` valid until `exp` (unix seconds).
+    pub fn sign(&self, path: &str, exp: u64) -> String {
+        hex::encode(self.mac(path, exp).finalize().into_bytes())
+    }
+
+    /// Relative URL for `path` that stays valid until `exp`.
+    pub fn signed_url(&self, path: &str, exp: u64) -> String {
+        format!("{}?exp={}&sig={}", path, exp, self.sign(path, exp))
+    }
+
+    /// Check a request for `path` at time `now` (unix seconds).
+    pub fn verify(&self, path: &str, query: &SignedQuery, now: u64) -> Result<(), SignatureError> {
+        let (Some(exp), Some(sig)) = (query.exp, query.sig.as_deref()) else {
+            return Err(SignatureError::Missing);
+        };
+        let sig = hex::decode(sig).map_err(|_| SignatureError::Invalid)?;
+        // Constant-time comparison via the MAC itself
+        self.mac(path, exp)
+            .verify_slice(&sig)
+            .map_err(|_| SignatureError::Invalid)?;
+        if now > exp {
+            return Err(SignatureError::Expired);
+        }
+        Ok(())
+    }
+
+    fn mac(&self, path: &str, exp: u64) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.key).expect("HMAC accepts any key length");
+        mac.update(path.as_bytes());
+        mac.update(b"\n");
+        mac.update(exp.to_string().as_bytes());
+        mac
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(exp: u64, sig: String) -> SignedQuery {
+        SignedQuery {
+            exp: Some(exp),
+            sig: Some(sig),
+        }
+    }
+
+    #[test]
+    fn valid_signature_is_accepted() {
+        let signer = UrlSigner::new("secret");
+        let q = query(1_000, signer.sign("/image", 1_000));
+        assert_eq!(signer.verify("/image", &q, 999), Ok(()));
+        assert_eq!(signer.verify("/image", &q, 1_000), Ok(()));
+    }
+
+    #[test]
+    fn expired_signature_is_rejected() {
+        let signer = UrlSigner::new("secret");
+        let q = query(1_000, signer.sign("/image", 1_000));
+        assert_eq!(
+            signer.verify("/image", &q, 1_001),
+            Err(SignatureError::Expired)
+        );
+    }
+
+    #[test]
+    fn tampered_signature_is_rejected() {
+        let signer = UrlSigner::new("secret");
+        let sig = signer.sign("/image", 1_000);
+        // Extended expiry, other path, other key, garbage and missing params all fail
+        assert_eq!(
+            signer.verify("/image", &query(2_000, sig.clone()), 0),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            signer.verify("/image_stream", &query(1_000, sig.clone()), 0),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            UrlSigner::new("other").verify("/image", &query(1_000, sig), 0),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            signer.verify("/image", &query(1_000, "zz".into()), 0),
+            Err(SignatureError::Invalid)
+        );
+        assert_eq!(
+            signer.verify("/image", &SignedQuery::default(), 0),
+            Err(SignatureError::Missing)
+        );
+    }
+
+    #[test]
+    fn key_check() {
+        let signer = UrlSigner::new("secret");
+        assert!(signer.is_key("secret"));
+        assert!(!signer.is_key("guess"));
+    }
+}
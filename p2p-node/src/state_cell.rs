@@ -0,0 +1,84 @@
+use std::ops::{Deref, DerefMut};
+
+use tokio::sync::{watch, Mutex, MutexGuard};
+
+/// A mutex that announces every write: dropping a guard that was used
+/// mutably bumps a `watch` counter, so `GET /status/stream` can follow
+/// `NodeState` without each mutation site having to remember to notify.
+#[derive(Debug)]
+pub struct StateCell<T> {
+    inner: Mutex<T>,
+    changed: watch::Sender<u64>,
+}
+
+impl<T> StateCell<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            inner: Mutex::new(value),
+            changed: watch::channel(0).0,
+        }
+    }
+
+    pub async fn lock(&self) -> StateGuard<'_, T> {
+        StateGuard {
+            guard: self.inner.lock().await,
+            changed: &self.changed,
+            written: false,
+        }
+    }
+
+    /// Marked changed after each write. Writes that land while a receiver
+    /// is busy coalesce into one change.
+    pub fn subscribe(&self) -> watch::Receiver<u64> {
+        self.changed.subscribe()
+    }
+}
+
+pub struct StateGuard<'a, T> {
+    guard: MutexGuard<'a, T>,
+    changed: &'a watch::Sender<u64>,
+    written: bool,
+}
+
+impl<T> Deref for StateGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.guard
+    }
+}
+
+impl<T> DerefMut for StateGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.written = true;
+        &mut self.guard
+    }
+}
+
+impl<T> Drop for StateGuard<'_, T> {
+    fn drop(&mut self) {
+        if self.written {
+            self.changed.send_modify(|n| *n = n.wrapping_add(1));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn only_writes_notify() {
+        let cell = StateCell::new(1);
+        let mut rx = cell.subscribe();
+        assert_eq!(*cell.lock().await, 1);
+        assert!(!rx.has_changed().unwrap());
+
+        *cell.lock().await += 1;
+        *cell.lock().await += 1;
+        assert!(rx.has_changed().unwrap());
+        rx.borrow_and_update();
+        assert!(!rx.has_changed().unwrap());
+        assert_eq!(*cell.lock().await, 3);
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    collections::{HashMap, HashSet},
+    time::Instant,
+};
+
+use iroh_blobs::Hash;
+
+/// Size and recency bookkeeping for the blobs this node keeps in its store.
+///
+/// Recency is a logical clock bumped on every ingest and serve, so ordering is
+/// exact even when two accesses land in the same millisecond. Used to turn the
+/// store into a bounded LRU cache when `STORE_MAX_BYTES` is set. With
+/// `BLOB_TTL_MS` each blob also carries an expiry, refreshed when it is
+/// stored again.
+#[derive(Debug, Default)]
+pub struct StoreUsage {
+    entries: HashMap<Hash, Entry>,
+    clock: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    size: u64,
+    last_access: u64,
+    expires_at: Option<Instant>,
+}
+
+impl StoreUsage {
+    /// Track a newly stored (or re-stored) blob as most recently used.
+    pub fn record(&mut self, hash: Hash, size: u64) {
+        self.clock += 1;
+        self.entries.insert(
+            hash,
+            Entry {
+                size,
+                last_access: self.clock,
+                expires_at: None,
+            },
+        );
+    }
+
+    /// Set when a tracked blob expires.
+    pub fn expire_at(&mut self, hash: &Hash, at: Instant) {
+        if let Some(entry) = self.entries.get_mut(hash) {
+            entry.expires_at = Some(at);
+        }
+    }
+
+    /// Mark a tracked blob as just served.
+    pub fn touch(&mut self, hash: &Hash) {
+        if let Some(entry) = self.entries.get_mut(hash) {
+            self.clock += 1;
+            entry.last_access = self.clock;
+        }
+    }
+
+    pub fn remove(&mut self, hash: &Hash) {
+        self.entries.remove(hash);
+    }
+
+    pub fn total_bytes(&self) -> u64 {
+        self.entries.values().map(|e| e.size).sum()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Every tracked blob with its size and expiry.
+    pub fn blobs(&self) -> impl Iterator<Item = (&Hash, u64, Option<Instant>)> {
+        self.entries
+            .iter()
+            .map(|(hash, e)| (hash, e.size, e.expires_at))
+    }
+
+    /// Blobs whose expiry has passed by `now`, skipping `protected` ones.
+    pub fn expired(&self, now: Instant, protected: &HashSet<Hash>) -> Vec<(Hash, u64)> {
+        self.entries
+            .iter()
+            .filter(|(hash, e)| {
+                !protected.contains(*hash) && e.expires_at.is_some_and(|at| at <= now)
+            })
+            .map(|(hash, e)| (*hash, e.size))
+            .collect()
+    }
+
+    /// Blobs to evict, least recently used first, to get under `cap` bytes.
+    ///
+    /// `protected` blobs are never chosen, so the plan may leave usage above
+    /// the cap when only protected blobs remain.
+    pub fn eviction_plan(&self, cap: u64, protected: &HashSet<Hash>) -> Vec<(Hash, u64)> {
+        let mut total = self.total_bytes();
+        if total <= cap {
+            return Vec::new();
+        }
+        let mut candidates: Vec<(&Hash, &Entry)> = self
+            .entries
+            .iter()
+            .filter(|(hash, _)| !protected.contains(*hash))
+            .collect();
+        candidates.sort_by_key(|(_, e)| e.last_access);
+
+        let mut plan = Vec::new();
+        for (hash, entry) in candidates {
+            if total <= cap {
+                break;
+            }
+            total -= entry.size;
+            plan.push((*hash, entry.size));
+        }
+        plan
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn h(n: u8) -> Hash {
+        Hash::from_bytes([n; 32])
+    }
+
+    #[test]
+    fn evicts_least_recently_used_first() {
+        let mut usage = StoreUsage::default();
+        usage.record(h(1), 40);
+        usage.record(h(2), 40);
+        usage.record(h(3), 40);
+        // Serving blob 1 makes blob 2 the oldest
+        usage.touch(&h(1));
+        assert_eq!(usage.total_bytes(), 120);
+        assert_eq!(usage.eviction_plan(100, &HashSet::new()), vec![(h(2), 40)]);
+        assert_eq!(
+            usage.eviction_plan(40, &HashSet::new()),
+            vec![(h(2), 40), (h(3), 40)]
+        );
+    }
+
+    #[test]
+    fn protected_blobs_are_skipped() {
+        let mut usage = StoreUsage::default();
+        usage.record(h(1), 50);
+        usage.record(h(2), 50);
+        usage.record(h(3), 50);
+        let protected = HashSet::from([h(1), h(3)]);
+        assert_eq!(usage.eviction_plan(0, &protected), vec![(h(2), 50)]);
+    }
+
+    #[test]
+    fn expired_skips_protected_and_refreshed_blobs() {
+        let now = Instant::now();
+        let later = now + std::time::Duration::from_secs(10);
+        let mut usage = StoreUsage::default();
+        for n in 1..=3 {
+            usage.record(h(n), 10);
+            usage.expire_at(&h(n), now);
+        }
+        usage.record(h(4), 10);
+        // Re-storing blob 2 clears its old expiry; the new one is later
+        usage.record(h(2), 10);
+        usage.expire_at(&h(2), later);
+        assert_eq!(usage.expired(now, &HashSet::from([h(3)])), vec![(h(1), 10)]);
+        let mut all = usage.expired(later, &HashSet::new());
+        all.sort();
+        assert_eq!(all, vec![(h(1), 10), (h(2), 10), (h(3), 10)]);
+    }
+
+    #[test]
+    fn under_cap_evicts_nothing() {
+        let mut usage = StoreUsage::default();
+        usage.record(h(1), 10);
+        assert!(usage.eviction_plan(10, &HashSet::new()).is_empty());
+        usage.remove(&h(1));
+        assert_eq!(usage.len(), 0);
+    }
+}
@@ -0,0 +1,83 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// How far back the receive speed on `/status` looks.
+pub const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Most samples kept; progress events can arrive far faster than the window
+/// needs, so older ones are dropped early rather than buffered.
+const MAX_SAMPLES: usize = 64;
+
+/// Recent `(when, bytes_received)` samples of the in-flight transfer.
+#[derive(Debug, Default, Clone)]
+pub struct RateWindow {
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl RateWindow {
+    /// Record that `bytes` had been received by `now` and return the
+    /// bytes/second across the window. A count lower than the last one means
+    /// the transfer restarted, so the window starts over.
+    pub fn record(&mut self, now: Instant, bytes: u64) -> f32 {
+        if self.samples.back().is_some_and(|&(_, b)| bytes < b) {
+            self.samples.clear();
+        }
+        self.samples.push_back((now, bytes));
+        // Keep the newest sample from before the window as its baseline
+        while self.samples.len() > MAX_SAMPLES
+            || self
+                .samples
+                .get(1)
+                .is_some_and(|&(at, _)| now.saturating_duration_since(at) >= RATE_WINDOW)
+        {
+            self.samples.pop_front();
+        }
+        let (Some(&(t0, b0)), Some(&(t1, b1))) = (self.samples.front(), self.samples.back()) else {
+            return 0.0;
+        };
+        let secs = t1.saturating_duration_since(t0).as_secs_f32();
+        if secs > 0.0 {
+            (b1 - b0) as f32 / secs
+        } else {
+            0.0
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+}
+
+/// Whole seconds to receive `remaining` bytes at `bytes_per_sec`; `None`
+/// while nothing is moving.
+pub fn eta_seconds(remaining: u64, bytes_per_sec: f32) -> Option<u64> {
+    (bytes_per_sec > 0.0).then(|| (remaining as f64 / bytes_per_sec as f64).ceil() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rate_covers_only_the_window() {
+        let mut w = RateWindow::default();
+        let t0 = Instant::now();
+        assert_eq!(w.record(t0, 0), 0.0);
+        assert_eq!(w.record(t0 + Duration::from_secs(1), 1000), 1000.0);
+        // A fast start falls out of the window
+        w.record(t0 + Duration::from_secs(2), 10_000);
+        let rate = w.record(t0 + Duration::from_secs(8), 13_000);
+        assert_eq!(rate, 3000.0 / 6.0);
+        // Restarted transfer
+        assert_eq!(w.record(t0 + Duration::from_secs(9), 10), 0.0);
+    }
+
+    #[test]
+    fn eta_rounds_up_and_needs_movement() {
+        assert_eq!(eta_seconds(1001, 1000.0), Some(2));
+        assert_eq!(eta_seconds(0, 1000.0), Some(0));
+        assert_eq!(eta_seconds(10, 0.0), None);
+    }
+}